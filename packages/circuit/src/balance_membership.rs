@@ -0,0 +1,309 @@
+// Typed API to generate and verify proofs that the signer's balance in a balance tree
+// is at least a threshold. The leaves of a balance tree are `Poseidon(address, balance)`,
+// so the threshold can be chosen when the proof is generated instead of when the tree is built.
+
+use crate::eth_balance_membership::{compute_balance_leaf, BALANCE_BITS};
+use crate::eth_membership::to_cs_field;
+use crate::membership::{check_merkle_path, to_s_bits, MembershipError};
+use crate::utils::{efficient_ecdsa, to_affine, verify_efficient_ecdsa};
+use crate::{prove_circuit, verify_circuit, Circuit, Curve, SpartanProof, F};
+use ark_ff::{BigInteger, PrimeField};
+use ark_secp256k1::{Fq, Fr};
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
+use num_bigint::BigUint;
+
+// Check that the balance or the threshold fits in `BALANCE_BITS`
+fn check_balance(balance: &BigUint) -> Result<(), MembershipError> {
+    if balance.bits() > BALANCE_BITS as u64 {
+        return Err(MembershipError::BalanceOutOfRange);
+    }
+
+    Ok(())
+}
+
+/// Compute the leaf of `address` with `balance` in a balance tree
+pub fn balance_leaf(address: &[u8; 20], balance: &BigUint) -> Result<F, MembershipError> {
+    check_balance(balance)?;
+
+    Ok(compute_balance_leaf(
+        F::from(BigUint::from_bytes_be(address)),
+        F::from(balance.clone()),
+    ))
+}
+
+/// Compute the leaf of `address` with `balance` in a balance tree in 32 big-endian bytes
+pub fn balance_leaf_bytes(
+    address: &[u8; 20],
+    balance: &BigUint,
+) -> Result<Vec<u8>, MembershipError> {
+    Ok(balance_leaf(address, balance)?.into_bigint().to_bytes_be())
+}
+
+/// The private inputs of a balance membership proof
+#[derive(Debug, Clone)]
+pub struct BalanceMembershipWitness {
+    /// `s` part of the signature
+    pub s: Fr,
+    /// `r` part of the signature
+    pub r: Fq,
+    /// Whether the y coordinate of the signature's `R` point is odd
+    pub is_y_odd: bool,
+    /// The balance committed in the signer's leaf
+    pub balance: BigUint,
+    /// The siblings of the signer's leaf from the bottom of the tree
+    pub merkle_siblings: Vec<F>,
+    /// The position of the signer's leaf at each layer (0 for left, 1 for right)
+    pub merkle_indices: Vec<F>,
+}
+
+impl BalanceMembershipWitness {
+    /// The depth of the tree the witness is for
+    pub fn tree_depth(&self) -> usize {
+        self.merkle_siblings.len()
+    }
+
+    fn check(&self) -> Result<(), MembershipError> {
+        check_merkle_path(&self.merkle_siblings, &self.merkle_indices)?;
+        check_balance(&self.balance)
+    }
+}
+
+/// The values a balance membership proof is verified against
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BalancePublicInputs {
+    /// The root of the balance tree the signer is a member of
+    pub merkle_root: F,
+    /// The hash of the signed message
+    pub msg_hash: BigUint,
+    pub sign_in_sig: Fq,
+    pub tree_depth: usize,
+    /// The balance the signer has at least
+    pub threshold: BigUint,
+}
+
+impl BalancePublicInputs {
+    /// The Merkle root in 32 big-endian bytes
+    pub fn merkle_root_bytes(&self) -> Vec<u8> {
+        self.merkle_root.into_bigint().to_bytes_be()
+    }
+}
+
+// `BalanceMembershipProof` consists of a Spartan proof
+// and auxiliary inputs necessary for full verification.
+#[derive(CanonicalSerialize, CanonicalDeserialize)]
+pub struct BalanceMembershipProof {
+    pub proof: Vec<u8>,
+    r: Fq,
+    is_y_odd: bool,
+    msg_hash: BigUint,
+    sign_in_sig: Fq,
+    tree_depth: u8,
+}
+
+impl BalanceMembershipProof {
+    pub fn from_bytes(creddd_proof: &[u8]) -> Result<Self, MembershipError> {
+        Self::deserialize_compressed(creddd_proof).map_err(|_| MembershipError::InvalidEncoding)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        self.serialize_compressed(&mut bytes).unwrap();
+        bytes
+    }
+
+    fn spartan_proof(&self) -> Result<SpartanProof<Curve>, MembershipError> {
+        SpartanProof::<Curve>::deserialize_compressed(self.proof.as_slice())
+            .map_err(|_| MembershipError::InvalidEncoding)
+    }
+
+    /// Get the public inputs of the proof without verifying it
+    pub fn public_inputs(&self) -> Result<BalancePublicInputs, MembershipError> {
+        let pub_inputs = self.spartan_proof()?.pub_input;
+
+        // The first 4 elements of the public input are the efficient ECDSA inputs,
+        // and the 5th element is the sign_in_sig.
+        // The 6th element is the threshold, followed by the Merkle root and the overflow.
+        if pub_inputs.len() != 8 {
+            return Err(MembershipError::InvalidEncoding);
+        }
+
+        Ok(BalancePublicInputs {
+            merkle_root: pub_inputs[6],
+            msg_hash: self.msg_hash.clone(),
+            sign_in_sig: self.sign_in_sig,
+            tree_depth: self.tree_depth as usize,
+            threshold: BigUint::from(pub_inputs[5].into_bigint()),
+        })
+    }
+}
+
+/// Generate a proof that the signer of `public_inputs.msg_hash` has a leaf in the balance tree
+/// with the root `public_inputs.merkle_root`, with a balance of at least `public_inputs.threshold`
+pub fn prove(
+    witness: &BalanceMembershipWitness,
+    public_inputs: &BalancePublicInputs,
+) -> Result<BalanceMembershipProof, MembershipError> {
+    witness.check()?;
+    check_balance(&public_inputs.threshold)?;
+
+    if public_inputs.tree_depth != witness.tree_depth() {
+        return Err(MembershipError::TreeDepthMismatch);
+    }
+
+    if witness.balance < public_inputs.threshold {
+        return Err(MembershipError::BalanceBelowThreshold);
+    }
+
+    // Compute the efficient ECDSA input
+    let (u, t) = efficient_ecdsa(public_inputs.msg_hash.clone(), witness.r, witness.is_y_odd)
+        .ok_or(MembershipError::InvalidSignature)?;
+
+    // Construct the private input
+    let mut priv_input = to_s_bits(witness.s);
+
+    // Append the Merkle indices and siblings, and the balance to the private input
+    priv_input.extend_from_slice(&witness.merkle_indices);
+    priv_input.extend_from_slice(&witness.merkle_siblings);
+    priv_input.push(F::from(witness.balance.clone()));
+
+    // Construct the public input
+    let pub_input = vec![
+        to_cs_field(t.x),
+        to_cs_field(t.y),
+        to_cs_field(u.x),
+        to_cs_field(u.y),
+        to_cs_field(public_inputs.sign_in_sig),
+        F::from(public_inputs.threshold.clone()),
+        public_inputs.merkle_root,
+        F::from(0u32),
+    ];
+
+    // Generate the proof
+    let proof = prove_circuit(
+        Circuit::BalanceMembership {
+            tree_depth: witness.tree_depth(),
+        },
+        &pub_input,
+        &priv_input,
+    )?;
+
+    Ok(BalanceMembershipProof {
+        proof,
+        r: witness.r,
+        is_y_odd: witness.is_y_odd,
+        msg_hash: public_inputs.msg_hash.clone(),
+        sign_in_sig: public_inputs.sign_in_sig,
+        tree_depth: witness.tree_depth() as u8,
+    })
+}
+
+/// Verify a balance membership proof and return its public inputs
+pub fn verify(
+    creddd_proof: &BalanceMembershipProof,
+) -> Result<BalancePublicInputs, MembershipError> {
+    let public_inputs = creddd_proof.public_inputs()?;
+    let spartan_proof = creddd_proof.spartan_proof()?;
+    let pub_inputs = &spartan_proof.pub_input;
+
+    // The range check in the circuit only holds for thresholds that fit in `BALANCE_BITS`
+    check_balance(&public_inputs.threshold)?;
+
+    if pub_inputs[7] != F::from(0u32) {
+        return Err(MembershipError::BalanceBelowThreshold);
+    }
+
+    // A malformed proof can have points that aren't on the curve
+    let t = to_affine(pub_inputs[0], pub_inputs[1]).ok_or(MembershipError::InvalidSignature)?;
+    let u = to_affine(pub_inputs[2], pub_inputs[3]).ok_or(MembershipError::InvalidSignature)?;
+
+    // Verify the proof with the circuit of the tree depth
+    let is_proof_valid = verify_circuit(
+        Circuit::BalanceMembership {
+            tree_depth: public_inputs.tree_depth,
+        },
+        &spartan_proof,
+    )?;
+
+    if !is_proof_valid {
+        return Err(MembershipError::InvalidProof);
+    }
+
+    // Verify the efficient ECDSA input
+    let msg_hash = public_inputs.msg_hash.clone();
+    if !verify_efficient_ecdsa(msg_hash, creddd_proof.r, creddd_proof.is_y_odd, t, u) {
+        return Err(MembershipError::InvalidSignature);
+    }
+
+    Ok(public_inputs)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils::test_utils::{invalid_sig, zero_merkle_path};
+
+    fn witness(balance: u64) -> BalanceMembershipWitness {
+        let (s, r, is_y_odd) = invalid_sig();
+        let (merkle_siblings, merkle_indices) = zero_merkle_path(16);
+
+        BalanceMembershipWitness {
+            s,
+            r,
+            is_y_odd,
+            balance: BigUint::from(balance),
+            merkle_siblings,
+            merkle_indices,
+        }
+    }
+
+    fn public_inputs(threshold: BigUint) -> BalancePublicInputs {
+        BalancePublicInputs {
+            merkle_root: F::from(0u32),
+            msg_hash: BigUint::from(0u32),
+            sign_in_sig: Fq::from(0u32),
+            tree_depth: 16,
+            threshold,
+        }
+    }
+
+    #[test]
+    fn test_invalid_witness() {
+        assert_eq!(
+            prove(&witness(10), &public_inputs(BigUint::from(11u32))).err(),
+            Some(MembershipError::BalanceBelowThreshold)
+        );
+
+        let too_large = BigUint::from(1u32) << BALANCE_BITS;
+        assert_eq!(
+            prove(&witness(10), &public_inputs(too_large.clone())).err(),
+            Some(MembershipError::BalanceOutOfRange)
+        );
+
+        let mut large_balance = witness(0);
+        large_balance.balance = too_large;
+        assert_eq!(
+            prove(&large_balance, &public_inputs(BigUint::from(0u32))).err(),
+            Some(MembershipError::BalanceOutOfRange)
+        );
+    }
+
+    #[test]
+    fn test_balance_leaf() {
+        let address = [1u8; 20];
+        let leaf = balance_leaf(&address, &BigUint::from(10u32)).unwrap();
+
+        // The leaf commits to the balance
+        assert_ne!(leaf, balance_leaf(&address, &BigUint::from(11u32)).unwrap());
+        assert_ne!(leaf, F::from(BigUint::from_bytes_be(&address)));
+
+        assert_eq!(
+            balance_leaf(&address, &(BigUint::from(1u32) << BALANCE_BITS)).err(),
+            Some(MembershipError::BalanceOutOfRange)
+        );
+
+        assert_eq!(
+            balance_leaf_bytes(&address, &BigUint::from(10u32)).unwrap(),
+            leaf.into_bigint().to_bytes_be()
+        );
+    }
+}
@@ -0,0 +1,190 @@
+use crate::eth_membership::{recover_address, AssignedMerkleProof};
+use ark_ff::PrimeField;
+use sapir::{
+    constraint_system::ConstraintSystem,
+    frontend::gadgets::{poseidon::poseidon::PoseidonChip, to_le_bits, verify_merkle_proof},
+    merkle_tree::tree::MerkleTree,
+    poseidon::constants::secp256k1_w3,
+};
+
+/// The number of bits the balances in the leaves and the thresholds fit in.
+/// The difference of two such values doesn't wrap around the field
+/// unless the balance is below the threshold.
+pub const BALANCE_BITS: usize = 128;
+
+/// Proves that the signer's leaf `Poseidon(address, balance)` is in a tree of `tree_depth`,
+/// and that the balance is at least the public threshold.
+/// The root is exposed after the threshold, followed by the number of the bits of
+/// `balance - threshold` above `BALANCE_BITS`, which is 0 if and only if the balance is at least the threshold.
+pub fn eth_balance_membership<F: PrimeField>(cs: &mut ConstraintSystem<F>, tree_depth: usize) {
+    // #############################################
+    // Private inputs
+    // #############################################
+
+    // `s` part of the signature
+    let s_bits = cs.alloc_priv_inputs(256);
+
+    let merkle_indices = cs.alloc_priv_inputs(tree_depth);
+    let merkle_siblings = cs.alloc_priv_inputs(tree_depth);
+
+    let merkle_proof = AssignedMerkleProof::new(merkle_siblings, merkle_indices);
+
+    // The balance committed in the signer's leaf
+    let balance = cs.alloc_priv_inputs(1)[0];
+
+    // #############################################
+    // Public inputs
+    // #############################################
+
+    let t_x = cs.alloc_pub_input();
+    let t_y = cs.alloc_pub_input();
+
+    let u_x = cs.alloc_pub_input();
+    let u_y = cs.alloc_pub_input();
+
+    let sign_in_sig = cs.alloc_pub_input();
+
+    // The balance the signer has at least
+    let threshold = cs.alloc_pub_input();
+
+    // #############################################
+    // Constraints
+    // #############################################
+
+    // Bound the `sign_in_sig` to the proof
+    let _sign_in_sig_squared = sign_in_sig * sign_in_sig;
+
+    // 1. Recover the address from the signature
+    let address = recover_address(cs, &s_bits, t_x, t_y, u_x, u_y);
+
+    let mut poseidon_chip = PoseidonChip::new(cs, secp256k1_w3());
+
+    // 2. Verify the Merkle proof of the leaf that commits to the address and the balance
+    let leaf = poseidon_chip.hash(vec![address, balance]);
+
+    let root = verify_merkle_proof(
+        leaf,
+        &merkle_proof.siblings,
+        &merkle_proof.indices,
+        poseidon_chip.clone(),
+        cs,
+    );
+
+    cs.expose_public(root);
+
+    // 3. Check that the balance is at least the threshold.
+    // The balances in the leaves and the thresholds are less than 2^BALANCE_BITS,
+    // so `balance - threshold` has no bits above `BALANCE_BITS` only if it's non-negative.
+    // Otherwise it wraps around to a field element close to the modulus.
+    let diff_bits = to_le_bits(balance - threshold);
+
+    let mut overflow = diff_bits[BALANCE_BITS];
+    for bit in &diff_bits[(BALANCE_BITS + 1)..] {
+        overflow = overflow + *bit;
+    }
+
+    // The verifier checks that the overflow is 0
+    cs.expose_public(overflow);
+}
+
+/// Compute the leaf `Poseidon(address, balance)` of a balance tree outside of the circuit
+pub fn compute_balance_leaf(
+    address: ark_secq256k1::Fr,
+    balance: ark_secq256k1::Fr,
+) -> ark_secq256k1::Fr {
+    let mut tree = MerkleTree::<ark_secq256k1::Fr, 3>::new(secp256k1_w3());
+    MerkleTree::hash(&mut tree.poseidon, &[address, balance])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eth_membership::to_cs_field;
+    use crate::utils::test_utils::mock_eff_ecdsa_input;
+    use ark_ec::AffineRepr;
+    use ark_ff::BigInteger;
+    use num_bigint::BigUint;
+
+    type F = ark_secq256k1::Fr;
+
+    const TREE_DEPTH: usize = 4;
+
+    #[test]
+    fn test_eth_balance_membership() {
+        let synthesizer = |cs: &mut ConstraintSystem<_>| {
+            eth_balance_membership(cs, TREE_DEPTH);
+        };
+
+        let mut cs = ConstraintSystem::<_>::new();
+        cs.set_constraints(&synthesizer);
+
+        let eff_ecdsa_input = mock_eff_ecdsa_input(42);
+        let address = F::from(BigUint::from_bytes_be(
+            &eff_ecdsa_input.address.to_fixed_bytes(),
+        ));
+
+        let balance = F::from(1000u32);
+        let leaf = compute_balance_leaf(address, balance);
+
+        // Construct a mock tree
+        let mut leaves = vec![leaf];
+        for i in 0..(2usize.pow(TREE_DEPTH as u32) - 1) {
+            leaves.push(compute_balance_leaf(F::from(i as u32), F::from(1u32)));
+        }
+
+        let mut tree = MerkleTree::<F, 3>::new(secp256k1_w3());
+        for leaf in &leaves {
+            tree.insert(*leaf);
+        }
+
+        tree.finish();
+
+        let merkle_proof = tree.create_proof(leaf);
+
+        let mut priv_input = eff_ecdsa_input
+            .s
+            .into_bigint()
+            .to_bits_le()
+            .iter()
+            .map(|b| F::from(*b))
+            .collect::<Vec<F>>();
+
+        let merkle_indices = merkle_proof
+            .path_indices
+            .iter()
+            .map(|i| F::from(*i as u32))
+            .collect::<Vec<F>>();
+
+        priv_input.extend_from_slice(&merkle_indices);
+        priv_input.extend_from_slice(&merkle_proof.siblings);
+        priv_input.push(balance);
+
+        // Dummy sign_in_sig
+        let sign_in_sig = F::from(42u32);
+
+        let pub_input = |threshold: F| {
+            vec![
+                to_cs_field(*eff_ecdsa_input.t.x().unwrap()),
+                to_cs_field(*eff_ecdsa_input.t.y().unwrap()),
+                to_cs_field(*eff_ecdsa_input.u.x().unwrap()),
+                to_cs_field(*eff_ecdsa_input.u.y().unwrap()),
+                to_cs_field(sign_in_sig),
+                threshold,
+                to_cs_field(merkle_proof.root),
+                F::from(0u32),
+            ]
+        };
+
+        // The balance is at least the threshold
+        for threshold in [F::from(0u32), F::from(999u32), balance] {
+            let pub_input = pub_input(threshold);
+            let witness: Vec<F> = cs.gen_witness(&synthesizer, &pub_input, &priv_input);
+            assert!(cs.is_sat(&witness, &pub_input));
+        }
+
+        // The overflow isn't 0 if the balance is below the threshold
+        let pub_input = pub_input(F::from(1001u32));
+        let witness: Vec<F> = cs.gen_witness(&synthesizer, &pub_input, &priv_input);
+        assert!(!cs.is_sat(&witness, &pub_input));
+    }
+}
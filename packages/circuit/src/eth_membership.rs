@@ -1,4 +1,5 @@
 use ark_ff::PrimeField;
+use num_bigint::BigUint;
 use sapir::{
     constraint_system::{ConstraintSystem, Wire},
     frontend::gadgets::{
@@ -7,10 +8,15 @@ use sapir::{
         weierstrass::{ec_add_complete, ec_mul},
         AffinePoint,
     },
+    merkle_tree::tree::MerkleTree,
     poseidon::constants::secp256k1_w3,
 };
 
-pub const TREE_DEPTH: usize = 18;
+/// The tree depths there are circuits for
+pub const TREE_DEPTHS: [usize; 5] = [16, 18, 20, 24, 32];
+
+/// The tree depth of the proofs generated before the depth became configurable per group
+pub const DEFAULT_TREE_DEPTH: usize = 18;
 
 pub struct AssignedMerkleProof<F: PrimeField> {
     pub siblings: Vec<Wire<F>>,
@@ -23,7 +29,15 @@ impl<F: PrimeField> AssignedMerkleProof<F> {
     }
 }
 
-pub fn eth_membership<F: PrimeField>(cs: &mut ConstraintSystem<F>) {
+/// The membership circuit for a tree of `tree_depth`.
+/// With `with_nullifier`, the circuit takes the bound of a low `s` (see `low_s_bound`)
+/// after the `sign_in_sig`, and also exposes the scope, the nullifier `Poseidon(s, scope)`
+/// and whether `s` is high after the Merkle root.
+pub fn eth_membership<F: PrimeField>(
+    cs: &mut ConstraintSystem<F>,
+    tree_depth: usize,
+    with_nullifier: bool,
+) {
     // #############################################
     // Private inputs
     // #############################################
@@ -31,11 +45,18 @@ pub fn eth_membership<F: PrimeField>(cs: &mut ConstraintSystem<F>) {
     // `s` part of the signature
     let s_bits = cs.alloc_priv_inputs(256);
 
-    let merkle_indices = cs.alloc_priv_inputs(TREE_DEPTH);
-    let merkle_siblings = cs.alloc_priv_inputs(TREE_DEPTH);
+    let merkle_indices = cs.alloc_priv_inputs(tree_depth);
+    let merkle_siblings = cs.alloc_priv_inputs(tree_depth);
 
     let merkle_proof = AssignedMerkleProof::new(merkle_siblings, merkle_indices);
 
+    // The scope the nullifier is for
+    let scope = if with_nullifier {
+        Some(cs.alloc_priv_inputs(1)[0])
+    } else {
+        None
+    };
+
     // #############################################
     // Public inputs
     // #############################################
@@ -48,6 +69,13 @@ pub fn eth_membership<F: PrimeField>(cs: &mut ConstraintSystem<F>) {
 
     let sign_in_sig = cs.alloc_pub_input();
 
+    // `(n - 1) / 2` where `n` is the secp256k1 order, which the verifier checks
+    let low_s_bound = if with_nullifier {
+        Some(cs.alloc_pub_input())
+    } else {
+        None
+    };
+
     // #############################################
     // Constraints
     // #############################################
@@ -55,10 +83,61 @@ pub fn eth_membership<F: PrimeField>(cs: &mut ConstraintSystem<F>) {
     // Bound the `sign_in_sig` to the proof
     let _sign_in_sig_squared = sign_in_sig * sign_in_sig;
 
-    // 1. Recover the public key from the signature
+    // 1. Recover the address from the signature
+    let address = recover_address(cs, &s_bits, t_x, t_y, u_x, u_y);
+
+    let mut poseidon_chip = PoseidonChip::new(cs, secp256k1_w3());
+
+    // Verify the Merkle proof
+    let root = verify_merkle_proof(
+        address,
+        &merkle_proof.siblings,
+        &merkle_proof.indices,
+        poseidon_chip.clone(),
+        cs,
+    );
+
+    cs.expose_public(root);
+
+    // 2. Compute the nullifier of the signer in the scope.
+    // The verifier checks that the signed message is the scope, and wallets sign deterministically
+    // (RFC 6979), so `s` and thus the nullifier are the same for each proof of the signer.
+    // The nullifier doesn't reveal `s`, so it can't be linked to the signer's address.
+    if let (Some(scope), Some(low_s_bound)) = (scope, low_s_bound) {
+        // Pack the bits of `s` into a field element
+        let mut s = s_bits[255];
+        for bit in s_bits[..255].iter().rev() {
+            s = s + s + *bit;
+        }
+
+        let nullifier = poseidon_chip.hash(vec![s, scope]);
+
+        cs.expose_public(scope);
+        cs.expose_public(nullifier);
+
+        // 3. Check that `s` is low, since `(r, n - s)` is also a valid signature
+        // and would give another nullifier.
+        // `low_s_bound - s` is below 2^255 if `s <= low_s_bound`,
+        // and wraps around to a value above 2^255 otherwise,
+        // so its 256th bit is exposed and checked to be 0 by the verifier.
+        // This also rejects the bits of `s + n`, which are another representation of `s`.
+        let diff_bits = to_le_bits(low_s_bound - s);
+        cs.expose_public(diff_bits[255]);
+    }
+}
+
+/// Recover the Ethereum address of the signer from the efficient ECDSA signature
+pub fn recover_address<F: PrimeField>(
+    cs: &mut ConstraintSystem<F>,
+    s_bits: &[Wire<F>],
+    t_x: Wire<F>,
+    t_y: Wire<F>,
+    u_x: Wire<F>,
+    u_y: Wire<F>,
+) -> Wire<F> {
     // s_mul_t = s * T
     let t = AffinePoint::new(t_x, t_y);
-    let s_mul_t = ec_mul(t, &s_bits, cs);
+    let s_mul_t = ec_mul(t, s_bits, cs);
 
     // pubKey = sMultT + U
     let u = AffinePoint::new(u_x, u_y);
@@ -86,20 +165,27 @@ pub fn eth_membership<F: PrimeField>(cs: &mut ConstraintSystem<F>) {
     let pub_key_bits = [pub_key_x_bits_be, pub_key_y_bits_be].concat();
 
     // Get the Ethereum address from the public key
-    let address = to_addr(pub_key_bits.try_into().unwrap());
+    to_addr(pub_key_bits.try_into().unwrap())
+}
 
-    let poseidon_chip = PoseidonChip::new(cs, secp256k1_w3());
+/// The largest low `s` of a signature, `(n - 1) / 2` where `n` is the secp256k1 order
+pub fn low_s_bound() -> ark_secq256k1::Fr {
+    ark_secq256k1::Fr::from(ark_secp256k1::Fr::MODULUS_MINUS_ONE_DIV_TWO)
+}
 
-    // Verify the Merkle proof
-    let root = verify_merkle_proof(
-        address,
-        &merkle_proof.siblings,
-        &merkle_proof.indices,
-        poseidon_chip.clone(),
-        cs,
-    );
+/// The scope of the nullifier of a signature of `msg_hash`.
+/// Binding the scope to the signed message keeps a signature of another message
+/// from giving another nullifier in the scope.
+pub fn scope_of(msg_hash: &BigUint) -> ark_secq256k1::Fr {
+    ark_secq256k1::Fr::from(msg_hash.clone())
+}
 
-    cs.expose_public(root);
+/// Compute the nullifier `Poseidon(s, scope)` of a signature outside of the circuit
+pub fn compute_nullifier(s: ark_secp256k1::Fr, scope: ark_secq256k1::Fr) -> ark_secq256k1::Fr {
+    let s = ark_secq256k1::Fr::from(s.into_bigint());
+
+    let mut tree = MerkleTree::<ark_secq256k1::Fr, 3>::new(secp256k1_w3());
+    MerkleTree::hash(&mut tree.poseidon, &[s, scope])
 }
 
 pub fn to_cs_field(x: ark_secp256k1::Fq) -> ark_secq256k1::Fr {
@@ -109,18 +195,15 @@ pub fn to_cs_field(x: ark_secp256k1::Fq) -> ark_secq256k1::Fr {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::utils::test_utils::mock_eff_ecdsa_input;
+    use crate::utils::test_utils::{mock_eff_ecdsa_input, mock_merkle_path};
     use ark_ec::AffineRepr;
     use ark_ff::BigInteger;
-    use num_bigint::BigUint;
-    use sapir::merkle_tree::tree::{MerkleProof, MerkleTree};
 
     type F = ark_secq256k1::Fr;
 
-    #[test]
-    fn test_eth_membership() {
+    fn test_eth_membership_of_depth(tree_depth: usize) {
         let synthesizer = |cs: &mut ConstraintSystem<_>| {
-            eth_membership(cs);
+            eth_membership(cs, tree_depth, true);
         };
 
         let mut cs = ConstraintSystem::<_>::new();
@@ -131,20 +214,7 @@ mod tests {
             &eff_ecdsa_input.address.to_fixed_bytes(),
         ));
 
-        // Construct a mock tree
-        let mut leaves = vec![address];
-        for i in 0..(2usize.pow(TREE_DEPTH as u32) - 1) {
-            leaves.push(F::from(i as u32));
-        }
-
-        let mut tree = MerkleTree::<F, 3>::new(secp256k1_w3());
-        for leaf in &leaves {
-            tree.insert(*leaf);
-        }
-
-        tree.finish();
-
-        let merkle_proof: MerkleProof<F> = tree.create_proof(address);
+        let (merkle_siblings, merkle_indices, merkle_root) = mock_merkle_path(address, tree_depth);
 
         let mut priv_input = vec![];
 
@@ -156,17 +226,12 @@ mod tests {
             .map(|b| F::from(*b))
             .collect::<Vec<F>>();
 
-        let merkle_indices = merkle_proof
-            .path_indices
-            .iter()
-            .map(|i| F::from(*i as u32))
-            .collect::<Vec<F>>();
-
         priv_input.extend_from_slice(&s_bits);
-
-        // Just pass duplicate indices and siblings
         priv_input.extend_from_slice(&merkle_indices);
-        priv_input.extend_from_slice(&merkle_proof.siblings);
+        priv_input.extend_from_slice(&merkle_siblings);
+
+        let scope = F::from(7u32);
+        priv_input.push(scope);
 
         // Dummy sign_in_sig
         let sign_in_sig = F::from(42u32);
@@ -179,10 +244,45 @@ mod tests {
             to_cs_field(sign_in_sig),
         ];
 
-        pub_input.push(to_cs_field(merkle_proof.root));
+        pub_input.push(low_s_bound());
+        pub_input.push(merkle_root);
+        pub_input.push(scope);
+        pub_input.push(compute_nullifier(eff_ecdsa_input.s, scope));
+        pub_input.push(F::from(0u32));
 
         let witness: Vec<F> = cs.gen_witness(&synthesizer, &pub_input, &priv_input);
 
         assert!(cs.is_sat(&witness, &pub_input));
+
+        // The malleated signature `(r, n - s)` with `-T` recovers the same address,
+        // but its `s` is high
+        let high_s = -eff_ecdsa_input.s;
+        let mut priv_input = priv_input;
+        for (i, bit) in high_s.into_bigint().to_bits_le().iter().enumerate() {
+            priv_input[i] = F::from(*bit);
+        }
+
+        let mut pub_input = pub_input;
+        pub_input[1] = -pub_input[1];
+        pub_input[8] = compute_nullifier(high_s, scope);
+
+        let witness: Vec<F> = cs.gen_witness(&synthesizer, &pub_input, &priv_input);
+        assert!(!cs.is_sat(&witness, &pub_input));
+
+        pub_input[9] = F::from(1u32);
+        let witness: Vec<F> = cs.gen_witness(&synthesizer, &pub_input, &priv_input);
+        assert!(cs.is_sat(&witness, &pub_input));
+    }
+
+    #[test]
+    fn test_eth_membership() {
+        test_eth_membership_of_depth(DEFAULT_TREE_DEPTH);
+    }
+
+    #[test]
+    fn test_eth_membership_depths() {
+        for tree_depth in TREE_DEPTHS {
+            test_eth_membership_of_depth(tree_depth);
+        }
     }
 }
@@ -0,0 +1,150 @@
+use crate::eth_membership::{recover_address, AssignedMerkleProof};
+use ark_ff::PrimeField;
+use sapir::{
+    constraint_system::ConstraintSystem, frontend::gadgets::poseidon::poseidon::PoseidonChip,
+    frontend::gadgets::verify_merkle_proof, poseidon::constants::secp256k1_w3,
+};
+
+/// The maximum number of trees a multi-membership proof can be for
+pub const MAX_MULTI_MEMBERSHIP_TREES: usize = 8;
+
+/// Proves that the signer is a member of multiple trees.
+/// The address is recovered from the signature once,
+/// and the Merkle proof of each tree in `tree_depths` is verified against it.
+/// The roots are exposed in the order of `tree_depths`.
+pub fn eth_multi_membership<F: PrimeField>(cs: &mut ConstraintSystem<F>, tree_depths: &[usize]) {
+    // #############################################
+    // Private inputs
+    // #############################################
+
+    // `s` part of the signature
+    let s_bits = cs.alloc_priv_inputs(256);
+
+    // The Merkle indices and siblings of each tree
+    let merkle_proofs = tree_depths
+        .iter()
+        .map(|tree_depth| {
+            let merkle_indices = cs.alloc_priv_inputs(*tree_depth);
+            let merkle_siblings = cs.alloc_priv_inputs(*tree_depth);
+            AssignedMerkleProof::new(merkle_siblings, merkle_indices)
+        })
+        .collect::<Vec<AssignedMerkleProof<F>>>();
+
+    // #############################################
+    // Public inputs
+    // #############################################
+
+    let t_x = cs.alloc_pub_input();
+    let t_y = cs.alloc_pub_input();
+
+    let u_x = cs.alloc_pub_input();
+    let u_y = cs.alloc_pub_input();
+
+    let sign_in_sig = cs.alloc_pub_input();
+
+    // #############################################
+    // Constraints
+    // #############################################
+
+    // Bound the `sign_in_sig` to the proof
+    let _sign_in_sig_squared = sign_in_sig * sign_in_sig;
+
+    // 1. Recover the address from the signature
+    let address = recover_address(cs, &s_bits, t_x, t_y, u_x, u_y);
+
+    let poseidon_chip = PoseidonChip::new(cs, secp256k1_w3());
+
+    // 2. Verify the Merkle proof of each tree
+    for merkle_proof in &merkle_proofs {
+        let root = verify_merkle_proof(
+            address,
+            &merkle_proof.siblings,
+            &merkle_proof.indices,
+            poseidon_chip.clone(),
+            cs,
+        );
+
+        cs.expose_public(root);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eth_membership::to_cs_field;
+    use crate::utils::test_utils::mock_eff_ecdsa_input;
+    use ark_ec::AffineRepr;
+    use ark_ff::BigInteger;
+    use num_bigint::BigUint;
+    use sapir::merkle_tree::tree::MerkleTree;
+
+    type F = ark_secq256k1::Fr;
+
+    #[test]
+    fn test_eth_multi_membership() {
+        let tree_depths = [4, 6];
+
+        let synthesizer = |cs: &mut ConstraintSystem<_>| {
+            eth_multi_membership(cs, &tree_depths);
+        };
+
+        let mut cs = ConstraintSystem::<_>::new();
+        cs.set_constraints(&synthesizer);
+
+        let eff_ecdsa_input = mock_eff_ecdsa_input(42);
+        let address = F::from(BigUint::from_bytes_be(
+            &eff_ecdsa_input.address.to_fixed_bytes(),
+        ));
+
+        let mut priv_input = eff_ecdsa_input
+            .s
+            .into_bigint()
+            .to_bits_le()
+            .iter()
+            .map(|b| F::from(*b))
+            .collect::<Vec<F>>();
+
+        // Dummy sign_in_sig
+        let sign_in_sig = F::from(42u32);
+
+        let mut pub_input = vec![
+            to_cs_field(*eff_ecdsa_input.t.x().unwrap()),
+            to_cs_field(*eff_ecdsa_input.t.y().unwrap()),
+            to_cs_field(*eff_ecdsa_input.u.x().unwrap()),
+            to_cs_field(*eff_ecdsa_input.u.y().unwrap()),
+            to_cs_field(sign_in_sig),
+        ];
+
+        // Construct a mock tree of each depth with the address at a different position
+        for (i, tree_depth) in tree_depths.iter().enumerate() {
+            let mut leaves = (0..(2usize.pow(*tree_depth as u32) - 1))
+                .map(|j| F::from(j as u32))
+                .collect::<Vec<F>>();
+            leaves.insert(i, address);
+
+            let mut tree = MerkleTree::<F, 3>::new(secp256k1_w3());
+            for leaf in &leaves {
+                tree.insert(*leaf);
+            }
+
+            tree.finish();
+
+            let merkle_proof = tree.create_proof(address);
+
+            let merkle_indices = merkle_proof
+                .path_indices
+                .iter()
+                .map(|i| F::from(*i as u32))
+                .collect::<Vec<F>>();
+
+            priv_input.extend_from_slice(&merkle_indices);
+            priv_input.extend_from_slice(&merkle_proof.siblings);
+
+            pub_input.push(to_cs_field(merkle_proof.root));
+        }
+
+        let witness: Vec<F> = cs.gen_witness(&synthesizer, &pub_input, &priv_input);
+
+        assert!(cs.is_sat(&witness, &pub_input));
+    }
+}
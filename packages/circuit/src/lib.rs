@@ -1,38 +1,214 @@
+pub mod balance_membership;
+mod eth_balance_membership;
 mod eth_membership;
+mod eth_multi_membership;
+pub mod membership;
+pub mod multi_membership;
 mod utils;
 
-use crate::utils::{efficient_ecdsa, verify_efficient_ecdsa};
 use ark_ff::BigInteger;
-use ark_secp256k1::{Affine, Fq, Fr};
-use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
+use ark_secp256k1::{Fq, Fr};
+use ark_serialize::CanonicalSerialize;
+use balance_membership::{BalanceMembershipProof, BalanceMembershipWitness, BalancePublicInputs};
 use bloomfilter::Bloom;
-use eth_membership::{eth_membership, to_cs_field, TREE_DEPTH};
+use eth_balance_membership::eth_balance_membership;
+use eth_membership::{eth_membership, scope_of};
+use eth_multi_membership::eth_multi_membership;
+use membership::{MembershipError, MembershipProof, MembershipWitness, PublicInputs};
+use multi_membership::{
+    MerklePath, MultiMembershipProof, MultiMembershipWitness, MultiPublicInputs,
+};
 use num_bigint::BigUint;
 use sapir::constraint_system::ConstraintSystem;
-use sapir::{embed_to_wasm, wasm::prelude::*};
+use sapir::spartan::{
+    spartan::{Spartan, SpartanProof},
+    transcript::Transcript,
+};
+use sapir::wasm::prelude::*;
+use std::sync::{Arc, Mutex, OnceLock};
+
+pub use eth_balance_membership::BALANCE_BITS;
+pub use eth_membership::{DEFAULT_TREE_DEPTH, TREE_DEPTHS};
+pub use eth_multi_membership::MAX_MULTI_MEMBERSHIP_TREES;
 
 type Curve = ark_secq256k1::Projective;
-type F = ark_secq256k1::Fr;
-
-// Produce the code to generate and verify the proof of the `eth_membership` circuit.
-// We wrap the `prove` and `verify` functions with additional logic
-// and expose them to the JavaScript runtime.
-embed_to_wasm!(
-    |cs: &mut ConstraintSystem<F>| { eth_membership(cs) },
-    Curve,
-    b"creddd"
-);
-
-// `MembershipProof` consists of a Spartan proof
-// and auxiliary inputs necessary for full verification.
-// This proof is serialized and passed around in the JavaScript runtime.
-#[derive(CanonicalSerialize, CanonicalDeserialize)]
-pub struct MembershipProof {
-    pub proof: Vec<u8>,
-    r: Fq,
-    is_y_odd: bool,
-    msg_hash: BigUint,
-    sign_in_sig: Fq,
+pub type F = ark_secq256k1::Fr;
+
+// A variant of the circuit.
+// There is a circuit for each tree depth in `TREE_DEPTHS`,
+// and each variant is prepared once and kept in `PREPARED_CIRCUITS`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Circuit {
+    // `eth_membership`. Only the proofs generated before the nullifier was added
+    // are verified without the nullifier
+    Membership {
+        tree_depth: usize,
+        with_nullifier: bool,
+    },
+    // `eth_multi_membership`
+    MultiMembership {
+        tree_depths: Vec<usize>,
+    },
+    // `eth_balance_membership`
+    BalanceMembership {
+        tree_depth: usize,
+    },
+}
+
+impl Circuit {
+    fn synthesize(&self, cs: &mut ConstraintSystem<F>) {
+        match self {
+            Circuit::Membership {
+                tree_depth,
+                with_nullifier,
+            } => eth_membership(cs, *tree_depth, *with_nullifier),
+            Circuit::MultiMembership { tree_depths } => eth_multi_membership(cs, tree_depths),
+            Circuit::BalanceMembership { tree_depth } => eth_balance_membership(cs, *tree_depth),
+        }
+    }
+
+    fn tree_depths(&self) -> Vec<usize> {
+        match self {
+            Circuit::Membership { tree_depth, .. } => vec![*tree_depth],
+            Circuit::MultiMembership { tree_depths } => tree_depths.clone(),
+            Circuit::BalanceMembership { tree_depth } => vec![*tree_depth],
+        }
+    }
+}
+
+// The constraint system and the Spartan instance of a circuit variant
+struct PreparedCircuit {
+    circuit: Circuit,
+    // Generating a witness needs the constraint system mutably,
+    // so the proofs of the same variant are generated one at a time
+    cs: Mutex<ConstraintSystem<F>>,
+    spartan: Spartan<Curve>,
+}
+
+impl PreparedCircuit {
+    fn new(circuit: Circuit) -> Self {
+        let mut cs = ConstraintSystem::new();
+        cs.set_constraints(&|cs: &mut ConstraintSystem<F>| circuit.synthesize(cs));
+        let spartan = Spartan::new(b"creddd", cs.to_r1cs());
+
+        Self {
+            circuit,
+            cs: Mutex::new(cs),
+            spartan,
+        }
+    }
+
+    fn prove(&self, pub_input: &[F], priv_input: &[F]) -> Vec<u8> {
+        let witness = self
+            .cs
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .gen_witness(
+                &|cs: &mut ConstraintSystem<F>| self.circuit.synthesize(cs),
+                pub_input,
+                priv_input,
+            );
+
+        let mut transcript = Transcript::new(b"creddd");
+        let (proof, _) = self.spartan.prove(&witness, pub_input, &mut transcript);
+
+        let mut proof_bytes = vec![];
+        proof.serialize_compressed(&mut proof_bytes).unwrap();
+        proof_bytes
+    }
+
+    fn verify(&self, proof: &SpartanProof<Curve>) -> bool {
+        let mut transcript = Transcript::new(b"creddd");
+        self.spartan.verify(proof, &mut transcript)
+    }
+}
+
+// Maximum number of circuit variants kept prepared.
+// There is a multi-membership variant for each combination of tree depths,
+// so the least recently used variants are dropped.
+const MAX_PREPARED_CIRCUITS: usize = 8;
+
+// The prepared circuit variants, from the least to the most recently used.
+// The lock is only held to look up a variant. A variant is prepared in its `OnceLock`,
+// so the other variants can be used while it's being prepared.
+static PREPARED_CIRCUITS: Mutex<Vec<(Circuit, Arc<OnceLock<PreparedCircuit>>)>> =
+    Mutex::new(Vec::new());
+
+// Call `f` with the prepared `circuit`, preparing it on first use
+fn with_prepared_circuit<T>(
+    circuit: Circuit,
+    f: impl FnOnce(&PreparedCircuit) -> T,
+) -> Result<T, MembershipError> {
+    if let Some(tree_depth) = circuit
+        .tree_depths()
+        .into_iter()
+        .find(|tree_depth| !TREE_DEPTHS.contains(tree_depth))
+    {
+        return Err(MembershipError::UnsupportedTreeDepth(tree_depth));
+    }
+
+    let prepared = {
+        let mut prepared_circuits = PREPARED_CIRCUITS
+            .lock()
+            .unwrap_or_else(|err| err.into_inner());
+
+        let prepared = match prepared_circuits
+            .iter()
+            .position(|(prepared_circuit, _)| *prepared_circuit == circuit)
+        {
+            Some(i) => prepared_circuits.remove(i).1,
+            None => Arc::new(OnceLock::new()),
+        };
+
+        if prepared_circuits.len() >= MAX_PREPARED_CIRCUITS {
+            prepared_circuits.remove(0);
+        }
+        prepared_circuits.push((circuit.clone(), prepared.clone()));
+
+        prepared
+    };
+
+    Ok(f(prepared.get_or_init(|| PreparedCircuit::new(circuit))))
+}
+
+// Generate a proof with the prepared `circuit`
+fn prove_circuit(
+    circuit: Circuit,
+    pub_input: &[F],
+    priv_input: &[F],
+) -> Result<Vec<u8>, MembershipError> {
+    with_prepared_circuit(circuit, |prepared| prepared.prove(pub_input, priv_input))
+}
+
+// Verify a proof with the prepared `circuit`, without blocking the proofs of other circuits
+fn verify_circuit(circuit: Circuit, proof: &SpartanProof<Curve>) -> Result<bool, MembershipError> {
+    with_prepared_circuit(circuit, |prepared| prepared.verify(proof))
+}
+
+// Prepare the default membership circuit of `prove_membership` ahead of the first proof.
+// The other variants are prepared on their first use.
+#[wasm_bindgen]
+pub fn prepare() {
+    with_prepared_circuit(
+        Circuit::Membership {
+            tree_depth: DEFAULT_TREE_DEPTH,
+            with_nullifier: false,
+        },
+        |_| {},
+    )
+    .unwrap();
+}
+
+// Split the concatenated 32-byte field elements
+fn to_field_elements(bytes: &[u8], input: &'static str) -> Result<Vec<F>, MembershipError> {
+    if bytes.len() % 32 != 0 {
+        return Err(MembershipError::InvalidInputLength(input));
+    }
+
+    Ok(bytes
+        .chunks(32)
+        .map(|chunk| F::from(BigUint::from_bytes_be(chunk)))
+        .collect())
 }
 
 #[wasm_bindgen]
@@ -46,119 +222,222 @@ pub fn prove_membership(
     root: &[u8],
     sign_in_sig: &[u8],
 ) -> Vec<u8> {
-    assert!(merkle_siblings.len() == TREE_DEPTH * 32);
-    assert!(merkle_indices.len() == TREE_DEPTH * 32);
-    assert!(root.len() == 32);
-
-    // Deserialize the inputs
-    let s = Fr::from(BigUint::from_bytes_be(s));
-    let r = Fq::from(BigUint::from_bytes_be(r));
-    let msg_hash = BigUint::from_bytes_be(msg_hash);
-    let sign_in_sig = Fq::from(BigUint::from_bytes_be(sign_in_sig));
+    prove_membership_with(
+        s,
+        r,
+        is_y_odd,
+        msg_hash,
+        merkle_siblings,
+        merkle_indices,
+        root,
+        sign_in_sig,
+        false,
+    )
+}
 
-    let merkle_siblings = merkle_siblings
-        .to_vec()
-        .chunks(32)
-        .map(|sibling| F::from(BigUint::from_bytes_be(&sibling)))
-        .collect::<Vec<F>>();
+// Generate a proof with the nullifier of the signer in the scope of the signed message.
+// The message must be fixed for the scope, and the scope is `scope_of(msg_hash)`.
+#[wasm_bindgen]
+pub fn prove_membership_in_scope(
+    s: &[u8],
+    r: &[u8],
+    is_y_odd: bool,
+    msg_hash: &[u8],
+    merkle_siblings: &[u8],
+    merkle_indices: &[u8],
+    root: &[u8],
+    sign_in_sig: &[u8],
+) -> Vec<u8> {
+    prove_membership_with(
+        s,
+        r,
+        is_y_odd,
+        msg_hash,
+        merkle_siblings,
+        merkle_indices,
+        root,
+        sign_in_sig,
+        true,
+    )
+}
 
-    let merkle_indices = merkle_indices
-        .to_vec()
-        .chunks(32)
-        .map(|index| F::from(BigUint::from_bytes_be(&index)))
-        .collect::<Vec<F>>();
+#[allow(clippy::too_many_arguments)]
+fn prove_membership_with(
+    s: &[u8],
+    r: &[u8],
+    is_y_odd: bool,
+    msg_hash: &[u8],
+    merkle_siblings: &[u8],
+    merkle_indices: &[u8],
+    root: &[u8],
+    sign_in_sig: &[u8],
+    with_nullifier: bool,
+) -> Vec<u8> {
+    if root.len() != 32 {
+        panic!("{}", MembershipError::InvalidInputLength("root"));
+    }
 
-    let root = F::from(BigUint::from_bytes_be(root));
+    let merkle_siblings = to_field_elements(merkle_siblings, "merkle_siblings")
+        .unwrap_or_else(|err| panic!("{}", err));
+    let merkle_indices =
+        to_field_elements(merkle_indices, "merkle_indices").unwrap_or_else(|err| panic!("{}", err));
 
-    // Compute the efficient ECDSA input
-    let (u, t) = efficient_ecdsa(msg_hash.clone(), r, is_y_odd);
+    // Deserialize the inputs
+    let witness = MembershipWitness {
+        s: Fr::from(BigUint::from_bytes_be(s)),
+        r: Fq::from(BigUint::from_bytes_be(r)),
+        is_y_odd,
+        merkle_siblings,
+        merkle_indices,
+    };
 
-    // Construct the private input
-    let mut priv_input = vec![];
+    let msg_hash = BigUint::from_bytes_be(msg_hash);
 
-    let s_bits = s
-        .into_bigint()
-        .to_bits_le()
-        .iter()
-        .map(|b| F::from(*b))
-        .collect::<Vec<F>>();
+    let public_inputs = PublicInputs {
+        merkle_root: F::from(BigUint::from_bytes_be(root)),
+        scope: with_nullifier.then(|| scope_of(&msg_hash)),
+        msg_hash,
+        sign_in_sig: Fq::from(BigUint::from_bytes_be(sign_in_sig)),
+        tree_depth: witness.tree_depth(),
+        nullifier: None,
+    };
 
-    priv_input.extend_from_slice(&s_bits);
+    match membership::prove(&witness, &public_inputs) {
+        Ok(membership_proof) => membership_proof.to_bytes(),
+        Err(err) => panic!("{}", err),
+    }
+}
 
-    // Append the Merkle indices and siblings to the private input
-    priv_input.extend_from_slice(&merkle_indices);
-    priv_input.extend_from_slice(&merkle_siblings);
+#[wasm_bindgen]
+pub fn verify_membership(creddd_proof: &[u8]) -> bool {
+    MembershipProof::from_bytes(creddd_proof)
+        .and_then(|creddd_proof| membership::verify(&creddd_proof))
+        .is_ok()
+}
 
-    // Convert the private input to bytes
-    let priv_input = priv_input
+// Generate a proof of membership in multiple trees.
+// `merkle_siblings`, `merkle_indices` and `roots` are the concatenation of those of each tree,
+// and `tree_depths` has the depth of each tree.
+#[wasm_bindgen]
+pub fn prove_multi_membership(
+    s: &[u8],
+    r: &[u8],
+    is_y_odd: bool,
+    msg_hash: &[u8],
+    merkle_siblings: &[u8],
+    merkle_indices: &[u8],
+    tree_depths: &[u8],
+    roots: &[u8],
+    sign_in_sig: &[u8],
+) -> Vec<u8> {
+    let tree_depths = tree_depths
         .iter()
-        .flat_map(|x| x.into_bigint().to_bytes_be())
-        .collect::<Vec<u8>>();
-
-    // Construct the public input
-    let mut pub_input = vec![
-        to_cs_field(t.x),
-        to_cs_field(t.y),
-        to_cs_field(u.x),
-        to_cs_field(u.y),
-        to_cs_field(sign_in_sig),
-    ];
+        .map(|tree_depth| *tree_depth as usize)
+        .collect::<Vec<usize>>();
 
-    // Append the Merkle roots to the public input
-    pub_input.push(to_cs_field(root));
+    let mut merkle_siblings = to_field_elements(merkle_siblings, "merkle_siblings")
+        .unwrap_or_else(|err| panic!("{}", err));
+    let mut merkle_indices =
+        to_field_elements(merkle_indices, "merkle_indices").unwrap_or_else(|err| panic!("{}", err));
+    let merkle_roots = to_field_elements(roots, "roots").unwrap_or_else(|err| panic!("{}", err));
 
-    let pub_input = pub_input
-        .iter()
-        .flat_map(|x| x.into_bigint().to_bytes_be())
-        .collect::<Vec<u8>>();
+    if merkle_siblings.len() != tree_depths.iter().sum::<usize>() {
+        panic!("{}", MembershipError::InvalidInputLength("merkle_siblings"));
+    }
 
-    // Generate the proof
-    let proof = prove(&pub_input, &priv_input);
+    if merkle_indices.len() != merkle_siblings.len() {
+        panic!("{}", MembershipError::InvalidInputLength("merkle_indices"));
+    }
 
-    let membership_proof = MembershipProof {
-        proof,
-        r,
+    // Split the Merkle paths of the trees
+    let merkle_paths = tree_depths
+        .iter()
+        .map(|tree_depth| MerklePath {
+            siblings: merkle_siblings.drain(..*tree_depth).collect(),
+            indices: merkle_indices.drain(..*tree_depth).collect(),
+        })
+        .collect();
+
+    // Deserialize the inputs
+    let witness = MultiMembershipWitness {
+        s: Fr::from(BigUint::from_bytes_be(s)),
+        r: Fq::from(BigUint::from_bytes_be(r)),
         is_y_odd,
-        msg_hash,
-        sign_in_sig,
+        merkle_paths,
     };
 
-    // Serialize the full proof
-    let mut membership_proof_bytes = Vec::new();
-    membership_proof
-        .serialize_compressed(&mut membership_proof_bytes)
-        .unwrap();
+    let public_inputs = MultiPublicInputs {
+        merkle_roots,
+        msg_hash: BigUint::from_bytes_be(msg_hash),
+        sign_in_sig: Fq::from(BigUint::from_bytes_be(sign_in_sig)),
+        tree_depths,
+    };
 
-    membership_proof_bytes
+    match multi_membership::prove(&witness, &public_inputs) {
+        Ok(multi_membership_proof) => multi_membership_proof.to_bytes(),
+        Err(err) => panic!("{}", err),
+    }
 }
 
 #[wasm_bindgen]
-pub fn verify_membership(creddd_proof: &[u8]) -> bool {
-    // Get the public inputs from the proof
-    let creddd_proof = MembershipProof::deserialize_compressed(creddd_proof).unwrap();
-    let spartan_proof =
-        SpartanProof::<Curve>::deserialize_compressed(creddd_proof.proof.as_slice()).unwrap();
-    let pub_inputs = spartan_proof.pub_input.clone();
+pub fn verify_multi_membership(creddd_proof: &[u8]) -> bool {
+    MultiMembershipProof::from_bytes(creddd_proof)
+        .and_then(|creddd_proof| multi_membership::verify(&creddd_proof))
+        .is_ok()
+}
 
-    let tx = pub_inputs[0];
-    let ty = pub_inputs[1];
-    let ux = pub_inputs[2];
-    let uy = pub_inputs[3];
+// Generate a proof that the balance in the signer's leaf of a balance tree is at least `threshold`
+#[wasm_bindgen]
+pub fn prove_balance_membership(
+    s: &[u8],
+    r: &[u8],
+    is_y_odd: bool,
+    msg_hash: &[u8],
+    merkle_siblings: &[u8],
+    merkle_indices: &[u8],
+    balance: &[u8],
+    root: &[u8],
+    threshold: &[u8],
+    sign_in_sig: &[u8],
+) -> Vec<u8> {
+    if root.len() != 32 {
+        panic!("{}", MembershipError::InvalidInputLength("root"));
+    }
 
-    let t = Affine::new(tx, ty);
-    let u = Affine::new(ux, uy);
+    let merkle_siblings = to_field_elements(merkle_siblings, "merkle_siblings")
+        .unwrap_or_else(|err| panic!("{}", err));
+    let merkle_indices =
+        to_field_elements(merkle_indices, "merkle_indices").unwrap_or_else(|err| panic!("{}", err));
 
-    let r = creddd_proof.r;
-    let is_y_odd = creddd_proof.is_y_odd;
-    let msg_hash = creddd_proof.msg_hash;
+    // Deserialize the inputs
+    let witness = BalanceMembershipWitness {
+        s: Fr::from(BigUint::from_bytes_be(s)),
+        r: Fq::from(BigUint::from_bytes_be(r)),
+        is_y_odd,
+        balance: BigUint::from_bytes_be(balance),
+        merkle_siblings,
+        merkle_indices,
+    };
 
-    // Verify the proof
-    let is_proof_valid = verify(&creddd_proof.proof);
+    let public_inputs = BalancePublicInputs {
+        merkle_root: F::from(BigUint::from_bytes_be(root)),
+        msg_hash: BigUint::from_bytes_be(msg_hash),
+        sign_in_sig: Fq::from(BigUint::from_bytes_be(sign_in_sig)),
+        tree_depth: witness.tree_depth(),
+        threshold: BigUint::from_bytes_be(threshold),
+    };
 
-    // Verify the efficient ECDSA input
-    let is_efficient_ecdsa_valid = verify_efficient_ecdsa(msg_hash, r, is_y_odd, t, u);
+    match balance_membership::prove(&witness, &public_inputs) {
+        Ok(balance_membership_proof) => balance_membership_proof.to_bytes(),
+        Err(err) => panic!("{}", err),
+    }
+}
 
-    is_proof_valid && is_efficient_ecdsa_valid
+#[wasm_bindgen]
+pub fn verify_balance_membership(creddd_proof: &[u8]) -> bool {
+    BalanceMembershipProof::from_bytes(creddd_proof)
+        .and_then(|creddd_proof| balance_membership::verify(&creddd_proof))
+        .is_ok()
 }
 
 // ####################################
@@ -168,29 +447,82 @@ pub fn verify_membership(creddd_proof: &[u8]) -> bool {
 // Get the Merkle root from the proof's public input
 #[wasm_bindgen]
 pub fn get_merkle_root(creddd_proof: &[u8]) -> Vec<u8> {
-    let creddd_proof = MembershipProof::deserialize_compressed(creddd_proof).unwrap();
-    let spartan_proof =
-        SpartanProof::<Curve>::deserialize_compressed(creddd_proof.proof.as_slice()).unwrap();
-    let pub_inputs = spartan_proof.pub_input.clone();
-    // The first 4 elements of the public input are the efficient ECDSA inputs,
-    // and the 5th element is the sign_in_sig.
-    // The 6th element is the Merkle root.
-    let root = &pub_inputs[5];
-
-    root.into_bigint().to_bytes_be()
+    let public_inputs = MembershipProof::from_bytes(creddd_proof)
+        .and_then(|creddd_proof| creddd_proof.public_inputs())
+        .unwrap();
+
+    public_inputs.merkle_root.into_bigint().to_bytes_be()
+}
+
+// Get the concatenated Merkle roots from a multi-membership proof's public input
+#[wasm_bindgen]
+pub fn get_multi_merkle_roots(creddd_proof: &[u8]) -> Vec<u8> {
+    let public_inputs = MultiMembershipProof::from_bytes(creddd_proof)
+        .and_then(|creddd_proof| creddd_proof.public_inputs())
+        .unwrap();
+
+    public_inputs.merkle_roots_bytes().concat()
+}
+
+// Get the Merkle root and the threshold from a balance membership proof's public input
+#[wasm_bindgen]
+pub fn get_balance_merkle_root(creddd_proof: &[u8]) -> Vec<u8> {
+    let public_inputs = BalanceMembershipProof::from_bytes(creddd_proof)
+        .and_then(|creddd_proof| creddd_proof.public_inputs())
+        .unwrap();
+
+    public_inputs.merkle_root_bytes()
+}
+
+#[wasm_bindgen]
+pub fn get_balance_threshold(creddd_proof: &[u8]) -> Vec<u8> {
+    let public_inputs = BalanceMembershipProof::from_bytes(creddd_proof)
+        .and_then(|creddd_proof| creddd_proof.public_inputs())
+        .unwrap();
+
+    public_inputs.threshold.to_bytes_be()
 }
 
 // Get the  message hash from the proof's public input
 #[wasm_bindgen]
 pub fn get_msg_hash(creddd_proof: &[u8]) -> Vec<u8> {
-    let creddd_proof = MembershipProof::deserialize_compressed(creddd_proof).unwrap();
-    creddd_proof.msg_hash.to_bytes_be()
+    let creddd_proof = MembershipProof::from_bytes(creddd_proof).unwrap();
+    creddd_proof.msg_hash().to_bytes_be()
+}
+
+// Get the depth of the tree the proof is for
+#[wasm_bindgen]
+pub fn get_tree_depth(creddd_proof: &[u8]) -> usize {
+    let creddd_proof = MembershipProof::from_bytes(creddd_proof).unwrap();
+    creddd_proof.tree_depth()
+}
+
+// Get the nullifier from the proof's public input
+// (empty if the proof has no nullifier or can't be deserialized)
+#[wasm_bindgen]
+pub fn get_nullifier(creddd_proof: &[u8]) -> Vec<u8> {
+    MembershipProof::from_bytes(creddd_proof)
+        .and_then(|creddd_proof| creddd_proof.public_inputs())
+        .ok()
+        .and_then(|public_inputs| public_inputs.nullifier_bytes())
+        .unwrap_or_default()
+}
+
+// Get the scope of the nullifier from the proof's public input
+// (empty if the proof has no nullifier or can't be deserialized)
+#[wasm_bindgen]
+pub fn get_scope(creddd_proof: &[u8]) -> Vec<u8> {
+    MembershipProof::from_bytes(creddd_proof)
+        .and_then(|creddd_proof| creddd_proof.public_inputs())
+        .ok()
+        .and_then(|public_inputs| public_inputs.scope_bytes())
+        .unwrap_or_default()
 }
 
 #[wasm_bindgen]
 pub fn get_sign_in_sig(creddd_proof: &[u8]) -> Vec<u8> {
-    let creddd_proof = MembershipProof::deserialize_compressed(creddd_proof).unwrap();
-    creddd_proof.sign_in_sig.into_bigint().to_bytes_be()
+    let creddd_proof = MembershipProof::from_bytes(creddd_proof).unwrap();
+    creddd_proof.sign_in_sig().into_bigint().to_bytes_be()
 }
 
 #[wasm_bindgen]
@@ -223,7 +555,6 @@ mod tests {
     use crate::utils::test_utils::mock_sig;
     use ark_ff::BigInteger;
     use ark_std::{end_timer, start_timer};
-    use eth_membership::TREE_DEPTH;
     use num_bigint::BigUint;
     use sapir::{merkle_tree::tree::MerkleTree, poseidon::constants::secp256k1_w3};
 
@@ -236,7 +567,7 @@ mod tests {
 
         // Construct a mock tree
         let mut leaves = vec![address];
-        for i in 0..(2usize.pow(TREE_DEPTH as u32) - 1) {
+        for i in 0..(2usize.pow(DEFAULT_TREE_DEPTH as u32) - 1) {
             leaves.push(F::from(i as u32));
         }
 
@@ -287,5 +618,15 @@ mod tests {
         let verifier_timer = start_timer!(|| "verify");
         assert!(verify_membership(&proof));
         end_timer!(verifier_timer);
+
+        // The proof has no nullifier
+        assert!(get_nullifier(&proof).is_empty());
+        assert!(get_scope(&proof).is_empty());
+    }
+
+    #[test]
+    fn test_get_nullifier_invalid_proof() {
+        assert!(get_nullifier(&[1, 2, 3]).is_empty());
+        assert!(get_scope(&[1, 2, 3]).is_empty());
     }
 }
@@ -0,0 +1,620 @@
+// Typed API to generate and verify membership proofs natively.
+// The functions exported to the JavaScript runtime in `lib.rs` are wrappers around this API.
+
+use crate::eth_membership::{
+    compute_nullifier, low_s_bound, scope_of, to_cs_field, DEFAULT_TREE_DEPTH, TREE_DEPTHS,
+};
+use crate::utils::{efficient_ecdsa, to_affine, verify_efficient_ecdsa};
+use crate::{prove_circuit, verify_circuit, Circuit, Curve, SpartanProof, F};
+use ark_ff::{BigInteger, PrimeField};
+use ark_secp256k1::{Fq, Fr};
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
+use num_bigint::BigUint;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MembershipError {
+    /// There is no circuit for the tree depth
+    UnsupportedTreeDepth(usize),
+    /// The number of the Merkle siblings and indices don't match,
+    /// or an index isn't 0 or 1
+    InvalidMerklePath,
+    /// The depth of the public inputs doesn't match the depth of the Merkle path
+    TreeDepthMismatch,
+    /// An input in bytes has the wrong length
+    InvalidInputLength(&'static str),
+    /// The proof couldn't be deserialized
+    InvalidEncoding,
+    /// A multi-membership proof must be for 1 to `MAX_MULTI_MEMBERSHIP_TREES` trees
+    InvalidNumberOfTrees(usize),
+    /// The public inputs of a multi-membership proof don't have a Merkle root for each tree
+    InvalidNumberOfRoots(usize),
+    /// The Spartan proof is invalid
+    InvalidProof,
+    /// The efficient ECDSA input doesn't match the signature
+    InvalidSignature,
+    /// A balance or a threshold doesn't fit in `BALANCE_BITS`
+    BalanceOutOfRange,
+    /// The balance in the leaf is below the threshold
+    BalanceBelowThreshold,
+    /// The scope of the nullifier isn't the hash of the signed message
+    InvalidScope,
+    /// `s` of the signature isn't low, so the signature could be malleated to get another nullifier
+    MalleableSignature,
+    /// The nullifier doesn't match the signature and the scope
+    NullifierMismatch,
+}
+
+impl fmt::Display for MembershipError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MembershipError::UnsupportedTreeDepth(depth) => {
+                write!(f, "Unsupported tree depth {}", depth)
+            }
+            MembershipError::InvalidMerklePath => write!(f, "Invalid Merkle path"),
+            MembershipError::TreeDepthMismatch => write!(f, "Tree depth mismatch"),
+            MembershipError::InvalidInputLength(input) => write!(f, "Invalid length of {}", input),
+            MembershipError::InvalidEncoding => write!(f, "Invalid proof encoding"),
+            MembershipError::InvalidNumberOfTrees(num_trees) => {
+                write!(f, "Invalid number of trees {}", num_trees)
+            }
+            MembershipError::InvalidNumberOfRoots(num_roots) => {
+                write!(f, "Invalid number of roots {}", num_roots)
+            }
+            MembershipError::InvalidProof => write!(f, "Invalid proof"),
+            MembershipError::InvalidSignature => write!(f, "Invalid signature"),
+            MembershipError::BalanceOutOfRange => write!(f, "Balance out of range"),
+            MembershipError::BalanceBelowThreshold => write!(f, "Balance below the threshold"),
+            MembershipError::InvalidScope => write!(f, "Invalid scope"),
+            MembershipError::MalleableSignature => write!(f, "Malleable signature"),
+            MembershipError::NullifierMismatch => write!(f, "Nullifier mismatch"),
+        }
+    }
+}
+
+impl std::error::Error for MembershipError {}
+
+/// The private inputs of a membership proof
+#[derive(Debug, Clone)]
+pub struct MembershipWitness {
+    /// `s` part of the signature
+    pub s: Fr,
+    /// `r` part of the signature
+    pub r: Fq,
+    /// Whether the y coordinate of the signature's `R` point is odd
+    pub is_y_odd: bool,
+    /// The siblings of the signer's leaf from the bottom of the tree
+    pub merkle_siblings: Vec<F>,
+    /// The position of the signer's leaf at each layer (0 for left, 1 for right)
+    pub merkle_indices: Vec<F>,
+}
+
+impl MembershipWitness {
+    /// The depth of the tree the witness is for
+    pub fn tree_depth(&self) -> usize {
+        self.merkle_siblings.len()
+    }
+
+    fn check(&self) -> Result<(), MembershipError> {
+        check_merkle_path(&self.merkle_siblings, &self.merkle_indices)
+    }
+}
+
+// Check that there is a circuit for the depth of the Merkle path and that the path is well-formed
+pub(crate) fn check_merkle_path(
+    merkle_siblings: &[F],
+    merkle_indices: &[F],
+) -> Result<(), MembershipError> {
+    if !TREE_DEPTHS.contains(&merkle_siblings.len()) {
+        return Err(MembershipError::UnsupportedTreeDepth(merkle_siblings.len()));
+    }
+
+    if merkle_indices.len() != merkle_siblings.len()
+        || merkle_indices
+            .iter()
+            .any(|index| *index != F::from(0u32) && *index != F::from(1u32))
+    {
+        return Err(MembershipError::InvalidMerklePath);
+    }
+
+    Ok(())
+}
+
+// Convert the bits of `s` to the private input of the circuits
+pub(crate) fn to_s_bits(s: Fr) -> Vec<F> {
+    s.into_bigint()
+        .to_bits_le()
+        .iter()
+        .map(|b| F::from(*b))
+        .collect()
+}
+
+/// The values a membership proof is verified against
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PublicInputs {
+    /// The root of the tree the signer is a member of
+    pub merkle_root: F,
+    /// The hash of the signed message
+    pub msg_hash: BigUint,
+    pub sign_in_sig: Fq,
+    pub tree_depth: usize,
+    /// The scope of the nullifier, which must be `scope_of(msg_hash)`,
+    /// so the signed message is fixed for the scope, e.g. an application or a group.
+    /// `None` for the proofs without a nullifier.
+    pub scope: Option<F>,
+    /// The nullifier of the signer in the scope.
+    /// It's computed when proving, and checked against the computed nullifier if it's given.
+    pub nullifier: Option<F>,
+}
+
+impl PublicInputs {
+    /// The Merkle root in 32 big-endian bytes
+    pub fn merkle_root_bytes(&self) -> Vec<u8> {
+        self.merkle_root.into_bigint().to_bytes_be()
+    }
+
+    /// The scope in 32 big-endian bytes
+    pub fn scope_bytes(&self) -> Option<Vec<u8>> {
+        self.scope.map(|scope| scope.into_bigint().to_bytes_be())
+    }
+
+    /// The nullifier in 32 big-endian bytes
+    pub fn nullifier_bytes(&self) -> Option<Vec<u8>> {
+        self.nullifier
+            .map(|nullifier| nullifier.into_bigint().to_bytes_be())
+    }
+}
+
+// `MembershipProof` consists of a Spartan proof
+// and auxiliary inputs necessary for full verification.
+// This proof is serialized and passed around in the JavaScript runtime.
+#[derive(CanonicalSerialize, CanonicalDeserialize)]
+pub struct MembershipProof {
+    pub proof: Vec<u8>,
+    r: Fq,
+    is_y_odd: bool,
+    msg_hash: BigUint,
+    sign_in_sig: Fq,
+    tree_depth: u8,
+}
+
+// `MembershipProof` before the tree depth was added.
+// The tree depth of these proofs is `DEFAULT_TREE_DEPTH`.
+#[derive(CanonicalSerialize, CanonicalDeserialize)]
+struct LegacyMembershipProof {
+    proof: Vec<u8>,
+    r: Fq,
+    is_y_odd: bool,
+    msg_hash: BigUint,
+    sign_in_sig: Fq,
+}
+
+impl MembershipProof {
+    /// Deserialize a proof, including the proofs serialized before the tree depth was added
+    pub fn from_bytes(creddd_proof: &[u8]) -> Result<Self, MembershipError> {
+        if let Ok(proof) = Self::deserialize_compressed(creddd_proof) {
+            return Ok(proof);
+        }
+
+        let legacy_proof = LegacyMembershipProof::deserialize_compressed(creddd_proof)
+            .map_err(|_| MembershipError::InvalidEncoding)?;
+
+        Ok(MembershipProof {
+            proof: legacy_proof.proof,
+            r: legacy_proof.r,
+            is_y_odd: legacy_proof.is_y_odd,
+            msg_hash: legacy_proof.msg_hash,
+            sign_in_sig: legacy_proof.sign_in_sig,
+            tree_depth: DEFAULT_TREE_DEPTH as u8,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        self.serialize_compressed(&mut bytes).unwrap();
+        bytes
+    }
+
+    pub fn msg_hash(&self) -> &BigUint {
+        &self.msg_hash
+    }
+
+    pub fn sign_in_sig(&self) -> Fq {
+        self.sign_in_sig
+    }
+
+    pub fn tree_depth(&self) -> usize {
+        self.tree_depth as usize
+    }
+
+    fn spartan_proof(&self) -> Result<SpartanProof<Curve>, MembershipError> {
+        SpartanProof::<Curve>::deserialize_compressed(self.proof.as_slice())
+            .map_err(|_| MembershipError::InvalidEncoding)
+    }
+
+    /// Get the public inputs of the proof without verifying it
+    pub fn public_inputs(&self) -> Result<PublicInputs, MembershipError> {
+        let pub_inputs = self.spartan_proof()?.pub_input;
+
+        // The first 4 elements of the public input are the efficient ECDSA inputs,
+        // and the 5th element is the sign_in_sig.
+        // The 6th element is the Merkle root.
+        // If the proof has the nullifier, the 6th element is the bound of a low `s`
+        // and the Merkle root is followed by the scope, the nullifier and whether `s` is high.
+        let (merkle_root, scope, nullifier) = match pub_inputs.len() {
+            6 => (pub_inputs[5], None, None),
+            10 => (pub_inputs[6], Some(pub_inputs[7]), Some(pub_inputs[8])),
+            _ => return Err(MembershipError::InvalidEncoding),
+        };
+
+        Ok(PublicInputs {
+            merkle_root,
+            msg_hash: self.msg_hash.clone(),
+            sign_in_sig: self.sign_in_sig,
+            tree_depth: self.tree_depth as usize,
+            scope,
+            nullifier,
+        })
+    }
+}
+
+/// Generate a proof that the signer of `public_inputs.msg_hash` is a leaf of the tree
+/// with the root `public_inputs.merkle_root`
+pub fn prove(
+    witness: &MembershipWitness,
+    public_inputs: &PublicInputs,
+) -> Result<MembershipProof, MembershipError> {
+    witness.check()?;
+
+    if public_inputs.tree_depth != witness.tree_depth() {
+        return Err(MembershipError::TreeDepthMismatch);
+    }
+
+    // The proof has the nullifier of the signer if it's for a scope
+    if let Some(scope) = public_inputs.scope {
+        if scope != scope_of(&public_inputs.msg_hash) {
+            return Err(MembershipError::InvalidScope);
+        }
+
+        if witness.s.into_bigint() > Fr::MODULUS_MINUS_ONE_DIV_TWO {
+            return Err(MembershipError::MalleableSignature);
+        }
+    }
+
+    let nullifier = public_inputs
+        .scope
+        .map(|scope| compute_nullifier(witness.s, scope));
+
+    if public_inputs.nullifier.is_some() && public_inputs.nullifier != nullifier {
+        return Err(MembershipError::NullifierMismatch);
+    }
+
+    // Compute the efficient ECDSA input
+    let (u, t) = efficient_ecdsa(public_inputs.msg_hash.clone(), witness.r, witness.is_y_odd)
+        .ok_or(MembershipError::InvalidSignature)?;
+
+    // Construct the private input
+    let mut priv_input = vec![];
+
+    priv_input.extend_from_slice(&to_s_bits(witness.s));
+
+    // Append the Merkle indices and siblings to the private input
+    priv_input.extend_from_slice(&witness.merkle_indices);
+    priv_input.extend_from_slice(&witness.merkle_siblings);
+
+    // Append the scope of the nullifier to the private input
+    if let Some(scope) = public_inputs.scope {
+        priv_input.push(scope);
+    }
+
+    // Construct the public input
+    let mut pub_input = vec![
+        to_cs_field(t.x),
+        to_cs_field(t.y),
+        to_cs_field(u.x),
+        to_cs_field(u.y),
+        to_cs_field(public_inputs.sign_in_sig),
+    ];
+
+    match (public_inputs.scope, nullifier) {
+        (Some(scope), Some(nullifier)) => {
+            pub_input.push(low_s_bound());
+            pub_input.push(to_cs_field(public_inputs.merkle_root));
+            pub_input.push(scope);
+            pub_input.push(nullifier);
+            // `s` is low
+            pub_input.push(F::from(0u32));
+        }
+        _ => pub_input.push(to_cs_field(public_inputs.merkle_root)),
+    }
+
+    // Generate the proof
+    let proof = prove_circuit(
+        Circuit::Membership {
+            tree_depth: witness.tree_depth(),
+            with_nullifier: nullifier.is_some(),
+        },
+        &pub_input,
+        &priv_input,
+    )?;
+
+    Ok(MembershipProof {
+        proof,
+        r: witness.r,
+        is_y_odd: witness.is_y_odd,
+        msg_hash: public_inputs.msg_hash.clone(),
+        sign_in_sig: public_inputs.sign_in_sig,
+        tree_depth: witness.tree_depth() as u8,
+    })
+}
+
+/// Verify a proof and return its public inputs
+pub fn verify(creddd_proof: &MembershipProof) -> Result<PublicInputs, MembershipError> {
+    let public_inputs = creddd_proof.public_inputs()?;
+    let spartan_proof = creddd_proof.spartan_proof()?;
+    let pub_inputs = &spartan_proof.pub_input;
+
+    // A malformed proof can have points that aren't on the curve
+    let t = to_affine(pub_inputs[0], pub_inputs[1]).ok_or(MembershipError::InvalidSignature)?;
+    let u = to_affine(pub_inputs[2], pub_inputs[3]).ok_or(MembershipError::InvalidSignature)?;
+
+    // Verify the proof with the circuit of the tree depth
+    let is_proof_valid = verify_circuit(
+        Circuit::Membership {
+            tree_depth: public_inputs.tree_depth,
+            with_nullifier: public_inputs.nullifier.is_some(),
+        },
+        &spartan_proof,
+    )?;
+
+    if !is_proof_valid {
+        return Err(MembershipError::InvalidProof);
+    }
+
+    // Verify the efficient ECDSA input
+    let msg_hash = public_inputs.msg_hash.clone();
+    if !verify_efficient_ecdsa(msg_hash, creddd_proof.r, creddd_proof.is_y_odd, t, u) {
+        return Err(MembershipError::InvalidSignature);
+    }
+
+    // The nullifier is only unique to the signer in the scope
+    // if the signed message is fixed for the scope and `s` is low
+    if let Some(scope) = public_inputs.scope {
+        if scope != scope_of(&public_inputs.msg_hash) {
+            return Err(MembershipError::InvalidScope);
+        }
+
+        if pub_inputs[5] != low_s_bound() || pub_inputs[9] != F::from(0u32) {
+            return Err(MembershipError::MalleableSignature);
+        }
+    }
+
+    Ok(public_inputs)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils::test_utils::{
+        address_leaf, invalid_sig, mock_merkle_path, mock_sig, zero_merkle_path,
+    };
+
+    fn witness(tree_depth: usize) -> MembershipWitness {
+        let (s, r, is_y_odd) = invalid_sig();
+        let (merkle_siblings, merkle_indices) = zero_merkle_path(tree_depth);
+
+        MembershipWitness {
+            s,
+            r,
+            is_y_odd,
+            merkle_siblings,
+            merkle_indices,
+        }
+    }
+
+    fn public_inputs(tree_depth: usize) -> PublicInputs {
+        PublicInputs {
+            merkle_root: F::from(0u32),
+            msg_hash: BigUint::from(0u32),
+            sign_in_sig: Fq::from(0u32),
+            tree_depth,
+            scope: None,
+            nullifier: None,
+        }
+    }
+
+    #[test]
+    fn test_invalid_witness() {
+        assert_eq!(
+            prove(&witness(17), &public_inputs(17)).err(),
+            Some(MembershipError::UnsupportedTreeDepth(17))
+        );
+
+        let mut invalid_index = witness(16);
+        invalid_index.merkle_indices[0] = F::from(2u32);
+        assert_eq!(
+            prove(&invalid_index, &public_inputs(16)).err(),
+            Some(MembershipError::InvalidMerklePath)
+        );
+
+        assert_eq!(
+            prove(&witness(16), &public_inputs(18)).err(),
+            Some(MembershipError::TreeDepthMismatch)
+        );
+
+        // The scope must be the hash of the signed message
+        let mut in_scope = public_inputs(16);
+        in_scope.scope = Some(F::from(1u32));
+        assert_eq!(
+            prove(&witness(16), &in_scope).err(),
+            Some(MembershipError::InvalidScope)
+        );
+
+        in_scope.scope = Some(scope_of(&in_scope.msg_hash));
+
+        let mut high_s = witness(16);
+        high_s.s = -Fr::from(1u32);
+        assert_eq!(
+            prove(&high_s, &in_scope).err(),
+            Some(MembershipError::MalleableSignature)
+        );
+
+        in_scope.nullifier = Some(F::from(1u32));
+        assert_eq!(
+            prove(&witness(16), &in_scope).err(),
+            Some(MembershipError::NullifierMismatch)
+        );
+
+        let mut without_scope = public_inputs(16);
+        without_scope.nullifier = Some(F::from(1u32));
+        assert_eq!(
+            prove(&witness(16), &without_scope).err(),
+            Some(MembershipError::NullifierMismatch)
+        );
+    }
+
+    // A valid witness for a tree of `tree_depth` and its public inputs without a nullifier
+    fn valid_inputs(tree_depth: usize) -> (MembershipWitness, PublicInputs) {
+        let (s, r, is_y_odd, msg_hash, _, address) = mock_sig(42);
+        let (merkle_siblings, merkle_indices, merkle_root) =
+            mock_merkle_path(address_leaf(&address), tree_depth);
+
+        let witness = MembershipWitness {
+            s,
+            r,
+            is_y_odd,
+            merkle_siblings,
+            merkle_indices,
+        };
+
+        let public_inputs = PublicInputs {
+            merkle_root,
+            msg_hash,
+            sign_in_sig: Fq::from(42u32),
+            tree_depth,
+            scope: None,
+            nullifier: None,
+        };
+
+        (witness, public_inputs)
+    }
+
+    // A valid proof for a tree of `tree_depth` and its public inputs
+    fn valid_proof(tree_depth: usize) -> (MembershipProof, PublicInputs) {
+        let (witness, public_inputs) = valid_inputs(tree_depth);
+
+        let proof = prove(&witness, &public_inputs).unwrap();
+        let proof = MembershipProof::from_bytes(&proof.to_bytes()).unwrap();
+
+        (proof, public_inputs)
+    }
+
+    #[test]
+    fn test_prove_verify() {
+        let (proof, public_inputs) = valid_proof(16);
+        assert_eq!(verify(&proof).unwrap(), public_inputs);
+
+        // A proof with a point that isn't on the curve is rejected without panicking
+        let mut spartan_proof = proof.spartan_proof().unwrap();
+        spartan_proof.pub_input[0] += F::from(1u32);
+
+        let mut tampered_proof = vec![];
+        spartan_proof
+            .serialize_compressed(&mut tampered_proof)
+            .unwrap();
+
+        let tampered_proof = MembershipProof {
+            proof: tampered_proof,
+            ..proof
+        };
+        assert_eq!(
+            verify(&tampered_proof).err(),
+            Some(MembershipError::InvalidSignature)
+        );
+    }
+
+    #[test]
+    fn test_prove_verify_in_scope() {
+        let (witness, public_inputs) = valid_inputs(16);
+
+        // A proof with the nullifier of the signer in the scope
+        let scope = scope_of(&public_inputs.msg_hash);
+        let public_inputs = PublicInputs {
+            scope: Some(scope),
+            ..public_inputs
+        };
+
+        let proof = prove(&witness, &public_inputs).unwrap();
+        let proof = MembershipProof::from_bytes(&proof.to_bytes()).unwrap();
+
+        let verified_inputs = verify(&proof).unwrap();
+        assert_eq!(verified_inputs.merkle_root, public_inputs.merkle_root);
+        assert_eq!(verified_inputs.msg_hash, public_inputs.msg_hash);
+        assert_eq!(verified_inputs.sign_in_sig, public_inputs.sign_in_sig);
+        assert_eq!(verified_inputs.tree_depth, 16);
+        assert_eq!(verified_inputs.scope, Some(scope));
+        assert_eq!(
+            verified_inputs.nullifier,
+            Some(compute_nullifier(witness.s, scope))
+        );
+
+        // The nullifier is the same for each proof of the signer in the scope
+        let other_proof = prove(&witness, &public_inputs).unwrap();
+        let other_proof = MembershipProof::from_bytes(&other_proof.to_bytes()).unwrap();
+        assert_eq!(
+            verify(&other_proof).unwrap().nullifier,
+            verified_inputs.nullifier
+        );
+    }
+
+    #[test]
+    fn test_verify_tree_depths_concurrently() {
+        let proofs = [valid_proof(16), valid_proof(18)];
+
+        // The proofs of each depth are verified with the circuit of the depth,
+        // while the proofs of the other depth are being verified
+        std::thread::scope(|scope| {
+            for (proof, public_inputs) in proofs.iter().cycle().take(4) {
+                scope.spawn(move || assert_eq!(verify(proof).as_ref(), Ok(public_inputs)));
+            }
+        });
+    }
+
+    #[test]
+    fn test_legacy_proof() {
+        // Proofs serialized before the tree depth was added are for `DEFAULT_TREE_DEPTH`
+        let legacy_proof = LegacyMembershipProof {
+            proof: vec![1, 2, 3],
+            r: Fq::from(4u32),
+            is_y_odd: true,
+            msg_hash: BigUint::from(5u32),
+            sign_in_sig: Fq::from(6u32),
+        };
+
+        let mut bytes = vec![];
+        legacy_proof.serialize_compressed(&mut bytes).unwrap();
+
+        let proof = MembershipProof::from_bytes(&bytes).unwrap();
+        assert_eq!(proof.proof, vec![1, 2, 3]);
+        assert_eq!(proof.msg_hash(), &BigUint::from(5u32));
+        assert_eq!(proof.sign_in_sig(), Fq::from(6u32));
+        assert_eq!(proof.tree_depth(), DEFAULT_TREE_DEPTH);
+
+        // The depth of the current proofs is kept
+        let proof = MembershipProof {
+            tree_depth: 20,
+            ..proof
+        };
+        let proof = MembershipProof::from_bytes(&proof.to_bytes()).unwrap();
+        assert_eq!(proof.tree_depth(), 20);
+        assert_eq!(proof.proof, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_invalid_encoding() {
+        assert_eq!(
+            MembershipProof::from_bytes(&[1, 2, 3]).err(),
+            Some(MembershipError::InvalidEncoding)
+        );
+    }
+}
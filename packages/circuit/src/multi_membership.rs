@@ -0,0 +1,368 @@
+// Typed API to generate and verify proofs of membership in multiple trees.
+// A multi-membership proof recovers the signer's address once for all trees,
+// so it's cheaper to generate and smaller than a membership proof for each tree.
+
+use crate::eth_membership::to_cs_field;
+use crate::eth_multi_membership::MAX_MULTI_MEMBERSHIP_TREES;
+use crate::membership::{check_merkle_path, to_s_bits, MembershipError};
+use crate::utils::{efficient_ecdsa, to_affine, verify_efficient_ecdsa};
+use crate::{prove_circuit, verify_circuit, Circuit, Curve, SpartanProof, F};
+use ark_ff::{BigInteger, PrimeField};
+use ark_secp256k1::{Fq, Fr};
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
+use num_bigint::BigUint;
+
+/// The Merkle path of the signer's leaf in a tree
+#[derive(Debug, Clone)]
+pub struct MerklePath {
+    /// The siblings of the leaf from the bottom of the tree
+    pub siblings: Vec<F>,
+    /// The position of the leaf at each layer (0 for left, 1 for right)
+    pub indices: Vec<F>,
+}
+
+/// The private inputs of a multi-membership proof
+#[derive(Debug, Clone)]
+pub struct MultiMembershipWitness {
+    /// `s` part of the signature
+    pub s: Fr,
+    /// `r` part of the signature
+    pub r: Fq,
+    /// Whether the y coordinate of the signature's `R` point is odd
+    pub is_y_odd: bool,
+    /// The Merkle path in each tree
+    pub merkle_paths: Vec<MerklePath>,
+}
+
+impl MultiMembershipWitness {
+    /// The depth of each tree
+    pub fn tree_depths(&self) -> Vec<usize> {
+        self.merkle_paths
+            .iter()
+            .map(|merkle_path| merkle_path.siblings.len())
+            .collect()
+    }
+
+    fn check(&self) -> Result<(), MembershipError> {
+        let num_trees = self.merkle_paths.len();
+        if num_trees == 0 || num_trees > MAX_MULTI_MEMBERSHIP_TREES {
+            return Err(MembershipError::InvalidNumberOfTrees(num_trees));
+        }
+
+        for merkle_path in &self.merkle_paths {
+            check_merkle_path(&merkle_path.siblings, &merkle_path.indices)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The values a multi-membership proof is verified against
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MultiPublicInputs {
+    /// The root of each tree the signer is a member of
+    pub merkle_roots: Vec<F>,
+    /// The hash of the signed message
+    pub msg_hash: BigUint,
+    pub sign_in_sig: Fq,
+    /// The depth of each tree
+    pub tree_depths: Vec<usize>,
+}
+
+impl MultiPublicInputs {
+    /// The Merkle roots in 32 big-endian bytes each
+    pub fn merkle_roots_bytes(&self) -> Vec<Vec<u8>> {
+        self.merkle_roots
+            .iter()
+            .map(|merkle_root| merkle_root.into_bigint().to_bytes_be())
+            .collect()
+    }
+}
+
+// `MultiMembershipProof` is a single Spartan proof for all trees
+// and the auxiliary inputs necessary for full verification.
+#[derive(CanonicalSerialize, CanonicalDeserialize)]
+pub struct MultiMembershipProof {
+    pub proof: Vec<u8>,
+    r: Fq,
+    is_y_odd: bool,
+    msg_hash: BigUint,
+    sign_in_sig: Fq,
+    tree_depths: Vec<u8>,
+}
+
+impl MultiMembershipProof {
+    pub fn from_bytes(creddd_proof: &[u8]) -> Result<Self, MembershipError> {
+        Self::deserialize_compressed(creddd_proof).map_err(|_| MembershipError::InvalidEncoding)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        self.serialize_compressed(&mut bytes).unwrap();
+        bytes
+    }
+
+    fn spartan_proof(&self) -> Result<SpartanProof<Curve>, MembershipError> {
+        SpartanProof::<Curve>::deserialize_compressed(self.proof.as_slice())
+            .map_err(|_| MembershipError::InvalidEncoding)
+    }
+
+    /// Get the public inputs of the proof without verifying it
+    pub fn public_inputs(&self) -> Result<MultiPublicInputs, MembershipError> {
+        let pub_inputs = self.spartan_proof()?.pub_input;
+
+        // The first 4 elements of the public input are the efficient ECDSA inputs,
+        // and the 5th element is the sign_in_sig.
+        // The rest are the Merkle roots.
+        if pub_inputs.len() != 5 + self.tree_depths.len() {
+            return Err(MembershipError::InvalidEncoding);
+        }
+
+        Ok(MultiPublicInputs {
+            merkle_roots: pub_inputs[5..].to_vec(),
+            msg_hash: self.msg_hash.clone(),
+            sign_in_sig: self.sign_in_sig,
+            tree_depths: self
+                .tree_depths
+                .iter()
+                .map(|tree_depth| *tree_depth as usize)
+                .collect(),
+        })
+    }
+}
+
+/// Generate a proof that the signer of `public_inputs.msg_hash` is a leaf of each tree
+/// with the roots `public_inputs.merkle_roots`
+pub fn prove(
+    witness: &MultiMembershipWitness,
+    public_inputs: &MultiPublicInputs,
+) -> Result<MultiMembershipProof, MembershipError> {
+    witness.check()?;
+
+    let tree_depths = witness.tree_depths();
+    if public_inputs.tree_depths != tree_depths {
+        return Err(MembershipError::TreeDepthMismatch);
+    }
+
+    if public_inputs.merkle_roots.len() != tree_depths.len() {
+        return Err(MembershipError::InvalidNumberOfRoots(
+            public_inputs.merkle_roots.len(),
+        ));
+    }
+
+    // Compute the efficient ECDSA input
+    let (u, t) = efficient_ecdsa(public_inputs.msg_hash.clone(), witness.r, witness.is_y_odd)
+        .ok_or(MembershipError::InvalidSignature)?;
+
+    // Construct the private input
+    let mut priv_input = to_s_bits(witness.s);
+
+    // Append the Merkle indices and siblings of each tree to the private input
+    for merkle_path in &witness.merkle_paths {
+        priv_input.extend_from_slice(&merkle_path.indices);
+        priv_input.extend_from_slice(&merkle_path.siblings);
+    }
+
+    // Construct the public input
+    let mut pub_input = vec![
+        to_cs_field(t.x),
+        to_cs_field(t.y),
+        to_cs_field(u.x),
+        to_cs_field(u.y),
+        to_cs_field(public_inputs.sign_in_sig),
+    ];
+
+    // Append the Merkle roots to the public input
+    pub_input.extend_from_slice(&public_inputs.merkle_roots);
+
+    // Generate the proof
+    let proof = prove_circuit(
+        Circuit::MultiMembership {
+            tree_depths: tree_depths.clone(),
+        },
+        &pub_input,
+        &priv_input,
+    )?;
+
+    Ok(MultiMembershipProof {
+        proof,
+        r: witness.r,
+        is_y_odd: witness.is_y_odd,
+        msg_hash: public_inputs.msg_hash.clone(),
+        sign_in_sig: public_inputs.sign_in_sig,
+        tree_depths: tree_depths
+            .iter()
+            .map(|tree_depth| *tree_depth as u8)
+            .collect(),
+    })
+}
+
+/// Verify a multi-membership proof and return its public inputs
+pub fn verify(creddd_proof: &MultiMembershipProof) -> Result<MultiPublicInputs, MembershipError> {
+    let public_inputs = creddd_proof.public_inputs()?;
+    let spartan_proof = creddd_proof.spartan_proof()?;
+    let pub_inputs = &spartan_proof.pub_input;
+
+    let num_trees = public_inputs.tree_depths.len();
+    if num_trees == 0 || num_trees > MAX_MULTI_MEMBERSHIP_TREES {
+        return Err(MembershipError::InvalidNumberOfTrees(num_trees));
+    }
+
+    // A malformed proof can have points that aren't on the curve
+    let t = to_affine(pub_inputs[0], pub_inputs[1]).ok_or(MembershipError::InvalidSignature)?;
+    let u = to_affine(pub_inputs[2], pub_inputs[3]).ok_or(MembershipError::InvalidSignature)?;
+
+    // Verify the proof with the circuit of the tree depths
+    let is_proof_valid = verify_circuit(
+        Circuit::MultiMembership {
+            tree_depths: public_inputs.tree_depths.clone(),
+        },
+        &spartan_proof,
+    )?;
+
+    if !is_proof_valid {
+        return Err(MembershipError::InvalidProof);
+    }
+
+    // Verify the efficient ECDSA input
+    let msg_hash = public_inputs.msg_hash.clone();
+    if !verify_efficient_ecdsa(msg_hash, creddd_proof.r, creddd_proof.is_y_odd, t, u) {
+        return Err(MembershipError::InvalidSignature);
+    }
+
+    Ok(public_inputs)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils::test_utils::{
+        address_leaf, invalid_sig, mock_merkle_path, mock_sig, zero_merkle_path,
+    };
+
+    fn merkle_path(tree_depth: usize) -> MerklePath {
+        let (siblings, indices) = zero_merkle_path(tree_depth);
+        MerklePath { siblings, indices }
+    }
+
+    fn witness(merkle_paths: Vec<MerklePath>) -> MultiMembershipWitness {
+        let (s, r, is_y_odd) = invalid_sig();
+
+        MultiMembershipWitness {
+            s,
+            r,
+            is_y_odd,
+            merkle_paths,
+        }
+    }
+
+    fn public_inputs(tree_depths: Vec<usize>) -> MultiPublicInputs {
+        MultiPublicInputs {
+            merkle_roots: vec![F::from(0u32); tree_depths.len()],
+            msg_hash: BigUint::from(0u32),
+            sign_in_sig: Fq::from(0u32),
+            tree_depths,
+        }
+    }
+
+    #[test]
+    fn test_invalid_witness() {
+        assert_eq!(
+            prove(&witness(vec![]), &public_inputs(vec![])).err(),
+            Some(MembershipError::InvalidNumberOfTrees(0))
+        );
+
+        let too_many_paths = vec![merkle_path(16); MAX_MULTI_MEMBERSHIP_TREES + 1];
+        assert_eq!(
+            prove(
+                &witness(too_many_paths),
+                &public_inputs(vec![16; MAX_MULTI_MEMBERSHIP_TREES + 1])
+            )
+            .err(),
+            Some(MembershipError::InvalidNumberOfTrees(
+                MAX_MULTI_MEMBERSHIP_TREES + 1
+            ))
+        );
+
+        assert_eq!(
+            prove(
+                &witness(vec![merkle_path(16), merkle_path(17)]),
+                &public_inputs(vec![16, 17])
+            )
+            .err(),
+            Some(MembershipError::UnsupportedTreeDepth(17))
+        );
+
+        // The public inputs must be for the trees of the witness
+        assert_eq!(
+            prove(
+                &witness(vec![merkle_path(16), merkle_path(18)]),
+                &public_inputs(vec![16])
+            )
+            .err(),
+            Some(MembershipError::TreeDepthMismatch)
+        );
+
+        // The public inputs must have the root of each tree
+        let mut missing_root = public_inputs(vec![16, 18]);
+        missing_root.merkle_roots.pop();
+        assert_eq!(
+            prove(
+                &witness(vec![merkle_path(16), merkle_path(18)]),
+                &missing_root
+            )
+            .err(),
+            Some(MembershipError::InvalidNumberOfRoots(1))
+        );
+    }
+
+    #[test]
+    fn test_prove_verify() {
+        let (s, r, is_y_odd, msg_hash, _, address) = mock_sig(42);
+
+        let tree_depths = vec![16, 18];
+        let mut merkle_paths = vec![];
+        let mut merkle_roots = vec![];
+        for tree_depth in &tree_depths {
+            let (siblings, indices, root) = mock_merkle_path(address_leaf(&address), *tree_depth);
+            merkle_paths.push(MerklePath { siblings, indices });
+            merkle_roots.push(root);
+        }
+
+        let witness = MultiMembershipWitness {
+            s,
+            r,
+            is_y_odd,
+            merkle_paths,
+        };
+
+        let public_inputs = MultiPublicInputs {
+            merkle_roots,
+            msg_hash,
+            sign_in_sig: Fq::from(42u32),
+            tree_depths,
+        };
+
+        let proof = prove(&witness, &public_inputs).unwrap();
+        let proof = MultiMembershipProof::from_bytes(&proof.to_bytes()).unwrap();
+        assert_eq!(verify(&proof).unwrap(), public_inputs);
+
+        // The proof isn't valid for other roots
+        let mut spartan_proof = proof.spartan_proof().unwrap();
+        spartan_proof.pub_input[5] += F::from(1u32);
+
+        let mut tampered_proof = vec![];
+        spartan_proof
+            .serialize_compressed(&mut tampered_proof)
+            .unwrap();
+
+        let tampered_proof = MultiMembershipProof {
+            proof: tampered_proof,
+            ..proof
+        };
+        assert_eq!(
+            verify(&tampered_proof).err(),
+            Some(MembershipError::InvalidProof)
+        );
+    }
+}
@@ -6,28 +6,41 @@ use ark_secp256k1::Fr;
 use num_bigint::BigUint;
 
 // Recover the point from the x coordinate and the parity bit
-// following the SEC 1 spec https://www.secg.org/sec1-v2.pdf
-pub fn from_x(x: Fq, is_y_odd: bool) -> Affine {
+// following the SEC 1 spec https://www.secg.org/sec1-v2.pdf.
+// Returns `None` if there's no point with the x coordinate.
+pub fn from_x(x: Fq, is_y_odd: bool) -> Option<Affine> {
     let y_squared = x * x * x + Fq::from(7u32);
-    let y = y_squared.sqrt().unwrap();
+    let y = y_squared.sqrt()?;
     if y.into_bigint().to_bits_le()[0] == !is_y_odd {
-        Affine::new(x, y)
+        Some(Affine::new(x, y))
     } else {
-        Affine::new(x, -y)
+        Some(Affine::new(x, -y))
     }
 }
 
-// Compute `T` and `U` for efficient ECDSA verification
-pub fn efficient_ecdsa(msg_hash: BigUint, r: Fq, is_y_odd: bool) -> (Affine, Affine) {
+// Construct a point from the coordinates in the public input of a proof.
+// Returns `None` instead of panicking like `Affine::new` if the point isn't on the curve.
+pub fn to_affine(x: Fq, y: Fq) -> Option<Affine> {
+    let point = Affine::new_unchecked(x, y);
+    if point.is_on_curve() {
+        Some(point)
+    } else {
+        None
+    }
+}
+
+// Compute `T` and `U` for efficient ECDSA verification.
+// Returns `None` if `r` isn't the x coordinate of a point or is 0.
+pub fn efficient_ecdsa(msg_hash: BigUint, r: Fq, is_y_odd: bool) -> Option<(Affine, Affine)> {
     let g = Affine::generator();
 
     // Recover the `R` point
-    let r_point = from_x(r, is_y_odd);
+    let r_point = from_x(r, is_y_odd)?;
 
     let one = BigUint::from(1u32);
     let modulus = BigUint::from(Fr::MODULUS);
 
-    let r_inv_mod_n = Fr::from(BigUint::from(r.into_bigint())).inverse().unwrap();
+    let r_inv_mod_n = Fr::from(BigUint::from(r.into_bigint())).inverse()?;
 
     // w = r^-1 * msg
     let w = -Fr::from(BigUint::from(msg_hash).modpow(&one, &modulus)) * r_inv_mod_n;
@@ -37,7 +50,7 @@ pub fn efficient_ecdsa(msg_hash: BigUint, r: Fq, is_y_odd: bool) -> (Affine, Aff
     // t = r^-1 * R
     let t = (r_point * r_inv_mod_n).into_affine();
 
-    (u, t)
+    Some((u, t))
 }
 
 // Verify that `T` and `U` are computed correctly
@@ -48,9 +61,10 @@ pub fn verify_efficient_ecdsa(
     t: Affine,
     u: Affine,
 ) -> bool {
-    let (expected_u, expected_t) = efficient_ecdsa(msg_hash, r, is_y_odd);
-
-    t == expected_t && u == expected_u
+    match efficient_ecdsa(msg_hash, r, is_y_odd) {
+        Some((expected_u, expected_t)) => t == expected_t && u == expected_u,
+        None => false,
+    }
 }
 
 #[cfg(test)]
@@ -62,6 +76,7 @@ pub mod test_utils {
         utils::{hash_message, secret_key_to_address},
     };
     use k256::{ecdsa::SigningKey, elliptic_curve::ScalarPrimitive, SecretKey};
+    use sapir::{merkle_tree::tree::MerkleTree, poseidon::constants::secp256k1_w3};
 
     pub struct MockEffEcdsaInput {
         pub s: Fr,
@@ -97,9 +112,56 @@ pub mod test_utils {
         (s, r, is_y_odd, msg_hash_bigint, pub_key, address)
     }
 
+    /// Compute a Merkle path of `leaf` in a tree of `tree_depth` without building the whole tree,
+    /// so the circuits of the deep trees can be tested.
+    /// Returns the siblings, the indices and the root.
+    pub fn mock_merkle_path<F: PrimeField>(leaf: F, tree_depth: usize) -> (Vec<F>, Vec<F>, F) {
+        let mut tree = MerkleTree::<F, 3>::new(secp256k1_w3());
+
+        let mut node = leaf;
+        let mut siblings = vec![];
+        let mut indices = vec![];
+
+        for layer in 0..tree_depth {
+            let sibling = F::from(layer as u32 + 1);
+
+            // Alternate between the left and the right child
+            let is_right = layer % 2 == 1;
+            let children = if is_right {
+                [sibling, node]
+            } else {
+                [node, sibling]
+            };
+
+            node = MerkleTree::hash(&mut tree.poseidon, &children);
+            siblings.push(sibling);
+            indices.push(F::from(is_right as u32));
+        }
+
+        (siblings, indices, node)
+    }
+
+    /// The signature of the witnesses that are rejected before proving
+    pub fn invalid_sig() -> (Fr, Fq, bool) {
+        (Fr::from(1u32), Fq::from(1u32), false)
+    }
+
+    /// The Merkle siblings and indices of the witnesses that are rejected before proving
+    pub fn zero_merkle_path(tree_depth: usize) -> (Vec<crate::F>, Vec<crate::F>) {
+        (
+            vec![crate::F::from(0u32); tree_depth],
+            vec![crate::F::from(0u32); tree_depth],
+        )
+    }
+
+    /// The leaf of the address of a mock signer
+    pub fn address_leaf(address: &H160) -> crate::F {
+        crate::F::from(BigUint::from_bytes_be(&address.to_fixed_bytes()))
+    }
+
     pub fn mock_eff_ecdsa_input(priv_key: u64) -> MockEffEcdsaInput {
         let (s, r, is_y_odd, msg_hash_bigint, pub_key, address) = mock_sig(priv_key);
-        let (u, t) = efficient_ecdsa(msg_hash_bigint.clone(), r, is_y_odd);
+        let (u, t) = efficient_ecdsa(msg_hash_bigint.clone(), r, is_y_odd).unwrap();
 
         MockEffEcdsaInput {
             s,
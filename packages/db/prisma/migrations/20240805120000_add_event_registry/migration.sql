@@ -0,0 +1,15 @@
+-- AlterTable
+ALTER TABLE "Contract" ADD COLUMN     "events" INTEGER[] DEFAULT ARRAY[]::INTEGER[];
+
+-- CreateTable
+CREATE TABLE "Event" (
+    "id" INTEGER NOT NULL,
+    "signature" TEXT NOT NULL,
+    "createdAt" TIMESTAMP(3) NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    "updatedAt" TIMESTAMP(3) NOT NULL,
+
+    CONSTRAINT "Event_pkey" PRIMARY KEY ("id")
+);
+
+-- CreateIndex
+CREATE UNIQUE INDEX "Event_signature_key" ON "Event"("signature");
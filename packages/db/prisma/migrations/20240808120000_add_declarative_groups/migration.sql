@@ -0,0 +1,5 @@
+-- AlterEnum
+ALTER TYPE "GroupType" ADD VALUE 'Declarative';
+
+-- AlterTable
+ALTER TABLE "Group" ADD COLUMN     "spec" TEXT;
@@ -0,0 +1,3 @@
+-- AlterTable
+ALTER TABLE "MerkleTree" ADD COLUMN     "chainBlockNumbers" BIGINT[] DEFAULT ARRAY[]::BIGINT[],
+ADD COLUMN     "chainIds" INTEGER[] DEFAULT ARRAY[]::INTEGER[];
@@ -0,0 +1,5 @@
+-- AlterTable
+ALTER TABLE "Group" ADD COLUMN     "treeDepth" INTEGER NOT NULL DEFAULT 18;
+
+-- AlterTable
+ALTER TABLE "MerkleTree" ADD COLUMN     "treeDepth" INTEGER NOT NULL DEFAULT 18;
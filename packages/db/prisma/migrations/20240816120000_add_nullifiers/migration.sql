@@ -0,0 +1,12 @@
+-- CreateTable
+CREATE TABLE "Nullifier" (
+    "scope" TEXT NOT NULL,
+    "nullifier" TEXT NOT NULL,
+    "groupId" TEXT NOT NULL,
+    "createdAt" TIMESTAMP(3) NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+    CONSTRAINT "Nullifier_pkey" PRIMARY KEY ("scope","nullifier")
+);
+
+-- AddForeignKey
+ALTER TABLE "Nullifier" ADD CONSTRAINT "Nullifier_groupId_fkey" FOREIGN KEY ("groupId") REFERENCES "Group"("id") ON DELETE RESTRICT ON UPDATE CASCADE;
@@ -0,0 +1,2 @@
+-- AlterEnum
+ALTER TYPE "GroupType" ADD VALUE 'BalanceHolder';
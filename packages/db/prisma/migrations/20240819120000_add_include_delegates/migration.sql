@@ -0,0 +1,2 @@
+-- AlterTable
+ALTER TABLE "Group" ADD COLUMN     "includeDelegates" BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,18 @@
+-- CreateTable
+CREATE TABLE "TreeArchive" (
+    "id" SERIAL NOT NULL,
+    "groupId" TEXT NOT NULL,
+    "blockNumber" BIGINT NOT NULL,
+    "merkleRoot" TEXT NOT NULL,
+    "isSnapshot" BOOLEAN NOT NULL,
+    "layers" BYTEA NOT NULL,
+    "createdAt" TIMESTAMP(3) NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+    CONSTRAINT "TreeArchive_pkey" PRIMARY KEY ("id")
+);
+
+-- CreateIndex
+CREATE UNIQUE INDEX "TreeArchive_groupId_blockNumber_key" ON "TreeArchive"("groupId", "blockNumber");
+
+-- AddForeignKey
+ALTER TABLE "TreeArchive" ADD CONSTRAINT "TreeArchive_groupId_fkey" FOREIGN KEY ("groupId") REFERENCES "Group"("id") ON DELETE RESTRICT ON UPDATE CASCADE;
@@ -0,0 +1,20 @@
+-- CreateTable
+CREATE TABLE "GroupMemberChange" (
+    "id" SERIAL NOT NULL,
+    "groupId" TEXT NOT NULL,
+    "blockNumber" BIGINT NOT NULL,
+    "address" TEXT NOT NULL,
+    "isAdded" BOOLEAN NOT NULL,
+    "createdAt" TIMESTAMP(3) NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+    CONSTRAINT "GroupMemberChange_pkey" PRIMARY KEY ("id")
+);
+
+-- CreateIndex
+CREATE INDEX "GroupMemberChange_address_idx" ON "GroupMemberChange"("address");
+
+-- CreateIndex
+CREATE UNIQUE INDEX "GroupMemberChange_groupId_blockNumber_address_key" ON "GroupMemberChange"("groupId", "blockNumber", "address");
+
+-- AddForeignKey
+ALTER TABLE "GroupMemberChange" ADD CONSTRAINT "GroupMemberChange_groupId_fkey" FOREIGN KEY ("groupId") REFERENCES "Group"("id") ON DELETE RESTRICT ON UPDATE CASCADE;
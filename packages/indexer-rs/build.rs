@@ -5,7 +5,9 @@ fn main() -> Result<()> {
             "../protobufs/schemas/erc20_transfer_event.proto",
             "../protobufs/schemas/erc721_transfer_event.proto",
             "../protobufs/schemas/erc1155_transfer_event.proto",
+            "../protobufs/schemas/generic_event.proto",
             "../protobufs/schemas/merkle_tree.proto",
+            "../protobufs/schemas/merkle_proof.proto",
         ],
         &["../protobufs/schemas"],
     )?;
@@ -1,10 +1,8 @@
 use rocksdb::IteratorMode;
 
-use crate::Address;
+use crate::{rocksdb_key::KeyType, Address};
 use std::{collections::HashSet, sync::Arc};
 
-const ADDRESS_TO_GROUP_IDS_KEY_PREFIX: u8 = 4;
-
 /// AddressGroups represents a record in RocksDB that maps an address to a set of group IDs
 /// the address belongs to.
 pub struct AddressGroups {
@@ -19,7 +17,7 @@ impl AddressGroups {
         let mut address_groups = vec![];
 
         // Construct the key to start the iterator from
-        let mut start_key = vec![ADDRESS_TO_GROUP_IDS_KEY_PREFIX];
+        let mut start_key = vec![KeyType::AddressGroupIds as u8];
         start_key.extend_from_slice(&[0; 20]);
 
         let iter =
@@ -28,7 +26,7 @@ impl AddressGroups {
         for item in iter {
             let (key, value) = item.unwrap();
 
-            if key[0] != ADDRESS_TO_GROUP_IDS_KEY_PREFIX {
+            if key[0] != KeyType::AddressGroupIds as u8 {
                 break;
             }
 
@@ -56,7 +54,7 @@ impl AddressGroups {
 
     /// Get the RocksDB key the given address
     pub fn get_key(address: Address) -> Vec<u8> {
-        let mut key = vec![ADDRESS_TO_GROUP_IDS_KEY_PREFIX];
+        let mut key = vec![KeyType::AddressGroupIds as u8];
         key.extend_from_slice(&address);
 
         key
@@ -110,6 +108,36 @@ impl AddressGroups {
         })
     }
 
+    /// Get the records for the given addresses from RocksDB in one multi-get,
+    /// in the order of the addresses
+    pub fn get_many(addresses: &[Address], rocksdb_client: Arc<rocksdb::DB>) -> Vec<Self> {
+        let values =
+            rocksdb_client.multi_get(addresses.iter().map(|address| Self::get_key(*address)));
+
+        addresses
+            .iter()
+            .zip(values)
+            .map(|(address, value)| {
+                let value = value.unwrap().unwrap_or(vec![]);
+
+                // Split the value into 32-byte chunks to get the group IDs
+                let group_ids = value
+                    .chunks(32)
+                    .map(|chunk| {
+                        let group_id: [u8; 32] = chunk.try_into().unwrap();
+                        group_id
+                    })
+                    .collect();
+
+                Self {
+                    address: *address,
+                    group_ids,
+                    rocksdb_client: rocksdb_client.clone(),
+                }
+            })
+            .collect()
+    }
+
     /// Add a group ID to the address -> group record.
     /// Call `save` to persist the changes to RocksDB.
     pub fn add_group(&mut self, group_id: [u8; 32]) {
@@ -124,7 +152,7 @@ impl AddressGroups {
     }
 
     pub fn to_key_value(&self) -> (Vec<u8>, Vec<u8>) {
-        let mut key = vec![ADDRESS_TO_GROUP_IDS_KEY_PREFIX];
+        let mut key = vec![KeyType::AddressGroupIds as u8];
         key.extend_from_slice(&self.address);
 
         let mut value = vec![];
@@ -174,4 +202,38 @@ mod tests {
 
         assert_eq!(address_groups.group_ids, group_ids);
     }
+
+    #[test]
+    fn test_get_many_address_groups() {
+        const TEST_ROCKSDB_PATH: &str = "test_get_many_address_groups";
+
+        let mut rocksdb_options = Options::default();
+        rocksdb_options.create_if_missing(true);
+
+        let rocksdb_client = DB::open(
+            &rocksdb_options,
+            format!("{}/{}", ROCKSDB_PATH, TEST_ROCKSDB_PATH),
+        )
+        .unwrap();
+
+        let rocksdb_client = Arc::new(rocksdb_client);
+
+        // Delete all records from the test db
+        delete_all(&rocksdb_client);
+
+        let mut group_ids = HashSet::new();
+        group_ids.insert([1u8; 32]);
+        group_ids.insert([2u8; 32]);
+
+        AddressGroups::create([3; 20], group_ids.clone(), rocksdb_client.clone());
+
+        // The address without a record has no groups
+        let address_groups = AddressGroups::get_many(&[[4; 20], [3; 20]], rocksdb_client.clone());
+
+        assert_eq!(address_groups.len(), 2);
+        assert_eq!(address_groups[0].address, [4; 20]);
+        assert!(address_groups[0].group_ids.is_empty());
+        assert_eq!(address_groups[1].address, [3; 20]);
+        assert_eq!(address_groups[1].group_ids, group_ids);
+    }
 }
@@ -0,0 +1,361 @@
+use crate::{
+    balance_state::{state_lock, BALANCE_STATE_LAG},
+    contract_event_iterator::ContractEventIterator,
+    rocksdb_key::{KeyType, ERC20_TRANSFER_EVENT_ID},
+    utils::{decode_erc20_transfer_event, MINTER_ADDRESS},
+    Address, BlockNum, ContractId, ERC20TransferEvent, Error, IndexerError,
+};
+use num_bigint::BigUint;
+use rocksdb::{Direction, IteratorMode, WriteBatch, DB};
+use std::{collections::HashMap, sync::Arc};
+
+/// Number of blocks between checkpoints.
+/// A query replays the logs of at most this many blocks on top of a checkpoint.
+pub const CHECKPOINT_INTERVAL: BlockNum = 10_000;
+
+// The keys of a balance history are the concatenation of the following fields:
+// [KeyType::BalanceHistory (1 byte)][contract id (2 bytes)][record (1 byte)][address (20 bytes)][checkpoint (8 bytes)]
+// Only the balance records have the address, and the meta record doesn't have the checkpoint.
+
+/// Type of a record in a balance history
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Record {
+    /// The latest checkpoint
+    Meta = 0,
+    /// The total supply at a checkpoint
+    TotalSupply = 1,
+    /// The balance of an address at a checkpoint
+    Balance = 2,
+}
+
+/// Balances and total supply of an ERC20 contract at any block,
+/// computed from the synced transfer logs instead of archive `eth_call`s.
+///
+/// Checkpoints are taken every `CHECKPOINT_INTERVAL` blocks, and only store the balances
+/// that changed since the previous checkpoint. The balance of an address at a checkpoint
+/// is the latest record of the address at or before the checkpoint.
+/// A query starts from the checkpoint before the block and replays the logs after it.
+pub struct BalanceHistory {
+    rocksdb_client: Arc<DB>,
+    contract_id: ContractId,
+}
+
+impl BalanceHistory {
+    pub fn new(rocksdb_client: Arc<DB>, contract_id: ContractId) -> Self {
+        Self {
+            rocksdb_client,
+            contract_id,
+        }
+    }
+
+    fn contract_prefix(contract_id: ContractId) -> Vec<u8> {
+        let mut key = vec![KeyType::BalanceHistory as u8];
+        key.extend_from_slice(&contract_id.to_be_bytes());
+        key
+    }
+
+    fn record_prefix(&self, record: Record) -> Vec<u8> {
+        let mut key = Self::contract_prefix(self.contract_id);
+        key.push(record as u8);
+        key
+    }
+
+    fn balance_prefix(&self, address: &Address) -> Vec<u8> {
+        let mut key = self.record_prefix(Record::Balance);
+        key.extend_from_slice(address);
+        key
+    }
+
+    /// Returns the latest checkpoint
+    pub fn last_checkpoint(&self) -> Option<BlockNum> {
+        self.rocksdb_client
+            .get(self.record_prefix(Record::Meta))
+            .unwrap()
+            .map(|value| BlockNum::from_be_bytes(value[..].try_into().unwrap()))
+    }
+
+    /// Returns the value of the latest record under `prefix` at or before `checkpoint`
+    fn get_latest(&self, prefix: Vec<u8>, checkpoint: BlockNum) -> Option<BigUint> {
+        let mut key = prefix.clone();
+        key.extend_from_slice(&checkpoint.to_be_bytes());
+
+        let (key, value) = self
+            .rocksdb_client
+            .iterator(IteratorMode::From(&key, Direction::Reverse))
+            .next()?
+            .unwrap();
+
+        if key.starts_with(&prefix) {
+            Some(BigUint::from_bytes_be(&value))
+        } else {
+            None
+        }
+    }
+
+    fn checkpoint_balance(&self, address: &Address, checkpoint: BlockNum) -> BigUint {
+        self.get_latest(self.balance_prefix(address), checkpoint)
+            .unwrap_or_default()
+    }
+
+    fn checkpoint_total_supply(&self, checkpoint: BlockNum) -> BigUint {
+        self.get_latest(self.record_prefix(Record::TotalSupply), checkpoint)
+            .unwrap_or_default()
+    }
+
+    /// Store a checkpoint with the balances that changed since the previous checkpoint
+    fn commit(
+        &self,
+        checkpoint: BlockNum,
+        changes: &mut HashMap<Address, BigUint>,
+        total_supply: &BigUint,
+    ) {
+        let mut batch = WriteBatch::default();
+
+        // Checkpoints without changes aren't stored
+        if !changes.is_empty() {
+            for (address, balance) in changes.drain() {
+                let mut key = self.balance_prefix(&address);
+                key.extend_from_slice(&checkpoint.to_be_bytes());
+
+                batch.put(key, balance.to_bytes_be());
+            }
+
+            let mut key = self.record_prefix(Record::TotalSupply);
+            key.extend_from_slice(&checkpoint.to_be_bytes());
+
+            batch.put(key, total_supply.to_bytes_be());
+        }
+
+        batch.put(self.record_prefix(Record::Meta), checkpoint.to_be_bytes());
+
+        self.rocksdb_client.write(batch).unwrap();
+    }
+
+    /// Apply a transfer to the balances that changed since the last checkpoint
+    fn apply_transfer(
+        &self,
+        log: &ERC20TransferEvent,
+        checkpoint: BlockNum,
+        changes: &mut HashMap<Address, BigUint>,
+        total_supply: &mut BigUint,
+    ) -> Result<(), Error> {
+        if log.to != MINTER_ADDRESS {
+            // Increase balance of `to` by `value`
+            *changes
+                .entry(log.to)
+                .or_insert_with(|| self.checkpoint_balance(&log.to, checkpoint)) += &log.value;
+        }
+
+        if log.from == MINTER_ADDRESS {
+            // Increase total supply by `value`
+            *total_supply += &log.value;
+        } else {
+            let balance = changes
+                .entry(log.from)
+                .or_insert_with(|| self.checkpoint_balance(&log.from, checkpoint));
+
+            if *balance < log.value {
+                return Err(IndexerError::InvalidBalance.into());
+            }
+
+            // Decrease balance of `from` by `value`
+            *balance -= &log.value;
+        }
+
+        if log.to == MINTER_ADDRESS {
+            // Decrease total supply by `value`
+            *total_supply -= &log.value;
+        }
+
+        Ok(())
+    }
+
+    /// Take the checkpoints up to `BALANCE_STATE_LAG` blocks before `block`.
+    /// The logs of the recent blocks can still be rolled back by a reorg, so they're only replayed by queries.
+    fn update(&self, block: BlockNum) -> Result<(), Error> {
+        let _guard = state_lock(&self.record_prefix(Record::Meta))
+            .lock()
+            .unwrap();
+
+        let target =
+            block.saturating_sub(BALANCE_STATE_LAG) / CHECKPOINT_INTERVAL * CHECKPOINT_INTERVAL;
+
+        let last_checkpoint = self.last_checkpoint();
+        if last_checkpoint.map_or(false, |checkpoint| checkpoint >= target) {
+            return Ok(());
+        }
+
+        let from_block = last_checkpoint.map_or(0, |checkpoint| checkpoint + 1);
+        let mut checkpoint =
+            last_checkpoint.map_or(0, |checkpoint| checkpoint + CHECKPOINT_INTERVAL);
+        let mut total_supply = last_checkpoint.map_or_else(BigUint::default, |checkpoint| {
+            self.checkpoint_total_supply(checkpoint)
+        });
+        let mut changes = HashMap::new();
+
+        let iterator = ContractEventIterator::new_from_block(
+            &self.rocksdb_client,
+            ERC20_TRANSFER_EVENT_ID,
+            self.contract_id,
+            from_block,
+            Some(target),
+        );
+
+        for (key, value) in iterator {
+            let block_num = key.block_num.unwrap();
+
+            if block_num > checkpoint {
+                // Take the checkpoint before the log, and skip to the checkpoint of the log
+                self.commit(checkpoint, &mut changes, &total_supply);
+                checkpoint = block_num.div_ceil(CHECKPOINT_INTERVAL) * CHECKPOINT_INTERVAL;
+            }
+
+            let log = decode_erc20_transfer_event(&value);
+            self.apply_transfer(&log, checkpoint, &mut changes, &mut total_supply)?;
+        }
+
+        // The balances at the checkpoint of the last log don't change until `target`
+        self.commit(target, &mut changes, &total_supply);
+
+        Ok(())
+    }
+
+    /// Take the checkpoints for `block`, and return the latest checkpoint at or before it
+    fn checkpoint_before(&self, block: BlockNum) -> Result<BlockNum, Error> {
+        self.update(block)?;
+
+        let last_checkpoint = self.last_checkpoint().unwrap();
+        Ok(last_checkpoint.min(block / CHECKPOINT_INTERVAL * CHECKPOINT_INTERVAL))
+    }
+
+    /// Iterate over the transfers after `checkpoint` up to `block`
+    fn transfers_after(
+        &self,
+        checkpoint: BlockNum,
+        block: BlockNum,
+    ) -> impl Iterator<Item = ERC20TransferEvent> + '_ {
+        ContractEventIterator::new_from_block(
+            &self.rocksdb_client,
+            ERC20_TRANSFER_EVENT_ID,
+            self.contract_id,
+            checkpoint + 1,
+            Some(block),
+        )
+        .map(|(_, value)| decode_erc20_transfer_event(&value))
+    }
+
+    /// Returns the balance of `address` at the end of `block`
+    pub fn balance_at(&self, address: &Address, block: BlockNum) -> Result<BigUint, Error> {
+        let checkpoint = self.checkpoint_before(block)?;
+        let mut balance = self.checkpoint_balance(address, checkpoint);
+
+        for log in self.transfers_after(checkpoint, block) {
+            if log.to == *address {
+                balance += &log.value;
+            }
+
+            // The minter doesn't have a balance
+            if log.from == *address && log.from != MINTER_ADDRESS {
+                if balance < log.value {
+                    return Err(IndexerError::InvalidBalance.into());
+                }
+
+                balance -= &log.value;
+            }
+        }
+
+        Ok(balance)
+    }
+
+    /// Returns the total supply at the end of `block`
+    pub fn total_supply_at(&self, block: BlockNum) -> Result<BigUint, Error> {
+        let checkpoint = self.checkpoint_before(block)?;
+        let mut total_supply = self.checkpoint_total_supply(checkpoint);
+
+        for log in self.transfers_after(checkpoint, block) {
+            if log.from == MINTER_ADDRESS {
+                total_supply += &log.value;
+            }
+
+            if log.to == MINTER_ADDRESS {
+                total_supply -= &log.value;
+            }
+        }
+
+        Ok(total_supply)
+    }
+
+    /// Delete the history of the contract if it has checkpoints at or after `from_block`,
+    /// so that it's rebuilt after the logs are rolled back
+    pub fn rollback(
+        rocksdb_client: &Arc<DB>,
+        contract_id: ContractId,
+        from_block: BlockNum,
+        batch: &mut WriteBatch,
+    ) {
+        let history = Self::new(rocksdb_client.clone(), contract_id);
+
+        if history
+            .last_checkpoint()
+            .map_or(false, |checkpoint| checkpoint >= from_block)
+        {
+            let start = Self::contract_prefix(contract_id);
+
+            // The end of the range is greater than all keys of the contract
+            let mut end = start.clone();
+            end.extend_from_slice(&[u8::MAX; 30]);
+
+            batch.delete_range(start, end);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::{init_test_rocksdb, put_erc20_transfer};
+
+    const CONTRACT_ID: ContractId = 1;
+
+    #[test]
+    fn test_balance_history() {
+        let db = init_test_rocksdb("test_balance_history");
+        let history = BalanceHistory::new(db.clone(), CONTRACT_ID);
+
+        let alice = [1; 20];
+        let bob = [2; 20];
+
+        put_erc20_transfer(&db, CONTRACT_ID, 5000, 0, MINTER_ADDRESS, alice, 100);
+        put_erc20_transfer(&db, CONTRACT_ID, 15000, 0, alice, bob, 30);
+        put_erc20_transfer(&db, CONTRACT_ID, 25000, 0, bob, alice, 10);
+        put_erc20_transfer(&db, CONTRACT_ID, 25100, 0, alice, MINTER_ADDRESS, 20);
+
+        let assert_history = |history: &BalanceHistory| {
+            assert_eq!(history.balance_at(&alice, 40000).unwrap(), 60u8.into());
+            assert_eq!(history.balance_at(&bob, 40000).unwrap(), 20u8.into());
+            assert_eq!(history.total_supply_at(40000).unwrap(), 80u8.into());
+
+            assert_eq!(history.balance_at(&alice, 4999).unwrap(), 0u8.into());
+            assert_eq!(history.balance_at(&alice, 5000).unwrap(), 100u8.into());
+            assert_eq!(history.balance_at(&alice, 20000).unwrap(), 70u8.into());
+            assert_eq!(history.balance_at(&bob, 24999).unwrap(), 30u8.into());
+            assert_eq!(history.balance_at(&bob, 25000).unwrap(), 20u8.into());
+            assert_eq!(history.total_supply_at(25099).unwrap(), 100u8.into());
+            assert_eq!(history.total_supply_at(25100).unwrap(), 80u8.into());
+        };
+
+        assert_history(&history);
+
+        // Checkpoints are only taken out of the reorg window
+        assert_eq!(history.last_checkpoint(), Some(30000));
+
+        // The history is rebuilt after a rollback of the checkpointed blocks
+        let mut batch = WriteBatch::default();
+        BalanceHistory::rollback(&db, CONTRACT_ID, 20000, &mut batch);
+        db.write(batch).unwrap();
+
+        assert_eq!(history.last_checkpoint(), None);
+
+        assert_history(&history);
+    }
+}
@@ -0,0 +1,577 @@
+use crate::{
+    contract_event_iterator::ContractEventIterator,
+    log_sync_engine::REORG_WINDOW,
+    rocksdb_key::{KeyType, ERC20_TRANSFER_EVENT_ID},
+    utils::{decode_erc20_transfer_event, MINTER_ADDRESS},
+    Address, BlockNum, ContractId, ERC20TransferEvent, Error, GroupId, IndexerError, TxIndex,
+};
+use num_bigint::BigUint;
+use rocksdb::{Direction, IteratorMode, WriteBatch, DB};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex, MutexGuard, OnceLock},
+};
+
+/// Number of blocks the persisted state lags behind the block the balances are requested at.
+/// The logs of the recent blocks can still be rolled back by a reorg,
+/// so they're replayed on top of the persisted state in memory instead.
+pub const BALANCE_STATE_LAG: BlockNum = REORG_WINDOW;
+
+// The keys of a balance state are the concatenation of the following fields:
+// [KeyType::BalanceState (1 byte)][contract id (2 bytes)][record (1 byte)][owner (32 bytes)][address (20 bytes)]
+// The meta record doesn't have the address.
+
+/// Type of a record in a balance state
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Record {
+    /// The block the state is at, and the total supply at the block
+    Meta = 0,
+    /// The balance of an address
+    Balance = 1,
+    /// An address marked by the owner of the state
+    Marked = 2,
+    /// An amount the owner of the state accumulates for an address (e.g. the USD value of its purchases)
+    Amount = 3,
+}
+
+/// Returns the lock of the records under `key`.
+/// A state can be updated concurrently (e.g. by a group and a composite group that contains it).
+pub(crate) fn state_lock(key: &[u8]) -> &'static Mutex<()> {
+    static LOCKS: OnceLock<Mutex<HashMap<Vec<u8>, &'static Mutex<()>>>> = OnceLock::new();
+
+    let mut locks = LOCKS.get_or_init(Default::default).lock().unwrap();
+    locks
+        .entry(key.to_vec())
+        .or_insert_with(|| Box::leak(Box::new(Mutex::new(()))))
+}
+
+/// A transfer that's applied to the balances
+pub struct AppliedTransfer<'a> {
+    pub log: &'a ERC20TransferEvent,
+    /// Block the transfer is in
+    pub block_num: BlockNum,
+    /// True if `to` had sent or received tokens before the transfer
+    pub to_seen: bool,
+    /// Balance of `to` after the transfer
+    pub to_balance: &'a BigUint,
+    /// Total supply before the transfer
+    pub total_supply: &'a BigUint,
+}
+
+/// Changes to the balances from replaying transfers on top of the persisted state
+struct BalanceChanges {
+    balances: HashMap<Address, BigUint>,
+    total_supply: BigUint,
+    marked: HashSet<Address>,
+    /// The accumulated amounts of the addresses whose amounts changed
+    amounts: HashMap<Address, f64>,
+}
+
+/// ERC20 balances of a contract that are persisted in RocksDB and advanced incrementally,
+/// so that the transfers are only replayed once.
+/// Each state is owned by a group, so that the processor of the group can persist
+/// the addresses it marks (e.g. the addresses that have been whales) along with the balances.
+pub struct BalanceState {
+    rocksdb_client: Arc<DB>,
+    contract_id: ContractId,
+    owner: [u8; 32],
+}
+
+impl BalanceState {
+    pub fn new(rocksdb_client: Arc<DB>, contract_id: ContractId, owner: &GroupId) -> Self {
+        let owner = hex::decode(owner)
+            .ok()
+            .and_then(|owner| owner.try_into().ok())
+            .unwrap_or_else(|| panic!("Invalid group id {}", owner));
+
+        Self {
+            rocksdb_client,
+            contract_id,
+            owner,
+        }
+    }
+
+    fn contract_prefix(contract_id: ContractId) -> Vec<u8> {
+        let mut key = vec![KeyType::BalanceState as u8];
+        key.extend_from_slice(&contract_id.to_be_bytes());
+        key
+    }
+
+    fn record_prefix(&self, record: Record) -> Vec<u8> {
+        let mut key = Self::contract_prefix(self.contract_id);
+        key.push(record as u8);
+        key.extend_from_slice(&self.owner);
+        key
+    }
+
+    fn record_key(&self, record: Record, address: &Address) -> Vec<u8> {
+        let mut key = self.record_prefix(record);
+        key.extend_from_slice(address);
+        key
+    }
+
+    /// Returns the block the state is at, and the total supply at the block
+    fn get_meta(&self) -> Option<(BlockNum, BigUint)> {
+        let value = self
+            .rocksdb_client
+            .get(self.record_prefix(Record::Meta))
+            .unwrap()?;
+
+        let block_num = BlockNum::from_be_bytes(value[..8].try_into().unwrap());
+        let total_supply = BigUint::from_bytes_be(&value[8..]);
+
+        Some((block_num, total_supply))
+    }
+
+    /// Returns the block the state is persisted at
+    pub fn block_num(&self) -> Option<BlockNum> {
+        self.get_meta().map(|(block_num, _)| block_num)
+    }
+
+    fn get_amount(&self, address: &Address) -> Option<f64> {
+        self.rocksdb_client
+            .get(self.record_key(Record::Amount, address))
+            .unwrap()
+            .map(|value| f64::from_be_bytes(value.try_into().unwrap()))
+    }
+
+    fn get_balance(&self, address: &Address) -> Option<BigUint> {
+        self.rocksdb_client
+            .get(self.record_key(Record::Balance, address))
+            .unwrap()
+            .map(|value| BigUint::from_bytes_be(&value))
+    }
+
+    /// Iterate over the addresses in a record and their values
+    fn iter_record(&self, record: Record) -> impl Iterator<Item = (Address, Vec<u8>)> + '_ {
+        let prefix = self.record_prefix(record);
+
+        self.rocksdb_client
+            .iterator(IteratorMode::From(&prefix, Direction::Forward))
+            .map(|item| item.unwrap())
+            .take_while(move |(key, _)| key.starts_with(&prefix))
+            .map(|(key, value)| {
+                let address = key[key.len() - 20..].try_into().unwrap();
+                (address, value.to_vec())
+            })
+    }
+
+    /// Delete all records of the state
+    fn clear(&self, batch: &mut WriteBatch) {
+        for record in [
+            Record::Meta,
+            Record::Balance,
+            Record::Marked,
+            Record::Amount,
+        ] {
+            let start = self.record_prefix(record);
+
+            // The end of the range is greater than all keys of the record
+            let mut end = start.clone();
+            end.extend_from_slice(&[u8::MAX; 21]);
+
+            batch.delete_range(start, end);
+        }
+    }
+
+    /// Load the balance of `address` from the persisted state into `balances` if it's not loaded yet.
+    /// Returns true if the address has a balance.
+    fn load_balance(&self, balances: &mut HashMap<Address, BigUint>, address: Address) -> bool {
+        if balances.contains_key(&address) {
+            return true;
+        }
+
+        match self.get_balance(&address) {
+            Some(balance) => {
+                balances.insert(address, balance);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Replay the transfers from `from_block` to `to_block` on top of the persisted state.
+    /// If `to_tx_index` is given, only the transfers in the transactions before it are replayed in `to_block`.
+    fn replay(
+        &self,
+        from_block: BlockNum,
+        to_block: BlockNum,
+        to_tx_index: Option<TxIndex>,
+        total_supply: BigUint,
+        mark: &mut impl FnMut(&AppliedTransfer) -> bool,
+        amount: &mut impl FnMut(&AppliedTransfer) -> Result<f64, Error>,
+    ) -> Result<BalanceChanges, Error> {
+        let iterator = ContractEventIterator::new_from_block(
+            &self.rocksdb_client,
+            ERC20_TRANSFER_EVENT_ID,
+            self.contract_id,
+            from_block,
+            Some(to_block),
+        );
+
+        let mut balances = HashMap::new();
+        let mut total_supply = total_supply;
+        let mut marked = HashSet::new();
+        let mut amounts = HashMap::new();
+
+        for (key, value) in iterator {
+            if let Some(to_tx_index) = to_tx_index {
+                if key.block_num.unwrap() == to_block && key.tx_index.unwrap() >= to_tx_index {
+                    break;
+                }
+            }
+
+            let log = decode_erc20_transfer_event(&value);
+
+            if log.value == BigUint::from(0u8) {
+                continue;
+            }
+
+            let to_seen = self.load_balance(&mut balances, log.to);
+            self.load_balance(&mut balances, log.from);
+
+            // Increase balance of `to` by `value`
+            let to_balance = balances.entry(log.to).or_default();
+            *to_balance += &log.value;
+
+            let transfer = AppliedTransfer {
+                log: &log,
+                block_num: key.block_num.unwrap(),
+                to_seen,
+                to_balance,
+                total_supply: &total_supply,
+            };
+
+            if mark(&transfer) {
+                marked.insert(log.to);
+            }
+
+            let transfer_amount = amount(&transfer)?;
+            if transfer_amount != 0.0 {
+                let to_amount = match amounts.get(&log.to) {
+                    Some(to_amount) => *to_amount,
+                    None => self.get_amount(&log.to).unwrap_or_default(),
+                };
+                amounts.insert(log.to, to_amount + transfer_amount);
+            }
+
+            // Initialize the balance of `from` to 0
+            let from_balance = balances.entry(log.from).or_default();
+
+            if log.from == MINTER_ADDRESS {
+                // Increase total supply by `value`
+                total_supply += &log.value;
+            } else {
+                if *from_balance < log.value {
+                    return Err(IndexerError::InvalidBalance.into());
+                }
+
+                // Decrease balance of `from` by `value`
+                *from_balance -= &log.value;
+            }
+
+            if log.to == MINTER_ADDRESS {
+                // Decrease total supply by `value`
+                total_supply -= &log.value;
+            }
+        }
+
+        Ok(BalanceChanges {
+            balances,
+            total_supply,
+            marked,
+            amounts,
+        })
+    }
+
+    /// Persist the changes as the state at `block_num`
+    fn commit(&self, block_num: BlockNum, changes: &BalanceChanges) {
+        let mut batch = WriteBatch::default();
+
+        for (address, balance) in &changes.balances {
+            batch.put(
+                self.record_key(Record::Balance, address),
+                balance.to_bytes_be(),
+            );
+        }
+
+        for address in &changes.marked {
+            batch.put(self.record_key(Record::Marked, address), []);
+        }
+
+        for (address, amount) in &changes.amounts {
+            batch.put(
+                self.record_key(Record::Amount, address),
+                amount.to_be_bytes(),
+            );
+        }
+
+        let mut meta = block_num.to_be_bytes().to_vec();
+        meta.extend_from_slice(&changes.total_supply.to_bytes_be());
+        batch.put(self.record_prefix(Record::Meta), meta);
+
+        self.rocksdb_client.write(batch).unwrap();
+    }
+
+    /// Get the balances at `to_block`.
+    /// If `to_tx_index` is given, the balances are before the transaction at the index in `to_block`.
+    ///
+    /// The persisted state is advanced to `BALANCE_STATE_LAG` blocks before `to_block`,
+    /// and the transfers of the remaining blocks are replayed in memory.
+    /// `mark` is called for each replayed transfer, and `to` is marked if it returns true.
+    pub fn get_balances(
+        &self,
+        to_block: BlockNum,
+        to_tx_index: Option<TxIndex>,
+        mark: impl FnMut(&AppliedTransfer) -> bool,
+    ) -> Result<Balances<'_>, Error> {
+        self.get_balances_with_amounts(to_block, to_tx_index, mark, |_| Ok(0.0))
+    }
+
+    /// Same as `get_balances`, but also accumulates an amount for each address.
+    /// `amount` is called for each replayed transfer, and the returned amount is added to the amount of `to`.
+    pub fn get_balances_with_amounts(
+        &self,
+        to_block: BlockNum,
+        to_tx_index: Option<TxIndex>,
+        mut mark: impl FnMut(&AppliedTransfer) -> bool,
+        mut amount: impl FnMut(&AppliedTransfer) -> Result<f64, Error>,
+    ) -> Result<Balances<'_>, Error> {
+        let guard = state_lock(&self.record_prefix(Record::Meta))
+            .lock()
+            .unwrap();
+
+        let persist_to = to_block.saturating_sub(BALANCE_STATE_LAG);
+
+        let (from_block, mut total_supply) = match self.get_meta() {
+            Some((block_num, total_supply)) if block_num <= persist_to => {
+                (block_num + 1, total_supply)
+            }
+            Some(_) => {
+                // The state is ahead of the requested block (e.g. the confirmations were changed),
+                // so we rebuild it from the start.
+                let mut batch = WriteBatch::default();
+                self.clear(&mut batch);
+                self.rocksdb_client.write(batch).unwrap();
+
+                (0, BigUint::from(0u8))
+            }
+            None => (0, BigUint::from(0u8)),
+        };
+
+        // Advance the persisted state
+        if from_block <= persist_to {
+            let changes = self.replay(
+                from_block,
+                persist_to,
+                None,
+                total_supply,
+                &mut mark,
+                &mut amount,
+            )?;
+            self.commit(persist_to, &changes);
+
+            total_supply = changes.total_supply;
+        }
+
+        // Replay the transfers of the recent blocks in memory
+        let changes = self.replay(
+            persist_to + 1,
+            to_block,
+            to_tx_index,
+            total_supply,
+            &mut mark,
+            &mut amount,
+        )?;
+
+        Ok(Balances {
+            state: self,
+            changes,
+            _guard: guard,
+        })
+    }
+
+    /// Delete the states of the contract that contain the transfers at or after `from_block`,
+    /// so that they're rebuilt after the logs are rolled back
+    pub fn rollback(
+        rocksdb_client: &Arc<DB>,
+        contract_id: ContractId,
+        from_block: BlockNum,
+        batch: &mut WriteBatch,
+    ) {
+        let mut prefix = Self::contract_prefix(contract_id);
+        prefix.push(Record::Meta as u8);
+
+        let owners = rocksdb_client
+            .iterator(IteratorMode::From(&prefix, Direction::Forward))
+            .map(|item| item.unwrap())
+            .take_while(|(key, _)| key.starts_with(&prefix))
+            .map(|(key, _)| key[prefix.len()..].try_into().unwrap())
+            .collect::<Vec<[u8; 32]>>();
+
+        for owner in owners {
+            let state = Self {
+                rocksdb_client: rocksdb_client.clone(),
+                contract_id,
+                owner,
+            };
+
+            if state
+                .block_num()
+                .map_or(false, |block_num| block_num >= from_block)
+            {
+                state.clear(batch);
+            }
+        }
+    }
+}
+
+/// Balances at a block: the persisted state with the changes of the recent blocks on top.
+/// The state can't be updated while this is alive.
+pub struct Balances<'a> {
+    state: &'a BalanceState,
+    changes: BalanceChanges,
+    _guard: MutexGuard<'static, ()>,
+}
+
+impl Balances<'_> {
+    pub fn get(&self, address: &Address) -> BigUint {
+        self.changes
+            .balances
+            .get(address)
+            .cloned()
+            .or_else(|| self.state.get_balance(address))
+            .unwrap_or_default()
+    }
+
+    pub fn total_supply(&self) -> &BigUint {
+        &self.changes.total_supply
+    }
+
+    /// Returns the balances of all addresses that have sent or received tokens
+    pub fn all(&self) -> HashMap<Address, BigUint> {
+        let mut balances = self
+            .state
+            .iter_record(Record::Balance)
+            .map(|(address, value)| (address, BigUint::from_bytes_be(&value)))
+            .collect::<HashMap<Address, BigUint>>();
+
+        for (address, balance) in &self.changes.balances {
+            balances.insert(*address, balance.clone());
+        }
+
+        balances
+    }
+
+    /// Returns the marked addresses
+    pub fn marked(&self) -> HashSet<Address> {
+        let mut marked = self
+            .state
+            .iter_record(Record::Marked)
+            .map(|(address, _)| address)
+            .collect::<HashSet<Address>>();
+
+        marked.extend(self.changes.marked.iter());
+
+        marked
+    }
+
+    /// Returns the accumulated amounts of all addresses that have an amount
+    pub fn amounts(&self) -> HashMap<Address, f64> {
+        let mut amounts = self
+            .state
+            .iter_record(Record::Amount)
+            .map(|(address, value)| (address, f64::from_be_bytes(value.try_into().unwrap())))
+            .collect::<HashMap<Address, f64>>();
+
+        for (address, amount) in &self.changes.amounts {
+            amounts.insert(*address, *amount);
+        }
+
+        amounts
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::{init_test_rocksdb, put_erc20_transfer};
+
+    const CONTRACT_ID: ContractId = 1;
+
+    #[test]
+    fn test_balance_state() {
+        let db = init_test_rocksdb("test_balance_state");
+
+        let owner = hex::encode([1; 32]);
+        let state = BalanceState::new(db.clone(), CONTRACT_ID, &owner);
+
+        let alice = [1; 20];
+        let bob = [2; 20];
+
+        put_erc20_transfer(&db, CONTRACT_ID, 1000, 0, MINTER_ADDRESS, alice, 100);
+        put_erc20_transfer(&db, CONTRACT_ID, 1100, 0, alice, bob, 30);
+        put_erc20_transfer(&db, CONTRACT_ID, 1200, 0, alice, bob, 20);
+        put_erc20_transfer(&db, CONTRACT_ID, 1200, 1, bob, alice, 10);
+
+        // Mark the addresses that received tokens before
+        let mark = |transfer: &AppliedTransfer| transfer.to_seen;
+
+        let balances = state.get_balances(1200, None, mark).unwrap();
+        assert_eq!(balances.get(&alice), BigUint::from(60u8));
+        assert_eq!(balances.get(&bob), BigUint::from(40u8));
+        assert_eq!(balances.total_supply(), &BigUint::from(100u8));
+        assert_eq!(balances.marked(), HashSet::from([alice, bob]));
+        drop(balances);
+
+        // Only the transfers before the lag are persisted
+        assert_eq!(state.block_num(), Some(1200 - BALANCE_STATE_LAG));
+
+        // Get the balances before the second transaction of block 1200
+        let balances = state.get_balances(1200, Some(1), mark).unwrap();
+        assert_eq!(balances.get(&alice), BigUint::from(50u8));
+        assert_eq!(balances.get(&bob), BigUint::from(50u8));
+        drop(balances);
+
+        // Advance the state with a new transfer
+        put_erc20_transfer(&db, CONTRACT_ID, 1500, 0, bob, MINTER_ADDRESS, 40);
+
+        let balances = state.get_balances(1500, None, mark).unwrap();
+        assert_eq!(balances.get(&bob), BigUint::from(0u8));
+        assert_eq!(balances.total_supply(), &BigUint::from(60u8));
+        assert_eq!(balances.all().len(), 3);
+        drop(balances);
+
+        assert_eq!(state.block_num(), Some(1500 - BALANCE_STATE_LAG));
+
+        // States that contain rolled back blocks are deleted
+        let mut batch = WriteBatch::default();
+        BalanceState::rollback(&db, CONTRACT_ID, 1300, &mut batch);
+        db.write(batch).unwrap();
+
+        assert_eq!(state.block_num(), None);
+
+        // Amounts are accumulated across the persisted state and the recent blocks
+        let owner = hex::encode([2; 32]);
+        let state = BalanceState::new(db.clone(), CONTRACT_ID, &owner);
+        let amount = |transfer: &AppliedTransfer| Ok(transfer.block_num as f64);
+
+        let balances = state
+            .get_balances_with_amounts(1200, None, |_| false, amount)
+            .unwrap();
+        assert_eq!(balances.amounts().get(&alice), Some(&2200.0));
+        assert_eq!(balances.amounts().get(&bob), Some(&2300.0));
+        drop(balances);
+
+        let balances = state
+            .get_balances_with_amounts(1500, None, |_| false, amount)
+            .unwrap();
+        assert_eq!(balances.amounts().get(&bob), Some(&2300.0));
+        assert_eq!(balances.amounts().get(&MINTER_ADDRESS), Some(&1500.0));
+        drop(balances);
+
+        // Invalid balances are detected
+        put_erc20_transfer(&db, CONTRACT_ID, 1600, 0, bob, alice, 1);
+        assert!(state.get_balances(1600, None, mark).is_err());
+    }
+}
@@ -0,0 +1,45 @@
+use indexer_rs::{postgres::init_postgres, server::auth::hash_api_key, utils::dotenv_config};
+use rand::Rng;
+use std::env;
+
+/// Create an API key of the indexer server and print it.
+/// Only the hash of the key is saved, so the key can't be printed again.
+///
+/// Usage: create_api_key <name> [rate limit per minute] [comma-separated allowed methods]
+#[tokio::main]
+async fn main() -> Result<(), tokio_postgres::Error> {
+    dotenv_config();
+
+    let args: Vec<String> = env::args().collect();
+
+    let name = args.get(1).expect("Name of the API key is required");
+    let rate_limit_per_minute = args
+        .get(2)
+        .map_or(600, |rate_limit| rate_limit.parse::<i32>().unwrap());
+    let allowed_methods = args.get(3).map_or(vec![], |methods| {
+        methods
+            .split(',')
+            .map(|method| method.trim().to_string())
+            .collect::<Vec<String>>()
+    });
+
+    let api_key = hex::encode(rand::thread_rng().gen::<[u8; 32]>());
+
+    let pg_client = init_postgres().await;
+
+    pg_client
+        .execute(
+            r#"INSERT INTO "ApiKey" ("name", "keyHash", "allowedMethods", "rateLimitPerMinute", "updatedAt") VALUES ($1, $2, $3, $4, NOW())"#,
+            &[
+                &name,
+                &hash_api_key(&api_key),
+                &allowed_methods,
+                &rate_limit_per_minute,
+            ],
+        )
+        .await?;
+
+    println!("API key of {}: {}", name, api_key);
+
+    Ok(())
+}
@@ -1,32 +1,28 @@
 use futures::future::join_all;
 use futures::join;
-use indexer_rs::contract::ContractType;
+use indexer_rs::contract::{get_contracts, ContractType};
+use indexer_rs::delegate_registries::{DelegateRegistries, Registry};
 use indexer_rs::eth_rpc::EthRpcClient;
+use indexer_rs::event_registry::get_registered_events;
 use indexer_rs::group::get_all_groups;
 use indexer_rs::intrinsic_creddd_sync_engine::IntrinsicCredddSyncEngine;
 use indexer_rs::log_sync_engine::LogSyncEngine;
 use indexer_rs::postgres::init_neynar_db;
 use indexer_rs::postgres::init_postgres;
-use indexer_rs::processors::all_holders::AllHoldersIndexer;
-use indexer_rs::processors::believer::BelieverIndexer;
-use indexer_rs::processors::creddd_team::CredddTeamIndexer;
-use indexer_rs::processors::early_holders::EarlyHolderIndexer;
-use indexer_rs::processors::salon::SalonIndexer;
-use indexer_rs::processors::ticker::TickerIndexer;
-use indexer_rs::processors::whales::WhaleIndexer;
-use indexer_rs::processors::GroupIndexer;
+use indexer_rs::processors::new_group_indexer;
 use indexer_rs::processors::IndexerResources;
 use indexer_rs::rocksdb_key::{
     ERC1155_TRANSFER_BATCH_EVENT_ID, ERC1155_TRANSFER_SINGLE_EVENT_ID, ERC20_TRANSFER_EVENT_ID,
     ERC721_TRANSFER_EVENT_ID,
 };
-use indexer_rs::server::server::start_server;
+use indexer_rs::server::server::{start_server, ServerContext};
+use indexer_rs::shutdown::{shutdown_signal, Shutdown};
 use indexer_rs::status_logger::start_status_logger;
+use indexer_rs::tree_events::TreeEventEmitter;
 use indexer_rs::tree_sync_engine::TreeSyncEngine;
 use indexer_rs::utils::dotenv_config;
-use indexer_rs::GroupType;
 use indexer_rs::ROCKSDB_PATH;
-use log::error;
+use log::{error, info};
 use rocksdb::{Options, DB};
 use std::collections::HashSet;
 use std::sync::Arc;
@@ -45,10 +41,18 @@ async fn main() {
 
     let rocksdb_conn = rocksdb_conn;
     let rocksdb_client = Arc::new(rocksdb_conn);
-    let eth_client = Arc::new(EthRpcClient::new());
+    let eth_client = match EthRpcClient::from_env() {
+        Ok(eth_client) => Arc::new(eth_client),
+        Err(err) => {
+            error!("Invalid RPC configuration: {:?}", err);
+            std::process::exit(1);
+        }
+    };
 
     // It's ok to unwrap here because this is a one-time operation
     let groups = get_all_groups(&pg_client).await.unwrap();
+    let registered_events = get_registered_events(&pg_client).await.unwrap();
+    let all_contracts = get_contracts(&pg_client).await.unwrap();
 
     // Set to store the contracts that the groups depend on
     let mut contracts = HashSet::new();
@@ -60,10 +64,46 @@ async fn main() {
         }
     }
 
+    // Sync the delegate registries if any group includes the delegates of its members
+    if groups.iter().any(|group| group.include_delegates) {
+        for contract in &all_contracts {
+            if Registry::of(contract).is_some() {
+                contracts.insert(contract.clone());
+            }
+        }
+    }
+
+    // Stop the jobs and the server on Ctrl-C or SIGTERM
+    let (shutdown_trigger, shutdown) = Shutdown::new();
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        info!("Shutting down...");
+        shutdown_trigger.trigger();
+    });
+
     let mut sync_jobs = vec![];
     for contract in contracts.clone() {
         let rocksdb_client = rocksdb_client.clone();
         let eth_client = eth_client.clone();
+        let shutdown = shutdown.clone();
+
+        // Get the registered events to sync for the contract
+        let contract_events = contract
+            .events
+            .iter()
+            .map(|event_id| {
+                registered_events
+                    .iter()
+                    .find(|event| event.id == *event_id)
+                    .unwrap_or_else(|| {
+                        panic!(
+                            "Event id {} specified in contract not found in the database",
+                            event_id
+                        )
+                    })
+                    .clone()
+            })
+            .collect::<Vec<_>>();
 
         let job = tokio::spawn(async move {
             // Determine the event_ids to sync from `contract.type`
@@ -75,20 +115,33 @@ async fn main() {
                     ERC1155_TRANSFER_SINGLE_EVENT_ID,
                     ERC1155_TRANSFER_BATCH_EVENT_ID,
                 ],
-                _ => panic!("Invalid contract type"),
+                // Contracts of other types only sync their registered events
+                ContractType::Other => vec![],
             };
 
             // Run the sync job for each event_id
-            join_all(event_id.iter().map(|event_id| async {
+            let builtin_event_jobs = join_all(event_id.iter().map(|event_id| async {
                 let contract_sync_engine = LogSyncEngine::new(
                     eth_client.clone(),
                     contract.clone(),
                     *event_id,
                     rocksdb_client.clone(),
                 );
-                contract_sync_engine.sync().await;
-            }))
-            .await;
+                contract_sync_engine.sync(&shutdown).await;
+            }));
+
+            // Run the sync job for each registered event
+            let registered_event_jobs = join_all(contract_events.into_iter().map(|event| async {
+                let contract_sync_engine = LogSyncEngine::new_with_event(
+                    eth_client.clone(),
+                    contract.clone(),
+                    event,
+                    rocksdb_client.clone(),
+                );
+                contract_sync_engine.sync(&shutdown).await;
+            }));
+
+            join!(builtin_event_jobs, registered_event_jobs);
         });
 
         sync_jobs.push(job);
@@ -98,6 +151,9 @@ async fn main() {
 
     let indexing_permits = Arc::new(Semaphore::new(20));
 
+    // The sinks of the tree events, shared by all groups
+    let tree_events = Arc::new(TreeEventEmitter::from_env(pg_client.clone()));
+
     for group in groups {
         // Initialize indexers for each group
         let resources = IndexerResources {
@@ -111,71 +167,51 @@ async fn main() {
         let eth_client = eth_client.clone();
 
         let indexing_permits = indexing_permits.clone();
+        let tree_events = tree_events.clone();
+        let shutdown = shutdown.clone();
+
+        let delegate_registries = if group.include_delegates {
+            Some(DelegateRegistries::new(
+                rocksdb_client.clone(),
+                eth_client.clone(),
+                &all_contracts,
+            ))
+        } else {
+            None
+        };
 
         let job = tokio::spawn(async move {
             let pg_client = pg_client.clone();
             let rocksdb_client = rocksdb_client.clone();
             let eth_client = eth_client.clone();
 
-            // Initialize the indexer for the target group
-            let indexer: Box<dyn GroupIndexer> = match group.group_type {
-                GroupType::AllHolders => {
-                    Box::new(AllHoldersIndexer::new(group.clone(), resources.clone()))
-                }
-                GroupType::EarlyHolder => {
-                    Box::new(EarlyHolderIndexer::new(group.clone(), resources.clone()))
-                }
-                GroupType::Whale => Box::new(WhaleIndexer::new(group.clone(), resources.clone())),
-                GroupType::Ticker => Box::new(TickerIndexer::new(group.clone(), resources.clone())),
-                GroupType::CredddTeam => {
-                    Box::new(CredddTeamIndexer::new(group.clone(), resources.clone()))
-                }
-                GroupType::Static => panic!("Static group type is deprecated"),
-                GroupType::Believer => {
-                    Box::new(BelieverIndexer::new(group.clone(), resources.clone()))
-                }
-                GroupType::BaseSalon => {
-                    Box::new(SalonIndexer::new(group.clone(), resources.clone()))
-                }
-                GroupType::BlastSalon => {
-                    Box::new(SalonIndexer::new(group.clone(), resources.clone()))
-                }
-                GroupType::EthSalon => {
-                    Box::new(SalonIndexer::new(group.clone(), resources.clone()))
-                }
-                GroupType::ArbSalon => {
-                    Box::new(SalonIndexer::new(group.clone(), resources.clone()))
-                }
-                GroupType::OpSalon => Box::new(SalonIndexer::new(group.clone(), resources.clone())),
-                GroupType::FriendBagHolder => {
-                    Box::new(SalonIndexer::new(group.clone(), resources.clone()))
-                }
-                GroupType::Farcaster1K => {
-                    Box::new(SalonIndexer::new(group.clone(), resources.clone()))
-                }
-                GroupType::Farcaster10K => {
-                    Box::new(SalonIndexer::new(group.clone(), resources.clone()))
-                }
-                GroupType::Farcaster100K => {
-                    Box::new(SalonIndexer::new(group.clone(), resources.clone()))
-                }
-                GroupType::FarcasterPowerUsers => {
-                    Box::new(SalonIndexer::new(group.clone(), resources.clone()))
+            // Initialize the indexer for the target group.
+            // A group that can't be indexed is skipped so the other groups keep syncing.
+            let indexer = match new_group_indexer(group.clone(), resources.clone()) {
+                Ok(indexer) => indexer,
+                Err(err) => {
+                    error!("Skipping group {}: {:?}", group.name, err);
+                    return;
                 }
             };
 
             // Initialize the tree sync engine for the group
-            let tree_syn_engine = TreeSyncEngine::new(
+            let mut tree_syn_engine = TreeSyncEngine::new(
                 indexer,
                 group,
                 pg_client.clone(),
                 rocksdb_client.clone(),
                 eth_client.clone(),
                 indexing_permits.clone(),
-            );
+            )
+            .with_events(tree_events);
+
+            if let Some(delegate_registries) = delegate_registries {
+                tree_syn_engine = tree_syn_engine.with_delegate_registries(delegate_registries);
+            }
 
             // Start the tree sync
-            tree_syn_engine.sync().await;
+            tree_syn_engine.sync(&shutdown).await;
         });
 
         indexing_jobs.push(job);
@@ -186,17 +222,26 @@ async fn main() {
     let intrinsic_creddd_sync_engine =
         IntrinsicCredddSyncEngine::new(fc_replica_db, pg_client.clone());
 
-    let intrinsic_creddd_sync_job = intrinsic_creddd_sync_engine.sync();
+    let intrinsic_shutdown = shutdown.clone();
+    let intrinsic_creddd_sync_job = intrinsic_creddd_sync_engine.sync(&intrinsic_shutdown);
 
     let _pg_client = pg_client.clone();
+    let server_shutdown = shutdown.clone();
     let sever_thread = tokio::spawn(async move {
-        start_server(rocksdb_client.clone(), pg_client).await;
+        let ctx = ServerContext {
+            pg_client,
+            rocksdb_conn: rocksdb_client.clone(),
+            tree_events,
+        };
+
+        start_server(ctx, server_shutdown.requested()).await;
     });
 
     let pg_client = _pg_client;
 
-    let status_logger_thread =
-        tokio::spawn(async move { start_status_logger(pg_client.clone()).await });
+    let status_logger_thread = tokio::spawn(async move {
+        start_status_logger(pg_client.clone(), eth_client.clone(), shutdown).await
+    });
 
     // Run the sync and indexing jobs concurrently
     let (sync_results, indexing_results, _intrinsic_result, server_result, _status_logger_result) = join!(
@@ -1,8 +1,13 @@
 use indexer_rs::{
-    contract::upsert_contract,
+    contract::{upsert_contract, Contract},
+    delegate_registries::Registry,
+    event_registry::upsert_event,
     group::upsert_group,
     postgres::init_postgres,
-    seeder::seed_groups::get_seed_groups,
+    seeder::{
+        seed_contracts::get_seed_contracts, seed_events::get_seed_events,
+        seed_groups::get_seed_groups,
+    },
     utils::{dotenv_config, is_prod},
 };
 use std::collections::HashSet;
@@ -14,6 +19,11 @@ async fn main() {
     // Connect to the database.
     let client = init_postgres().await;
 
+    // Populate the registered events.
+    for event in get_seed_events() {
+        upsert_event(&client, &event).await.unwrap();
+    }
+
     let groups = get_seed_groups();
 
     // Populate the contracts that the groups are based on.
@@ -24,6 +34,14 @@ async fn main() {
         }
     }
 
+    // Populate the delegate registries, which aren't inputs of any group.
+    for contract in get_seed_contracts() {
+        let contract = Contract::from_contract_data(contract);
+        if Registry::of(&contract).is_some() {
+            contracts.insert(contract);
+        }
+    }
+
     for contract in contracts {
         upsert_contract(&client, &contract).await.unwrap();
     }
@@ -0,0 +1,120 @@
+use rocksdb::{DBIteratorWithThreadMode, IteratorMode, ReadOptions, DB};
+
+use crate::{
+    rocksdb_key::{KeyType, RocksDbKey},
+    BlockNum, ContractId, EventId,
+};
+
+/// An iterator over the stored block hashes of a contract event.
+/// It wraps the RocksDB iterator and filters the block hashes for a specific contract and event.
+pub struct BlockHashIterator<'a> {
+    event_id: EventId,
+    contract_id: ContractId,
+    inner: DBIteratorWithThreadMode<'a, DB>,
+}
+
+impl<'a> BlockHashIterator<'a> {
+    pub fn new(db: &'a DB, event_id: EventId, contract_id: ContractId) -> Self {
+        // Initialize the RocksDB iterator that starts from the first block hash for `contract_id`
+        let mut iterator_ops = ReadOptions::default();
+        iterator_ops.set_async_io(true);
+
+        let start_key = RocksDbKey::new_start_key(KeyType::BlockHash, event_id, contract_id);
+
+        let iterator = db.iterator_opt(
+            IteratorMode::From(&start_key.to_bytes(), rocksdb::Direction::Forward),
+            iterator_ops,
+        );
+
+        Self {
+            event_id,
+            contract_id,
+            inner: iterator,
+        }
+    }
+}
+
+impl Iterator for BlockHashIterator<'_> {
+    type Item = (BlockNum, [u8; 32]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let item = self.inner.next();
+
+        if let Some(item) = item {
+            let (key, value) = item.unwrap();
+
+            // The keys of the other types that follow the block hashes (e.g. the balance states)
+            // have different lengths, so they can't be decoded as block hash keys
+            if key.first().copied().and_then(KeyType::from_byte) != Some(KeyType::BlockHash) {
+                return None;
+            }
+
+            let key = RocksDbKey::from_bytes(key.as_ref());
+
+            if key.key_type == KeyType::BlockHash
+                && key.contract_id.unwrap() == self.contract_id
+                && key.event_id.unwrap() == self.event_id
+            {
+                let block_hash = value.as_ref().try_into().unwrap();
+                Some((key.block_num.unwrap(), block_hash))
+            } else {
+                // Reached the end of the block hashes for the contract event
+                None
+            }
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{test_utils::delete_all, ROCKSDB_PATH};
+    use rocksdb::Options;
+
+    fn block_hash_key(event_id: EventId, contract_id: ContractId, block_num: BlockNum) -> Vec<u8> {
+        RocksDbKey {
+            key_type: KeyType::BlockHash,
+            event_id: Some(event_id),
+            contract_id: Some(contract_id),
+            block_num: Some(block_num),
+            log_index: None,
+            tx_index: None,
+            chunk_num: None,
+            chain_id: None,
+        }
+        .to_bytes()
+        .to_vec()
+    }
+
+    #[test]
+    fn test_stops_at_other_key_types() {
+        const TEST_ROCKSDB_PATH: &str = "test_block_hash_iterator";
+
+        let mut rocksdb_options = Options::default();
+        rocksdb_options.create_if_missing(true);
+
+        let rocksdb_client = DB::open(
+            &rocksdb_options,
+            format!("{}/{}", ROCKSDB_PATH, TEST_ROCKSDB_PATH),
+        )
+        .unwrap();
+
+        delete_all(&rocksdb_client);
+
+        rocksdb_client
+            .put(block_hash_key(1, 2, 100), [1u8; 32])
+            .unwrap();
+        rocksdb_client
+            .put(block_hash_key(1, 2, 101), [2u8; 32])
+            .unwrap();
+
+        // A key of another type with a different length right after the block hashes
+        rocksdb_client.put([6u8, 1, 2, 3], [0u8; 8]).unwrap();
+
+        let block_hashes = BlockHashIterator::new(&rocksdb_client, 1, 2).collect::<Vec<_>>();
+
+        assert_eq!(block_hashes, vec![(100, [1u8; 32]), (101, [2u8; 32])]);
+    }
+}
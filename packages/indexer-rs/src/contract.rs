@@ -1,4 +1,6 @@
-use crate::{eth_rpc::Chain, seeder::seed_contracts::ContractData, BlockNum, ContractId, Error};
+use crate::{
+    eth_rpc::Chain, seeder::seed_contracts::ContractData, BlockNum, ContractId, Error, EventId,
+};
 use postgres_types::{FromSql, ToSql};
 use serde::{Deserialize, Serialize};
 
@@ -20,6 +22,8 @@ pub struct Contract {
     pub contract_type: ContractType,
     pub name: String,
     pub deployed_block: BlockNum,
+    /// Ids of the registered events to sync in addition to the events of `contract_type`
+    pub events: Vec<EventId>,
 }
 
 impl Contract {
@@ -35,30 +39,42 @@ impl Contract {
             contract_type: data.contract_type,
             name: data.name,
             deployed_block: data.deployed_block,
+            events: data.events,
         }
     }
 }
 
-/// Upsert a contract into the database
-pub async fn upsert_contract(
-    pg_client: &tokio_postgres::Client,
-    contract: &Contract,
-) -> Result<(), tokio_postgres::Error> {
-    let chain = match contract.chain {
+/// The name of a chain in the `chain` column of the `Contract` table
+pub fn chain_name(chain: Chain) -> &'static str {
+    match chain {
         Chain::Mainnet => "Ethereum",
         Chain::Optimism => "OP Mainnet",
         Chain::Base => "Base",
         Chain::Arbitrum => "Arbitrum One",
         Chain::Blast => "Blast",
-    };
+    }
+}
+
+/// Upsert a contract into the database
+pub async fn upsert_contract(
+    pg_client: &tokio_postgres::Client,
+    contract: &Contract,
+) -> Result<(), tokio_postgres::Error> {
+    let chain = chain_name(contract.chain);
+
+    let events = contract
+        .events
+        .iter()
+        .map(|event_id| *event_id as i32)
+        .collect::<Vec<i32>>();
 
     // The contract doesn't exist yet so we insert it
 
     pg_client
         .execute(
-            r#"INSERT INTO "Contract" ("id", "address", "type", "name", "chain", "deployedBlock",  "updatedAt")
-            VALUES ($1, $2, $3, $4, $5, $6, NOW())
-            ON CONFLICT ("id", "address", "chain") DO UPDATE SET "type" = $3, "name" = $4, "deployedBlock" = $6, "updatedAt" = NOW()
+            r#"INSERT INTO "Contract" ("id", "address", "type", "name", "chain", "deployedBlock", "events", "updatedAt")
+            VALUES ($1, $2, $3, $4, $5, $6, $7, NOW())
+            ON CONFLICT ("id", "address", "chain") DO UPDATE SET "type" = $3, "name" = $4, "deployedBlock" = $6, "events" = $7, "updatedAt" = NOW()
             "#,
             &[
                 &(contract.id as i32),
@@ -67,6 +83,7 @@ pub async fn upsert_contract(
                 &contract.name,
                 &chain,
                 &(contract.deployed_block as i64) ,
+                &events,
             ],
         )
         .await?;
@@ -79,7 +96,7 @@ pub async fn get_contracts(pg_clinet: &tokio_postgres::Client) -> Result<Vec<Con
     // Get all contracts from the storage
     let result = pg_clinet
         .query(
-            r#"SELECT "id", "address", "type",  "name", "chain", "deployedBlock" FROM "Contract""#,
+            r#"SELECT "id", "address", "type",  "name", "chain", "deployedBlock", "events" FROM "Contract""#,
             &[],
         )
         .await?;
@@ -93,6 +110,7 @@ pub async fn get_contracts(pg_clinet: &tokio_postgres::Client) -> Result<Vec<Con
             let chain: String = row.get("chain");
             let contract_deployed_block: i64 = row.get("deployedBlock");
             let contract_type: ContractType = row.get("type");
+            let events: Vec<i32> = row.get("events");
 
             // Convert the chain string to Chain enum
             let chain = match chain.as_str() {
@@ -111,6 +129,7 @@ pub async fn get_contracts(pg_clinet: &tokio_postgres::Client) -> Result<Vec<Con
                 name: name.clone(),
                 deployed_block: contract_deployed_block as u64,
                 contract_type,
+                events: events.iter().map(|event_id| *event_id as EventId).collect(),
             }
         })
         .collect();
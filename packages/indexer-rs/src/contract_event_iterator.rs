@@ -1,6 +1,6 @@
 use crate::{
     rocksdb_key::{KeyType, RocksDbKey},
-    BlockNum, ContractId, EventId,
+    BlockNum, ContractId, EventId, LogIndex, TxIndex,
 };
 use rocksdb::{DBIteratorWithThreadMode, IteratorMode, ReadOptions, DB};
 
@@ -19,12 +19,36 @@ impl<'a> ContractEventIterator<'a> {
         event_id: EventId,
         contract_id: ContractId,
         to_block: Option<BlockNum>,
+    ) -> Self {
+        Self::new_from_block(db, event_id, contract_id, 0, to_block)
+    }
+
+    /// Create an iterator that starts from the first log at or after `from_block`
+    pub fn new_from_block(
+        db: &'a DB,
+        event_id: EventId,
+        contract_id: ContractId,
+        from_block: BlockNum,
+        to_block: Option<BlockNum>,
     ) -> Self {
         // Initialize the RocksDB iterator that starts from the first log for `contract.id`
         let mut iterator_ops = ReadOptions::default();
         iterator_ops.set_async_io(true);
 
-        let start_key = RocksDbKey::new_start_key(KeyType::EventLog, event_id, contract_id);
+        let start_key = RocksDbKey {
+            block_num: Some(from_block),
+            ..RocksDbKey::new_start_key(KeyType::EventLog, event_id, contract_id)
+        };
+
+        // Stop at the end of the logs for the contract event,
+        // so the iterator doesn't read keys of other types
+        let end_key = RocksDbKey {
+            block_num: Some(BlockNum::MAX),
+            tx_index: Some(TxIndex::MAX),
+            log_index: Some(LogIndex::MAX),
+            ..start_key
+        };
+        iterator_ops.set_iterate_upper_bound(end_key.to_bytes());
 
         let iterator = db.iterator_opt(
             IteratorMode::From(&start_key.to_bytes(), rocksdb::Direction::Forward),
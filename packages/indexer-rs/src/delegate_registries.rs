@@ -0,0 +1,815 @@
+use crate::{
+    balance_state::state_lock,
+    contract::Contract,
+    contract_event_iterator::ContractEventIterator,
+    eth_rpc::{Chain, ChainBlocks, EthRpcClient},
+    generic_event::EventField,
+    log_sync_engine::REORG_WINDOW,
+    rocksdb_key::KeyType,
+    utils::{decode_generic_event, is_event_logs_ready},
+    Address, BlockNum, ContractId, Error, EventId,
+};
+use rocksdb::{Direction, IteratorMode, WriteBatch, DB};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+/// Number of blocks the persisted registry states lag behind the block the delegates are requested at.
+/// The logs of the recent blocks can still be rolled back by a reorg,
+/// so they're replayed on top of the persisted states in memory instead.
+pub const REGISTRY_STATE_LAG: BlockNum = REORG_WINDOW;
+
+// The keys of a registry state are the concatenation of the following fields:
+// [KeyType::DelegateRegistryState (1 byte)][contract id (2 bytes)][record (1 byte)][record key]
+// where the record key is
+// - Meta: empty
+// - Delegation: [vault (20 bytes)][delegate (20 bytes)][contract (20 bytes, absent for all assets)]
+// - HotWallet: [cold wallet (20 bytes)]
+// and the value of a HotWallet is [hot wallet (20 bytes)][expiration timestamp (8 bytes)]
+
+/// The delegate.xyz v1 `DelegationRegistry`
+pub const DELEGATE_V1_REGISTRY: &str = "0x00000000000076a84fef008cdabe6409d2fe638b";
+
+/// The delegate.xyz v2 `DelegateRegistry`
+pub const DELEGATE_V2_REGISTRY: &str = "0x00000000000000447e69651d841bd8d104bed493";
+
+/// The warm.xyz `HotWalletProxy`
+pub const WARM_HOT_WALLET_PROXY: &str = "0xc3aa9bc72bd623168860a1e5c6a4530d3d80456c";
+
+// Ids of the registry events in seed_events.json
+
+/// `DelegateForAll(address vault, address delegate, bool value)` of delegate.xyz v1
+pub const DELEGATE_FOR_ALL_EVENT_ID: EventId = 103;
+/// `DelegateForContract(address vault, address delegate, address contract_, bool value)` of delegate.xyz v1
+pub const DELEGATE_FOR_CONTRACT_EVENT_ID: EventId = 104;
+/// `RevokeAllDelegates(address vault)` of delegate.xyz v1
+pub const REVOKE_ALL_DELEGATES_EVENT_ID: EventId = 105;
+/// `RevokeDelegate(address vault, address delegate)` of delegate.xyz v1
+pub const REVOKE_DELEGATE_EVENT_ID: EventId = 106;
+/// `DelegateAll(address indexed from, address indexed to, bytes32 rights, bool enable)` of delegate.xyz v2
+pub const DELEGATE_ALL_EVENT_ID: EventId = 107;
+/// `DelegateContract(address indexed from, address indexed to, address indexed contract_, bytes32 rights, bool enable)`
+/// of delegate.xyz v2
+pub const DELEGATE_CONTRACT_EVENT_ID: EventId = 108;
+/// `HotWalletChanged(address coldWallet, address from, address to, uint256 expirationTimestamp)` of warm.xyz
+pub const HOT_WALLET_CHANGED_EVENT_ID: EventId = 109;
+
+/// A delegate registry
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Registry {
+    DelegateV1,
+    DelegateV2,
+    Warm,
+}
+
+impl Registry {
+    /// Returns the registry `contract` is, if any
+    pub fn of(contract: &Contract) -> Option<Self> {
+        match contract.address.to_lowercase().as_str() {
+            DELEGATE_V1_REGISTRY => Some(Registry::DelegateV1),
+            DELEGATE_V2_REGISTRY => Some(Registry::DelegateV2),
+            WARM_HOT_WALLET_PROXY => Some(Registry::Warm),
+            _ => None,
+        }
+    }
+
+    /// The ids of the events the delegations of the registry are replayed from
+    pub fn event_ids(&self) -> &'static [EventId] {
+        match self {
+            Registry::DelegateV1 => &[
+                DELEGATE_FOR_ALL_EVENT_ID,
+                DELEGATE_FOR_CONTRACT_EVENT_ID,
+                REVOKE_ALL_DELEGATES_EVENT_ID,
+                REVOKE_DELEGATE_EVENT_ID,
+            ],
+            Registry::DelegateV2 => &[DELEGATE_ALL_EVENT_ID, DELEGATE_CONTRACT_EVENT_ID],
+            Registry::Warm => &[HOT_WALLET_CHANGED_EVENT_ID],
+        }
+    }
+}
+
+/// A change of the delegations in a registry
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum RegistryEvent {
+    /// A delegation of all the assets of the vault, or of the assets of a contract, is enabled or disabled
+    Delegate {
+        vault: Address,
+        delegate: Address,
+        contract: Option<Address>,
+        enable: bool,
+    },
+    /// All delegations of the vault are revoked
+    RevokeAll { vault: Address },
+    /// All delegations of the vault to the delegate are revoked
+    RevokeDelegate { vault: Address, delegate: Address },
+    /// The hot wallet of the cold wallet is replaced. The zero address removes the hot wallet.
+    HotWalletChanged {
+        cold_wallet: Address,
+        hot_wallet: Address,
+        expiration: u64,
+    },
+}
+
+fn field<'a>(fields: &'a [EventField], name: &str) -> Option<&'a [u8]> {
+    fields
+        .iter()
+        .find(|field| field.name == name)
+        .map(|field| field.value.as_slice())
+}
+
+fn address_field(fields: &[EventField], name: &str) -> Option<Address> {
+    field(fields, name)?.try_into().ok()
+}
+
+fn bool_field(fields: &[EventField], name: &str) -> Option<bool> {
+    Some(field(fields, name)?.first()? != &0)
+}
+
+/// Decode a uint256 field. Values that don't fit in u64 are capped.
+fn u64_field(fields: &[EventField], name: &str) -> Option<u64> {
+    let value = field(fields, name)?;
+
+    if value.len() != 32 {
+        return None;
+    }
+
+    if value[..24].iter().any(|byte| *byte != 0) {
+        return Some(u64::MAX);
+    }
+
+    Some(u64::from_be_bytes(value[24..].try_into().unwrap()))
+}
+
+/// Decode the fields of a registry event log.
+/// Returns `None` for the delegations of specific rights in delegate.xyz v2,
+/// which don't let the delegate act for the vault in general.
+fn decode_registry_event(event_id: EventId, fields: &[EventField]) -> Option<RegistryEvent> {
+    // Delegations of delegate.xyz v2 with rights other than the empty rights are subdelegations
+    let has_all_rights = || field(fields, "rights").map(|rights| rights.iter().all(|b| *b == 0));
+
+    match event_id {
+        DELEGATE_FOR_ALL_EVENT_ID => Some(RegistryEvent::Delegate {
+            vault: address_field(fields, "vault")?,
+            delegate: address_field(fields, "delegate")?,
+            contract: None,
+            enable: bool_field(fields, "value")?,
+        }),
+        DELEGATE_FOR_CONTRACT_EVENT_ID => Some(RegistryEvent::Delegate {
+            vault: address_field(fields, "vault")?,
+            delegate: address_field(fields, "delegate")?,
+            contract: Some(address_field(fields, "contract_")?),
+            enable: bool_field(fields, "value")?,
+        }),
+        REVOKE_ALL_DELEGATES_EVENT_ID => Some(RegistryEvent::RevokeAll {
+            vault: address_field(fields, "vault")?,
+        }),
+        REVOKE_DELEGATE_EVENT_ID => Some(RegistryEvent::RevokeDelegate {
+            vault: address_field(fields, "vault")?,
+            delegate: address_field(fields, "delegate")?,
+        }),
+        DELEGATE_ALL_EVENT_ID if has_all_rights()? => Some(RegistryEvent::Delegate {
+            vault: address_field(fields, "from")?,
+            delegate: address_field(fields, "to")?,
+            contract: None,
+            enable: bool_field(fields, "enable")?,
+        }),
+        DELEGATE_CONTRACT_EVENT_ID if has_all_rights()? => Some(RegistryEvent::Delegate {
+            vault: address_field(fields, "from")?,
+            delegate: address_field(fields, "to")?,
+            contract: Some(address_field(fields, "contract_")?),
+            enable: bool_field(fields, "enable")?,
+        }),
+        HOT_WALLET_CHANGED_EVENT_ID => Some(RegistryEvent::HotWalletChanged {
+            cold_wallet: address_field(fields, "coldWallet")?,
+            hot_wallet: address_field(fields, "to")?,
+            expiration: u64_field(fields, "expirationTimestamp")?,
+        }),
+        _ => None,
+    }
+}
+
+/// Type of a record in a registry state
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Record {
+    /// The block the state is at
+    Meta = 0,
+    /// An enabled delegation
+    Delegation = 1,
+    /// The hot wallet of a cold wallet
+    HotWallet = 2,
+}
+
+/// The delegations of a registry replayed from its logs
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+struct RegistryState {
+    /// The enabled delegations as (vault, delegate, contract).
+    /// The contract is `None` for the delegations of all the assets of the vault.
+    delegations: HashSet<(Address, Address, Option<Address>)>,
+    /// The hot wallet of each cold wallet along with the expiration timestamp of the link
+    hot_wallets: HashMap<Address, (Address, u64)>,
+}
+
+impl RegistryState {
+    fn apply(&mut self, event: RegistryEvent) {
+        match event {
+            RegistryEvent::Delegate {
+                vault,
+                delegate,
+                contract,
+                enable,
+            } => {
+                if enable {
+                    self.delegations.insert((vault, delegate, contract));
+                } else {
+                    self.delegations.remove(&(vault, delegate, contract));
+                }
+            }
+            RegistryEvent::RevokeAll { vault } => {
+                self.delegations.retain(|(v, _, _)| *v != vault);
+            }
+            RegistryEvent::RevokeDelegate { vault, delegate } => {
+                self.delegations
+                    .retain(|(v, d, _)| *v != vault || *d != delegate);
+            }
+            RegistryEvent::HotWalletChanged {
+                cold_wallet,
+                hot_wallet,
+                expiration,
+            } => {
+                if hot_wallet == Address::default() {
+                    self.hot_wallets.remove(&cold_wallet);
+                } else {
+                    self.hot_wallets
+                        .insert(cold_wallet, (hot_wallet, expiration));
+                }
+            }
+        }
+    }
+
+    /// Returns the delegates that can act for each vault with all of its assets,
+    /// or with its assets of any of `contracts`, at `timestamp`
+    fn delegates(
+        &self,
+        contracts: &HashSet<Address>,
+        timestamp: u64,
+    ) -> HashMap<Address, HashSet<Address>> {
+        let mut delegates = HashMap::<Address, HashSet<Address>>::new();
+
+        for (vault, delegate, contract) in &self.delegations {
+            let is_in_scope = contract.map_or(true, |contract| contracts.contains(&contract));
+
+            if is_in_scope && *delegate != Address::default() {
+                delegates.entry(*vault).or_default().insert(*delegate);
+            }
+        }
+
+        for (cold_wallet, (hot_wallet, expiration)) in &self.hot_wallets {
+            if *expiration > timestamp {
+                delegates
+                    .entry(*cold_wallet)
+                    .or_default()
+                    .insert(*hot_wallet);
+            }
+        }
+
+        delegates
+    }
+}
+
+/// The delegations of a registry that are persisted in RocksDB and advanced incrementally,
+/// so that the logs of the registry are only replayed once.
+/// A state is shared by all groups that include the delegates.
+struct RegistryStore {
+    rocksdb_client: Arc<DB>,
+    registry: Registry,
+    contract_id: ContractId,
+}
+
+impl RegistryStore {
+    fn new(rocksdb_client: Arc<DB>, registry: Registry, contract_id: ContractId) -> Self {
+        Self {
+            rocksdb_client,
+            registry,
+            contract_id,
+        }
+    }
+
+    fn record_prefix(contract_id: ContractId, record: Record) -> Vec<u8> {
+        let mut key = vec![KeyType::DelegateRegistryState as u8];
+        key.extend_from_slice(&contract_id.to_be_bytes());
+        key.push(record as u8);
+        key
+    }
+
+    fn delegation_key(&self, delegation: &(Address, Address, Option<Address>)) -> Vec<u8> {
+        let (vault, delegate, contract) = delegation;
+
+        let mut key = Self::record_prefix(self.contract_id, Record::Delegation);
+        key.extend_from_slice(vault);
+        key.extend_from_slice(delegate);
+        if let Some(contract) = contract {
+            key.extend_from_slice(contract);
+        }
+        key
+    }
+
+    fn hot_wallet_key(&self, cold_wallet: &Address) -> Vec<u8> {
+        let mut key = Self::record_prefix(self.contract_id, Record::HotWallet);
+        key.extend_from_slice(cold_wallet);
+        key
+    }
+
+    /// Returns the block the state is persisted at
+    fn block_num(rocksdb_client: &DB, contract_id: ContractId) -> Option<BlockNum> {
+        rocksdb_client
+            .get(Self::record_prefix(contract_id, Record::Meta))
+            .unwrap()
+            .map(|value| BlockNum::from_be_bytes(value[..8].try_into().unwrap()))
+    }
+
+    /// Iterate over the record keys of a record and their values
+    fn iter_record(&self, record: Record) -> impl Iterator<Item = (Vec<u8>, Vec<u8>)> + '_ {
+        let prefix = Self::record_prefix(self.contract_id, record);
+
+        self.rocksdb_client
+            .iterator(IteratorMode::From(&prefix, Direction::Forward))
+            .map(|item| item.unwrap())
+            .take_while(move |(key, _)| key.starts_with(&prefix))
+            .map(|(key, value)| (key[4..].to_vec(), value.to_vec()))
+    }
+
+    /// Load the persisted state
+    fn load(&self) -> RegistryState {
+        let delegations = self
+            .iter_record(Record::Delegation)
+            .map(|(key, _)| {
+                let contract = (key.len() > 40).then(|| key[40..].try_into().unwrap());
+                (
+                    key[..20].try_into().unwrap(),
+                    key[20..40].try_into().unwrap(),
+                    contract,
+                )
+            })
+            .collect();
+
+        let hot_wallets = self
+            .iter_record(Record::HotWallet)
+            .map(|(key, value)| {
+                let hot_wallet = value[..20].try_into().unwrap();
+                let expiration = u64::from_be_bytes(value[20..28].try_into().unwrap());
+                (key[..].try_into().unwrap(), (hot_wallet, expiration))
+            })
+            .collect();
+
+        RegistryState {
+            delegations,
+            hot_wallets,
+        }
+    }
+
+    /// Delete all records of the state of a registry
+    fn clear(contract_id: ContractId, batch: &mut WriteBatch) {
+        for record in [Record::Meta, Record::Delegation, Record::HotWallet] {
+            let start = Self::record_prefix(contract_id, record);
+
+            // The end of the range is greater than all keys of the record
+            let mut end = start.clone();
+            end.extend_from_slice(&[u8::MAX; 61]);
+
+            batch.delete_range(start, end);
+        }
+    }
+
+    /// Replay the logs of the registry from `from_block` to `to_block` on top of `state`
+    fn replay(&self, state: &mut RegistryState, from_block: BlockNum, to_block: BlockNum) {
+        let mut logs = vec![];
+
+        for event_id in self.registry.event_ids() {
+            let iterator = ContractEventIterator::new_from_block(
+                &self.rocksdb_client,
+                *event_id,
+                self.contract_id,
+                from_block,
+                Some(to_block),
+            );
+
+            for (key, value) in iterator {
+                let position = (key.block_num, key.tx_index, key.log_index);
+                logs.push((position, *event_id, value));
+            }
+        }
+
+        // The delegations depend on the order of the logs across the events
+        logs.sort_by_key(|(position, _, _)| *position);
+
+        for (_, event_id, value) in logs {
+            let fields = decode_generic_event(&value).fields;
+
+            if let Some(event) = decode_registry_event(event_id, &fields) {
+                state.apply(event);
+            }
+        }
+    }
+
+    /// Persist `state` as the state at `block_num`. Only the records that changed from `persisted` are written.
+    fn commit(&self, block_num: BlockNum, persisted: &RegistryState, state: &RegistryState) {
+        let mut batch = WriteBatch::default();
+
+        for delegation in persisted.delegations.difference(&state.delegations) {
+            batch.delete(self.delegation_key(delegation));
+        }
+
+        for delegation in state.delegations.difference(&persisted.delegations) {
+            batch.put(self.delegation_key(delegation), []);
+        }
+
+        for cold_wallet in persisted.hot_wallets.keys() {
+            if !state.hot_wallets.contains_key(cold_wallet) {
+                batch.delete(self.hot_wallet_key(cold_wallet));
+            }
+        }
+
+        for (cold_wallet, (hot_wallet, expiration)) in &state.hot_wallets {
+            if persisted.hot_wallets.get(cold_wallet) != Some(&(*hot_wallet, *expiration)) {
+                let value = [hot_wallet.as_slice(), &expiration.to_be_bytes()].concat();
+                batch.put(self.hot_wallet_key(cold_wallet), value);
+            }
+        }
+
+        batch.put(
+            Self::record_prefix(self.contract_id, Record::Meta),
+            block_num.to_be_bytes(),
+        );
+
+        self.rocksdb_client.write(batch).unwrap();
+    }
+
+    /// Get the delegations of the registry at `block_num`.
+    ///
+    /// The persisted state is advanced to `REGISTRY_STATE_LAG` blocks before `block_num`,
+    /// and the logs of the remaining blocks are replayed in memory.
+    fn get_state_at(&self, block_num: BlockNum) -> RegistryState {
+        let _guard = state_lock(&Self::record_prefix(self.contract_id, Record::Meta))
+            .lock()
+            .unwrap();
+
+        let persist_to = block_num.saturating_sub(REGISTRY_STATE_LAG);
+
+        let (from_block, persisted) = match Self::block_num(&self.rocksdb_client, self.contract_id)
+        {
+            Some(persisted_block) if persisted_block <= persist_to => {
+                (persisted_block + 1, self.load())
+            }
+            Some(_) => {
+                // The state is ahead of the requested block (e.g. the confirmations were changed),
+                // so we rebuild it from the start.
+                let mut batch = WriteBatch::default();
+                Self::clear(self.contract_id, &mut batch);
+                self.rocksdb_client.write(batch).unwrap();
+
+                (0, RegistryState::default())
+            }
+            None => (0, RegistryState::default()),
+        };
+
+        let mut state = persisted.clone();
+
+        // Advance the persisted state
+        if from_block <= persist_to {
+            self.replay(&mut state, from_block, persist_to);
+            self.commit(persist_to, &persisted, &state);
+        }
+
+        // Replay the logs of the recent blocks in memory
+        self.replay(&mut state, persist_to + 1, block_num);
+
+        state
+    }
+}
+
+/// The delegations of the delegate registries, replayed from the logs synced by `LogSyncEngine`.
+/// Only the delegations of all the assets of a vault, or of the assets of the contracts of a group,
+/// let the delegates join the group on behalf of the vault.
+pub struct DelegateRegistries {
+    rocksdb_client: Arc<DB>,
+    eth_client: Arc<EthRpcClient>,
+    registries: Vec<(Registry, Contract)>,
+}
+
+impl DelegateRegistries {
+    /// Create from the contracts in the database. Contracts that aren't registries are ignored.
+    pub fn new(
+        rocksdb_client: Arc<DB>,
+        eth_client: Arc<EthRpcClient>,
+        contracts: &[Contract],
+    ) -> Self {
+        let registries = contracts
+            .iter()
+            .filter_map(|contract| {
+                Registry::of(contract).map(|registry| (registry, contract.clone()))
+            })
+            .collect();
+
+        Self {
+            rocksdb_client,
+            eth_client,
+            registries,
+        }
+    }
+
+    /// Only keep the registries on `chains`.
+    /// Delegations are made per chain, so only the registries on the chains of a group apply to it.
+    pub fn on_chains(mut self, chains: &[Chain]) -> Self {
+        self.registries
+            .retain(|(_, contract)| chains.contains(&contract.chain));
+        self
+    }
+
+    /// Returns true if the logs of all registries are synced
+    pub async fn is_ready(&self) -> Result<bool, Error> {
+        for (registry, contract) in &self.registries {
+            for event_id in registry.event_ids() {
+                if !is_event_logs_ready(&self.rocksdb_client, &self.eth_client, *event_id, contract)
+                    .await?
+                {
+                    return Ok(false);
+                }
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Returns the delegates of each of `vaults` at the block numbers of `blocks`.
+    /// Contract delegations only count for the contracts in `contracts` on the registry's chain.
+    /// Registries on chains that aren't in `blocks` are skipped.
+    pub async fn get_delegates_at(
+        &self,
+        vaults: &HashSet<Address>,
+        contracts: &[Contract],
+        blocks: &ChainBlocks,
+    ) -> Result<HashMap<Address, HashSet<Address>>, Error> {
+        let mut delegates = HashMap::<Address, HashSet<Address>>::new();
+
+        for (registry, contract) in &self.registries {
+            let block_num = match blocks.get(&contract.chain) {
+                Some(block_num) => *block_num,
+                None => continue,
+            };
+
+            let timestamp = self
+                .eth_client
+                .get_block_timestamp(contract.chain, block_num)
+                .await?;
+
+            let chain_contracts = contracts
+                .iter()
+                .filter(|c| c.chain == contract.chain)
+                .filter_map(|c| hex::decode(c.address.trim_start_matches("0x")).ok())
+                .filter_map(|address| Address::try_from(address).ok())
+                .collect::<HashSet<Address>>();
+
+            let state = RegistryStore::new(self.rocksdb_client.clone(), *registry, contract.id)
+                .get_state_at(block_num);
+
+            for (vault, vault_delegates) in state.delegates(&chain_contracts, timestamp) {
+                if vaults.contains(&vault) {
+                    delegates.entry(vault).or_default().extend(vault_delegates);
+                }
+            }
+        }
+
+        Ok(delegates)
+    }
+
+    /// Delete the state of a registry if it contains the logs at or after `from_block`,
+    /// so that it's rebuilt after the logs are rolled back
+    pub fn rollback(
+        rocksdb_client: &Arc<DB>,
+        contract_id: ContractId,
+        from_block: BlockNum,
+        batch: &mut WriteBatch,
+    ) {
+        if RegistryStore::block_num(rocksdb_client, contract_id)
+            .map_or(false, |block_num| block_num >= from_block)
+        {
+            RegistryStore::clear(contract_id, batch);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::generic_event::GenericEvent;
+    use crate::rocksdb_key::{KeyType, RocksDbKey};
+    use crate::seeder::seed_events::get_seed_events;
+    use crate::test_utils::init_test_rocksdb;
+    use prost::Message;
+
+    const CONTRACT_ID: ContractId = 1;
+
+    fn event_field(name: &str, value: &[u8]) -> EventField {
+        EventField {
+            name: name.to_string(),
+            r#type: String::new(),
+            value: value.to_vec(),
+            indexed: false,
+        }
+    }
+
+    #[test]
+    fn test_registry_events_are_seeded() {
+        let seed_events = get_seed_events();
+
+        let event_names = [
+            (DELEGATE_FOR_ALL_EVENT_ID, "DelegateForAll"),
+            (DELEGATE_FOR_CONTRACT_EVENT_ID, "DelegateForContract"),
+            (REVOKE_ALL_DELEGATES_EVENT_ID, "RevokeAllDelegates"),
+            (REVOKE_DELEGATE_EVENT_ID, "RevokeDelegate"),
+            (DELEGATE_ALL_EVENT_ID, "DelegateAll"),
+            (DELEGATE_CONTRACT_EVENT_ID, "DelegateContract"),
+            (HOT_WALLET_CHANGED_EVENT_ID, "HotWalletChanged"),
+        ];
+
+        for (event_id, name) in event_names {
+            let event = seed_events
+                .iter()
+                .find(|event| event.id == event_id)
+                .unwrap();
+            assert_eq!(event.event.name, name);
+        }
+    }
+
+    #[test]
+    fn test_decode_registry_event() {
+        let fields = vec![
+            event_field("from", &[1; 20]),
+            event_field("to", &[2; 20]),
+            event_field("rights", &[0; 32]),
+            event_field("enable", &[1]),
+        ];
+
+        assert_eq!(
+            decode_registry_event(DELEGATE_ALL_EVENT_ID, &fields),
+            Some(RegistryEvent::Delegate {
+                vault: [1; 20],
+                delegate: [2; 20],
+                contract: None,
+                enable: true,
+            })
+        );
+
+        // Delegations of specific rights are ignored
+        let mut rights = [0; 32];
+        rights[31] = 1;
+        let fields = vec![
+            event_field("from", &[1; 20]),
+            event_field("to", &[2; 20]),
+            event_field("rights", &rights),
+            event_field("enable", &[1]),
+        ];
+        assert_eq!(decode_registry_event(DELEGATE_ALL_EVENT_ID, &fields), None);
+
+        // Expiration timestamps that don't fit in u64 are capped
+        let fields = vec![
+            event_field("coldWallet", &[1; 20]),
+            event_field("from", &[0; 20]),
+            event_field("to", &[2; 20]),
+            event_field("expirationTimestamp", &[u8::MAX; 32]),
+        ];
+        assert_eq!(
+            decode_registry_event(HOT_WALLET_CHANGED_EVENT_ID, &fields),
+            Some(RegistryEvent::HotWalletChanged {
+                cold_wallet: [1; 20],
+                hot_wallet: [2; 20],
+                expiration: u64::MAX,
+            })
+        );
+    }
+
+    #[test]
+    fn test_registry_state() {
+        let mut state = RegistryState::default();
+
+        let delegate = |vault, delegate, contract, enable| RegistryEvent::Delegate {
+            vault,
+            delegate,
+            contract,
+            enable,
+        };
+
+        state.apply(delegate([1; 20], [10; 20], None, true));
+        state.apply(delegate([1; 20], [11; 20], Some([100; 20]), true));
+        state.apply(delegate([2; 20], [12; 20], Some([101; 20]), true));
+        state.apply(delegate([3; 20], [13; 20], None, true));
+        state.apply(delegate([3; 20], [13; 20], None, false));
+
+        let contracts = HashSet::from([[100; 20]]);
+
+        // Only the delegations of all assets and of the given contracts count
+        assert_eq!(
+            state.delegates(&contracts, 0),
+            HashMap::from([([1; 20], HashSet::from([[10; 20], [11; 20]]))])
+        );
+
+        state.apply(RegistryEvent::RevokeDelegate {
+            vault: [1; 20],
+            delegate: [11; 20],
+        });
+        assert_eq!(
+            state.delegates(&contracts, 0),
+            HashMap::from([([1; 20], HashSet::from([[10; 20]]))])
+        );
+
+        state.apply(RegistryEvent::RevokeAll { vault: [1; 20] });
+        assert!(state.delegates(&contracts, 0).is_empty());
+
+        // Hot wallets count until they expire
+        state.apply(RegistryEvent::HotWalletChanged {
+            cold_wallet: [4; 20],
+            hot_wallet: [14; 20],
+            expiration: 1000,
+        });
+        assert_eq!(
+            state.delegates(&contracts, 999),
+            HashMap::from([([4; 20], HashSet::from([[14; 20]]))])
+        );
+        assert!(state.delegates(&contracts, 1000).is_empty());
+
+        // The zero address removes the hot wallet
+        state.apply(RegistryEvent::HotWalletChanged {
+            cold_wallet: [4; 20],
+            hot_wallet: [0; 20],
+            expiration: 0,
+        });
+        assert!(state.hot_wallets.is_empty());
+    }
+
+    /// Put a `DelegateForAll` log of delegate.xyz v1 in RocksDB
+    fn put_delegate_for_all(
+        db: &DB,
+        block_num: BlockNum,
+        vault: Address,
+        delegate: Address,
+        value: bool,
+    ) {
+        let key = RocksDbKey {
+            block_num: Some(block_num),
+            ..RocksDbKey::new_start_key(KeyType::EventLog, DELEGATE_FOR_ALL_EVENT_ID, CONTRACT_ID)
+        };
+
+        let event = GenericEvent {
+            fields: vec![
+                event_field("vault", &vault),
+                event_field("delegate", &delegate),
+                event_field("value", &[value as u8]),
+            ],
+        };
+
+        db.put(key.to_bytes(), event.encode_to_vec()).unwrap();
+    }
+
+    #[test]
+    fn test_registry_store() {
+        let db = init_test_rocksdb("test_registry_store");
+        let store = RegistryStore::new(db.clone(), Registry::DelegateV1, CONTRACT_ID);
+
+        put_delegate_for_all(&db, 1000, [1; 20], [10; 20], true);
+        put_delegate_for_all(&db, 1100, [2; 20], [11; 20], true);
+        put_delegate_for_all(&db, 1200, [1; 20], [10; 20], false);
+
+        let state = store.get_state_at(1200);
+        assert_eq!(
+            state.delegations,
+            HashSet::from([([2; 20], [11; 20], None)])
+        );
+
+        // Only the logs before the lag are persisted
+        assert_eq!(
+            RegistryStore::block_num(&db, CONTRACT_ID),
+            Some(1200 - REGISTRY_STATE_LAG)
+        );
+        assert_eq!(store.load().delegations.len(), 1);
+
+        // The persisted state is advanced with the new logs
+        put_delegate_for_all(&db, 1500, [3; 20], [12; 20], true);
+        let state = store.get_state_at(1500 + REGISTRY_STATE_LAG);
+        assert_eq!(store.load(), state);
+        assert_eq!(state.delegations.len(), 2);
+
+        // The state at an earlier block is replayed from the start
+        let state = store.get_state_at(1100);
+        assert_eq!(state.delegations.len(), 2);
+
+        // States that contain rolled back blocks are deleted
+        store.get_state_at(1500 + REGISTRY_STATE_LAG);
+        let mut batch = WriteBatch::default();
+        DelegateRegistries::rollback(&db, CONTRACT_ID, 1500, &mut batch);
+        db.write(batch).unwrap();
+
+        assert_eq!(RegistryStore::block_num(&db, CONTRACT_ID), None);
+        assert_eq!(store.load(), RegistryState::default());
+    }
+}
@@ -1,25 +1,29 @@
+use crate::rpc_health::{EndpointHealth, RequestOutcome, CIRCUIT_BREAKER_COOLDOWN};
+use crate::rpc_provider::{provider_from_env, RpcEndpoint, RpcProvider};
 use crate::BlockNum;
 use crate::Error;
 use crate::EthRpcError;
+use crate::IndexerError;
 use cached::proc_macro::cached;
 use cached::TimedSizedCache;
+use log::error;
 use serde::Deserialize;
 use serde::Serialize;
 use serde_json::{json, Value};
+use std::cmp::max;
 use std::collections::HashMap;
-use std::env;
-use std::env::VarError;
 use std::str::FromStr;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::sync::Mutex;
 use tokio::sync::Semaphore;
 
-const NUM_MAINNET_NODES: u32 = 10;
-
 /// Permits for throttling the requests to the Ethereum JSON-RPC API  
 pub static PERMITS: Semaphore = Semaphore::const_new(100);
 
+/// Maximum number of endpoints a request is tried on before giving up
+const MAX_REQUEST_ATTEMPTS: usize = 3;
+
 #[derive(Debug, Copy, Clone, Hash, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Chain {
     Mainnet,
@@ -44,75 +48,217 @@ impl FromStr for Chain {
     }
 }
 
-/// A load balancer to distribute requests across multiple RPC endpoints
+/// Block numbers of multiple chains (e.g. the chains a composite group depends on)
+pub type ChainBlocks = HashMap<Chain, BlockNum>;
+
+/// An endpoint and its load balancing state
+struct EndpointState {
+    endpoint: RpcEndpoint,
+    /// Current weight for the smooth weighted round-robin selection
+    current_weight: i64,
+    /// The earliest time the next request can be sent without exceeding the rate limit
+    next_request_at: Instant,
+    health: EndpointHealth,
+}
+
+/// A load balancer to distribute requests across multiple RPC endpoints.
+/// Endpoints that keep failing are ejected for a cool-down
+/// and the requests fail over to the remaining endpoints.
 struct LoadBalancer {
-    next_client_index: u32,
+    provider: Box<dyn RpcProvider>,
+    endpoints: HashMap<Chain, Vec<EndpointState>>,
 }
 
 impl LoadBalancer {
-    pub fn new() -> Self {
+    pub fn new(provider: Box<dyn RpcProvider>) -> Self {
         Self {
-            next_client_index: 0,
+            provider,
+            endpoints: HashMap::new(),
         }
     }
 
-    pub fn get_endpoint(&mut self, chain: Chain) -> Result<String, VarError> {
-        if chain == Chain::Blast {
-            return env::var("BLAST_RPC_URL");
+    /// Get the endpoints of `chain`, loading them from the provider on first use
+    fn chain_endpoints(&mut self, chain: Chain) -> &mut Vec<EndpointState> {
+        let provider = &self.provider;
+        self.endpoints.entry(chain).or_insert_with(|| {
+            provider
+                .endpoints(chain)
+                .into_iter()
+                .filter(|endpoint| endpoint.weight > 0)
+                .map(|endpoint| EndpointState {
+                    endpoint,
+                    current_weight: 0,
+                    next_request_at: Instant::now(),
+                    health: EndpointHealth::default(),
+                })
+                .collect()
+        })
+    }
+
+    /// Select the next endpoint for `chain` in proportion to the endpoint weights,
+    /// skipping the ejected endpoints, to send up to `num_requests` requests to.
+    /// Returns the endpoint, the number of requests to send to it in a batch
+    /// (at most its `max_batch_size`), and how long to wait before sending them
+    /// so that the rate limit of the endpoint isn't exceeded.
+    /// Each request of a batch counts towards the rate limit.
+    pub fn get_endpoint(
+        &mut self,
+        chain: Chain,
+        num_requests: usize,
+    ) -> Result<(RpcEndpoint, usize, Duration), Error> {
+        let endpoints = self.chain_endpoints(chain);
+        let now = Instant::now();
+
+        if endpoints.is_empty() {
+            return Err(Error::EthRpc(EthRpcError {
+                message: format!("No RPC endpoint configured for {:?}", chain),
+            }));
         }
 
-        let api_key = match chain {
-            Chain::Mainnet => env::var(format!("ALCHEMY_API_KEY_{}", self.next_client_index))?,
-            Chain::Optimism => env::var("ALCHEMY_OPT_API_KEY")?,
-            Chain::Base => env::var("ALCHEMY_BASE_API_KEY")?,
-            Chain::Arbitrum => env::var("ALCHEMY_ARB_API_KEY")?,
-            Chain::Blast => "".to_string(),
-        };
+        let mut candidates = (0..endpoints.len())
+            .filter(|i| !endpoints[*i].health.is_ejected(now))
+            .collect::<Vec<usize>>();
+
+        if candidates.is_empty() {
+            // All endpoints are ejected. Try the one whose cool-down ends first
+            // instead of stalling the requests.
+            let earliest = (0..endpoints.len())
+                .min_by_key(|i| endpoints[*i].health.ejected_until)
+                .unwrap();
+            candidates.push(earliest);
+        }
 
-        let subdomain = match chain {
-            Chain::Mainnet => "eth-mainnet",
-            Chain::Optimism => "opt-mainnet",
-            Chain::Base => "base-mainnet",
-            Chain::Arbitrum => "arb-mainnet",
-            Chain::Blast => "",
-        };
+        let total_weight = candidates
+            .iter()
+            .map(|i| endpoints[*i].endpoint.weight as i64)
+            .sum::<i64>();
+
+        for i in candidates.iter() {
+            endpoints[*i].current_weight += endpoints[*i].endpoint.weight as i64;
+        }
+
+        let selected_index = *candidates
+            .iter()
+            .max_by_key(|i| endpoints[**i].current_weight)
+            .unwrap();
+        let selected = &mut endpoints[selected_index];
+
+        selected.current_weight -= total_weight;
 
-        let url = format!("https://{}.g.alchemy.com/v2/{}", subdomain, api_key);
+        let send_at = max(now, selected.next_request_at);
+        let batch_size = num_requests.clamp(1, selected.endpoint.max_batch_size.max(1));
 
-        self.next_client_index = (self.next_client_index + 1) % NUM_MAINNET_NODES;
-        Ok(url)
+        if let Some(rate_limit) = selected.endpoint.rate_limit.filter(|limit| *limit > 0) {
+            selected.next_request_at =
+                send_at + Duration::from_secs_f64(batch_size as f64 / rate_limit as f64);
+        }
+
+        Ok((selected.endpoint.clone(), batch_size, send_at - now))
+    }
+
+    /// Record the outcome of a request sent to the endpoint with `url`
+    pub fn report(&mut self, chain: Chain, url: &str, outcome: RequestOutcome) {
+        let now = Instant::now();
+        let endpoints = self.chain_endpoints(chain);
+
+        if let Some((i, state)) = endpoints
+            .iter_mut()
+            .enumerate()
+            .find(|(_, state)| state.endpoint.url == url)
+        {
+            if state.health.record(outcome, now) {
+                error!(
+                    "RPC endpoint {} of {:?} failed {} times in a row. Ejecting it for {}s",
+                    endpoint_label(&state.endpoint, i),
+                    chain,
+                    state.health.consecutive_failures,
+                    CIRCUIT_BREAKER_COOLDOWN.as_secs()
+                );
+            }
+        }
+    }
+
+    /// Health of all endpoints that have been used so far
+    pub fn health(&self) -> Vec<(Chain, String, EndpointHealth)> {
+        let mut health = vec![];
+        for (chain, endpoints) in self.endpoints.iter() {
+            for (i, state) in endpoints.iter().enumerate() {
+                health.push((
+                    *chain,
+                    endpoint_label(&state.endpoint, i),
+                    state.health.clone(),
+                ));
+            }
+        }
+
+        health
     }
 }
 
-#[cached(
-    type = "TimedSizedCache<String, BlockNum>",
-    create = "{ TimedSizedCache::with_size_and_lifespan(100, 12) }",
-    convert = r#"{ format!("{}", url) }"#,
-    result = true
-)]
-/// Get the latest block number for a chain
-/// Caches the result for 12 seconds
-async fn get_block_number(client: &surf::Client, url: &str) -> Result<BlockNum, Error> {
-    let permit = PERMITS.acquire().await.unwrap();
+/// A label for logging an endpoint without leaking the API key in the URL
+fn endpoint_label(endpoint: &RpcEndpoint, index: usize) -> String {
+    let host = surf::Url::parse(&endpoint.url)
+        .ok()
+        .and_then(|url| url.host_str().map(|host| host.to_string()))
+        .unwrap_or_default();
 
-    let delay = rand::random::<u64>() % 500;
-    tokio::time::sleep(Duration::from_millis(delay)).await;
+    format!("#{} ({})", index, host)
+}
 
-    let mut res = client
-        .post(url)
-        .body_json(&json!({
-            "jsonrpc": "2.0",
-            "method": "eth_getBlockByNumber",
-            "params": ["finalized", false],
-            "id": 0,
-        }))?
-        .await?;
+/// Returns true if the response (or any response in a batch) is a rate limit error
+fn is_rate_limited(response: &Value) -> bool {
+    let is_429 = |response: &Value| response["error"]["code"].as_i64() == Some(429);
 
-    drop(permit);
+    match response {
+        Value::Array(responses) => responses.iter().any(is_429),
+        _ => is_429(response),
+    }
+}
 
-    let body_str = res.body_string().await?;
+/// Returns true if the error of an `eth_call` is the call reverting.
+/// Geth and most clients return the code 3 with the revert data,
+/// while others return the generic server error with the revert data or an "execution reverted" message.
+fn is_revert(error: &Value) -> bool {
+    match error["code"].as_i64() {
+        Some(3) => true,
+        Some(-32000) => {
+            error["data"].is_string()
+                || error["message"]
+                    .as_str()
+                    .is_some_and(|message| message.starts_with("execution reverted"))
+        }
+        _ => false,
+    }
+}
+
+/// Returns true if the request should be retried on the next endpoint
+fn should_retry(result: &Result<Value, Error>) -> bool {
+    match result {
+        Ok(response) => is_rate_limited(response),
+        Err(_) => true,
+    }
+}
 
-    let body: Value = serde_json::from_str(&body_str).unwrap();
+/// Get the number of the block with the given tag (e.g. "finalized" or "latest")
+async fn request_block_number(
+    eth_client: &EthRpcClient,
+    chain: Chain,
+    block_tag: &str,
+) -> Result<BlockNum, Error> {
+    let delay = rand::random::<u64>() % 500;
+    tokio::time::sleep(Duration::from_millis(delay)).await;
+
+    let body = eth_client
+        .request(
+            chain,
+            &json!({
+                "jsonrpc": "2.0",
+                "method": "eth_getBlockByNumber",
+                "params": [block_tag, false],
+                "id": 0,
+            }),
+        )
+        .await?;
 
     let error = body["error"].as_object();
 
@@ -124,30 +270,96 @@ async fn get_block_number(client: &surf::Client, url: &str) -> Result<BlockNum,
         return Err(Error::EthRpc(err));
     }
 
-    let finalized_block_number = body["result"]["number"].as_str();
+    let block_number = body["result"]["number"].as_str();
 
-    if finalized_block_number.is_none() {
+    if block_number.is_none() {
         return Err(Error::EthRpc(EthRpcError {
-            message: "Failed to get finalized block number".to_string(),
+            message: format!("Failed to get {} block number", block_tag),
         }));
     }
 
-    let finalized_block = u64::from_str_radix(
-        body["result"]["number"]
-            .as_str()
-            .unwrap()
-            .trim_start_matches("0x"),
-        16,
-    )
-    .unwrap();
+    let block_number = u64::from_str_radix(block_number.unwrap().trim_start_matches("0x"), 16)
+        .map_err(|e| {
+            Error::EthRpc(EthRpcError {
+                message: format!("Invalid block number: {}", e),
+            })
+        })?;
 
-    Ok(finalized_block)
+    Ok(block_number)
+}
+
+#[cached(
+    type = "TimedSizedCache<String, BlockNum>",
+    create = "{ TimedSizedCache::with_size_and_lifespan(100, 12) }",
+    convert = r#"{ format!("{:?}", chain) }"#,
+    result = true
+)]
+/// Get the finalized block number for a chain
+/// Caches the result for 12 seconds
+async fn get_finalized_block_number(
+    eth_client: &EthRpcClient,
+    chain: Chain,
+) -> Result<BlockNum, Error> {
+    request_block_number(eth_client, chain, "finalized").await
+}
+
+#[cached(
+    type = "TimedSizedCache<String, BlockNum>",
+    create = "{ TimedSizedCache::with_size_and_lifespan(100, 2) }",
+    convert = r#"{ format!("{:?}", chain) }"#,
+    result = true
+)]
+/// Get the latest block number for a chain
+/// Caches the result for 2 seconds
+async fn get_latest_block_number(
+    eth_client: &EthRpcClient,
+    chain: Chain,
+) -> Result<BlockNum, Error> {
+    request_block_number(eth_client, chain, "latest").await
+}
+
+/// Parse a 32-byte hex string (e.g. a block hash)
+fn parse_hash(value: &Value) -> Result<[u8; 32], Error> {
+    value
+        .as_str()
+        .and_then(|hash| hex::decode(hash.trim_start_matches("0x")).ok())
+        .and_then(|hash| hash.try_into().ok())
+        .ok_or(Error::EthRpc(EthRpcError {
+            message: format!("Invalid hash: {}", value),
+        }))
+}
+
+/// The fields of a block header that are needed to detect reorgs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockHeader {
+    pub number: BlockNum,
+    pub hash: [u8; 32],
+    pub parent_hash: [u8; 32],
+}
+
+/// Number of confirmations to sync behind the latest block, read from `SYNC_CONFIRMATIONS`
+fn confirmations_from_env() -> Result<Option<u64>, Error> {
+    std::env::var("SYNC_CONFIRMATIONS")
+        .ok()
+        .map(|confirmations| parse_confirmations(&confirmations))
+        .transpose()
+}
+
+fn parse_confirmations(confirmations: &str) -> Result<u64, Error> {
+    confirmations.trim().parse().map_err(|_| {
+        IndexerError::InvalidConfig(format!(
+            "Invalid SYNC_CONFIRMATIONS {}: expected a number of blocks",
+            confirmations
+        ))
+        .into()
+    })
 }
 
 /// A client for interacting with the Ethereum JSON-RPC API
 pub struct EthRpcClient {
     client: Arc<surf::Client>,
     load_balancer: Arc<Mutex<LoadBalancer>>,
+    confirmations: Option<u64>,
 }
 
 impl Default for EthRpcClient {
@@ -157,7 +369,23 @@ impl Default for EthRpcClient {
 }
 
 impl EthRpcClient {
+    /// Create a client that uses the endpoints and the confirmations configured by the environment
+    pub fn from_env() -> Result<Self, Error> {
+        let client = Self::with_provider(provider_from_env()?);
+
+        Ok(match confirmations_from_env()? {
+            Some(confirmations) => client.with_confirmations(confirmations),
+            None => client,
+        })
+    }
+
+    /// Same as `from_env`, but panics if the configuration is invalid
     pub fn new() -> Self {
+        Self::from_env().unwrap_or_else(|err| panic!("Invalid RPC configuration: {:?}", err))
+    }
+
+    /// Create a client that uses the endpoints of the given provider
+    pub fn with_provider(provider: Box<dyn RpcProvider>) -> Self {
         let client: surf::Client = surf::Config::new()
             .set_max_connections_per_host(0) // Unlimited connections
             .set_timeout(Some(Duration::from_secs(60 * 20))) // 20 minutes
@@ -165,48 +393,250 @@ impl EthRpcClient {
             .unwrap();
         Self {
             client: Arc::new(client),
-            load_balancer: Arc::new(Mutex::new(LoadBalancer::new())),
+            load_balancer: Arc::new(Mutex::new(LoadBalancer::new(provider))),
+            confirmations: None,
         }
     }
 
-    /// Get the latest block number for a chain
-    pub async fn get_block_number(&self, chain: Chain) -> Result<BlockNum, Error> {
-        let mut load_balancer = self.load_balancer.lock().await;
-        let url = load_balancer.get_endpoint(chain).unwrap();
-        drop(load_balancer);
+    /// Sync up to the latest block minus `confirmations` instead of the finalized block.
+    /// The blocks after the finalized block can be reorged,
+    /// so the sync engines have to check for reorgs in this mode.
+    pub fn with_confirmations(self, confirmations: u64) -> Self {
+        Self {
+            confirmations: Some(confirmations),
+            ..self
+        }
+    }
 
-        // Call the cached function to get the block number
-        get_block_number(&self.client, &url).await
+    /// The number of confirmations to sync behind the latest block,
+    /// or `None` if syncing to the finalized block
+    pub fn confirmations(&self) -> Option<u64> {
+        self.confirmations
     }
 
-    /// Get logs for a contract event in batch
-    /// - `batch_options`: An array of `[fromBlock, toBlock]` options
-    pub async fn get_logs(&self, chain: Chain, params: &Value) -> Result<Value, surf::Error> {
+    /// Get the health of the RPC endpoints.
+    /// Returns the chain, a label of the endpoint and its health for each endpoint.
+    pub async fn endpoint_health(&self) -> Vec<(Chain, String, EndpointHealth)> {
+        self.load_balancer.lock().await.health()
+    }
+
+    /// Select an endpoint for the chain to send up to `num_requests` requests to,
+    /// and wait until it can take them.
+    /// Returns the endpoint and the number of requests it takes in a batch.
+    async fn select_endpoint(
+        &self,
+        chain: Chain,
+        num_requests: usize,
+    ) -> Result<(RpcEndpoint, usize), Error> {
         let mut load_balancer = self.load_balancer.lock().await;
-        let url = load_balancer.get_endpoint(chain).unwrap();
+        let (endpoint, batch_size, wait) = load_balancer.get_endpoint(chain, num_requests)?;
         drop(load_balancer);
 
-        let delay = rand::random::<u64>() % 500;
-        tokio::time::sleep(Duration::from_millis(delay)).await;
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+
+        Ok((endpoint, batch_size))
+    }
 
+    /// Send a JSON-RPC request body to the given endpoint and parse the response
+    async fn post(&self, url: &str, body: &Value) -> Result<Value, Error> {
         let permit = PERMITS.acquire().await.unwrap();
 
-        let mut res = self
-            .client
-            .post(url)
-            .body_json(&json!({
-                "jsonrpc": "2.0",
-                "method": "eth_getLogs",
-                "params": vec![params],
-                "id": 1,
-            }))?
-            .await?;
+        let mut res = self.client.post(url).body_json(body)?.await?;
 
         drop(permit);
 
+        let status = res.status();
         let body_str = res.body_string().await?;
 
-        Ok(serde_json::from_str(&body_str).unwrap())
+        match serde_json::from_str(&body_str) {
+            Ok(body) => Ok(body),
+            // Some endpoints don't return a JSON-RPC error when rate limiting
+            Err(_) if status == surf::StatusCode::TooManyRequests => Ok(json!({
+                "jsonrpc": "2.0",
+                "error": { "code": 429, "message": "Too Many Requests" },
+            })),
+            Err(e) => Err(Error::EthRpc(EthRpcError {
+                message: format!("Invalid JSON-RPC response: {}", e),
+            })),
+        }
+    }
+
+    /// Send a JSON-RPC request to an endpoint of the chain.
+    /// If the request fails or is rate limited, it's retried on the next endpoint
+    /// up to `MAX_REQUEST_ATTEMPTS` times.
+    async fn request(&self, chain: Chain, body: &Value) -> Result<Value, Error> {
+        let mut attempt = 1;
+
+        loop {
+            let (endpoint, _) = self.select_endpoint(chain, 1).await?;
+            let result = self.post_and_report(chain, &endpoint, body).await;
+
+            if !should_retry(&result) || attempt >= MAX_REQUEST_ATTEMPTS {
+                return result;
+            }
+
+            attempt += 1;
+        }
+    }
+
+    /// Send the first requests of `requests` in a batch to an endpoint of the chain,
+    /// as many as the endpoint accepts in a batch.
+    /// Returns the response and the number of requests that were sent.
+    /// The batch is retried like in `request`.
+    async fn request_batch(
+        &self,
+        chain: Chain,
+        requests: &[Value],
+    ) -> Result<(Value, usize), Error> {
+        let mut attempt = 1;
+
+        loop {
+            let (endpoint, batch_size) = self.select_endpoint(chain, requests.len()).await?;
+            let body = json!(&requests[..batch_size]);
+            let result = self.post_and_report(chain, &endpoint, &body).await;
+
+            if !should_retry(&result) || attempt >= MAX_REQUEST_ATTEMPTS {
+                return result.map(|response| (response, batch_size));
+            }
+
+            attempt += 1;
+        }
+    }
+
+    /// Send a request body to the endpoint and report the outcome to the load balancer
+    async fn post_and_report(
+        &self,
+        chain: Chain,
+        endpoint: &RpcEndpoint,
+        body: &Value,
+    ) -> Result<Value, Error> {
+        let start = Instant::now();
+        let result = self.post(&endpoint.url, body).await;
+
+        let outcome = match &result {
+            Ok(response) if is_rate_limited(response) => RequestOutcome::RateLimited,
+            Ok(_) => RequestOutcome::Success(start.elapsed()),
+            Err(_) => RequestOutcome::Failure,
+        };
+
+        self.load_balancer
+            .lock()
+            .await
+            .report(chain, &endpoint.url, outcome);
+
+        result
+    }
+
+    /// Send a batch of JSON-RPC requests.
+    /// The batch is split so that each endpoint receives at most its `max_batch_size` requests,
+    /// and the responses are returned in a single array.
+    /// Returns an error if a batch fails as a whole, so the result is always an array.
+    async fn send_batch(&self, chain: Chain, requests: &[Value]) -> Result<Value, Error> {
+        let mut responses = vec![];
+        let mut remaining = requests;
+
+        while !remaining.is_empty() {
+            let delay = rand::random::<u64>() % 500;
+            tokio::time::sleep(Duration::from_millis(delay)).await;
+
+            let (response, num_sent) = self.request_batch(chain, remaining).await?;
+
+            match response {
+                Value::Array(batch_responses) => responses.extend(batch_responses),
+                // The whole batch failed (e.g. still rate limited after the retries)
+                _ => {
+                    return Err(Error::EthRpc(EthRpcError {
+                        message: format!("Batch request failed: {}", response),
+                    }))
+                }
+            }
+
+            remaining = &remaining[num_sent..];
+        }
+
+        Ok(Value::Array(responses))
+    }
+
+    /// Get the block number to sync to for a chain.
+    /// This is the finalized block, or the latest block minus the confirmations if they are set.
+    pub async fn get_block_number(&self, chain: Chain) -> Result<BlockNum, Error> {
+        // Call the cached functions to get the block number
+        match self.confirmations {
+            Some(confirmations) => Ok(get_latest_block_number(self, chain)
+                .await?
+                .saturating_sub(confirmations)),
+            None => get_finalized_block_number(self, chain).await,
+        }
+    }
+
+    /// Get the headers of the given blocks, sorted by block number
+    pub async fn get_block_headers_batch(
+        &self,
+        chain: Chain,
+        block_numbers: &[BlockNum],
+    ) -> Result<Vec<BlockHeader>, Error> {
+        let mut json_body = vec![];
+
+        for (i, block_number) in block_numbers.iter().enumerate() {
+            json_body.push(json!({
+                "jsonrpc": "2.0",
+                "method": "eth_getBlockByNumber",
+                "params": [
+                    format!("0x{:x}", block_number),
+                    false
+                ],
+                "id": i
+            }));
+        }
+
+        let body = self.send_batch(chain, &json_body).await?;
+
+        let results = body.as_array().ok_or(Error::EthRpc(EthRpcError {
+            message: format!("Failed to get block headers: {}", body),
+        }))?;
+
+        let mut headers = vec![];
+
+        for result in results {
+            let result = &result["result"];
+
+            let number = result["number"]
+                .as_str()
+                .and_then(|number| u64::from_str_radix(number.trim_start_matches("0x"), 16).ok())
+                .ok_or(Error::EthRpc(EthRpcError {
+                    message: format!("Failed to get block header: {}", result),
+                }))?;
+
+            headers.push(BlockHeader {
+                number,
+                hash: parse_hash(&result["hash"])?,
+                parent_hash: parse_hash(&result["parentHash"])?,
+            });
+        }
+
+        headers.sort_by_key(|header| header.number);
+
+        Ok(headers)
+    }
+
+    /// Get logs for a contract event in batch
+    /// - `batch_options`: An array of `[fromBlock, toBlock]` options
+    pub async fn get_logs(&self, chain: Chain, params: &Value) -> Result<Value, Error> {
+        let delay = rand::random::<u64>() % 500;
+        tokio::time::sleep(Duration::from_millis(delay)).await;
+
+        self.request(
+            chain,
+            &json!({
+                "jsonrpc": "2.0",
+                "method": "eth_getLogs",
+                "params": vec![params],
+                "id": 1,
+            }),
+        )
+        .await
     }
 
     /// Get logs for a contract event in batch
@@ -217,11 +647,7 @@ impl EthRpcClient {
         address: &str,
         event_signature: &str,
         batch_options: &[[BlockNum; 2]],
-    ) -> Result<Value, surf::Error> {
-        let mut load_balancer = self.load_balancer.lock().await;
-        let url = load_balancer.get_endpoint(chain).unwrap();
-        drop(load_balancer);
-
+    ) -> Result<Value, Error> {
         let mut json_body = vec![];
 
         for (i, batch_option) in batch_options.iter().enumerate() {
@@ -242,29 +668,14 @@ impl EthRpcClient {
             }));
         }
 
-        let delay = rand::random::<u64>() % 500;
-        tokio::time::sleep(Duration::from_millis(delay)).await;
-
-        let permit = PERMITS.acquire().await.unwrap();
-
-        let mut res = self.client.post(url).body_json(&json!(json_body))?.await?;
-
-        drop(permit);
-
-        let body_str = res.body_string().await?;
-
-        Ok(serde_json::from_str(&body_str).unwrap())
+        self.send_batch(chain, &json_body).await
     }
 
     pub async fn get_block_timestamp_batch(
         &self,
         chain: Chain,
         block_numbers: &[BlockNum],
-    ) -> Result<HashMap<BlockNum, u64>, surf::Error> {
-        let mut load_balancer = self.load_balancer.lock().await;
-        let url = load_balancer.get_endpoint(chain).unwrap();
-        drop(load_balancer);
-
+    ) -> Result<HashMap<BlockNum, u64>, Error> {
         let mut json_body = vec![];
 
         for (i, block_number) in block_numbers.iter().enumerate() {
@@ -279,18 +690,7 @@ impl EthRpcClient {
             }));
         }
 
-        let delay = rand::random::<u64>() % 500;
-        tokio::time::sleep(Duration::from_millis(delay)).await;
-
-        let permit = PERMITS.acquire().await.unwrap();
-
-        let mut res = self.client.post(url).body_json(&json!(json_body))?.await?;
-
-        drop(permit);
-
-        let body_str = res.body_string().await?;
-
-        let body: Value = serde_json::from_str(&body_str).unwrap();
+        let body = self.send_batch(chain, &json_body).await?;
 
         let mut timestamps = HashMap::new();
 
@@ -322,11 +722,7 @@ impl EthRpcClient {
         &self,
         chain: Chain,
         block_number: BlockNum,
-    ) -> Result<u64, surf::Error> {
-        let mut load_balancer = self.load_balancer.lock().await;
-        let url = load_balancer.get_endpoint(chain).unwrap();
-        drop(load_balancer);
-
+    ) -> Result<u64, Error> {
         let json_body = json!({
             "jsonrpc": "2.0",
             "method": "eth_getBlockByNumber",
@@ -337,15 +733,7 @@ impl EthRpcClient {
             "id": 1
         });
 
-        let permit = PERMITS.acquire().await.unwrap();
-
-        let mut res = self.client.post(url).body_json(&json!(json_body))?.await?;
-
-        drop(permit);
-
-        let body_str = res.body_string().await?;
-
-        let body: Value = serde_json::from_str(&body_str).unwrap();
+        let body = self.request(chain, &json_body).await?;
 
         Ok(u64::from_str_radix(
             body["result"]["timestamp"]
@@ -364,11 +752,7 @@ impl EthRpcClient {
         func_selector: &str,
         args: &[u8],
         block_number: BlockNum,
-    ) -> Result<Value, surf::Error> {
-        let mut load_balancer = self.load_balancer.lock().await;
-        let url = load_balancer.get_endpoint(chain).unwrap();
-        drop(load_balancer);
-
+    ) -> Result<Value, Error> {
         let json_body = json!({
             "jsonrpc": "2.0",
             "method": "eth_call",
@@ -382,14 +766,252 @@ impl EthRpcClient {
             "id": 1
         });
 
-        let permit = PERMITS.acquire().await.unwrap();
+        self.request(chain, &json_body).await
+    }
 
-        let mut res = self.client.post(url).body_json(&json!(json_body))?.await?;
+    /// Call the same function of multiple contracts in batch.
+    /// Returns the return data of each call in the order of `contract_addresses`,
+    /// or `None` if the call reverted.
+    pub async fn eth_call_batch(
+        &self,
+        chain: Chain,
+        contract_addresses: &[String],
+        func_selector: &str,
+        args: &[u8],
+        block_number: BlockNum,
+    ) -> Result<Vec<Option<Vec<u8>>>, Error> {
+        let json_body = contract_addresses
+            .iter()
+            .enumerate()
+            .map(|(i, contract_address)| {
+                json!({
+                    "jsonrpc": "2.0",
+                    "method": "eth_call",
+                    "params": [
+                        {
+                            "to": contract_address,
+                            "data": format!("0x{}{}", func_selector, hex::encode(args))
+                        },
+                        format!("0x{:x}", block_number)
+                    ],
+                    "id": i
+                })
+            })
+            .collect::<Vec<Value>>();
+
+        let body = self.send_batch(chain, &json_body).await?;
+
+        let responses = body
+            .as_array()
+            .filter(|responses| responses.len() == contract_addresses.len())
+            .ok_or(Error::EthRpc(EthRpcError {
+                message: format!("Failed to call contracts: {}", body),
+            }))?;
+
+        let mut results = vec![None; contract_addresses.len()];
+
+        for response in responses {
+            let id = response["id"]
+                .as_u64()
+                .map(|id| id as usize)
+                .filter(|id| *id < results.len())
+                .ok_or(Error::EthRpc(EthRpcError {
+                    message: format!("Invalid eth_call response: {}", response),
+                }))?;
+
+            // Reverted calls don't have a result, but other errors can be retried
+            if let Some(error) = response.get("error") {
+                if is_revert(error) {
+                    continue;
+                }
+
+                return Err(Error::EthRpc(EthRpcError {
+                    message: format!("Failed to call {}: {}", contract_addresses[id], error),
+                }));
+            }
+
+            let result = response["result"]
+                .as_str()
+                .and_then(|result| hex::decode(result.trim_start_matches("0x")).ok())
+                .ok_or(Error::EthRpc(EthRpcError {
+                    message: format!("Invalid eth_call result: {}", response),
+                }))?;
 
-        drop(permit);
+            results[id] = Some(result);
+        }
 
-        let body_str = res.body_string().await?;
+        Ok(results)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rpc_health::CIRCUIT_BREAKER_THRESHOLD;
+    use crate::rpc_provider::StaticRpcProvider;
+
+    fn test_endpoint(url: &str, weight: u32, rate_limit: Option<u32>) -> RpcEndpoint {
+        RpcEndpoint {
+            weight,
+            rate_limit,
+            ..RpcEndpoint::new(url.to_string())
+        }
+    }
+
+    #[test]
+    fn test_load_balancer_weights() {
+        let mut endpoints = HashMap::new();
+        endpoints.insert(
+            Chain::Mainnet,
+            vec![
+                test_endpoint("http://a", 3, None),
+                test_endpoint("http://b", 1, None),
+                test_endpoint("http://disabled", 0, None),
+            ],
+        );
+
+        let mut load_balancer = LoadBalancer::new(Box::new(StaticRpcProvider::new(endpoints)));
+
+        let mut counts = HashMap::new();
+        for _ in 0..100 {
+            let (endpoint, _, wait) = load_balancer.get_endpoint(Chain::Mainnet, 1).unwrap();
+            assert!(wait.is_zero());
+            *counts.entry(endpoint.url).or_insert(0) += 1;
+        }
+
+        assert_eq!(counts.get("http://a"), Some(&75));
+        assert_eq!(counts.get("http://b"), Some(&25));
+        assert_eq!(counts.get("http://disabled"), None);
+
+        // Chains without endpoints return an error
+        assert!(load_balancer.get_endpoint(Chain::Base, 1).is_err());
+    }
+
+    #[test]
+    fn test_load_balancer_rate_limit() {
+        let mut endpoints = HashMap::new();
+        endpoints.insert(Chain::Mainnet, vec![test_endpoint("http://a", 1, Some(10))]);
+
+        let mut load_balancer = LoadBalancer::new(Box::new(StaticRpcProvider::new(endpoints)));
+
+        // The first request can be sent right away,
+        // and the following requests are spaced 100ms apart.
+        let (_, _, first_wait) = load_balancer.get_endpoint(Chain::Mainnet, 1).unwrap();
+        let (_, _, second_wait) = load_balancer.get_endpoint(Chain::Mainnet, 1).unwrap();
+        let (_, _, third_wait) = load_balancer.get_endpoint(Chain::Mainnet, 1).unwrap();
+
+        assert!(first_wait.is_zero());
+        assert!(second_wait > Duration::from_millis(90));
+        assert!(third_wait > Duration::from_millis(190));
+
+        // Each request of a batch counts towards the rate limit
+        let (_, _, batch_wait) = load_balancer.get_endpoint(Chain::Mainnet, 10).unwrap();
+        let (_, _, next_wait) = load_balancer.get_endpoint(Chain::Mainnet, 1).unwrap();
+
+        assert!(batch_wait > Duration::from_millis(290));
+        assert!(next_wait > Duration::from_millis(1290));
+    }
+
+    #[test]
+    fn test_load_balancer_batches() {
+        let mut endpoints = HashMap::new();
+        endpoints.insert(
+            Chain::Mainnet,
+            vec![
+                RpcEndpoint {
+                    max_batch_size: 100,
+                    ..test_endpoint("http://a", 2, Some(10))
+                },
+                RpcEndpoint {
+                    max_batch_size: 10,
+                    ..test_endpoint("http://b", 1, None)
+                },
+            ],
+        );
+
+        let mut load_balancer = LoadBalancer::new(Box::new(StaticRpcProvider::new(endpoints)));
+
+        // Each endpoint takes a batch of up to its own batch size
+        let (endpoint, batch_size, wait) = load_balancer.get_endpoint(Chain::Mainnet, 50).unwrap();
+        assert_eq!(endpoint.url, "http://a");
+        assert_eq!(batch_size, 50);
+        assert!(wait.is_zero());
+
+        let (endpoint, batch_size, _) = load_balancer.get_endpoint(Chain::Mainnet, 50).unwrap();
+        assert_eq!(endpoint.url, "http://b");
+        assert_eq!(batch_size, 10);
+
+        // The 50 requests of the first batch count towards the rate limit of 10 per second
+        let (endpoint, _, wait) = load_balancer.get_endpoint(Chain::Mainnet, 1).unwrap();
+        assert_eq!(endpoint.url, "http://a");
+        assert!(wait > Duration::from_millis(4900));
+    }
+
+    #[test]
+    fn test_parse_confirmations() {
+        assert_eq!(parse_confirmations("12").unwrap(), 12);
+        assert!(matches!(
+            parse_confirmations("twelve"),
+            Err(Error::Indexer(IndexerError::InvalidConfig(_)))
+        ));
+    }
+
+    #[test]
+    fn test_is_revert() {
+        assert!(is_revert(
+            &json!({ "code": 3, "message": "execution reverted", "data": "0x" })
+        ));
+        assert!(is_revert(
+            &json!({ "code": -32000, "message": "execution reverted" })
+        ));
+        assert!(is_revert(
+            &json!({ "code": -32000, "message": "", "data": "0x" })
+        ));
+
+        // Errors that aren't reverts are retried even if they mention a revert
+        assert!(!is_revert(
+            &json!({ "code": -32000, "message": "header not found" })
+        ));
+        assert!(!is_revert(
+            &json!({ "code": -32603, "message": "failed to check revert" })
+        ));
+        assert!(!is_revert(
+            &json!({ "code": 429, "message": "rate limited" })
+        ));
+    }
+
+    #[test]
+    fn test_load_balancer_failover() {
+        let mut endpoints = HashMap::new();
+        endpoints.insert(
+            Chain::Mainnet,
+            vec![
+                test_endpoint("http://a", 1, None),
+                test_endpoint("http://b", 1, None),
+            ],
+        );
+
+        let mut load_balancer = LoadBalancer::new(Box::new(StaticRpcProvider::new(endpoints)));
+
+        for _ in 0..CIRCUIT_BREAKER_THRESHOLD {
+            load_balancer.report(Chain::Mainnet, "http://a", RequestOutcome::RateLimited);
+        }
+
+        // The requests go to the remaining endpoint while "a" is ejected
+        for _ in 0..10 {
+            let (endpoint, _, _) = load_balancer.get_endpoint(Chain::Mainnet, 1).unwrap();
+            assert_eq!(endpoint.url, "http://b");
+        }
+
+        // If every endpoint is ejected, the requests still go somewhere
+        for _ in 0..CIRCUIT_BREAKER_THRESHOLD {
+            load_balancer.report(Chain::Mainnet, "http://b", RequestOutcome::Failure);
+        }
+        let (endpoint, _, _) = load_balancer.get_endpoint(Chain::Mainnet, 1).unwrap();
+        assert_eq!(endpoint.url, "http://a");
 
-        Ok(serde_json::from_str(&body_str).unwrap())
+        let health = load_balancer.health();
+        assert_eq!(health.len(), 2);
+        assert!(health.iter().all(|(_, _, health)| health.requests == 5));
     }
 }
@@ -0,0 +1,150 @@
+use crate::{rocksdb_key::MIN_REGISTERED_EVENT_ID, Error, EventId};
+use alloy_json_abi::Event;
+
+/// An event that is defined by its ABI signature instead of a hardcoded event id.
+/// The logs of registered events are decoded into a `GenericEvent` protobuf.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EventDefinition {
+    pub id: EventId,
+    pub event: Event,
+}
+
+impl EventDefinition {
+    /// Create an event definition from a signature such as
+    /// `event Transfer(address indexed from, address indexed to, uint256 value)`
+    pub fn new(id: EventId, signature: &str) -> Result<Self, String> {
+        if id < MIN_REGISTERED_EVENT_ID {
+            return Err(format!(
+                "Event id {} is reserved. Registered event ids start from {}",
+                id, MIN_REGISTERED_EVENT_ID
+            ));
+        }
+
+        let event = Event::parse(signature).map_err(|e| e.to_string())?;
+
+        if event.anonymous {
+            return Err(format!("Anonymous events are not supported: {}", signature));
+        }
+
+        for input in &event.inputs {
+            if !is_supported_type(&input.ty) {
+                return Err(format!(
+                    "Unsupported type {} of {} in {}",
+                    input.ty, input.name, signature
+                ));
+            }
+        }
+
+        Ok(Self { id, event })
+    }
+
+    /// The first topic of the event logs (i.e. the hash of the event signature)
+    pub fn topic(&self) -> String {
+        format!("0x{}", hex::encode(self.event.selector().as_slice()))
+    }
+}
+
+/// Returns true if the type is an elementary type that can be decoded.
+/// Arrays and tuples aren't supported.
+fn is_supported_type(ty: &str) -> bool {
+    match ty {
+        "address" | "bool" | "string" | "bytes" => true,
+        _ => {
+            if let Some(size) = ty.strip_prefix("bytes") {
+                size.parse::<usize>()
+                    .map_or(false, |size| (1..=32).contains(&size))
+            } else if let Some(bits) = ty.strip_prefix("uint").or(ty.strip_prefix("int")) {
+                bits.parse::<usize>()
+                    .map_or(false, |bits| bits % 8 == 0 && (8..=256).contains(&bits))
+            } else {
+                false
+            }
+        }
+    }
+}
+
+/// Returns true if the values of the type are dynamically sized
+pub fn is_dynamic_type(ty: &str) -> bool {
+    ty == "string" || ty == "bytes"
+}
+
+/// Upsert an event definition into the database
+pub async fn upsert_event(
+    pg_client: &tokio_postgres::Client,
+    event: &EventDefinition,
+) -> Result<(), tokio_postgres::Error> {
+    pg_client
+        .execute(
+            r#"INSERT INTO "Event" ("id", "signature", "updatedAt") VALUES ($1, $2, NOW())
+            ON CONFLICT ("id") DO UPDATE SET "signature" = $2, "updatedAt" = NOW()
+            "#,
+            &[&(event.id as i32), &event.event.full_signature()],
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// Get all registered events from the database
+pub async fn get_registered_events(
+    pg_client: &tokio_postgres::Client,
+) -> Result<Vec<EventDefinition>, Error> {
+    let result = pg_client
+        .query(r#"SELECT "id", "signature" FROM "Event""#, &[])
+        .await?;
+
+    let events = result
+        .iter()
+        .map(|row| {
+            let id: i32 = row.get("id");
+            let signature: String = row.get("signature");
+
+            match EventDefinition::new(id as EventId, &signature) {
+                Ok(event) => event,
+                Err(e) => panic!("Invalid event {}: {}", id, e),
+            }
+        })
+        .collect();
+
+    Ok(events)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_event_definition() {
+        let event = EventDefinition::new(
+            MIN_REGISTERED_EVENT_ID,
+            "event Transfer(address indexed from, address indexed to, uint256 value)",
+        )
+        .unwrap();
+
+        assert_eq!(
+            event.topic(),
+            "0xddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef"
+        );
+
+        // Reserved event ids
+        assert!(EventDefinition::new(
+            1,
+            "event Transfer(address indexed from, address indexed to, uint256 value)"
+        )
+        .is_err());
+
+        // Arrays and tuples
+        assert!(EventDefinition::new(
+            MIN_REGISTERED_EVENT_ID,
+            "event TransferBatch(address indexed operator, uint256[] ids)"
+        )
+        .is_err());
+
+        // Anonymous events don't have a topic to filter the logs by
+        assert!(EventDefinition::new(
+            MIN_REGISTERED_EVENT_ID,
+            "event Deposit(address indexed user, uint256 amount) anonymous"
+        )
+        .is_err());
+    }
+}
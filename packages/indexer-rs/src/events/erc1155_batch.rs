@@ -9,11 +9,8 @@ pub struct ERC1155TransferBatchLog {
     meta: EventLogMeta,
 }
 
-impl EventLogLike for ERC1155TransferBatchLog {
-    fn from_json(log: &Value, contract_id: ContractId) -> Self
-    where
-        Self: Sized,
-    {
+impl ERC1155TransferBatchLog {
+    pub fn from_json(log: &Value, contract_id: ContractId) -> Self {
         let topics = &log["topics"].as_array().unwrap();
 
         let operator = &topics[1].as_str().unwrap();
@@ -56,7 +53,9 @@ impl EventLogLike for ERC1155TransferBatchLog {
             meta,
         }
     }
+}
 
+impl EventLogLike for ERC1155TransferBatchLog {
     fn event_meta(&self) -> EventLogMeta {
         self.meta
     }
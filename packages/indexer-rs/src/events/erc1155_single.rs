@@ -9,11 +9,8 @@ pub struct ERC1155TransferSingleLog {
     meta: EventLogMeta,
 }
 
-impl EventLogLike for ERC1155TransferSingleLog {
-    fn from_json(log: &Value, contract_id: ContractId) -> Self
-    where
-        Self: Sized,
-    {
+impl ERC1155TransferSingleLog {
+    pub fn from_json(log: &Value, contract_id: ContractId) -> Self {
         let topics = &log["topics"].as_array().unwrap();
 
         let operator = &topics[1].as_str().unwrap();
@@ -46,7 +43,9 @@ impl EventLogLike for ERC1155TransferSingleLog {
             meta,
         }
     }
+}
 
+impl EventLogLike for ERC1155TransferSingleLog {
     fn event_meta(&self) -> EventLogMeta {
         self.meta
     }
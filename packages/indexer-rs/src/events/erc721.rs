@@ -9,11 +9,8 @@ pub struct ERC721TransferLog {
     meta: EventLogMeta,
 }
 
-impl EventLogLike for ERC721TransferLog {
-    fn from_json(log: &Value, contract_id: ContractId) -> Self
-    where
-        Self: Sized,
-    {
+impl ERC721TransferLog {
+    pub fn from_json(log: &Value, contract_id: ContractId) -> Self {
         let topics = &log["topics"].as_array().unwrap();
 
         let from = &topics[1].as_str().unwrap();
@@ -33,7 +30,9 @@ impl EventLogLike for ERC721TransferLog {
             meta,
         }
     }
+}
 
+impl EventLogLike for ERC721TransferLog {
     fn event_meta(&self) -> EventLogMeta {
         self.meta
     }
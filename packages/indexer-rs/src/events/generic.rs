@@ -0,0 +1,209 @@
+use super::{EventLogLike, EventLogMeta};
+use crate::{
+    event_registry::{is_dynamic_type, EventDefinition},
+    generic_event::{EventField, GenericEvent},
+    ContractId,
+};
+use prost::Message;
+use serde_json::Value;
+
+/// A struct representing a single log of a registered event
+pub struct GenericEventLog {
+    inner: GenericEvent,
+    meta: EventLogMeta,
+}
+
+impl GenericEventLog {
+    /// Decode a log with the signature of `event`.
+    /// Returns `None` if the log doesn't match the signature.
+    /// (e.g. ERC721 `Transfer` logs have the same topic as ERC20 `Transfer` logs
+    /// but one more indexed parameter)
+    pub fn from_json(
+        log: &Value,
+        contract_id: ContractId,
+        event: &EventDefinition,
+    ) -> Option<Self> {
+        let topics = log["topics"]
+            .as_array()?
+            .iter()
+            .map(|topic| hex::decode(topic.as_str()?.trim_start_matches("0x")).ok())
+            .collect::<Option<Vec<Vec<u8>>>>()?;
+
+        let data = hex::decode(log["data"].as_str()?.trim_start_matches("0x")).ok()?;
+
+        let fields = decode_fields(event, &topics, &data)?;
+
+        let meta = EventLogMeta::from_json(log, contract_id, event.id);
+
+        Some(Self {
+            inner: GenericEvent { fields },
+            meta,
+        })
+    }
+}
+
+impl EventLogLike for GenericEventLog {
+    fn event_meta(&self) -> EventLogMeta {
+        self.meta
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        self.inner.encode(&mut buf).unwrap();
+        buf
+    }
+}
+
+/// Decode the event parameters from the log topics and data
+fn decode_fields(
+    event: &EventDefinition,
+    topics: &[Vec<u8>],
+    data: &[u8],
+) -> Option<Vec<EventField>> {
+    if topics.len() != event.event.num_topics() {
+        return None;
+    }
+
+    // The first topic is the event signature
+    let mut indexed_params = topics[1..].iter();
+    let mut head_words = data.chunks(32);
+
+    let mut fields = vec![];
+
+    for input in &event.event.inputs {
+        let value = if input.indexed {
+            let topic = indexed_params.next()?;
+
+            if is_dynamic_type(&input.ty) {
+                // Only the hash of the value is in the topic
+                topic.clone()
+            } else {
+                decode_word(&input.ty, topic)?
+            }
+        } else {
+            let word = head_words.next()?;
+
+            if is_dynamic_type(&input.ty) {
+                // The word is the offset of the value in the data,
+                // which starts with the length of the value
+                let offset = word_to_usize(word)?;
+                let length = word_to_usize(data.get(offset..offset.checked_add(32)?)?)?;
+
+                let value_start = offset + 32;
+                data.get(value_start..value_start.checked_add(length)?)?
+                    .to_vec()
+            } else {
+                decode_word(&input.ty, word)?
+            }
+        };
+
+        fields.push(EventField {
+            name: input.name.clone(),
+            r#type: input.ty.clone(),
+            value,
+            indexed: input.indexed,
+        });
+    }
+
+    Some(fields)
+}
+
+/// Decode a 32-byte word of a static type
+fn decode_word(ty: &str, word: &[u8]) -> Option<Vec<u8>> {
+    if word.len() != 32 {
+        return None;
+    }
+
+    if ty == "address" {
+        Some(word[12..].to_vec())
+    } else if ty == "bool" {
+        Some(vec![word[31]])
+    } else if let Some(size) = ty.strip_prefix("bytes") {
+        Some(word[..size.parse::<usize>().ok()?].to_vec())
+    } else {
+        // uintN and intN
+        Some(word.to_vec())
+    }
+}
+
+/// Convert a 32-byte word to usize. Returns `None` if the value doesn't fit.
+fn word_to_usize(word: &[u8]) -> Option<usize> {
+    if word.len() != 32 || word[..24].iter().any(|byte| *byte != 0) {
+        return None;
+    }
+
+    usize::try_from(u64::from_be_bytes(word[24..].try_into().unwrap())).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rocksdb_key::MIN_REGISTERED_EVENT_ID;
+    use serde_json::json;
+
+    #[test]
+    fn test_decode_generic_event() {
+        let event = EventDefinition::new(
+            MIN_REGISTERED_EVENT_ID,
+            "event NameRegistered(string name, bytes32 indexed label, address indexed owner, uint256 baseCost, uint256 premium, uint256 expires)",
+        )
+        .unwrap();
+
+        let label = [1; 32];
+        let owner = [2; 20];
+
+        let log = json!({
+            "topics": [
+                event.topic(),
+                format!("0x{}", hex::encode(label)),
+                format!("0x{:0>64}", hex::encode(owner)),
+            ],
+            "data": format!(
+                "0x{}{}{}{}{}{}",
+                // Offset of `name`
+                format!("{:064x}", 128),
+                format!("{:064x}", 3),
+                format!("{:064x}", 0),
+                format!("{:064x}", 1000),
+                // `name`
+                format!("{:064x}", 4),
+                format!("{:0<64}", hex::encode("cred")),
+            ),
+            "blockNumber": "0x1",
+            "transactionIndex": "0x2",
+            "logIndex": "0x3",
+        });
+
+        let event_log = GenericEventLog::from_json(&log, 1, &event).unwrap();
+
+        let fields = event_log
+            .inner
+            .fields
+            .iter()
+            .map(|field| (field.name.as_str(), field.value.clone()))
+            .collect::<Vec<(&str, Vec<u8>)>>();
+
+        let mut expires = vec![0; 32];
+        expires[30..].copy_from_slice(&1000u16.to_be_bytes());
+
+        assert_eq!(
+            fields,
+            vec![
+                ("name", b"cred".to_vec()),
+                ("label", label.to_vec()),
+                ("owner", owner.to_vec()),
+                ("baseCost", [vec![0; 31], vec![3]].concat()),
+                ("premium", vec![0; 32]),
+                ("expires", expires),
+            ]
+        );
+
+        assert_eq!(event_log.event_meta().event_id, MIN_REGISTERED_EVENT_ID);
+        assert_eq!(event_log.event_meta().block_num, 1);
+
+        // Logs with a different number of indexed parameters don't match the signature
+        let mut log = log;
+        log["topics"].as_array_mut().unwrap().pop();
+        assert!(GenericEventLog::from_json(&log, 1, &event).is_none());
+    }
+}
@@ -9,6 +9,7 @@ pub mod erc1155_batch;
 pub mod erc1155_single;
 pub mod erc20;
 pub mod erc721;
+pub mod generic;
 
 #[derive(Debug, Clone, Copy)]
 pub struct EventLogMeta {
@@ -35,9 +36,6 @@ impl EventLogMeta {
 }
 
 pub trait EventLogLike: Send + Sync {
-    fn from_json(log: &Value, contract_id: ContractId) -> Self
-    where
-        Self: Sized;
     fn to_bytes(&self) -> Vec<u8>;
     /// Return the metadata of the event
     fn event_meta(&self) -> EventLogMeta;
@@ -1,5 +1,7 @@
 use crate::{
     contract::{get_contracts, Contract},
+    group_spec::GroupSpec,
+    tree::{DEFAULT_TREE_DEPTH, TREE_DEPTHS},
     utils::{get_group_id, is_prod},
     Error, GroupId, GroupState, GroupType,
 };
@@ -27,6 +29,16 @@ pub struct Group {
     pub score: i64,
     pub group_type: GroupType,
     pub contract_inputs: Vec<Contract>,
+    /// The spec of a `GroupType::Declarative` group
+    pub spec: Option<GroupSpec>,
+    /// The depth of the group's Merkle trees
+    pub tree_depth: usize,
+    /// Whether the owners of the Safes among the members are added to the tree
+    /// on behalf of their wallets
+    pub include_safe_owners: bool,
+    /// Whether the hot wallets the members delegate to in the delegate registries
+    /// are added to the tree on behalf of the members
+    pub include_delegates: bool,
 }
 
 impl Group {
@@ -49,6 +61,31 @@ impl Group {
             group_type,
             contract_inputs,
             score,
+            spec: None,
+            tree_depth: DEFAULT_TREE_DEPTH,
+            include_safe_owners: false,
+            include_delegates: false,
+        }
+    }
+
+    /// Create a group that's defined by a spec.
+    /// The group id is the hash of the canonical spec, so equivalent specs share a group.
+    pub fn new_declarative(
+        name: String,
+        spec: GroupSpec,
+        contract_inputs: Vec<Contract>,
+        score: i64,
+    ) -> Self {
+        Group {
+            id: spec.group_id(),
+            name,
+            group_type: GroupType::Declarative,
+            contract_inputs,
+            score,
+            spec: Some(spec),
+            tree_depth: DEFAULT_TREE_DEPTH,
+            include_safe_owners: false,
+            include_delegates: false,
         }
     }
 }
@@ -80,15 +117,27 @@ pub async fn upsert_group(
         .map(|contract| contract.id as i32)
         .collect();
 
-    // Upsert the group object
+    let group_spec = group.spec.as_ref().map(|spec| spec.to_json());
+    let tree_depth = group.tree_depth as i32;
+
+    // Upsert the group object.
+    // The tree depth of an existing group isn't overwritten, so it can be changed in the database
     let result = pg_client
         .query_one(
             r#"
-            INSERT INTO "Group" ("id", "displayName", "typeId", "contractInputs", "score", "updatedAt") VALUES ($1, $2, $3, $4, $5, NOW())
-            ON CONFLICT ("id", "typeId", "contractInputs") DO UPDATE SET "displayName" = $2, "score" = $5, "updatedAt" = NOW()
+            INSERT INTO "Group" ("id", "displayName", "typeId", "contractInputs", "score", "spec", "treeDepth", "updatedAt") VALUES ($1, $2, $3, $4, $5, $6, $7, NOW())
+            ON CONFLICT ("id", "typeId", "contractInputs") DO UPDATE SET "displayName" = $2, "score" = $5, "spec" = $6, "updatedAt" = NOW()
             RETURNING id
         "#,
-            &[&group.id, &group.name, &group.group_type, &group_contract_inputs, &group.score],
+            &[
+                &group.id,
+                &group.name,
+                &group.group_type,
+                &group_contract_inputs,
+                &group.score,
+                &group_spec,
+                &tree_depth,
+            ],
         )
         .await?;
 
@@ -108,7 +157,11 @@ pub async fn get_all_groups(pg_client: &tokio_postgres::Client) -> Result<Vec<Gr
             "typeId",
             "contractInputs",
             "score",
-            "state"
+            "state",
+            "spec",
+            "treeDepth",
+            "includeSafeOwners",
+            "includeDelegates"
         FROM
             "Group"
         WHERE
@@ -133,6 +186,23 @@ pub async fn get_all_groups(pg_client: &tokio_postgres::Client) -> Result<Vec<Gr
             let group_type: GroupType = row.get("typeId");
             let contract_inputs: Vec<i32> = row.get("contractInputs");
             let score: i64 = row.get("score");
+            let spec: Option<String> = row.get("spec");
+            let tree_depth: i32 = row.get("treeDepth");
+            let include_safe_owners: bool = row.get("includeSafeOwners");
+            let include_delegates: bool = row.get("includeDelegates");
+
+            let spec = spec.map(|spec| match GroupSpec::from_json(&spec) {
+                Ok(spec) => spec,
+                Err(e) => panic!("Invalid spec of group {}: {}", group_id, e),
+            });
+
+            let tree_depth = tree_depth as usize;
+            if !TREE_DEPTHS.contains(&tree_depth) {
+                panic!(
+                    "Unsupported tree depth {} of group {}",
+                    tree_depth, group_id
+                );
+            }
 
             // Convert the contract inputs to Contract struct
             let contract_inputs = contract_inputs
@@ -158,6 +228,10 @@ pub async fn get_all_groups(pg_client: &tokio_postgres::Client) -> Result<Vec<Gr
                 group_type,
                 contract_inputs,
                 score,
+                spec,
+                tree_depth,
+                include_safe_owners,
+                include_delegates,
             }
         })
         .collect::<Vec<Group>>();
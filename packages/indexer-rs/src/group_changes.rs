@@ -0,0 +1,189 @@
+use crate::{
+    merkle_tree_proto::MerkleTree, tree_archive::RetentionPolicy, tree_store::proto_leaf_address,
+    Address, BlockNum, Error,
+};
+use std::collections::HashSet;
+
+/// The members added to and removed from a group between two consecutive trees
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MemberChanges {
+    pub added: Vec<Address>,
+    pub removed: Vec<Address>,
+}
+
+impl MemberChanges {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// Returns the addresses of the members in the leaves of a tree protobuf.
+/// The commitment to the delegations in a delegation tree isn't a member.
+fn leaf_addresses(tree: &MerkleTree) -> HashSet<Address> {
+    tree.layers
+        .first()
+        .map(|leaves| {
+            leaves
+                .nodes
+                .iter()
+                .filter_map(|node| proto_leaf_address(tree.leaf_format(), &node.node))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Returns the members of `new` that aren't in `old` and the members of `old` that aren't in `new`.
+/// A member whose balance changed isn't a change.
+pub fn member_changes(old: &MerkleTree, new: &MerkleTree) -> MemberChanges {
+    let old_members = leaf_addresses(old);
+    let new_members = leaf_addresses(new);
+
+    let mut added = new_members
+        .difference(&old_members)
+        .cloned()
+        .collect::<Vec<Address>>();
+    let mut removed = old_members
+        .difference(&new_members)
+        .cloned()
+        .collect::<Vec<Address>>();
+
+    added.sort();
+    removed.sort();
+
+    MemberChanges { added, removed }
+}
+
+/// Save the member changes of a group in the tree built at `block_number`
+pub async fn save_member_changes(
+    pg_client: &tokio_postgres::Client,
+    group_id: &str,
+    block_number: BlockNum,
+    changes: &MemberChanges,
+) -> Result<(), Error> {
+    let (addresses, is_added): (Vec<String>, Vec<bool>) = changes
+        .added
+        .iter()
+        .map(|address| (format!("0x{}", hex::encode(address)), true))
+        .chain(
+            changes
+                .removed
+                .iter()
+                .map(|address| (format!("0x{}", hex::encode(address)), false)),
+        )
+        .unzip();
+
+    pg_client
+        .execute(
+            r#"
+            INSERT INTO "GroupMemberChange" ("groupId", "blockNumber", "address", "isAdded")
+            SELECT $1, $2, * FROM UNNEST($3::text[], $4::boolean[])
+            ON CONFLICT ("groupId", "blockNumber", "address") DO NOTHING
+            "#,
+            &[&group_id, &(block_number as i64), &addresses, &is_added],
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// Delete the member changes of a group that are older than the retention period
+pub async fn prune_member_changes(
+    pg_client: &tokio_postgres::Client,
+    group_id: &str,
+    retention: &RetentionPolicy,
+) -> Result<(), Error> {
+    let retention_days = match retention.retention_days {
+        Some(retention_days) => retention_days as i32,
+        None => return Ok(()),
+    };
+
+    pg_client
+        .execute(
+            r#"
+            DELETE FROM
+                "GroupMemberChange"
+            WHERE
+                "groupId" = $1
+                AND "createdAt" < NOW() - make_interval(days => $2)
+            "#,
+            &[&group_id, &retention_days],
+        )
+        .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{merkle_tree_proto::LeafFormat, test_utils::proto_tree};
+
+    #[test]
+    fn test_member_changes() {
+        let balance_leaf =
+            |address: Address, balance: u8| [address.to_vec(), vec![balance; 32]].concat();
+
+        let old = proto_tree(
+            &[&[
+                (0, &balance_leaf([1; 20], 1)),
+                (1, &balance_leaf([2; 20], 1)),
+                (2, &balance_leaf([3; 20], 1)),
+            ]],
+            LeafFormat::Balance,
+        );
+        let new = proto_tree(
+            &[&[
+                (0, &balance_leaf([1; 20], 1)),
+                (1, &balance_leaf([3; 20], 2)),
+                (2, &balance_leaf([4; 20], 1)),
+            ]],
+            LeafFormat::Balance,
+        );
+
+        let changes = member_changes(&old, &new);
+
+        // The balance change of [3; 20] isn't a change
+        assert_eq!(
+            changes,
+            MemberChanges {
+                added: vec![[4; 20]],
+                removed: vec![[2; 20]],
+            }
+        );
+
+        assert!(member_changes(&new, &new).is_empty());
+    }
+
+    #[test]
+    fn test_member_changes_with_delegations() {
+        let delegated_leaf = |address: Address, wallet: Address| {
+            [address.to_vec(), wallet.to_vec(), vec![0]].concat()
+        };
+
+        // The commitment to the delegations in the last leaf changes with the delegations
+        let old = proto_tree(
+            &[&[
+                (0, &[1; 20]),
+                (1, &delegated_leaf([2; 20], [9; 20])),
+                (15, &[10; 32]),
+            ]],
+            LeafFormat::Delegation,
+        );
+        let new = proto_tree(
+            &[&[
+                (0, &[1; 20]),
+                (1, &delegated_leaf([3; 20], [9; 20])),
+                (15, &[11; 32]),
+            ]],
+            LeafFormat::Delegation,
+        );
+
+        assert_eq!(
+            member_changes(&old, &new),
+            MemberChanges {
+                added: vec![[3; 20]],
+                removed: vec![[2; 20]],
+            }
+        );
+    }
+}
@@ -0,0 +1,405 @@
+use crate::{
+    contract::{Contract, ContractType},
+    rocksdb_key::{ERC20_TRANSFER_EVENT_ID, ERC721_TRANSFER_EVENT_ID},
+    BlockNum, ContractId, EventId, GroupId, GroupType,
+};
+use num_bigint::BigUint;
+use serde::{Deserialize, Serialize};
+use sha3::{Digest, Keccak256};
+use std::str::FromStr;
+
+/// A declarative definition of the members of a group.
+/// Declarative groups are indexed by `SpecIndexer`, so adding a group only requires writing its spec.
+///
+/// Example: addresses that held at least 1 token of contract 12,
+/// and were in the first 5% of the receivers of contract 13
+/// ```json
+/// {
+///   "type": "intersection",
+///   "specs": [
+///     { "type": "balanceAtLeast", "contract": 12, "amount": "1000000000000000000" },
+///     { "type": "earlyReceivers", "contract": 13, "percent": 5 }
+///   ]
+/// }
+/// ```
+///
+/// Specs can also combine the members of built-in groups, which may be on different chains.
+/// Example: early holders of contract 1 that are whales of contract 2
+/// ```json
+/// {
+///   "type": "intersection",
+///   "specs": [
+///     { "type": "group", "groupType": "EarlyHolder", "contracts": [1] },
+///     { "type": "group", "groupType": "Whale", "contracts": [2] }
+///   ]
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum GroupSpec {
+    /// Addresses with a balance of at least `amount` at `block`,
+    /// or at the block the tree is built at if `block` isn't set.
+    /// The balance of an ERC721 contract is the number of tokens held.
+    BalanceAtLeast {
+        contract: ContractId,
+        /// Decimal string of the amount in the smallest unit of the token
+        amount: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        block: Option<BlockNum>,
+    },
+    /// The first `percent`% of the addresses that received the token
+    EarlyReceivers { contract: ContractId, percent: u8 },
+    /// Addresses that received the token from the address `from`
+    ReceivedFrom { contract: ContractId, from: String },
+    /// Addresses in the `field` parameter of the logs of a registered event
+    EventParticipants {
+        contract: ContractId,
+        event: EventId,
+        field: String,
+    },
+    /// Members of the group of a built-in type over `contracts`
+    #[serde(rename_all = "camelCase")]
+    Group {
+        group_type: GroupType,
+        contracts: Vec<ContractId>,
+    },
+    /// Addresses that are members of any of the specs
+    Union { specs: Vec<GroupSpec> },
+    /// Addresses that are members of all of the specs
+    Intersection { specs: Vec<GroupSpec> },
+    /// Addresses that are members of the first spec but none of the others
+    Difference { specs: Vec<GroupSpec> },
+}
+
+impl GroupSpec {
+    /// Parse a spec from JSON and convert it to the canonical form
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        let spec: GroupSpec = serde_json::from_str(json).map_err(|e| e.to_string())?;
+        spec.canonicalize()
+    }
+
+    /// Validate the spec and convert it to the canonical form,
+    /// so that equivalent specs have the same JSON representation.
+    pub fn canonicalize(self) -> Result<Self, String> {
+        match self {
+            GroupSpec::BalanceAtLeast {
+                contract,
+                amount,
+                block,
+            } => {
+                let amount = BigUint::from_str(&amount)
+                    .map_err(|_| format!("Invalid amount: {}", amount))?;
+
+                Ok(GroupSpec::BalanceAtLeast {
+                    contract,
+                    amount: amount.to_string(),
+                    block,
+                })
+            }
+            GroupSpec::EarlyReceivers { contract, percent } => {
+                if percent == 0 || percent > 100 {
+                    return Err(format!("Invalid percent: {}", percent));
+                }
+
+                Ok(GroupSpec::EarlyReceivers { contract, percent })
+            }
+            GroupSpec::ReceivedFrom { contract, from } => {
+                let from = hex::decode(from.trim_start_matches("0x"))
+                    .ok()
+                    .filter(|from| from.len() == 20)
+                    .ok_or(format!("Invalid address: {}", from))?;
+
+                Ok(GroupSpec::ReceivedFrom {
+                    contract,
+                    from: format!("0x{}", hex::encode(from)),
+                })
+            }
+            GroupSpec::EventParticipants { .. } => Ok(self),
+            GroupSpec::Group { group_type, .. } => match group_type {
+                GroupType::Static | GroupType::Declarative => Err(format!(
+                    "Group type {:?} can't be used in specs",
+                    group_type
+                )),
+                _ => Ok(self),
+            },
+            GroupSpec::Union { specs } => Ok(GroupSpec::Union {
+                specs: canonicalize_children(specs)?,
+            }),
+            GroupSpec::Intersection { specs } => Ok(GroupSpec::Intersection {
+                specs: canonicalize_children(specs)?,
+            }),
+            GroupSpec::Difference { mut specs } => {
+                if specs.len() < 2 {
+                    return Err("Difference requires at least two specs".to_string());
+                }
+
+                // Only the order of the subtracted specs doesn't change the members
+                let minuend = specs.remove(0).canonicalize()?;
+                let subtrahends = canonicalize_children(specs)?;
+
+                Ok(GroupSpec::Difference {
+                    specs: [vec![minuend], subtrahends].concat(),
+                })
+            }
+        }
+    }
+
+    /// The JSON representation of the spec.
+    /// The spec must be in the canonical form for equivalent specs to have the same JSON.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+
+    /// The group id of the spec, which is the hash of the canonical JSON
+    pub fn group_id(&self) -> GroupId {
+        let mut hasher = Keccak256::new();
+        hasher.update(self.to_json().as_bytes());
+        hex::encode(hasher.finalize())
+    }
+
+    /// The ids of the contracts the spec depends on, sorted and deduplicated
+    pub fn contract_ids(&self) -> Vec<ContractId> {
+        let mut contract_ids = match self {
+            GroupSpec::BalanceAtLeast { contract, .. }
+            | GroupSpec::EarlyReceivers { contract, .. }
+            | GroupSpec::ReceivedFrom { contract, .. }
+            | GroupSpec::EventParticipants { contract, .. } => vec![*contract],
+            GroupSpec::Group { contracts, .. } => contracts.clone(),
+            GroupSpec::Union { specs }
+            | GroupSpec::Intersection { specs }
+            | GroupSpec::Difference { specs } => {
+                specs.iter().flat_map(|spec| spec.contract_ids()).collect()
+            }
+        };
+
+        contract_ids.sort();
+        contract_ids.dedup();
+
+        contract_ids
+    }
+
+    /// Check that the contracts of the spec are in `contracts`,
+    /// that the token specs are over token contracts
+    /// and that the events of the event specs are synced for their contracts
+    pub fn check_contracts(&self, contracts: &[Contract]) -> Result<(), String> {
+        let find_contract = |contract_id: &ContractId| {
+            contracts
+                .iter()
+                .find(|contract| contract.id == *contract_id)
+                .ok_or(format!(
+                    "Contract {} is not an input of the group",
+                    contract_id
+                ))
+        };
+
+        match self {
+            GroupSpec::BalanceAtLeast { contract, .. }
+            | GroupSpec::EarlyReceivers { contract, .. }
+            | GroupSpec::ReceivedFrom { contract, .. } => {
+                transfer_event_id(find_contract(contract)?)?;
+            }
+            GroupSpec::EventParticipants {
+                contract, event, ..
+            } => {
+                let contract = find_contract(contract)?;
+                if !contract.events.contains(event) {
+                    return Err(format!(
+                        "Event {} is not synced for contract {}",
+                        event, contract.id
+                    ));
+                }
+            }
+            GroupSpec::Group { contracts, .. } => {
+                for contract in contracts {
+                    find_contract(contract)?;
+                }
+            }
+            GroupSpec::Union { specs }
+            | GroupSpec::Intersection { specs }
+            | GroupSpec::Difference { specs } => {
+                for spec in specs {
+                    spec.check_contracts(contracts)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the id of the transfer event the token specs read for a contract
+pub fn transfer_event_id(contract: &Contract) -> Result<EventId, String> {
+    match contract.contract_type {
+        ContractType::ERC20 => Ok(ERC20_TRANSFER_EVENT_ID),
+        ContractType::ERC721 | ContractType::Punk => Ok(ERC721_TRANSFER_EVENT_ID),
+        _ => Err(format!(
+            "Contract type {:?} of contract {} is not supported in group specs",
+            contract.contract_type, contract.id
+        )),
+    }
+}
+
+/// Canonicalize the children of a set operation.
+/// The order of the children doesn't change the members, so they're sorted by their JSON.
+fn canonicalize_children(specs: Vec<GroupSpec>) -> Result<Vec<GroupSpec>, String> {
+    if specs.is_empty() {
+        return Err("Set operations require at least one spec".to_string());
+    }
+
+    let mut specs = specs
+        .into_iter()
+        .map(|spec| spec.canonicalize())
+        .collect::<Result<Vec<GroupSpec>, String>>()?;
+
+    specs.sort_by_cached_key(|spec| spec.to_json());
+    specs.dedup();
+
+    Ok(specs)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::{erc1155_test_contract, erc20_test_contract};
+
+    #[test]
+    fn test_canonical_group_id() {
+        let spec = GroupSpec::from_json(
+            r#"{
+                "type": "union",
+                "specs": [
+                    { "type": "receivedFrom", "contract": 2, "from": "0xABCDEFABCDEFABCDEFABCDEFABCDEFABCDEFABCD" },
+                    { "type": "balanceAtLeast", "contract": 1, "amount": "0100" }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        // The same spec with a different order, letter case and number format
+        let equivalent_spec = GroupSpec::from_json(
+            r#"{
+                "type": "union",
+                "specs": [
+                    { "type": "balanceAtLeast", "contract": 1, "amount": "100" },
+                    { "type": "receivedFrom", "contract": 2, "from": "abcdefabcdefabcdefabcdefabcdefabcdefabcd" }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(spec, equivalent_spec);
+        assert_eq!(spec.group_id(), equivalent_spec.group_id());
+        assert_eq!(spec.contract_ids(), vec![1, 2]);
+
+        let different_spec = GroupSpec::from_json(
+            r#"{ "type": "balanceAtLeast", "contract": 1, "amount": "100", "block": 1 }"#,
+        )
+        .unwrap();
+        assert_ne!(spec.group_id(), different_spec.group_id());
+
+        // Invalid specs
+        assert!(GroupSpec::from_json(r#"{ "type": "union", "specs": [] }"#).is_err());
+        assert!(GroupSpec::from_json(
+            r#"{ "type": "earlyReceivers", "contract": 1, "percent": 0 }"#
+        )
+        .is_err());
+        assert!(GroupSpec::from_json(
+            r#"{ "type": "balanceAtLeast", "contract": 1, "amount": "-1" }"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_composite_spec() {
+        let spec = GroupSpec::from_json(
+            r#"{
+                "type": "difference",
+                "specs": [
+                    { "type": "group", "groupType": "Whale", "contracts": [3] },
+                    { "type": "group", "groupType": "EarlyHolder", "contracts": [2] },
+                    { "type": "balanceAtLeast", "contract": 1, "amount": "1" }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        // The first spec stays first, and the subtracted specs are sorted
+        assert_eq!(
+            spec,
+            GroupSpec::Difference {
+                specs: vec![
+                    GroupSpec::Group {
+                        group_type: GroupType::Whale,
+                        contracts: vec![3]
+                    },
+                    GroupSpec::BalanceAtLeast {
+                        contract: 1,
+                        amount: "1".to_string(),
+                        block: None
+                    },
+                    GroupSpec::Group {
+                        group_type: GroupType::EarlyHolder,
+                        contracts: vec![2]
+                    },
+                ]
+            }
+        );
+        assert_eq!(spec.contract_ids(), vec![1, 2, 3]);
+
+        // Declarative groups are nested as specs instead
+        assert!(GroupSpec::from_json(
+            r#"{ "type": "group", "groupType": "Declarative", "contracts": [] }"#
+        )
+        .is_err());
+        assert!(GroupSpec::from_json(
+            r#"{ "type": "difference", "specs": [{ "type": "earlyReceivers", "contract": 1, "percent": 5 }] }"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_check_contracts() {
+        let erc20_contract = Contract {
+            id: 1,
+            events: vec![10],
+            ..erc20_test_contract()
+        };
+        let erc1155_contract = Contract {
+            id: 2,
+            ..erc1155_test_contract()
+        };
+        let contracts = [erc20_contract, erc1155_contract];
+
+        let spec = GroupSpec::from_json(
+            r#"{
+                "type": "union",
+                "specs": [
+                    { "type": "balanceAtLeast", "contract": 1, "amount": "1" },
+                    { "type": "eventParticipants", "contract": 1, "event": 10, "field": "to" },
+                    { "type": "group", "groupType": "Whale", "contracts": [1] }
+                ]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(spec.check_contracts(&contracts), Ok(()));
+
+        // A contract that isn't an input of the group
+        let spec =
+            GroupSpec::from_json(r#"{ "type": "group", "groupType": "Whale", "contracts": [3] }"#)
+                .unwrap();
+        assert!(spec.check_contracts(&contracts).is_err());
+
+        // The transfers of ERC1155 contracts aren't supported
+        let spec =
+            GroupSpec::from_json(r#"{ "type": "earlyReceivers", "contract": 2, "percent": 5 }"#)
+                .unwrap();
+        assert!(spec.check_contracts(&contracts).is_err());
+
+        // An event that isn't synced for the contract
+        let spec = GroupSpec::from_json(
+            r#"{ "type": "eventParticipants", "contract": 1, "event": 11, "field": "to" }"#,
+        )
+        .unwrap();
+        assert!(spec.check_contracts(&contracts).is_err());
+    }
+}
@@ -1,6 +1,7 @@
 use crate::{
     merkle_tree_proto::MerkleTree,
     neynar::{get_custody_addresses, get_verified_addresses},
+    shutdown::Shutdown,
     utils::format_address,
     Error, Fid, TreeId,
 };
@@ -180,10 +181,12 @@ impl IntrinsicCredddSyncEngine {
         Ok(())
     }
 
-    /// Sync the intrinsic creddd every 10 minutes
-    pub async fn sync(&self) {
+    /// Sync the intrinsic creddd every 10 minutes until the shutdown is requested
+    // `shutdown` is only used by the sync loop, which is disabled for now
+    #[allow(unused_variables)]
+    pub async fn sync(&self, shutdown: &Shutdown) {
         /*
-        loop {
+        while !shutdown.is_requested() {
 
             let result = self.sync_once().await;
 
@@ -191,7 +194,7 @@ impl IntrinsicCredddSyncEngine {
                 error!("IntrinsicCredddSyncEngine: {:?}", result.err().unwrap());
             }
 
-            tokio::time::sleep(std::time::Duration::from_secs(600)).await;
+            shutdown.sleep(std::time::Duration::from_secs(600)).await;
         }
         */
     }
@@ -1,25 +1,39 @@
 use num_bigint::BigUint;
 
 pub mod address_groups;
+pub mod balance_history;
+pub mod balance_state;
+pub mod block_hash_iterator;
 pub mod block_timestamp_iterator;
 pub mod block_timestamp_sync_engine;
 pub mod coingecko;
 pub mod contract;
 pub mod contract_event_iterator;
+pub mod delegate_registries;
 pub mod eth_rpc;
+pub mod event_registry;
 pub mod events;
 pub mod group;
+pub mod group_changes;
+pub mod group_spec;
 pub mod intrinsic_creddd_sync_engine;
 pub mod log_sync_engine;
 pub mod neynar;
 pub mod postgres;
 pub mod processors;
 pub mod rocksdb_key;
+pub mod rpc_health;
+pub mod rpc_provider;
+pub mod safe_owners;
 pub mod seeder;
 pub mod server;
+pub mod shutdown;
 pub mod status_logger;
 pub mod synched_chunks_iterator;
 pub mod tree;
+pub mod tree_archive;
+pub mod tree_events;
+pub mod tree_store;
 pub mod tree_sync_engine;
 pub mod utils;
 
@@ -27,6 +41,7 @@ pub mod utils;
 pub mod test_utils;
 
 use postgres_types::{FromSql, ToSql};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 // Define the types for the RocksDB key and value
@@ -68,6 +83,14 @@ pub const ROCKSDB_PATH: &str = "./db";
 pub enum IndexerError {
     InvalidBalance,
     NoBlockTimestamp,
+    InvalidTreeArchive,
+    /// The configuration in the environment is invalid
+    InvalidConfig(String),
+    /// The group depends on multiple chains, so its members can't be read at a single block number.
+    /// (Use `get_members_at` with a block number for each chain instead)
+    MultiChainGroup(GroupId),
+    /// The spec of the declarative group can't be indexed with its contract inputs
+    InvalidGroupSpec(GroupId, String),
 }
 
 #[derive(Debug)]
@@ -82,6 +105,7 @@ pub enum Error {
     Surf(surf::Error),
     Indexer(IndexerError),
     EthRpc(EthRpcError),
+    Io(std::io::Error),
 }
 
 impl From<rocksdb::Error> for Error {
@@ -102,13 +126,21 @@ impl From<surf::Error> for Error {
     }
 }
 
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
 impl From<IndexerError> for Error {
     fn from(e: IndexerError) -> Self {
         Error::Indexer(e)
     }
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, FromSql, ToSql, Serialize, Deserialize)]
+#[derive(
+    Debug, Copy, Clone, PartialEq, Eq, FromSql, ToSql, Serialize, Deserialize, JsonSchema,
+)]
 #[postgres(name = "GroupType")]
 pub enum GroupType {
     Static,
@@ -127,7 +159,12 @@ pub enum GroupType {
     Farcaster1K,
     Farcaster10K,
     Farcaster100K,
-    FarcasterPowerUsers
+    FarcasterPowerUsers,
+    /// Groups defined by a `GroupSpec`
+    Declarative,
+    /// Holders of an ERC20 token whose tree leaves commit to their balances,
+    /// so the members can prove that their balance is at least any threshold
+    BalanceHolder,
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, FromSql, ToSql, Serialize, Deserialize)]
@@ -179,6 +216,14 @@ pub mod erc1155_transfer_event {
     include!(concat!(env!("OUT_DIR"), "/erc1155_transfer_event.rs"));
 }
 
+pub mod generic_event {
+    include!(concat!(env!("OUT_DIR"), "/generic_event.rs"));
+}
+
 pub mod merkle_tree_proto {
     include!(concat!(env!("OUT_DIR"), "/merkle_tree_proto.rs"));
 }
+
+pub mod merkle_proof_proto {
+    include!(concat!(env!("OUT_DIR"), "/merkle_proof.rs"));
+}
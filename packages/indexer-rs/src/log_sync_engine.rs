@@ -1,24 +1,32 @@
+use crate::balance_history::BalanceHistory;
+use crate::balance_state::BalanceState;
+use crate::block_hash_iterator::BlockHashIterator;
 use crate::block_timestamp_sync_engine::BlockTimestampSyncEngine;
 use crate::contract::Contract;
-use crate::eth_rpc::EthRpcClient;
+use crate::delegate_registries::{DelegateRegistries, Registry};
+use crate::eth_rpc::{BlockHeader, EthRpcClient};
+use crate::event_registry::EventDefinition;
 use crate::events::erc1155_batch::ERC1155TransferBatchLog;
 use crate::events::erc1155_single::ERC1155TransferSingleLog;
 use crate::events::erc20::ERC20TransferLog;
 use crate::events::erc721::ERC721TransferLog;
+use crate::events::generic::GenericEventLog;
 use crate::events::EventLogLike;
 use crate::rocksdb_key::{
     KeyType, RocksDbKey, ERC1155_TRANSFER_BATCH_EVENT_ID, ERC1155_TRANSFER_SINGLE_EVENT_ID,
     ERC20_TRANSFER_EVENT_ID, ERC721_TRANSFER_EVENT_ID,
 };
+use crate::shutdown::Shutdown;
 use crate::utils::{get_latest_synched_chunk, search_missing_chunks};
-use crate::{BlockNum, ChunkNum, EventId};
+use crate::{BlockNum, ChunkNum, Error, EthRpcError, EventId};
 use core::panic;
 use futures::future::join_all;
 use log::{error, info};
 use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
 use rocksdb::WriteBatch;
 use serde_json::Value;
-use std::cmp::min;
+use std::cmp::{max, min};
+use std::collections::HashMap;
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -33,17 +41,58 @@ pub const ERC1155_TRANSFER_BATCH_SIG: &str =
 pub const ERC1155_TRANSFER_SINGLE_SIG: &str =
     "0xc3d58168c5ae7397731d063d5bbf3d657854427343f4c083240f7aacaa2d0f62";
 
+/// Number of the most recent block hashes to keep for detecting reorgs
+pub const REORG_WINDOW: u64 = 128;
+
+/// The result of checking the synched blocks for reorgs
+enum ReorgCheck {
+    /// The synched blocks are canonical. Contains the headers of the blocks to sync.
+    Canonical(Vec<BlockHeader>),
+    /// The synched blocks were reorged. The logs from the block were rolled back.
+    RolledBack(BlockNum),
+}
+
+/// Find the block to roll back from, by comparing the stored block hashes
+/// (sorted by block number) with the canonical block headers.
+/// Returns `None` if all stored blocks are canonical.
+fn find_reorged_block(
+    stored: &[(BlockNum, [u8; 32])],
+    canonical: &[BlockHeader],
+) -> Option<BlockNum> {
+    let canonical = canonical
+        .iter()
+        .map(|header| (header.number, header.hash))
+        .collect::<HashMap<BlockNum, [u8; 32]>>();
+
+    let mut last_canonical_block = None;
+
+    for (block_num, hash) in stored {
+        if canonical.get(block_num) == Some(hash) {
+            last_canonical_block = Some(*block_num);
+        } else {
+            // If none of the stored blocks are canonical, the reorg is deeper than the window
+            // and we can only roll back from the oldest stored block.
+            return Some(last_canonical_block.map_or(*block_num, |block_num| block_num + 1));
+        }
+    }
+
+    None
+}
+
 /// A struct to sync all logs of a particular contract event
 pub struct LogSyncEngine {
     eth_client: Arc<EthRpcClient>,
     contract: Contract,
     event_id: EventId,
-    event_sig: &'static str,
+    event_sig: String,
+    /// Definition of the event if it's a registered event
+    event: Option<EventDefinition>,
     rocksdb_client: Arc<rocksdb::DB>,
     block_timestamp_sync_engine: BlockTimestampSyncEngine,
 }
 
 impl LogSyncEngine {
+    /// Create a sync engine for one of the built-in events
     pub fn new(
         eth_client: Arc<EthRpcClient>,
         contract: Contract,
@@ -59,6 +108,41 @@ impl LogSyncEngine {
             _ => panic!("Invalid event_id"),
         };
 
+        Self::init(
+            eth_client,
+            contract,
+            event_id,
+            event_sig.to_string(),
+            None,
+            rocksdb_client,
+        )
+    }
+
+    /// Create a sync engine for an event registered by its ABI signature
+    pub fn new_with_event(
+        eth_client: Arc<EthRpcClient>,
+        contract: Contract,
+        event: EventDefinition,
+        rocksdb_client: Arc<rocksdb::DB>,
+    ) -> Self {
+        Self::init(
+            eth_client,
+            contract,
+            event.id,
+            event.topic(),
+            Some(event),
+            rocksdb_client,
+        )
+    }
+
+    fn init(
+        eth_client: Arc<EthRpcClient>,
+        contract: Contract,
+        event_id: EventId,
+        event_sig: String,
+        event: Option<EventDefinition>,
+        rocksdb_client: Arc<rocksdb::DB>,
+    ) -> Self {
         let block_timestamp_sync_engine: BlockTimestampSyncEngine = BlockTimestampSyncEngine::new(
             eth_client.clone(),
             rocksdb_client.clone(),
@@ -72,6 +156,7 @@ impl LogSyncEngine {
             contract,
             event_id,
             event_sig,
+            event,
             rocksdb_client,
             block_timestamp_sync_engine,
         }
@@ -98,8 +183,16 @@ impl LogSyncEngine {
         let parsed_logs: Vec<(Vec<u8>, Vec<u8>)> = logs_batch
             .par_iter()
             .flat_map(|logs_batch| {
-                logs_batch.par_iter().map(|log| {
+                logs_batch.par_iter().filter_map(|log| {
                     let contract_id = self.contract.id;
+
+                    if let Some(event) = &self.event {
+                        // Skip the logs that have the same topic but don't match the signature
+                        let event_log = GenericEventLog::from_json(log, contract_id, event)?;
+                        let (key, value) = event_log.to_rocksdb_record();
+                        return Some((key.to_bytes(), value));
+                    }
+
                     let event_log: Box<dyn EventLogLike> = match self.event_id {
                         ERC20_TRANSFER_EVENT_ID => {
                             Box::new(ERC20TransferLog::from_json(log, contract_id))
@@ -117,7 +210,7 @@ impl LogSyncEngine {
                     };
 
                     let (key, value) = event_log.to_rocksdb_record();
-                    (key.to_bytes(), value)
+                    Some((key.to_bytes(), value))
                 })
             })
             .collect();
@@ -165,7 +258,7 @@ impl LogSyncEngine {
                 .get_logs_batch(
                     self.contract.chain,
                     &self.contract.address,
-                    self.event_sig,
+                    &self.event_sig,
                     &batch,
                 )
                 .await;
@@ -253,6 +346,187 @@ impl LogSyncEngine {
         }
     }
 
+    /// Check that the synched blocks are still canonical, using the stored block hashes.
+    /// The headers of the blocks up to `to_block` are fetched and checked to build on the stored blocks.
+    /// If they don't, the logs of the reorged blocks are rolled back.
+    async fn check_reorg(&self, to_block: BlockNum) -> Result<ReorgCheck, Error> {
+        let stored = BlockHashIterator::new(&self.rocksdb_client, self.event_id, self.contract.id)
+            .collect::<Vec<(BlockNum, [u8; 32])>>();
+
+        let tip = stored.last().map(|(block_num, _)| *block_num);
+
+        // Only the blocks within the reorg window are tracked
+        let from_block = max(
+            tip.map_or(0, |tip| tip + 1),
+            max(
+                to_block.saturating_sub(REORG_WINDOW - 1),
+                self.contract.deployed_block,
+            ),
+        );
+
+        // Fetch the stored tip along with the new blocks
+        let mut block_numbers = tip.into_iter().collect::<Vec<BlockNum>>();
+        block_numbers.extend(from_block..=to_block);
+
+        let headers = self
+            .eth_client
+            .get_block_headers_batch(self.contract.chain, &block_numbers)
+            .await?;
+
+        let is_tip_canonical = match stored.last() {
+            Some((_, hash)) => headers.first().map(|header| header.hash) == Some(*hash),
+            None => true,
+        };
+
+        let is_linked = headers.windows(2).all(|pair| {
+            pair[1].number != pair[0].number + 1 || pair[1].parent_hash == pair[0].hash
+        });
+
+        if is_tip_canonical && is_linked {
+            let new_headers = headers
+                .into_iter()
+                .filter(|header| Some(header.number) != tip)
+                .collect();
+
+            return Ok(ReorgCheck::Canonical(new_headers));
+        }
+
+        // Find the block where the stored blocks diverge from the chain
+        let stored_block_numbers = stored
+            .iter()
+            .map(|(block_num, _)| *block_num)
+            .collect::<Vec<BlockNum>>();
+
+        let canonical = self
+            .eth_client
+            .get_block_headers_batch(self.contract.chain, &stored_block_numbers)
+            .await?;
+
+        match find_reorged_block(&stored, &canonical) {
+            Some(reorged_block) => {
+                self.rollback(reorged_block);
+                Ok(ReorgCheck::RolledBack(reorged_block))
+            }
+            // The chain changed while the headers were fetched
+            None => Err(Error::EthRpc(EthRpcError {
+                message: "Block headers changed while fetching".to_string(),
+            })),
+        }
+    }
+
+    /// Delete the logs, the sync logs and the block hashes from `from_block` onwards,
+    /// so the logs are fetched again in the next sync
+    fn rollback(&self, from_block: BlockNum) {
+        let mut batch = WriteBatch::default();
+
+        let event_log_key = |block_num, index| RocksDbKey {
+            key_type: KeyType::EventLog,
+            event_id: Some(self.event_id),
+            contract_id: Some(self.contract.id),
+            block_num: Some(block_num),
+            log_index: Some(index),
+            tx_index: Some(index),
+            chunk_num: None,
+            chain_id: None,
+        };
+
+        batch.delete_range(
+            event_log_key(from_block, 0).to_bytes(),
+            event_log_key(BlockNum::MAX, u32::MAX).to_bytes(),
+        );
+
+        // Chunks overlap at their boundaries, so a block at the start of a chunk
+        // is also in the previous chunk.
+        let from_chunk = from_block
+            .saturating_sub(self.contract.deployed_block)
+            .saturating_sub(1)
+            / CHUNK_SIZE;
+
+        let sync_log_key = |chunk_num| RocksDbKey {
+            key_type: KeyType::SyncLog,
+            event_id: Some(self.event_id),
+            contract_id: Some(self.contract.id),
+            block_num: None,
+            log_index: None,
+            tx_index: None,
+            chunk_num: Some(chunk_num),
+            chain_id: None,
+        };
+
+        batch.delete_range(
+            sync_log_key(from_chunk).to_bytes(),
+            sync_log_key(ChunkNum::MAX).to_bytes(),
+        );
+
+        batch.delete_range(
+            self.block_hash_key(from_block).to_bytes(),
+            self.block_hash_key(BlockNum::MAX).to_bytes(),
+        );
+
+        // Balance states and histories are persisted only for blocks out of the reorg window,
+        // but they're also deleted here in case a reorg was deeper than that.
+        if self.event_id == ERC20_TRANSFER_EVENT_ID {
+            BalanceState::rollback(
+                &self.rocksdb_client,
+                self.contract.id,
+                from_block,
+                &mut batch,
+            );
+            BalanceHistory::rollback(
+                &self.rocksdb_client,
+                self.contract.id,
+                from_block,
+                &mut batch,
+            );
+        }
+
+        if Registry::of(&self.contract).is_some() {
+            DelegateRegistries::rollback(
+                &self.rocksdb_client,
+                self.contract.id,
+                from_block,
+                &mut batch,
+            );
+        }
+
+        self.rocksdb_client.write(batch).unwrap();
+    }
+
+    fn block_hash_key(&self, block_num: BlockNum) -> RocksDbKey {
+        RocksDbKey {
+            key_type: KeyType::BlockHash,
+            event_id: Some(self.event_id),
+            contract_id: Some(self.contract.id),
+            block_num: Some(block_num),
+            log_index: None,
+            tx_index: None,
+            chunk_num: None,
+            chain_id: None,
+        }
+    }
+
+    /// Save the hashes of the synched blocks, and delete the hashes that are out of the reorg window
+    fn save_block_hashes(&self, headers: &[BlockHeader]) {
+        let tip = match headers.last() {
+            Some(header) => header.number,
+            None => return,
+        };
+
+        let mut batch = WriteBatch::default();
+
+        for header in headers {
+            batch.put(self.block_hash_key(header.number).to_bytes(), header.hash);
+        }
+
+        batch.delete_range(
+            self.block_hash_key(0).to_bytes(),
+            self.block_hash_key(tip.saturating_sub(REORG_WINDOW - 1))
+                .to_bytes(),
+        );
+
+        self.rocksdb_client.write(batch).unwrap();
+    }
+
     /// Sync contract logs up to the given block number
     pub async fn sync_to_block(&self, to_block: BlockNum) {
         let batch_size = 50;
@@ -287,9 +561,9 @@ impl LogSyncEngine {
         info!("${} Synced to block: {}", self.contract.name, to_block);
     }
 
-    pub async fn sync(self) {
-        // Start the background sync loop
-        loop {
+    /// Start the background sync loop, which runs until the shutdown is requested
+    pub async fn sync(self, shutdown: &Shutdown) {
+        while !shutdown.is_requested() {
             let latest_block = self.eth_client.get_block_number(self.contract.chain).await;
 
             if latest_block.is_err() {
@@ -298,22 +572,44 @@ impl LogSyncEngine {
                     self.contract.name,
                     latest_block.err().unwrap()
                 );
-                tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+                shutdown.sleep(std::time::Duration::from_secs(1)).await;
                 continue;
             }
 
             let latest_block = latest_block.unwrap();
 
+            // When syncing past the finalized block, roll back the logs of reorged blocks first
+            let mut new_headers = vec![];
+            if self.eth_client.confirmations().is_some() {
+                match self.check_reorg(latest_block).await {
+                    Ok(ReorgCheck::Canonical(headers)) => new_headers = headers,
+                    Ok(ReorgCheck::RolledBack(reorged_block)) => {
+                        info!(
+                            "${} Reorg detected. Rolled back logs from block {}",
+                            self.contract.name, reorged_block
+                        );
+                        continue;
+                    }
+                    Err(e) => {
+                        error!("${} Error checking for reorgs: {:?}", self.contract.name, e);
+                        shutdown.sleep(std::time::Duration::from_secs(1)).await;
+                        continue;
+                    }
+                }
+            }
+
             // Sync logs to the latest block
             self.sync_to_block(latest_block).await;
 
+            self.save_block_hashes(&new_headers);
+
             // Sync block timestamps
             // Only sync block timestamps for the $higher contract for now
             if self.contract.id == 86 {
                 self.block_timestamp_sync_engine.sync().await;
             }
 
-            tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+            shutdown.sleep(std::time::Duration::from_secs(5)).await;
         }
     }
 }
@@ -326,6 +622,101 @@ mod test {
         utils::{count_synched_chunks, count_synched_logs, dotenv_config, missing_chunk_exists},
     };
 
+    fn test_header(number: BlockNum, hash: u8) -> BlockHeader {
+        BlockHeader {
+            number,
+            hash: [hash; 32],
+            parent_hash: [0; 32],
+        }
+    }
+
+    #[test]
+    fn test_find_reorged_block() {
+        let stored = vec![(10, [1; 32]), (11, [2; 32]), (12, [3; 32])];
+
+        // All blocks are canonical
+        let canonical = vec![test_header(10, 1), test_header(11, 2), test_header(12, 3)];
+        assert_eq!(find_reorged_block(&stored, &canonical), None);
+
+        // Blocks 11 and 12 were reorged
+        let canonical = vec![test_header(10, 1), test_header(11, 4), test_header(12, 5)];
+        assert_eq!(find_reorged_block(&stored, &canonical), Some(11));
+
+        // The reorg is deeper than the stored blocks
+        let canonical = vec![test_header(10, 4), test_header(11, 5), test_header(12, 6)];
+        assert_eq!(find_reorged_block(&stored, &canonical), Some(10));
+    }
+
+    #[test]
+    fn test_rollback() {
+        let rocksdb_client = init_test_rocksdb("test_rollback");
+        let eth_client = Arc::new(EthRpcClient::new());
+
+        let contract = erc20_test_contract();
+
+        let contract_sync_engine = LogSyncEngine::new(
+            eth_client,
+            contract.clone(),
+            ERC20_TRANSFER_EVENT_ID,
+            rocksdb_client.clone(),
+        );
+
+        // Save a log and a block hash for every 1000 blocks in chunks 0~9
+        let mut batch = WriteBatch::default();
+        for chunk in 0..10 {
+            let sync_log_key =
+                RocksDbKey::new_start_key(KeyType::SyncLog, ERC20_TRANSFER_EVENT_ID, contract.id);
+            batch.put(
+                RocksDbKey {
+                    chunk_num: Some(chunk),
+                    ..sync_log_key
+                }
+                .to_bytes(),
+                [1],
+            );
+
+            for i in 0..2 {
+                let block_num = contract.deployed_block + chunk * CHUNK_SIZE + i * 1000;
+                let event_log_key = RocksDbKey::new_start_key(
+                    KeyType::EventLog,
+                    ERC20_TRANSFER_EVENT_ID,
+                    contract.id,
+                );
+                batch.put(
+                    RocksDbKey {
+                        block_num: Some(block_num),
+                        ..event_log_key
+                    }
+                    .to_bytes(),
+                    [],
+                );
+                batch.put(
+                    contract_sync_engine.block_hash_key(block_num).to_bytes(),
+                    [0; 32],
+                );
+            }
+        }
+        rocksdb_client.write(batch).unwrap();
+
+        // Roll back from the first block of chunk 5
+        contract_sync_engine.rollback(contract.deployed_block + 5 * CHUNK_SIZE);
+
+        // The logs before the block are kept
+        let count = count_synched_logs(&rocksdb_client, ERC20_TRANSFER_EVENT_ID, contract.id, None);
+        assert_eq!(count, 10);
+
+        // Chunk 4 also contains the block, so it's synched again
+        let num_synched_chunks =
+            count_synched_chunks(&rocksdb_client, ERC20_TRANSFER_EVENT_ID, contract.id);
+        assert_eq!(num_synched_chunks, 4);
+
+        let num_block_hashes =
+            BlockHashIterator::new(&rocksdb_client, ERC20_TRANSFER_EVENT_ID, contract.id).count();
+        assert_eq!(num_block_hashes, 10);
+
+        delete_all(&rocksdb_client);
+    }
+
     #[tokio::test]
     async fn test_sync_engine() {
         dotenv_config();
@@ -0,0 +1,201 @@
+use super::GroupIndexer;
+
+use crate::balance_state::BalanceState;
+use crate::contract::Contract;
+use crate::eth_rpc::{Chain, ChainBlocks};
+use crate::group::Group;
+use crate::processors::IndexerResources;
+use crate::rocksdb_key::ERC20_TRANSFER_EVENT_ID;
+use crate::utils::{get_balance_at_block, is_event_logs_ready};
+use crate::Error;
+use crate::{Address, BlockNum};
+use num_bigint::BigUint;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+/// Indexer of the holders of an ERC20 token along with their balances.
+/// The leaves of the group's tree commit to the balances,
+/// so a single tree serves the proofs of any balance threshold.
+pub struct BalanceHolderIndexer {
+    group: Group,
+    resources: IndexerResources,
+    /// The holder balances at the last block they were computed at.
+    /// The members, the balances and the sanity check of a sync are all at the same block,
+    /// so the balance state is only replayed once per sync.
+    holder_balances: Mutex<Option<(BlockNum, HashMap<Address, BigUint>)>>,
+}
+
+impl BalanceHolderIndexer {
+    pub fn new(group: Group, resources: IndexerResources) -> Self {
+        BalanceHolderIndexer {
+            group,
+            resources,
+            holder_balances: Mutex::new(None),
+        }
+    }
+
+    fn contract(&self) -> &Contract {
+        &self.group.contract_inputs[0]
+    }
+
+    /// Get the addresses with a positive balance at `block_number`, along with the balances
+    fn get_holder_balances(
+        &self,
+        block_number: BlockNum,
+    ) -> Result<HashMap<Address, BigUint>, Error> {
+        let mut holder_balances = self.holder_balances.lock().unwrap();
+
+        if let Some((cached_block_number, balances)) = holder_balances.as_ref() {
+            if *cached_block_number == block_number {
+                return Ok(balances.clone());
+            }
+        }
+
+        let state = BalanceState::new(
+            self.resources.rocksdb_client.clone(),
+            self.contract().id,
+            &self.group.id,
+        );
+
+        let balances = state.get_balances(block_number, None, |_| false)?.all();
+
+        let balances = balances
+            .into_iter()
+            .filter(|(_, balance)| *balance > BigUint::from(0u8))
+            .collect::<HashMap<Address, BigUint>>();
+
+        *holder_balances = Some((block_number, balances.clone()));
+
+        Ok(balances)
+    }
+}
+
+#[async_trait::async_trait]
+impl GroupIndexer for BalanceHolderIndexer {
+    fn chain(&self) -> Chain {
+        self.contract().chain
+    }
+
+    fn group(&self) -> &Group {
+        &self.group
+    }
+
+    async fn is_ready(&self) -> Result<bool, Error> {
+        is_event_logs_ready(
+            &self.resources.rocksdb_client,
+            &self.resources.eth_client,
+            ERC20_TRANSFER_EVENT_ID,
+            &self.contract(),
+        )
+        .await
+    }
+
+    async fn get_members(&self, block_number: BlockNum) -> Result<HashSet<Address>, Error> {
+        let holders = self.get_holder_balances(block_number)?;
+        Ok(holders.into_keys().collect())
+    }
+
+    async fn get_member_balances_at(
+        &self,
+        blocks: &ChainBlocks,
+    ) -> Result<Option<HashMap<Address, BigUint>>, Error> {
+        let holders = self.get_holder_balances(blocks[&self.chain()])?;
+        Ok(Some(holders))
+    }
+
+    async fn sanity_check_members(
+        &self,
+        members: &[Address],
+        block_number: BlockNum,
+    ) -> Result<bool, Error> {
+        let holders = self.get_holder_balances(block_number)?;
+
+        // The leaves commit to the balances, so they must match the balances on chain
+        for member in members {
+            let balance = get_balance_at_block(
+                &self.resources.eth_client,
+                self.contract(),
+                &hex::encode(member),
+                block_number,
+            )
+            .await;
+
+            if holders.get(member) != Some(&balance) {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        eth_rpc::EthRpcClient,
+        log_sync_engine::LogSyncEngine,
+        postgres::init_postgres,
+        test_utils::{erc20_test_contract, init_test_rocksdb},
+        utils::dotenv_config,
+        GroupType,
+    };
+
+    #[tokio::test]
+    async fn test_balance_holder_indexer() {
+        dotenv_config();
+
+        let db = init_test_rocksdb("test_balance_holder_indexer");
+
+        let pg_client = init_postgres().await;
+
+        let contract = erc20_test_contract();
+
+        let to_block = 19473397;
+
+        let eth_client = Arc::new(EthRpcClient::new());
+
+        let contract_sync_engine = LogSyncEngine::new(
+            eth_client.clone(),
+            contract.clone(),
+            ERC20_TRANSFER_EVENT_ID,
+            db.clone(),
+        );
+        contract_sync_engine.sync_to_block(to_block).await;
+
+        let resources = IndexerResources {
+            rocksdb_client: db.clone(),
+            pg_client,
+            eth_client,
+        };
+
+        let group = Group::new(
+            "Test group".to_string(),
+            GroupType::BalanceHolder,
+            vec![contract.clone()],
+            0,
+        );
+
+        let indexer = BalanceHolderIndexer::new(group, resources);
+
+        let members = indexer.get_members(to_block).await.unwrap();
+
+        let blocks = ChainBlocks::from([(contract.chain, to_block)]);
+        let balances = indexer
+            .get_member_balances_at(&blocks)
+            .await
+            .unwrap()
+            .unwrap();
+
+        // Each member has a positive balance
+        assert_eq!(
+            balances.keys().copied().collect::<HashSet<Address>>(),
+            members
+        );
+        assert!(balances
+            .values()
+            .all(|balance| *balance > BigUint::from(0u8)));
+    }
+}
@@ -1,15 +1,12 @@
 use crate::{
+    balance_state::BalanceState,
     coingecko::CoingeckoClient,
     contract::Contract,
-    contract_event_iterator::ContractEventIterator,
     eth_rpc::Chain,
     group::Group,
     processors::{GroupIndexer, IndexerResources},
     rocksdb_key::{KeyType, RocksDbKey, ERC20_TRANSFER_EVENT_ID},
-    utils::{
-        decode_erc20_transfer_event, get_chain_id, is_block_timestamps_ready, is_event_logs_ready,
-        MINTER_ADDRESS,
-    },
+    utils::{get_chain_id, is_block_timestamps_ready, is_event_logs_ready},
     Address, BlockNum, Error, IndexerError,
 };
 use log::info;
@@ -110,99 +107,54 @@ impl GroupIndexer for BelieverIndexer {
     }
 
     async fn get_members(&self, block_number: BlockNum) -> Result<HashSet<Address>, Error> {
-        let iterator = ContractEventIterator::new(
-            &self.resources.rocksdb_client,
-            ERC20_TRANSFER_EVENT_ID,
-            self.contract().id,
-            Some(block_number),
-        );
-
         // Get the timestamp to price mapping from Coingecko
         let timestamp_to_price = self.get_token_prices().await;
 
-        // Holds the balances of all addresses
-        let mut balances = HashMap::new();
+        let mut sorted_timestamps = timestamp_to_price.keys().copied().collect::<Vec<u64>>();
+        sorted_timestamps.sort();
 
-        // Holds the total purchase amount in USD purchased by each address.
+        // The total purchase amount in USD of each address is accumulated in the balance state,
+        // so only the transfers since the last sync are replayed.
         // If an address has more than one purchase,
         // the total purchase amount is the sum of all purchases where each purchase price is
         // determined individually by the price at the block timestamp.
-        let mut total_purchase_usd = HashMap::new();
-
-        let mut believers = HashSet::new();
-
-        for (key, value) in iterator {
-            let log = decode_erc20_transfer_event(&value);
-
-            // ---------------------------------
-            // 1. Update the `balances` mapping
-            // ---------------------------------
-
-            if log.value == BigUint::from(0u8) {
-                continue;
-            }
-
-            if balances.get(&log.to).is_none() {
-                // Initialize the balance of `to` to `value`
-                balances.insert(log.to, log.value.clone());
-            } else {
-                // Increase balance of `to` by `value`
-                let balance = balances.get_mut(&log.to).unwrap();
-                *balance += &log.value;
-            }
-
-            if log.from != MINTER_ADDRESS {
-                if balances.get(&log.from).is_none() {
-                    // Initialize the balance of `from` to 0
-                    balances.insert(log.from, BigUint::from(0u8));
-                }
-
-                let balance = balances.get(&log.from).unwrap();
-                if balance < &log.value {
-                    return Err(IndexerError::InvalidBalance.into());
-                }
-
-                // Decrease balance of `from` by `value`
-                let balance = balances.get_mut(&log.from).unwrap();
-                *balance -= &log.value;
-            }
-
-            // ---------------------------------
-            // 2. Update the `total_purchase_usd` mapping
-            // ---------------------------------
-
-            // Find the timestamp of the block number
-            let timestamp = self.get_block_timestamp(key.block_num.unwrap())?;
+        let state = BalanceState::new(
+            self.resources.rocksdb_client.clone(),
+            self.contract().id,
+            &self.group.id,
+        );
 
-            let mut sorted_timestamps = timestamp_to_price.keys().copied().collect::<Vec<u64>>();
-            sorted_timestamps.sort();
+        let balances = state.get_balances_with_amounts(
+            block_number,
+            None,
+            |_| false,
+            |transfer| {
+                // Find the timestamp of the block number
+                let timestamp = self.get_block_timestamp(transfer.block_num)?;
 
-            // Find the closest timestamp in the (timestamp -> price) mapping that is greater than or equal to the block number's timestamp
-            let closest_timestamp = sorted_timestamps
-                .iter()
-                .find(|&&t| t >= timestamp)
-                .unwrap_or(sorted_timestamps.last().unwrap());
+                // Find the closest timestamp in the (timestamp -> price) mapping that is greater than or equal to the block number's timestamp
+                let closest_timestamp = sorted_timestamps
+                    .iter()
+                    .find(|&&t| t >= timestamp)
+                    .unwrap_or(sorted_timestamps.last().unwrap());
 
-            let price = timestamp_to_price.get(closest_timestamp).unwrap();
+                let price = timestamp_to_price.get(closest_timestamp).unwrap();
 
-            // Calculate the USD amount of this transfer
-            let purchase_amount = log.value.clone();
-            let purchase_amount = purchase_amount / BigUint::from(10u8).pow(18u32);
-            let purchase_amount = purchase_amount.to_f64().unwrap();
-            let transfer_usd_amount = price * purchase_amount;
+                // Calculate the USD amount of this transfer
+                let purchase_amount = &transfer.log.value / BigUint::from(10u8).pow(18u32);
+                let purchase_amount = purchase_amount.to_f64().unwrap();
 
-            // Add the USD amount of this transfer to the total purchase amount of the `to` address
-            let total_purchase =
-                total_purchase_usd.get(&log.to).unwrap_or(&0f64) + transfer_usd_amount;
-            total_purchase_usd.insert(log.to, total_purchase);
-        }
+                Ok(price * purchase_amount)
+            },
+        )?;
 
         // Add the addresses that have purchased more than $50 worth of the token
-        for (address, total_purchase) in total_purchase_usd {
-            if total_purchase > 50f64 {
-                believers.insert(address);
-            }
-        }
+        let believers = balances
+            .amounts()
+            .into_iter()
+            .filter(|(_, total_purchase)| *total_purchase > 50f64)
+            .map(|(address, _)| address)
+            .collect::<HashSet<Address>>();
 
         info!("Found {:?} believers", believers.len());
 
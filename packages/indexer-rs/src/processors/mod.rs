@@ -1,18 +1,31 @@
 #![allow(async_fn_in_trait)]
 
-use crate::eth_rpc::{Chain, EthRpcClient};
+use crate::eth_rpc::{Chain, ChainBlocks, EthRpcClient};
 use crate::group::Group;
-use crate::{Address, BlockNum, Error};
-use std::collections::HashSet;
+use crate::{Address, BlockNum, Error, GroupType};
+use num_bigint::BigUint;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 
+use self::all_holders::AllHoldersIndexer;
+use self::balance_holders::BalanceHolderIndexer;
+use self::believer::BelieverIndexer;
+use self::creddd_team::CredddTeamIndexer;
+use self::early_holders::EarlyHolderIndexer;
+use self::salon::SalonIndexer;
+use self::spec::SpecIndexer;
+use self::ticker::TickerIndexer;
+use self::whales::WhaleIndexer;
+
 pub mod all_holders;
+pub mod balance_holders;
 pub mod believer;
 pub mod creddd_team;
 pub mod early_holders;
+pub mod salon;
+pub mod spec;
 pub mod ticker;
 pub mod whales;
-pub mod salon;
 
 pub const SYNC_WINDOW_SECS: u64 = 300; // 5 minutes
 
@@ -23,10 +36,27 @@ pub trait GroupIndexer: Send + Sync {
     fn group(&self) -> &Group;
     /// Returns the chain the logs the indexer depends on are on
     fn chain(&self) -> Chain;
+    /// Returns all chains the logs the indexer depends on are on.
+    /// Indexers of composite groups can depend on multiple chains.
+    fn chains(&self) -> Vec<Chain> {
+        vec![self.chain()]
+    }
     /// Returns true if the logs which the indexer depends on are ready
     async fn is_ready(&self) -> Result<bool, Error>;
     /// Return all members
     async fn get_members(&self, block_number: BlockNum) -> Result<HashSet<Address>, Error>;
+    /// Return all members at the given block number of each chain in `chains()`
+    async fn get_members_at(&self, blocks: &ChainBlocks) -> Result<HashSet<Address>, Error> {
+        self.get_members(blocks[&self.chain()]).await
+    }
+    /// Return the balance of each member at the given block number of each chain in `chains()`
+    /// if the leaves of the group's tree commit to the balances
+    async fn get_member_balances_at(
+        &self,
+        _blocks: &ChainBlocks,
+    ) -> Result<Option<HashMap<Address, BigUint>>, Error> {
+        Ok(None)
+    }
     /// Sanity check that the given members are eligible to be in the group
     async fn sanity_check_members(
         &self,
@@ -41,3 +71,34 @@ pub struct IndexerResources {
     pub rocksdb_client: Arc<rocksdb::DB>,
     pub eth_client: Arc<EthRpcClient>,
 }
+
+/// Initialize the indexer for the type of the group.
+/// Returns an error if the group is declarative and its spec can't be indexed.
+pub fn new_group_indexer(
+    group: Group,
+    resources: IndexerResources,
+) -> Result<Box<dyn GroupIndexer>, Error> {
+    let indexer: Box<dyn GroupIndexer> = match group.group_type {
+        GroupType::AllHolders => Box::new(AllHoldersIndexer::new(group, resources)),
+        GroupType::EarlyHolder => Box::new(EarlyHolderIndexer::new(group, resources)),
+        GroupType::Whale => Box::new(WhaleIndexer::new(group, resources)),
+        GroupType::BalanceHolder => Box::new(BalanceHolderIndexer::new(group, resources)),
+        GroupType::Ticker => Box::new(TickerIndexer::new(group, resources)),
+        GroupType::CredddTeam => Box::new(CredddTeamIndexer::new(group, resources)),
+        GroupType::Static => panic!("Static group type is deprecated"),
+        GroupType::Believer => Box::new(BelieverIndexer::new(group, resources)),
+        GroupType::BaseSalon
+        | GroupType::BlastSalon
+        | GroupType::EthSalon
+        | GroupType::ArbSalon
+        | GroupType::OpSalon
+        | GroupType::FriendBagHolder
+        | GroupType::Farcaster1K
+        | GroupType::Farcaster10K
+        | GroupType::Farcaster100K
+        | GroupType::FarcasterPowerUsers => Box::new(SalonIndexer::new(group, resources)),
+        GroupType::Declarative => Box::new(SpecIndexer::new(group, resources)?),
+    };
+
+    Ok(indexer)
+}
@@ -0,0 +1,592 @@
+use crate::{
+    balance_state::BalanceState,
+    contract::{Contract, ContractType},
+    contract_event_iterator::ContractEventIterator,
+    eth_rpc::{Chain, ChainBlocks},
+    group::Group,
+    group_spec::{transfer_event_id, GroupSpec},
+    processors::{new_group_indexer, GroupIndexer, IndexerResources},
+    rocksdb_key::ERC20_TRANSFER_EVENT_ID,
+    utils::{
+        decode_erc20_transfer_event, decode_erc721_transfer_event, decode_generic_event,
+        get_group_id, is_event_logs_ready, MINTER_ADDRESS,
+    },
+    Address, BlockNum, ContractId, Error, EventId, GroupId, IndexerError,
+};
+use num_bigint::BigUint;
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+};
+
+/// A transfer of `value` from `from` to `to`.
+/// The value of an ERC721 transfer is 1.
+struct Transfer {
+    from: Address,
+    to: Address,
+    value: BigUint,
+}
+
+/// An indexer that evaluates the `GroupSpec` of a declarative group
+/// over the event logs stored in RocksDB.
+/// The members of `GroupSpec::Group` are computed by the indexer of the child group,
+/// so a spec can combine groups of different types and chains.
+pub struct SpecIndexer {
+    group: Group,
+    spec: GroupSpec,
+    /// The events of the contracts the spec depends on
+    dependencies: Vec<(EventId, Contract)>,
+    /// Indexers of the child groups in the spec
+    children: HashMap<GroupId, Box<dyn GroupIndexer>>,
+    resources: IndexerResources,
+}
+
+impl SpecIndexer {
+    /// Returns an error if the spec refers to contracts or events the group can't be indexed with
+    pub fn new(group: Group, resources: IndexerResources) -> Result<Self, Error> {
+        let invalid_spec =
+            |message: String| IndexerError::InvalidGroupSpec(group.id.clone(), message);
+
+        let spec = group
+            .spec
+            .clone()
+            .ok_or_else(|| invalid_spec("The group doesn't have a spec".to_string()))?;
+
+        spec.check_contracts(&group.contract_inputs)
+            .map_err(invalid_spec)?;
+
+        let mut dependencies = vec![];
+        let mut child_groups = vec![];
+        collect_dependencies(&spec, &group, &mut dependencies, &mut child_groups);
+
+        let mut children = HashMap::new();
+        for child_group in child_groups {
+            let child_id = child_group.id.clone();
+            children.insert(child_id, new_group_indexer(child_group, resources.clone())?);
+        }
+
+        Ok(SpecIndexer {
+            group,
+            spec,
+            dependencies,
+            children,
+            resources,
+        })
+    }
+
+    fn contract(&self, contract_id: ContractId) -> &Contract {
+        // The contracts are checked to exist in `new`
+        self.group
+            .contract_inputs
+            .iter()
+            .find(|contract| contract.id == contract_id)
+            .unwrap()
+    }
+
+    /// Iterate over the transfers of a token contract up to `block_number`
+    fn transfers(
+        &self,
+        contract_id: ContractId,
+        block_number: BlockNum,
+    ) -> Box<dyn Iterator<Item = Transfer> + '_> {
+        let contract = self.contract(contract_id);
+
+        // The contract types are checked in `new`
+        let event_id = transfer_event_id(contract).unwrap();
+        let iterator = ContractEventIterator::new(
+            &self.resources.rocksdb_client,
+            event_id,
+            contract.id,
+            Some(block_number),
+        );
+
+        if event_id == ERC20_TRANSFER_EVENT_ID {
+            Box::new(iterator.map(|(_, value)| {
+                let log = decode_erc20_transfer_event(&value);
+                Transfer {
+                    from: log.from,
+                    to: log.to,
+                    value: log.value,
+                }
+            }))
+        } else {
+            Box::new(iterator.map(|(_, value)| {
+                let log = decode_erc721_transfer_event(&value);
+                Transfer {
+                    from: log.from,
+                    to: log.to,
+                    value: BigUint::from(1u8),
+                }
+            }))
+        }
+    }
+
+    /// Get the addresses with a balance of at least `amount`
+    fn get_balance_at_least(
+        &self,
+        contract_id: ContractId,
+        amount: &BigUint,
+        block_number: BlockNum,
+    ) -> Result<HashSet<Address>, Error> {
+        let mut balances = HashMap::<Address, BigUint>::new();
+
+        for transfer in self.transfers(contract_id, block_number) {
+            if transfer.from != MINTER_ADDRESS {
+                // Initialize the balance of `from` to 0,
+                // since a transfer of 0 tokens from an address that never held any is valid
+                let balance = balances.entry(transfer.from).or_default();
+
+                if *balance < transfer.value {
+                    return Err(IndexerError::InvalidBalance.into());
+                }
+
+                // Decrease balance of `from` by `value`
+                *balance -= &transfer.value;
+            }
+
+            // Increase balance of `to` by `value`
+            *balances.entry(transfer.to).or_default() += &transfer.value;
+        }
+
+        Ok(balances
+            .into_iter()
+            .filter(|(address, balance)| *address != MINTER_ADDRESS && balance >= amount)
+            .map(|(address, _)| address)
+            .collect())
+    }
+
+    /// Get the addresses with a balance of at least `amount` of an ERC20 contract
+    /// from the balance state of the group, which is advanced incrementally
+    /// instead of replaying all transfers on every sync.
+    fn get_balance_at_least_from_state(
+        &self,
+        contract_id: ContractId,
+        amount: &BigUint,
+        block_number: BlockNum,
+    ) -> Result<HashSet<Address>, Error> {
+        let state = BalanceState::new(
+            self.resources.rocksdb_client.clone(),
+            contract_id,
+            &self.group.id,
+        );
+
+        let balances = state.get_balances(block_number, None, |_| false)?;
+
+        Ok(balances
+            .all()
+            .into_iter()
+            .filter(|(address, balance)| *address != MINTER_ADDRESS && balance >= amount)
+            .map(|(address, _)| address)
+            .collect())
+    }
+
+    /// Get the first `percent`% of the addresses that received the token
+    fn get_early_receivers(
+        &self,
+        contract_id: ContractId,
+        percent: u8,
+        block_number: BlockNum,
+    ) -> HashSet<Address> {
+        let mut unique_receivers = HashSet::<Address>::new();
+        let mut ordered_receivers: Vec<Address> = vec![];
+
+        for transfer in self.transfers(contract_id, block_number) {
+            if transfer.to != MINTER_ADDRESS && unique_receivers.insert(transfer.to) {
+                ordered_receivers.push(transfer.to);
+            }
+        }
+
+        let earliness_threshold = ordered_receivers.len() * percent as usize / 100;
+
+        ordered_receivers
+            .into_iter()
+            .take(earliness_threshold)
+            .collect()
+    }
+
+    /// Get the addresses that received the token from `from`
+    fn get_received_from(
+        &self,
+        contract_id: ContractId,
+        from: Address,
+        block_number: BlockNum,
+    ) -> HashSet<Address> {
+        self.transfers(contract_id, block_number)
+            .filter(|transfer| transfer.from == from)
+            .map(|transfer| transfer.to)
+            .collect()
+    }
+
+    /// Get the addresses in the `field` parameter of the logs of a registered event
+    fn get_event_participants(
+        &self,
+        contract_id: ContractId,
+        event_id: EventId,
+        field: &str,
+        block_number: BlockNum,
+    ) -> HashSet<Address> {
+        let iterator = ContractEventIterator::new(
+            &self.resources.rocksdb_client,
+            event_id,
+            contract_id,
+            Some(block_number),
+        );
+
+        iterator
+            .filter_map(|(_, value)| {
+                decode_generic_event(&value)
+                    .fields
+                    .into_iter()
+                    .find(|event_field| {
+                        event_field.name == field && event_field.r#type == "address"
+                    })
+                    .and_then(|event_field| Address::try_from(event_field.value).ok())
+            })
+            .collect()
+    }
+
+    /// Evaluate the spec at the block numbers of `blocks`.
+    /// The members of the child groups are computed beforehand and passed in `child_members`.
+    fn evaluate(
+        &self,
+        spec: &GroupSpec,
+        blocks: &ChainBlocks,
+        child_members: &HashMap<GroupId, HashSet<Address>>,
+    ) -> Result<HashSet<Address>, Error> {
+        // The block number of the chain the contract is on
+        let block_of = |contract_id: &ContractId| blocks[&self.contract(*contract_id).chain];
+
+        match spec {
+            GroupSpec::BalanceAtLeast {
+                contract,
+                amount,
+                block,
+            } => {
+                // The spec is canonicalized, so the amount is a valid decimal
+                let amount = BigUint::from_str(amount).unwrap();
+                let block_number = block_of(contract);
+
+                if block.is_none() && self.contract(*contract).contract_type == ContractType::ERC20
+                {
+                    return self.get_balance_at_least_from_state(*contract, &amount, block_number);
+                }
+
+                let block_number = block.map_or(block_number, |block| block.min(block_number));
+
+                self.get_balance_at_least(*contract, &amount, block_number)
+            }
+            GroupSpec::EarlyReceivers { contract, percent } => {
+                Ok(self.get_early_receivers(*contract, *percent, block_of(contract)))
+            }
+            GroupSpec::ReceivedFrom { contract, from } => {
+                let from = hex::decode(from.trim_start_matches("0x")).unwrap();
+                let from = Address::try_from(from).unwrap();
+
+                Ok(self.get_received_from(*contract, from, block_of(contract)))
+            }
+            GroupSpec::EventParticipants {
+                contract,
+                event,
+                field,
+            } => Ok(self.get_event_participants(*contract, *event, field, block_of(contract))),
+            GroupSpec::Group {
+                group_type,
+                contracts,
+            } => {
+                let child_id = get_group_id(*group_type, &self.contract_addresses(contracts));
+                Ok(child_members[&child_id].clone())
+            }
+            GroupSpec::Union { specs } => {
+                let mut members = HashSet::new();
+                for spec in specs {
+                    members.extend(self.evaluate(spec, blocks, child_members)?);
+                }
+
+                Ok(members)
+            }
+            GroupSpec::Intersection { specs } => {
+                let mut members: Option<HashSet<Address>> = None;
+                for spec in specs {
+                    let spec_members = self.evaluate(spec, blocks, child_members)?;
+
+                    members = Some(match members {
+                        Some(members) => members.intersection(&spec_members).copied().collect(),
+                        None => spec_members,
+                    });
+                }
+
+                Ok(members.unwrap_or_default())
+            }
+            GroupSpec::Difference { specs } => {
+                let mut members = self.evaluate(&specs[0], blocks, child_members)?;
+                for spec in &specs[1..] {
+                    let spec_members = self.evaluate(spec, blocks, child_members)?;
+                    members.retain(|member| !spec_members.contains(member));
+                }
+
+                Ok(members)
+            }
+        }
+    }
+
+    fn contract_addresses(&self, contract_ids: &[ContractId]) -> Vec<String> {
+        contract_ids
+            .iter()
+            .map(|contract_id| self.contract(*contract_id).address.clone())
+            .collect()
+    }
+}
+
+/// Collect the events of the contracts and the child groups that the spec of `group` depends on.
+/// The spec must be checked with `GroupSpec::check_contracts` beforehand.
+fn collect_dependencies(
+    spec: &GroupSpec,
+    group: &Group,
+    dependencies: &mut Vec<(EventId, Contract)>,
+    child_groups: &mut Vec<Group>,
+) {
+    let find_contract = |contract_id: &ContractId| {
+        group
+            .contract_inputs
+            .iter()
+            .find(|contract| contract.id == *contract_id)
+            .unwrap()
+            .clone()
+    };
+
+    let dependency = match spec {
+        GroupSpec::BalanceAtLeast { contract, .. }
+        | GroupSpec::EarlyReceivers { contract, .. }
+        | GroupSpec::ReceivedFrom { contract, .. } => {
+            let contract = find_contract(contract);
+            (transfer_event_id(&contract).unwrap(), contract)
+        }
+        GroupSpec::EventParticipants {
+            contract, event, ..
+        } => (*event, find_contract(contract)),
+        GroupSpec::Group {
+            group_type,
+            contracts,
+        } => {
+            let child_group = Group::new(
+                format!("{} ({:?})", group.name, group_type),
+                *group_type,
+                contracts.iter().map(find_contract).collect(),
+                0,
+            );
+
+            if !child_groups.iter().any(|child| child.id == child_group.id) {
+                child_groups.push(child_group);
+            }
+            return;
+        }
+        GroupSpec::Union { specs }
+        | GroupSpec::Intersection { specs }
+        | GroupSpec::Difference { specs } => {
+            for spec in specs {
+                collect_dependencies(spec, group, dependencies, child_groups);
+            }
+            return;
+        }
+    };
+
+    if !dependencies.contains(&dependency) {
+        dependencies.push(dependency);
+    }
+}
+
+#[async_trait::async_trait]
+impl GroupIndexer for SpecIndexer {
+    fn group(&self) -> &Group {
+        &self.group
+    }
+
+    fn chain(&self) -> Chain {
+        self.chains()[0]
+    }
+
+    fn chains(&self) -> Vec<Chain> {
+        let mut chains = vec![];
+
+        let dependency_chains = self.dependencies.iter().map(|(_, contract)| contract.chain);
+        let child_chains = self.children.values().flat_map(|child| child.chains());
+
+        for chain in dependency_chains.chain(child_chains) {
+            if !chains.contains(&chain) {
+                chains.push(chain);
+            }
+        }
+
+        chains
+    }
+
+    async fn is_ready(&self) -> Result<bool, Error> {
+        for (event_id, contract) in &self.dependencies {
+            let is_ready = is_event_logs_ready(
+                &self.resources.rocksdb_client,
+                &self.resources.eth_client,
+                *event_id,
+                contract,
+            )
+            .await?;
+
+            if !is_ready {
+                return Ok(false);
+            }
+        }
+
+        for child in self.children.values() {
+            if !child.is_ready().await? {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    async fn get_members(&self, block_number: BlockNum) -> Result<HashSet<Address>, Error> {
+        let chains = self.chains();
+
+        if chains.len() > 1 {
+            // A single block number can't be applied to multiple chains.
+            // Return an error instead of panicking so the sync of the group can report it.
+            return Err(IndexerError::MultiChainGroup(self.group.id.clone()).into());
+        }
+
+        let blocks = chains
+            .into_iter()
+            .map(|chain| (chain, block_number))
+            .collect::<ChainBlocks>();
+
+        self.get_members_at(&blocks).await
+    }
+
+    async fn get_members_at(&self, blocks: &ChainBlocks) -> Result<HashSet<Address>, Error> {
+        let mut child_members = HashMap::new();
+        for (child_id, child) in &self.children {
+            child_members.insert(child_id.clone(), child.get_members_at(blocks).await?);
+        }
+
+        self.evaluate(&self.spec, blocks, &child_members)
+    }
+
+    async fn sanity_check_members(
+        &self,
+        _members: &[Address],
+        _block_number: BlockNum,
+    ) -> Result<bool, Error> {
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        eth_rpc::EthRpcClient,
+        log_sync_engine::LogSyncEngine,
+        postgres::init_postgres,
+        processors::whales::WhaleIndexer,
+        test_utils::{erc20_test_contract, init_test_rocksdb},
+        utils::dotenv_config,
+        GroupType,
+    };
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_spec_indexer() {
+        dotenv_config();
+
+        let db = init_test_rocksdb("test_spec_indexer");
+
+        let pg_client = init_postgres().await;
+
+        let contract = erc20_test_contract();
+
+        // Hardcoded to the latest block number at the time of writing this test,
+        // so we can hardcode other values as well.
+        let to_block = 19473397;
+
+        let eth_client = Arc::new(EthRpcClient::new());
+
+        let contract_sync_engine = LogSyncEngine::new(
+            eth_client.clone(),
+            contract.clone(),
+            ERC20_TRANSFER_EVENT_ID,
+            db.clone(),
+        );
+        contract_sync_engine.sync_to_block(to_block).await;
+
+        let resources = IndexerResources {
+            pg_client,
+            rocksdb_client: db.clone(),
+            eth_client,
+        };
+
+        let holders = GroupSpec::BalanceAtLeast {
+            contract: contract.id,
+            amount: "1".to_string(),
+            block: None,
+        };
+        let early_receivers = GroupSpec::EarlyReceivers {
+            contract: contract.id,
+            percent: 5,
+        };
+        let whales = GroupSpec::Group {
+            group_type: GroupType::Whale,
+            contracts: vec![contract.id],
+        };
+        let spec = GroupSpec::Difference {
+            specs: vec![
+                GroupSpec::Intersection {
+                    specs: vec![holders.clone(), early_receivers.clone()],
+                },
+                whales,
+            ],
+        }
+        .canonicalize()
+        .unwrap();
+
+        let group = Group::new_declarative(
+            "Test group".to_string(),
+            spec.clone(),
+            vec![contract.clone()],
+            0,
+        );
+
+        let indexer = SpecIndexer::new(group, resources.clone()).unwrap();
+        assert_eq!(indexer.chains(), vec![contract.chain]);
+
+        let blocks = ChainBlocks::from([(contract.chain, to_block)]);
+
+        let holders = indexer
+            .evaluate(&holders, &blocks, &HashMap::new())
+            .unwrap();
+        let early_receivers = indexer
+            .evaluate(&early_receivers, &blocks, &HashMap::new())
+            .unwrap();
+
+        // 5% of the receivers
+        assert_eq!(early_receivers.len(), 133);
+
+        // The whales are computed by the indexer of the child group
+        let whale_group = Group::new(
+            "Test whale group".to_string(),
+            GroupType::Whale,
+            vec![contract.clone()],
+            0,
+        );
+        let whales = WhaleIndexer::new(whale_group, resources)
+            .get_members(to_block)
+            .await
+            .unwrap();
+
+        let members = indexer.get_members(to_block).await.unwrap();
+        let expected_members = holders
+            .intersection(&early_receivers)
+            .filter(|member| !whales.contains(*member))
+            .copied()
+            .collect::<HashSet<Address>>();
+        assert_eq!(members, expected_members);
+    }
+}
@@ -1,16 +1,15 @@
 use crate::{
+    balance_state::BalanceState,
     contract::Contract,
-    contract_event_iterator::ContractEventIterator,
     eth_rpc::Chain,
     group::Group,
     processors::{GroupIndexer, IndexerResources},
     rocksdb_key::ERC20_TRANSFER_EVENT_ID,
     seeder::seed_contracts::get_seed_contracts,
-    utils::{decode_erc20_transfer_event, is_event_logs_ready, MINTER_ADDRESS},
-    Address, BlockNum, Error, IndexerError, TxIndex,
+    utils::is_event_logs_ready,
+    Address, BlockNum, Error, TxIndex,
 };
 use num_bigint::BigUint;
-use std::collections::HashMap;
 use std::collections::HashSet;
 
 // The block num and index of transaction: https://basescan.org/tx/0x3122445f0240df9530c8a360fb7631ad5aca4e24503e8856b9aedae05dab830c
@@ -62,57 +61,20 @@ impl GroupIndexer for TickerIndexer {
     }
 
     async fn get_members(&self, block_number: BlockNum) -> Result<HashSet<Address>, Error> {
-        let iterator = ContractEventIterator::new(
-            &self.resources.rocksdb_client,
-            ERC20_TRANSFER_EVENT_ID,
+        // Only process logs up until the specified block number and transaction index
+        let (to_block, to_tx_index) = if block_number >= TO_BLOCK_NUMBER {
+            (TO_BLOCK_NUMBER, Some(TO_TX_INDEX))
+        } else {
+            (block_number, None)
+        };
+
+        let state = BalanceState::new(
+            self.resources.rocksdb_client.clone(),
             self.contract.id,
-            Some(block_number),
+            &self.group.id,
         );
 
-        let mut balances = HashMap::new();
-
-        for (key, value) in iterator {
-            let block_num = key.block_num.unwrap();
-            let tx_index = key.tx_index.unwrap();
-
-            // Only process logs up until the specified block number and transaction index
-            if block_num > TO_BLOCK_NUMBER
-                || (block_num == TO_BLOCK_NUMBER && tx_index >= TO_TX_INDEX)
-            {
-                break;
-            }
-
-            let log = decode_erc20_transfer_event(&value);
-
-            if log.value == BigUint::from(0u8) {
-                continue;
-            }
-
-            if balances.get(&log.to).is_none() {
-                // Initialize the balance of `to` to `value`
-                balances.insert(log.to, log.value.clone());
-            } else {
-                // Increase balance of `to` by `value`
-                let balance = balances.get_mut(&log.to).unwrap();
-                *balance += &log.value;
-            }
-
-            if balances.get(&log.from).is_none() {
-                // Initialize the balance of `from` to 0
-                balances.insert(log.from, BigUint::from(0u8));
-            }
-
-            if log.from != MINTER_ADDRESS {
-                let balance = balances.get(&log.from).unwrap();
-                if balance < &log.value {
-                    return Err(IndexerError::InvalidBalance.into());
-                }
-
-                // Decrease balance of `from` by `value`
-                let balance = balances.get_mut(&log.from).unwrap();
-                *balance -= &log.value;
-            }
-        }
+        let balances = state.get_balances(to_block, to_tx_index, |_| false)?.all();
 
         let holders_at_block = balances
             .iter()
@@ -1,18 +1,16 @@
 use super::GroupIndexer;
 
+use crate::balance_history::BalanceHistory;
+use crate::balance_state::BalanceState;
 use crate::contract::Contract;
-use crate::contract_event_iterator::ContractEventIterator;
 use crate::eth_rpc::Chain;
 use crate::group::Group;
 use crate::log_sync_engine::TRANSFER_EVENT_SIG;
 use crate::processors::IndexerResources;
 use crate::rocksdb_key::ERC20_TRANSFER_EVENT_ID;
-use crate::utils::{
-    decode_erc20_transfer_event, get_balance_at_block, get_total_supply_at_block,
-    is_event_logs_ready, MINTER_ADDRESS,
-};
+use crate::utils::is_event_logs_ready;
+use crate::Error;
 use crate::{Address, BlockNum};
-use crate::{Error, IndexerError};
 use num_bigint::BigUint;
 use serde_json::{json, Value};
 use std::collections::{HashMap, HashSet};
@@ -35,72 +33,29 @@ impl WhaleIndexer {
         &self.group.contract_inputs[0]
     }
 
+    /// Get the addresses that have been whales up to `block_number`,
+    /// along with the balances and the total supply at the block.
+    /// An address is a whale if its balance reached 0.1% of the total supply.
     fn get_whales(
         &self,
         block_number: u64,
     ) -> Result<(HashSet<Address>, HashMap<Address, BigUint>, BigUint), Error> {
-        let iterator = ContractEventIterator::new(
-            &self.resources.rocksdb_client,
-            ERC20_TRANSFER_EVENT_ID,
+        let state = BalanceState::new(
+            self.resources.rocksdb_client.clone(),
             self.contract().id,
-            Some(block_number),
+            &self.group.id,
         );
 
-        let mut balances = HashMap::new();
-        let mut total_supply = BigUint::from(0u32);
-        let mut whale_threshold = BigUint::from(0u32);
-        let mut whales = HashSet::new();
-
-        for (_, value) in iterator {
-            let log = decode_erc20_transfer_event(&value);
-
-            if log.value == BigUint::from(0u8) {
-                continue;
-            }
-
-            if balances.get(&log.to).is_none() {
-                // Initialize the balance of `to` to `value`
-                balances.insert(log.to, log.value.clone());
-            } else {
-                // Increase balance of `to` by `value`
-                let balance = balances.get_mut(&log.to).unwrap();
-                *balance += &log.value;
-
-                if *balance >= whale_threshold {
-                    whales.insert(log.to);
-                }
-            }
-
-            if balances.get(&log.from).is_none() {
-                // Initialize the balance of `from` to 0
-                balances.insert(log.from, BigUint::from(0u8));
-            }
-
-            if log.from == MINTER_ADDRESS {
-                // Increase total supply by `value`
-                total_supply += &log.value;
-
-                whale_threshold = total_supply.clone() / BigUint::from(1000u32);
-            } else {
-                let balance = balances.get(&log.from).unwrap();
-                if balance < &log.value {
-                    return Err(IndexerError::InvalidBalance.into());
-                }
+        let balances = state.get_balances(block_number, None, |transfer| {
+            let whale_threshold = transfer.total_supply / BigUint::from(1000u32);
+            transfer.to_seen && *transfer.to_balance >= whale_threshold
+        })?;
 
-                // Decrease balance of `from` by `value`
-                let balance = balances.get_mut(&log.from).unwrap();
-                *balance -= &log.value;
-            }
-
-            if log.to == MINTER_ADDRESS {
-                // Decrease total supply by `value`
-                total_supply -= &log.value;
-
-                whale_threshold = total_supply.clone() / BigUint::from(1000u32);
-            };
-        }
-
-        Ok((whales, balances, total_supply))
+        Ok((
+            balances.marked(),
+            balances.all(),
+            balances.total_supply().clone(),
+        ))
     }
 }
 
@@ -131,20 +86,23 @@ impl GroupIndexer for WhaleIndexer {
 
     async fn sanity_check_members(
         &self,
-        _members: &[Address],
-        _block_number: BlockNum,
+        members: &[Address],
+        block_number: BlockNum,
     ) -> Result<bool, Error> {
-        /*
+        // The balances and the total supply are read from the synced logs
+        // instead of archive `eth_call`s
+        let history =
+            BalanceHistory::new(self.resources.rocksdb_client.clone(), self.contract().id);
+
         let contract = self.contract();
         for member in members {
-            let member = hex::encode(member);
             // Get logs where the balance of the address increases
             let params = json!({
                 "address": contract.address.clone(),
                 "topics": [
                     TRANSFER_EVENT_SIG,
                     Value::Null,
-                    format!("0x{:0>width$}", member, width = 64),
+                    format!("0x{:0>width$}", hex::encode(member), width = 64),
                 ],
                 "fromBlock": "earliest",
                 "toBlock": format!("0x{:x}", block_number),
@@ -154,41 +112,25 @@ impl GroupIndexer for WhaleIndexer {
                 .resources
                 .eth_client
                 .get_logs(contract.chain, &params)
-                .await
-                .unwrap();
-
-            let block_nums = result["result"]
-                .as_array()
-                .unwrap()
-                .iter()
-                .map(|log| {
-                    let block_num = log["blockNumber"].as_str().unwrap();
-                    u64::from_str_radix(block_num.trim_start_matches("0x"), 16).unwrap()
-                })
-                .collect::<Vec<BlockNum>>();
-
-            // Make sure that the address has received a transfer before
-
-            let mut is_whale = false;
+                .await?;
+
+            let block_nums = result["result"].as_array().map_or(vec![], |logs| {
+                logs.iter()
+                    .filter_map(|log| log["blockNumber"].as_str())
+                    .filter_map(|block_num| {
+                        u64::from_str_radix(block_num.trim_start_matches("0x"), 16).ok()
+                    })
+                    .collect::<Vec<BlockNum>>()
+            });
 
             // Check the balance of the address at each block
             // where the balance increased
+            let mut is_whale = false;
             for block_num in &block_nums {
-                // Get the balance of the address at the block
-                let balance = get_balance_at_block(
-                    &self.resources.eth_client,
-                    &contract,
-                    &member,
-                    block_num + 1,
-                )
-                .await;
-
-                // Get the total supply at the block
-                let total_supply =
-                    get_total_supply_at_block(&self.resources.eth_client, &contract, block_num + 1)
-                        .await;
-
-                let whale_threshold = total_supply.clone() / BigUint::from(1000u32);
+                let balance = history.balance_at(member, *block_num)?;
+                let total_supply = history.total_supply_at(*block_num)?;
+
+                let whale_threshold = total_supply / BigUint::from(1000u32);
 
                 if balance >= whale_threshold {
                     // We found a block where the address had a balance greater than 0.1% of the total supply
@@ -197,11 +139,10 @@ impl GroupIndexer for WhaleIndexer {
                 }
             }
 
-            if !is_whale || block_nums.is_empty() {
+            if !is_whale {
                 return Ok(false);
             }
         }
-         */
 
         Ok(true)
     }
@@ -5,6 +5,10 @@ pub const ERC721_TRANSFER_EVENT_ID: EventId = 2;
 pub const ERC1155_TRANSFER_SINGLE_EVENT_ID: EventId = 3;
 pub const ERC1155_TRANSFER_BATCH_EVENT_ID: EventId = 4;
 
+/// Event ids below this are reserved for the built-in events above.
+/// Events registered by their ABI signature (see `event_registry`) use ids from here.
+pub const MIN_REGISTERED_EVENT_ID: EventId = 100;
+
 /// Type of a RocksDB key
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum KeyType {
@@ -14,6 +18,39 @@ pub enum KeyType {
     SyncLog = 2,
     /// Key for block number to timestamp mapping
     BlockTimestamp = 3,
+    /// Prefix of the address to group ids mapping (see `address_groups`)
+    AddressGroupIds = 4,
+    /// Key for the hashes of the recently synched blocks, used to detect reorgs.
+    BlockHash = 5,
+    /// Prefix of the balance states (see `balance_state`)
+    BalanceState = 6,
+    /// Prefix of the balance histories (see `balance_history`)
+    BalanceHistory = 7,
+    /// Prefix of the tree stores (see `tree_store`)
+    TreeStore = 8,
+    /// Prefix of the Safe owners (see `safe_owners`)
+    SafeOwners = 9,
+    /// Prefix of the delegate registry states (see `delegate_registries`)
+    DelegateRegistryState = 10,
+}
+
+impl KeyType {
+    /// Returns the key type of the first byte of a key
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            1 => Some(KeyType::EventLog),
+            2 => Some(KeyType::SyncLog),
+            3 => Some(KeyType::BlockTimestamp),
+            4 => Some(KeyType::AddressGroupIds),
+            5 => Some(KeyType::BlockHash),
+            6 => Some(KeyType::BalanceState),
+            7 => Some(KeyType::BalanceHistory),
+            8 => Some(KeyType::TreeStore),
+            9 => Some(KeyType::SafeOwners),
+            10 => Some(KeyType::DelegateRegistryState),
+            _ => None,
+        }
+    }
 }
 
 // The RocksDB keys are the concatenation of the following fields:
@@ -96,6 +133,16 @@ impl RocksDbKey {
                 tx_index: None,
                 chain_id: None,
             },
+            KeyType::BlockHash => Self {
+                key_type,
+                event_id: Some(event_id),
+                contract_id: Some(contract_id),
+                chunk_num: None,
+                block_num: Some(0),
+                log_index: None,
+                tx_index: None,
+                chain_id: None,
+            },
             _ => panic!("Invalid key type"),
         }
     }
@@ -123,12 +170,12 @@ impl RocksDbKey {
             panic!("Block number is required for event log key");
         }
 
+        if self.key_type == KeyType::BlockHash && self.block_num.is_none() {
+            panic!("Block number is required for block hash key");
+        }
+
         let mut key = vec![];
-        let key_type_num = match self.key_type {
-            KeyType::EventLog => 1u8,
-            KeyType::SyncLog => 2u8,
-            KeyType::BlockTimestamp => 3u8,
-        };
+        let key_type_num = self.key_type as u8;
 
         match self.key_type {
             KeyType::EventLog => {
@@ -152,6 +199,13 @@ impl RocksDbKey {
                 key.extend_from_slice(&self.chain_id.unwrap().to_be_bytes());
                 key.extend_from_slice(&self.block_num.unwrap().to_be_bytes());
             }
+            KeyType::BlockHash => {
+                key.extend_from_slice(&key_type_num.to_be_bytes());
+                key.extend_from_slice(&self.event_id.unwrap().to_be_bytes());
+                key.extend_from_slice(&self.contract_id.unwrap().to_be_bytes());
+                key.extend_from_slice(&self.block_num.unwrap().to_be_bytes());
+            }
+            _ => panic!("Invalid key type"),
         }
 
         key.try_into().unwrap()
@@ -159,12 +213,7 @@ impl RocksDbKey {
 
     /// Convert a byte array to a RocksDB key
     pub fn from_bytes(key: &[u8]) -> Self {
-        let key_type = match key[KEY_TYPE_BEGIN] {
-            1 => KeyType::EventLog,
-            2 => KeyType::SyncLog,
-            3 => KeyType::BlockTimestamp,
-            _ => panic!("Invalid key type"),
-        };
+        let key_type = KeyType::from_byte(key[KEY_TYPE_BEGIN]).expect("Invalid key type");
 
         match key_type {
             KeyType::EventLog => {
@@ -250,6 +299,31 @@ impl RocksDbKey {
                     chain_id: Some(chain_id),
                 }
             }
+            KeyType::BlockHash => {
+                let mut event_id_bytes = [0; EVENT_ID_BYTES];
+                event_id_bytes.copy_from_slice(&key[EVENT_ID_BEGIN..EVENT_ID_END]);
+                let event_id = EventId::from_be_bytes(event_id_bytes);
+
+                let mut contract_id_bytes = [0; CONTRACT_ID_BYTES];
+                contract_id_bytes.copy_from_slice(&key[CONTRACT_ID_BEGIN..CONTRACT_ID_END]);
+                let contract_id = ContractId::from_be_bytes(contract_id_bytes);
+
+                let mut block_num_bytes = [0; BLOCK_NUM_BYTES];
+                block_num_bytes.copy_from_slice(&key[BLOCK_NUM_BEGIN..BLOCK_NUM_END]);
+                let block_num = BlockNum::from_be_bytes(block_num_bytes);
+
+                Self {
+                    key_type,
+                    event_id: Some(event_id),
+                    contract_id: Some(contract_id),
+                    chunk_num: None,
+                    block_num: Some(block_num),
+                    log_index: None,
+                    tx_index: None,
+                    chain_id: None,
+                }
+            }
+            _ => panic!("Invalid key type"),
         }
     }
 }
@@ -0,0 +1,121 @@
+use std::time::{Duration, Instant};
+
+/// Number of consecutive failures after which an endpoint is ejected
+pub const CIRCUIT_BREAKER_THRESHOLD: u32 = 5;
+
+/// How long an ejected endpoint is kept out of the rotation
+pub const CIRCUIT_BREAKER_COOLDOWN: Duration = Duration::from_secs(30);
+
+/// Weight of the latest sample in the latency moving average
+const LATENCY_EWMA_ALPHA: f64 = 0.2;
+
+/// The result of a request sent to an endpoint
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RequestOutcome {
+    Success(Duration),
+    /// The endpoint responded with HTTP 429 or a JSON-RPC error with code 429
+    RateLimited,
+    /// The request failed (e.g. connection error, timeout or invalid response)
+    Failure,
+}
+
+/// Health statistics of an endpoint
+#[derive(Debug, Clone, Default)]
+pub struct EndpointHealth {
+    pub requests: u64,
+    pub errors: u64,
+    pub rate_limited: u64,
+    /// Exponential moving average of the latency of successful requests
+    pub latency_ms: Option<f64>,
+    pub consecutive_failures: u32,
+    /// The endpoint is skipped by the load balancer until this time
+    pub ejected_until: Option<Instant>,
+}
+
+impl EndpointHealth {
+    /// Record the outcome of a request.
+    /// Returns true if the endpoint got ejected by this outcome.
+    pub fn record(&mut self, outcome: RequestOutcome, now: Instant) -> bool {
+        self.requests += 1;
+
+        match outcome {
+            RequestOutcome::Success(latency) => {
+                let latency_ms = latency.as_secs_f64() * 1000.0;
+                self.latency_ms = Some(match self.latency_ms {
+                    Some(avg) => avg + LATENCY_EWMA_ALPHA * (latency_ms - avg),
+                    None => latency_ms,
+                });
+                self.consecutive_failures = 0;
+                self.ejected_until = None;
+                false
+            }
+            RequestOutcome::RateLimited | RequestOutcome::Failure => {
+                if outcome == RequestOutcome::RateLimited {
+                    self.rate_limited += 1;
+                } else {
+                    self.errors += 1;
+                }
+
+                self.consecutive_failures += 1;
+
+                // Once the threshold is reached, a single failure during the
+                // trial after the cool-down ejects the endpoint again.
+                if self.consecutive_failures >= CIRCUIT_BREAKER_THRESHOLD && !self.is_ejected(now) {
+                    self.ejected_until = Some(now + CIRCUIT_BREAKER_COOLDOWN);
+                    true
+                } else {
+                    false
+                }
+            }
+        }
+    }
+
+    /// Returns true if the endpoint is in its cool-down
+    pub fn is_ejected(&self, now: Instant) -> bool {
+        self.ejected_until.map_or(false, |until| until > now)
+    }
+
+    /// Share of the requests that failed or were rate limited
+    pub fn error_rate(&self) -> f64 {
+        if self.requests == 0 {
+            0.0
+        } else {
+            (self.errors + self.rate_limited) as f64 / self.requests as f64
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_circuit_breaker() {
+        let mut health = EndpointHealth::default();
+        let now = Instant::now();
+
+        for _ in 0..CIRCUIT_BREAKER_THRESHOLD - 1 {
+            assert!(!health.record(RequestOutcome::Failure, now));
+        }
+        assert!(!health.is_ejected(now));
+
+        assert!(health.record(RequestOutcome::RateLimited, now));
+        assert!(health.is_ejected(now));
+        assert!(!health.is_ejected(now + CIRCUIT_BREAKER_COOLDOWN));
+
+        // A failure after the cool-down ejects the endpoint again
+        let after_cooldown = now + CIRCUIT_BREAKER_COOLDOWN;
+        assert!(health.record(RequestOutcome::Failure, after_cooldown));
+        assert!(health.is_ejected(after_cooldown));
+
+        // A success closes the circuit
+        health.record(RequestOutcome::Success(Duration::from_millis(100)), now);
+        assert!(!health.is_ejected(now));
+        assert_eq!(health.consecutive_failures, 0);
+
+        assert_eq!(health.requests, CIRCUIT_BREAKER_THRESHOLD as u64 + 2);
+        assert_eq!(health.errors, CIRCUIT_BREAKER_THRESHOLD as u64);
+        assert_eq!(health.rate_limited, 1);
+        assert_eq!(health.latency_ms, Some(100.0));
+    }
+}
@@ -0,0 +1,237 @@
+use crate::eth_rpc::Chain;
+use crate::{Error, IndexerError};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::str::FromStr;
+
+const NUM_MAINNET_NODES: u32 = 10;
+
+/// Default maximum number of requests to send in a single JSON-RPC batch
+pub const DEFAULT_MAX_BATCH_SIZE: usize = 1000;
+
+fn default_weight() -> u32 {
+    1
+}
+
+fn default_max_batch_size() -> usize {
+    DEFAULT_MAX_BATCH_SIZE
+}
+
+/// An Ethereum JSON-RPC endpoint and the limits that apply to it
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcEndpoint {
+    pub url: String,
+    /// Relative share of the requests this endpoint receives
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+    /// Maximum number of requests per second. `None` means unlimited.
+    #[serde(default)]
+    pub rate_limit: Option<u32>,
+    /// Maximum number of requests in a single JSON-RPC batch
+    #[serde(default = "default_max_batch_size")]
+    pub max_batch_size: usize,
+}
+
+impl RpcEndpoint {
+    pub fn new(url: String) -> Self {
+        Self {
+            url,
+            weight: default_weight(),
+            rate_limit: None,
+            max_batch_size: default_max_batch_size(),
+        }
+    }
+}
+
+/// A source of RPC endpoints for each chain
+pub trait RpcProvider: Send + Sync {
+    /// Returns the endpoints to distribute the requests for `chain` across
+    fn endpoints(&self, chain: Chain) -> Vec<RpcEndpoint>;
+}
+
+/// Provides Alchemy endpoints from the `ALCHEMY_*` environment variables
+/// and the Blast endpoint from `BLAST_RPC_URL`.
+pub struct AlchemyProvider;
+
+impl RpcProvider for AlchemyProvider {
+    fn endpoints(&self, chain: Chain) -> Vec<RpcEndpoint> {
+        if chain == Chain::Blast {
+            return env::var("BLAST_RPC_URL")
+                .map(|url| vec![RpcEndpoint::new(url)])
+                .unwrap_or_default();
+        }
+
+        let api_keys = match chain {
+            Chain::Mainnet => (0..NUM_MAINNET_NODES)
+                .filter_map(|i| env::var(format!("ALCHEMY_API_KEY_{}", i)).ok())
+                .collect(),
+            Chain::Optimism => env::var("ALCHEMY_OPT_API_KEY").into_iter().collect(),
+            Chain::Base => env::var("ALCHEMY_BASE_API_KEY").into_iter().collect(),
+            Chain::Arbitrum => env::var("ALCHEMY_ARB_API_KEY").into_iter().collect(),
+            Chain::Blast => vec![],
+        };
+
+        let subdomain = match chain {
+            Chain::Mainnet => "eth-mainnet",
+            Chain::Optimism => "opt-mainnet",
+            Chain::Base => "base-mainnet",
+            Chain::Arbitrum => "arb-mainnet",
+            Chain::Blast => "",
+        };
+
+        api_keys
+            .iter()
+            .map(|api_key| {
+                RpcEndpoint::new(format!(
+                    "https://{}.g.alchemy.com/v2/{}",
+                    subdomain, api_key
+                ))
+            })
+            .collect()
+    }
+}
+
+/// Provides a fixed list of endpoints per chain.
+///
+/// The list can be loaded from a JSON file of the form
+/// `{ "mainnet": [{ "url": "http://localhost:8545", "weight": 1, "rateLimit": 25, "maxBatchSize": 100 }] }`.
+/// `${VAR}` in a URL is replaced with the value of the environment variable `VAR`,
+/// so API keys don't have to be written to the file.
+pub struct StaticRpcProvider {
+    endpoints: HashMap<Chain, Vec<RpcEndpoint>>,
+}
+
+impl StaticRpcProvider {
+    pub fn new(endpoints: HashMap<Chain, Vec<RpcEndpoint>>) -> Self {
+        Self { endpoints }
+    }
+
+    /// Parse the endpoint configuration from a JSON string
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        let config: HashMap<String, Vec<RpcEndpoint>> =
+            serde_json::from_str(json).map_err(|e| e.to_string())?;
+
+        let mut endpoints = HashMap::new();
+        for (chain, chain_endpoints) in config {
+            let chain = Chain::from_str(&chain)?;
+
+            let chain_endpoints = chain_endpoints
+                .into_iter()
+                .map(|endpoint| {
+                    Ok(RpcEndpoint {
+                        url: expand_env_vars(&endpoint.url)?,
+                        ..endpoint
+                    })
+                })
+                .collect::<Result<Vec<RpcEndpoint>, String>>()?;
+
+            endpoints.insert(chain, chain_endpoints);
+        }
+
+        Ok(Self::new(endpoints))
+    }
+
+    /// Load the endpoint configuration from a JSON file
+    pub fn from_file(path: &str) -> Result<Self, String> {
+        let json = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        Self::from_json(&json)
+    }
+}
+
+impl RpcProvider for StaticRpcProvider {
+    fn endpoints(&self, chain: Chain) -> Vec<RpcEndpoint> {
+        self.endpoints.get(&chain).cloned().unwrap_or_default()
+    }
+}
+
+/// Return the provider configured by the environment.
+/// Reads the endpoints from the file at `RPC_CONFIG_PATH` if it is set,
+/// otherwise falls back to the Alchemy endpoints.
+pub fn provider_from_env() -> Result<Box<dyn RpcProvider>, Error> {
+    provider_from_config_path(env::var("RPC_CONFIG_PATH").ok().as_deref())
+}
+
+fn provider_from_config_path(path: Option<&str>) -> Result<Box<dyn RpcProvider>, Error> {
+    match path {
+        Some(path) => {
+            let provider = StaticRpcProvider::from_file(path).map_err(|err| {
+                IndexerError::InvalidConfig(format!("Invalid RPC_CONFIG_PATH {}", err))
+            })?;
+
+            Ok(Box::new(provider))
+        }
+        None => Ok(Box::new(AlchemyProvider)),
+    }
+}
+
+/// Replace `${VAR}` with the value of the environment variable `VAR`
+fn expand_env_vars(value: &str) -> Result<String, String> {
+    let mut expanded = String::new();
+    let mut rest = value;
+
+    while let Some(start) = rest.find("${") {
+        let end = rest[start..]
+            .find('}')
+            .ok_or(format!("Unterminated variable in {}", value))?;
+
+        let var_name = &rest[start + 2..start + end];
+        let var_value = env::var(var_name).map_err(|_| format!("{} is not set", var_name))?;
+
+        expanded.push_str(&rest[..start]);
+        expanded.push_str(&var_value);
+        rest = &rest[start + end + 1..];
+    }
+
+    expanded.push_str(rest);
+
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_static_provider_from_json() {
+        env::set_var("TEST_RPC_PROVIDER_KEY", "secret");
+
+        let provider = StaticRpcProvider::from_json(
+            r#"{
+                "mainnet": [
+                    { "url": "http://localhost:8545" },
+                    { "url": "https://node.example/${TEST_RPC_PROVIDER_KEY}", "weight": 3, "rateLimit": 25, "maxBatchSize": 100 }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        let endpoints = provider.endpoints(Chain::Mainnet);
+        assert_eq!(endpoints.len(), 2);
+
+        assert_eq!(
+            endpoints[0],
+            RpcEndpoint::new("http://localhost:8545".to_string())
+        );
+
+        assert_eq!(endpoints[1].url, "https://node.example/secret");
+        assert_eq!(endpoints[1].weight, 3);
+        assert_eq!(endpoints[1].rate_limit, Some(25));
+        assert_eq!(endpoints[1].max_batch_size, 100);
+
+        // Chains that aren't configured have no endpoints
+        assert!(provider.endpoints(Chain::Base).is_empty());
+    }
+
+    #[test]
+    fn test_provider_from_invalid_config_path() {
+        let result = provider_from_config_path(Some("./does_not_exist.json"));
+
+        assert!(matches!(
+            result,
+            Err(Error::Indexer(IndexerError::InvalidConfig(_)))
+        ));
+    }
+}
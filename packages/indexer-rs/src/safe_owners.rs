@@ -0,0 +1,717 @@
+use crate::{
+    eth_rpc::{Chain, EthRpcClient},
+    log_sync_engine::{CHUNK_SIZE, REORG_WINDOW},
+    rocksdb_key::KeyType,
+    utils::get_chain_id,
+    Address, BlockNum, Error, EthRpcError, LogIndex,
+};
+use log::info;
+use rocksdb::{Direction, IteratorMode, WriteBatch, DB};
+use serde_json::{json, Value};
+use std::{
+    cmp::min,
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::Arc,
+};
+
+/// `AddedOwner(address owner)` of Safe.
+/// The owner is indexed from Safe v1.4.0, and in the data before that.
+pub const ADDED_OWNER_SIG: &str =
+    "0x9465fa0c962cc76958e6373a993326400c1c94f8be2fe3a952adfa7f60b2ea26";
+
+/// `RemovedOwner(address owner)` of Safe
+pub const REMOVED_OWNER_SIG: &str =
+    "0xf8d49fc529812e9a7c5c50e69c20f0dccc0db8fa95c98bc58cc9a4f1c1299eaf";
+
+/// Selector of `getOwners()` of Safe
+const GET_OWNERS_SELECTOR: &str = "a0e67e2b";
+
+/// Multicall3, which is deployed at the same address on all the chains
+const MULTICALL3_ADDRESS: &str = "0xcA11bde05977b3631167028862bE2a173976CA11";
+
+/// Selector of `aggregate3((address,bool,bytes)[])` of Multicall3
+const AGGREGATE3_SELECTOR: &str = "82ad56cb";
+
+/// Maximum number of wallets to read the owners of in a single `eth_call` to Multicall3
+const WALLETS_PER_MULTICALL: usize = 500;
+
+/// Number of blocks after which a wallet that wasn't a Safe is checked again,
+/// since a Safe can be deployed to a counterfactual address after it's first seen
+pub const NOT_SAFE_RECHECK_BLOCKS: BlockNum = 7200;
+
+/// Number of blocks the persisted owner sets lag behind the block the owners are requested at.
+/// The logs of the recent blocks can still be rolled back by a reorg,
+/// so they're replayed on top of the persisted owner sets in memory instead.
+pub const SAFE_OWNERS_LAG: BlockNum = REORG_WINDOW;
+
+/// Maximum number of wallets to get the logs of in a single request
+const WALLETS_PER_REQUEST: usize = 100;
+
+// The keys of the Safe owners are the concatenation of the following fields:
+// [KeyType::SafeOwners (1 byte)][chain id (2 bytes)][record (1 byte)][wallet (20 bytes)][owner (20 bytes)]
+// The wallet record doesn't have the owner,
+// and its value is [is Safe (1 byte)][block number (8 bytes)].
+// The wallets recorded as not Safes before the block was added only have the first byte.
+
+/// Type of a record of the Safe owners
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Record {
+    /// Whether the wallet is a Safe, and the block its owners are persisted
+    /// or it was found not to be a Safe at
+    Wallet = 0,
+    /// An owner of a Safe
+    Owner = 1,
+}
+
+/// What's known about a wallet
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum WalletState {
+    /// The wallet wasn't a Safe at a block
+    NotSafe { block_num: BlockNum },
+    /// The owners of a Safe at a block
+    Safe {
+        block_num: BlockNum,
+        owners: HashSet<Address>,
+    },
+}
+
+/// An owner added to or removed from a Safe
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct OwnerChange {
+    block_num: BlockNum,
+    log_index: LogIndex,
+    wallet: Address,
+    owner: Address,
+    is_added: bool,
+}
+
+fn parse_hex_u64(value: &Value) -> Option<u64> {
+    u64::from_str_radix(value.as_str()?.trim_start_matches("0x"), 16).ok()
+}
+
+fn parse_address(bytes: &[u8]) -> Option<Address> {
+    if bytes.len() != 32 || bytes[..12].iter().any(|byte| *byte != 0) {
+        return None;
+    }
+
+    bytes[12..].try_into().ok()
+}
+
+/// Decode an `AddedOwner` or `RemovedOwner` log.
+/// Returns `None` if the log isn't a Safe owner change.
+fn decode_owner_change(log: &Value) -> Option<OwnerChange> {
+    let topics = log["topics"]
+        .as_array()?
+        .iter()
+        .map(|topic| topic.as_str())
+        .collect::<Option<Vec<&str>>>()?;
+
+    let is_added = match topics.first().copied() {
+        Some(ADDED_OWNER_SIG) => true,
+        Some(REMOVED_OWNER_SIG) => false,
+        _ => return None,
+    };
+
+    let owner = match topics.get(1) {
+        Some(topic) => hex::decode(topic.trim_start_matches("0x")).ok()?,
+        None => hex::decode(log["data"].as_str()?.trim_start_matches("0x")).ok()?,
+    };
+
+    let wallet = hex::decode(log["address"].as_str()?.trim_start_matches("0x")).ok()?;
+
+    Some(OwnerChange {
+        block_num: parse_hex_u64(&log["blockNumber"])?,
+        log_index: parse_hex_u64(&log["logIndex"])? as LogIndex,
+        wallet: wallet.try_into().ok()?,
+        owner: parse_address(owner.get(..32)?)?,
+        is_added,
+    })
+}
+
+/// Encode the arguments of `aggregate3` of Multicall3 to call `call_data` on each target,
+/// allowing the calls to fail
+fn encode_aggregate3(targets: &[Address], call_data: &[u8]) -> Vec<u8> {
+    let word = |value: usize| format!("{:064x}", value);
+
+    // The calls are dynamic tuples of the target, `allowFailure` and the offset, length and data of `callData`
+    let padded_len = call_data.len().div_ceil(32) * 32;
+    let call_len = 32 * 4 + padded_len;
+
+    let mut encoded = word(32) + &word(targets.len());
+
+    // The offsets of the calls from the start of the offsets
+    for i in 0..targets.len() {
+        encoded += &word(32 * targets.len() + i * call_len);
+    }
+
+    let mut padded_call_data = call_data.to_vec();
+    padded_call_data.resize(padded_len, 0);
+
+    for target in targets {
+        encoded += &format!("{:0>64}", hex::encode(target));
+        encoded += &word(1);
+        encoded += &word(32 * 3);
+        encoded += &word(call_data.len());
+        encoded += &hex::encode(&padded_call_data);
+    }
+
+    hex::decode(encoded).unwrap()
+}
+
+/// Decode the return data of `aggregate3`, which is the `(bool success, bytes returnData)` of each call.
+/// Returns the return data of the successful calls, and `None` for the failed calls,
+/// or `None` if the data isn't the results of `num_calls` calls.
+fn decode_aggregate3(data: &[u8], num_calls: usize) -> Option<Vec<Option<Vec<u8>>>> {
+    let word = |offset: usize| -> Option<usize> {
+        let word = data.get(offset..offset.checked_add(32)?)?;
+        if word[..24].iter().any(|byte| *byte != 0) {
+            return None;
+        }
+
+        usize::try_from(u64::from_be_bytes(word[24..].try_into().unwrap())).ok()
+    };
+
+    let array = word(0)?;
+    if word(array)? != num_calls {
+        return None;
+    }
+
+    let offsets = array + 32;
+
+    (0..num_calls)
+        .map(|i| {
+            let result = offsets.checked_add(word(offsets + i * 32)?)?;
+            let success = word(result)? == 1;
+
+            let return_data = result.checked_add(word(result + 32)?)?;
+            let len = word(return_data)?;
+            let return_data = data.get(return_data + 32..(return_data + 32).checked_add(len)?)?;
+
+            Some(success.then(|| return_data.to_vec()))
+        })
+        .collect()
+}
+
+/// Decode the return data of `getOwners()`.
+/// Returns `None` if the data isn't a non-empty array of addresses, i.e. the wallet isn't a Safe.
+fn decode_owners(data: &[u8]) -> Option<HashSet<Address>> {
+    // The array is the only return value, so its offset is 32
+    if data.len() < 64 || data[..31].iter().any(|byte| *byte != 0) || data[31] != 32 {
+        return None;
+    }
+
+    if data[32..56].iter().any(|byte| *byte != 0) {
+        return None;
+    }
+
+    let num_owners = u64::from_be_bytes(data[56..64].try_into().unwrap()) as usize;
+
+    if num_owners == 0 {
+        return None;
+    }
+
+    if data.len() != 64 + num_owners * 32 {
+        return None;
+    }
+
+    data[64..].chunks(32).map(parse_address).collect()
+}
+
+/// Returns the wallet each owner is added to a group on behalf of.
+/// Members aren't added on behalf of a wallet,
+/// and the owners of multiple wallets are added on behalf of the lowest wallet address.
+pub fn to_delegations(
+    owners: &HashMap<Address, HashSet<Address>>,
+    members: &HashSet<Address>,
+) -> HashMap<Address, Address> {
+    let mut delegations = HashMap::<Address, Address>::new();
+
+    for (wallet, wallet_owners) in owners {
+        for owner in wallet_owners {
+            if members.contains(owner) {
+                continue;
+            }
+
+            delegations
+                .entry(*owner)
+                .and_modify(|delegator| *delegator = min(*delegator, *wallet))
+                .or_insert(*wallet);
+        }
+    }
+
+    delegations
+}
+
+/// The owner sets of the Safes of a chain that are persisted in RocksDB and advanced incrementally.
+/// The owners of a Safe are read with `getOwners()` through Multicall3 when it's first seen,
+/// and then follow its `AddedOwner` and `RemovedOwner` logs.
+/// Addresses that aren't Safes are checked again every `NOT_SAFE_RECHECK_BLOCKS`.
+///
+/// The owner set of each wallet is written along with its block in a single batch,
+/// so groups that share wallets can advance them concurrently.
+pub struct SafeOwners {
+    rocksdb_client: Arc<DB>,
+    eth_client: Arc<EthRpcClient>,
+    chain: Chain,
+}
+
+impl SafeOwners {
+    pub fn new(rocksdb_client: Arc<DB>, eth_client: Arc<EthRpcClient>, chain: Chain) -> Self {
+        Self {
+            rocksdb_client,
+            eth_client,
+            chain,
+        }
+    }
+
+    fn record_prefix(&self, record: Record) -> Vec<u8> {
+        let mut key = vec![KeyType::SafeOwners as u8];
+        key.extend_from_slice(&get_chain_id(self.chain).to_be_bytes());
+        key.push(record as u8);
+        key
+    }
+
+    fn wallet_key(&self, record: Record, wallet: &Address) -> Vec<u8> {
+        let mut key = self.record_prefix(record);
+        key.extend_from_slice(wallet);
+        key
+    }
+
+    fn get_wallet(&self, wallet: &Address) -> Option<WalletState> {
+        let value = self
+            .rocksdb_client
+            .get(self.wallet_key(Record::Wallet, wallet))
+            .unwrap()?;
+
+        // The wallets recorded before the block was added are checked again
+        let block_num = value.get(1..9).map_or(0, |block| {
+            BlockNum::from_be_bytes(block.try_into().unwrap())
+        });
+
+        if value[0] == 0 {
+            return Some(WalletState::NotSafe { block_num });
+        }
+
+        let prefix = self.wallet_key(Record::Owner, wallet);
+        let owners = self
+            .rocksdb_client
+            .iterator(IteratorMode::From(&prefix, Direction::Forward))
+            .map(|item| item.unwrap())
+            .take_while(|(key, _)| key.starts_with(&prefix))
+            .map(|(key, _)| key[prefix.len()..].try_into().unwrap())
+            .collect();
+
+        Some(WalletState::Safe { block_num, owners })
+    }
+
+    fn put_wallet(&self, batch: &mut WriteBatch, wallet: &Address, state: &WalletState) {
+        let owner_prefix = self.wallet_key(Record::Owner, wallet);
+        batch.delete_range(
+            owner_prefix.clone(),
+            [owner_prefix.clone(), vec![u8::MAX; 21]].concat(),
+        );
+
+        match state {
+            WalletState::NotSafe { block_num } => {
+                let value = [[0].as_slice(), &block_num.to_be_bytes()].concat();
+                batch.put(self.wallet_key(Record::Wallet, wallet), value);
+            }
+            WalletState::Safe { block_num, owners } => {
+                let value = [[1].as_slice(), &block_num.to_be_bytes()].concat();
+                batch.put(self.wallet_key(Record::Wallet, wallet), value);
+
+                for owner in owners {
+                    batch.put([owner_prefix.as_slice(), owner].concat(), []);
+                }
+            }
+        }
+    }
+
+    /// Read the owners of the wallets with `getOwners()` at `block_num`.
+    /// The calls are aggregated with Multicall3, so a wallet doesn't take a request of its own.
+    async fn fetch_wallets(
+        &self,
+        wallets: &[Address],
+        block_num: BlockNum,
+    ) -> Result<Vec<WalletState>, Error> {
+        let get_owners = hex::decode(GET_OWNERS_SELECTOR).unwrap();
+        let multicall_args = wallets
+            .chunks(WALLETS_PER_MULTICALL)
+            .map(|wallets| encode_aggregate3(wallets, &get_owners))
+            .collect::<Vec<Vec<u8>>>();
+
+        let mut states = vec![];
+
+        for (wallets, args) in wallets.chunks(WALLETS_PER_MULTICALL).zip(&multicall_args) {
+            let results = self
+                .eth_client
+                .eth_call_batch(
+                    self.chain,
+                    &[MULTICALL3_ADDRESS.to_string()],
+                    AGGREGATE3_SELECTOR,
+                    args,
+                    block_num,
+                )
+                .await?;
+
+            let results = results[0]
+                .as_ref()
+                .and_then(|data| decode_aggregate3(data, wallets.len()))
+                .ok_or(Error::EthRpc(EthRpcError {
+                    message: format!("Failed to read the owners of {} wallets", wallets.len()),
+                }))?;
+
+            states.extend(results.iter().map(|result| {
+                match result.as_ref().and_then(|data| decode_owners(data)) {
+                    Some(owners) => WalletState::Safe { block_num, owners },
+                    None => WalletState::NotSafe { block_num },
+                }
+            }));
+        }
+
+        Ok(states)
+    }
+
+    /// Get the owner changes of the wallets in the blocks from `from_block` to `to_block` (inclusive),
+    /// sorted by the order of the logs
+    async fn fetch_owner_changes(
+        &self,
+        wallets: &[Address],
+        from_block: BlockNum,
+        to_block: BlockNum,
+    ) -> Result<Vec<OwnerChange>, Error> {
+        let mut changes = vec![];
+
+        for wallets in wallets.chunks(WALLETS_PER_REQUEST) {
+            let addresses = wallets
+                .iter()
+                .map(|wallet| format!("0x{}", hex::encode(wallet)))
+                .collect::<Vec<String>>();
+
+            for chunk_from in (from_block..=to_block).step_by(CHUNK_SIZE as usize) {
+                let chunk_to = min(chunk_from + CHUNK_SIZE - 1, to_block);
+
+                let params = json!({
+                    "address": addresses,
+                    "topics": [[ADDED_OWNER_SIG, REMOVED_OWNER_SIG]],
+                    "fromBlock": format!("0x{:x}", chunk_from),
+                    "toBlock": format!("0x{:x}", chunk_to),
+                });
+
+                let result = self.eth_client.get_logs(self.chain, &params).await?;
+
+                let logs = result["result"]
+                    .as_array()
+                    .ok_or(Error::EthRpc(EthRpcError {
+                        message: format!("Failed to get the Safe owner changes: {}", result),
+                    }))?;
+
+                changes.extend(logs.iter().filter_map(decode_owner_change));
+            }
+        }
+
+        changes.sort_by_key(|change| (change.block_num, change.log_index));
+
+        Ok(changes)
+    }
+
+    /// Returns the owners of each Safe among `wallets` at `block_number`.
+    /// The persisted owner sets are advanced to `block_number - SAFE_OWNERS_LAG`.
+    pub async fn get_owners_at(
+        &self,
+        wallets: &[Address],
+        block_number: BlockNum,
+    ) -> Result<HashMap<Address, HashSet<Address>>, Error> {
+        let persist_to = block_number.saturating_sub(SAFE_OWNERS_LAG);
+
+        // The Safes and the blocks their owners are persisted at
+        let mut safes = HashMap::new();
+        let mut unknown_wallets = vec![];
+
+        for wallet in wallets {
+            match self.get_wallet(wallet) {
+                // A Safe may have been deployed to the wallet since it was checked
+                Some(WalletState::NotSafe { block_num })
+                    if block_num + NOT_SAFE_RECHECK_BLOCKS > persist_to => {}
+                Some(WalletState::Safe { block_num, owners }) if block_num <= persist_to => {
+                    safes.insert(*wallet, (block_num, owners));
+                }
+                // The owners of the Safes that are ahead of the requested block
+                // (e.g. the confirmations were changed) are read again
+                _ => unknown_wallets.push(*wallet),
+            }
+        }
+
+        let mut batch = WriteBatch::default();
+
+        // Read the owners of the wallets that haven't been seen or are checked again
+        if !unknown_wallets.is_empty() {
+            let states = self.fetch_wallets(&unknown_wallets, persist_to).await?;
+
+            for (wallet, state) in unknown_wallets.iter().zip(states) {
+                self.put_wallet(&mut batch, wallet, &state);
+
+                if let WalletState::Safe { block_num, owners } = state {
+                    safes.insert(*wallet, (block_num, owners));
+                }
+            }
+
+            info!(
+                "Read the owners of {} wallets on {:?}",
+                unknown_wallets.len(),
+                self.chain
+            );
+        }
+
+        // Advance the persisted owner sets, grouping the Safes persisted at the same block
+        let mut behind = BTreeMap::<BlockNum, Vec<Address>>::new();
+        for (wallet, (block_num, _)) in &safes {
+            if *block_num < persist_to {
+                behind.entry(*block_num).or_default().push(*wallet);
+            }
+        }
+
+        for (block_num, wallets) in behind {
+            let changes = self
+                .fetch_owner_changes(&wallets, block_num + 1, persist_to)
+                .await?;
+
+            apply_owner_changes(&mut safes, &changes);
+
+            for wallet in &wallets {
+                let (_, owners) = safes.remove(wallet).unwrap();
+
+                let state = WalletState::Safe {
+                    block_num: persist_to,
+                    owners: owners.clone(),
+                };
+                self.put_wallet(&mut batch, wallet, &state);
+
+                safes.insert(*wallet, (persist_to, owners));
+            }
+        }
+
+        self.rocksdb_client.write(batch).unwrap();
+
+        // Replay the owner changes of the recent blocks in memory
+        if persist_to < block_number && !safes.is_empty() {
+            let wallets = safes.keys().copied().collect::<Vec<Address>>();
+            let changes = self
+                .fetch_owner_changes(&wallets, persist_to + 1, block_number)
+                .await?;
+
+            apply_owner_changes(&mut safes, &changes);
+        }
+
+        Ok(safes
+            .into_iter()
+            .filter(|(_, (_, owners))| !owners.is_empty())
+            .map(|(wallet, (_, owners))| (wallet, owners))
+            .collect())
+    }
+}
+
+/// Apply the owner changes to the owner sets of the Safes
+fn apply_owner_changes(
+    safes: &mut HashMap<Address, (BlockNum, HashSet<Address>)>,
+    changes: &[OwnerChange],
+) {
+    for change in changes {
+        if let Some((_, owners)) = safes.get_mut(&change.wallet) {
+            if change.is_added {
+                owners.insert(change.owner);
+            } else {
+                owners.remove(&change.owner);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn word(address: &Address) -> String {
+        format!("{:0>64}", hex::encode(address))
+    }
+
+    #[test]
+    fn test_decode_owner_change() {
+        let wallet = [1; 20];
+        let owner = [2; 20];
+
+        // Safe v1.4.0 and later index the owner
+        let log = json!({
+            "address": format!("0x{}", hex::encode(wallet)),
+            "topics": [ADDED_OWNER_SIG, format!("0x{}", word(&owner))],
+            "data": "0x",
+            "blockNumber": "0x10",
+            "logIndex": "0x2",
+        });
+
+        let expected = OwnerChange {
+            block_num: 16,
+            log_index: 2,
+            wallet,
+            owner,
+            is_added: true,
+        };
+
+        assert_eq!(decode_owner_change(&log), Some(expected.clone()));
+
+        // Safe v1.3.0 has the owner in the data
+        let log = json!({
+            "address": format!("0x{}", hex::encode(wallet)),
+            "topics": [REMOVED_OWNER_SIG],
+            "data": format!("0x{}", word(&owner)),
+            "blockNumber": "0x10",
+            "logIndex": "0x2",
+        });
+
+        assert_eq!(
+            decode_owner_change(&log),
+            Some(OwnerChange {
+                is_added: false,
+                ..expected
+            })
+        );
+
+        // Other logs are skipped
+        let log = json!({
+            "address": format!("0x{}", hex::encode(wallet)),
+            "topics": ["0xddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef"],
+            "data": "0x",
+            "blockNumber": "0x10",
+            "logIndex": "0x2",
+        });
+
+        assert_eq!(decode_owner_change(&log), None);
+    }
+
+    #[test]
+    fn test_decode_owners() {
+        let owners = [[1; 20], [2; 20]];
+
+        let data = hex::decode(format!(
+            "{:064x}{:064x}{}{}",
+            32,
+            owners.len(),
+            word(&owners[0]),
+            word(&owners[1])
+        ))
+        .unwrap();
+
+        assert_eq!(decode_owners(&data), Some(HashSet::from(owners)));
+
+        // Calls to addresses without code return nothing
+        assert_eq!(decode_owners(&[]), None);
+
+        // Safes have at least one owner
+        let data = hex::decode(format!("{:064x}{:064x}", 32, 0)).unwrap();
+        assert_eq!(decode_owners(&data), None);
+
+        // The length must match the number of owners
+        assert_eq!(decode_owners(&data[..data.len() - 1]), None);
+    }
+
+    #[test]
+    fn test_encode_aggregate3() {
+        let targets = [[1; 20], [2; 20]];
+        let call_data = hex::decode(GET_OWNERS_SELECTOR).unwrap();
+
+        let call = |target: &Address| {
+            format!(
+                "{}{:064x}{:064x}{:064x}{:0<64}",
+                word(target),
+                1,
+                96,
+                4,
+                GET_OWNERS_SELECTOR
+            )
+        };
+
+        let expected = format!(
+            "{:064x}{:064x}{:064x}{:064x}{}{}",
+            32,
+            2,
+            64,
+            64 + 160,
+            call(&targets[0]),
+            call(&targets[1])
+        );
+
+        assert_eq!(
+            hex::encode(encode_aggregate3(&targets, &call_data)),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_decode_aggregate3() {
+        let return_data = hex::decode(format!("{:064x}{:064x}{}", 32, 1, word(&[1; 20]))).unwrap();
+
+        // A successful call with the owners, and a failed call without return data
+        let data = hex::decode(format!(
+            "{:064x}{:064x}{:064x}{:064x}{:064x}{:064x}{:064x}{}{:064x}{:064x}{:064x}",
+            32,
+            2,
+            64,
+            64 + 32 * 3 + return_data.len(),
+            1,
+            64,
+            return_data.len(),
+            hex::encode(&return_data),
+            0,
+            64,
+            0
+        ))
+        .unwrap();
+
+        assert_eq!(
+            decode_aggregate3(&data, 2),
+            Some(vec![Some(return_data), None])
+        );
+
+        // The number of results must match the number of calls
+        assert_eq!(decode_aggregate3(&data, 3), None);
+
+        // The results must be in the data
+        assert_eq!(decode_aggregate3(&data[..data.len() - 32], 2), None);
+    }
+
+    #[test]
+    fn test_owner_changes() {
+        let wallet = [1; 20];
+        let mut safes = HashMap::from([(wallet, (0, HashSet::from([[2; 20]])))]);
+
+        let change = |owner: Address, is_added| OwnerChange {
+            block_num: 1,
+            log_index: 0,
+            wallet,
+            owner,
+            is_added,
+        };
+
+        apply_owner_changes(&mut safes, &[change([3; 20], true), change([2; 20], false)]);
+
+        assert_eq!(safes[&wallet].1, HashSet::from([[3; 20]]));
+    }
+
+    #[test]
+    fn test_to_delegations() {
+        let owners = HashMap::from([
+            ([1; 20], HashSet::from([[10; 20], [11; 20]])),
+            ([2; 20], HashSet::from([[10; 20], [12; 20]])),
+        ]);
+
+        // A member that owns a wallet doesn't need to be added on its behalf
+        let members = HashSet::from([[1; 20], [2; 20], [12; 20]]);
+
+        let delegations = to_delegations(&owners, &members);
+
+        assert_eq!(
+            delegations,
+            HashMap::from([([10; 20], [1; 20]), ([11; 20], [1; 20])])
+        );
+    }
+}
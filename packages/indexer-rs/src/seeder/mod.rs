@@ -1,3 +1,4 @@
 pub mod assets_with_prices;
 pub mod seed_contracts;
+pub mod seed_events;
 pub mod seed_groups;
@@ -1,4 +1,4 @@
-use crate::{contract::ContractType, eth_rpc::Chain, GroupType};
+use crate::{contract::ContractType, eth_rpc::Chain, EventId, GroupType};
 use serde::{Deserialize, Serialize};
 use std::{
     env,
@@ -18,6 +18,10 @@ pub struct ContractData {
     /// The groups that this contract should be derived from.
     /// This is only used for groups that are derived from a single contract.
     pub derive_groups: Vec<GroupType>,
+
+    /// Ids of the registered events (in seed_events.json) to sync for this contract
+    #[serde(default)]
+    pub events: Vec<EventId>,
 }
 
 /// Get the seed contracts from the seed_contracts.json file
@@ -0,0 +1,33 @@
+use crate::{event_registry::EventDefinition, EventId};
+use serde::{Deserialize, Serialize};
+use std::{env, fs};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EventData {
+    pub id: EventId,
+    /// ABI signature of the event
+    pub signature: String,
+}
+
+/// Get the events to register from the seed_events.json file
+pub fn get_seed_events() -> Vec<EventDefinition> {
+    let manifest_dir = env::var("CARGO_MANIFEST_DIR");
+
+    let events = if manifest_dir.is_err() {
+        fs::read_to_string("./seed_events.json").expect("Unable to open seed_events.json")
+    } else {
+        fs::read_to_string(format!(
+            "{}/src/seeder/seed_events.json",
+            manifest_dir.unwrap()
+        ))
+        .expect("Unable to open seed_events.json")
+    };
+
+    let events: Vec<EventData> =
+        serde_json::from_str(&events).expect("Unable to parse seed_events.json");
+
+    events
+        .iter()
+        .map(|event| EventDefinition::new(event.id, &event.signature).unwrap())
+        .collect()
+}
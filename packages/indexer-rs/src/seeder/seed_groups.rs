@@ -1,6 +1,7 @@
 use crate::{
     contract::Contract,
     group::Group,
+    group_spec::GroupSpec,
     seeder::{
         assets_with_prices::get_assets_with_prices,
         seed_contracts::{get_seed_contracts, ContractData},
@@ -8,6 +9,33 @@ use crate::{
     utils::is_prod,
     GroupType,
 };
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{env, fs};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GroupSpecData {
+    pub name: String,
+    /// The spec of the group. See `GroupSpec` for the format.
+    pub spec: Value,
+}
+
+/// Get the declarative groups from the seed_group_specs.json file
+fn get_seed_group_specs() -> Vec<GroupSpecData> {
+    let manifest_dir = env::var("CARGO_MANIFEST_DIR");
+
+    let group_specs = if manifest_dir.is_err() {
+        fs::read_to_string("./seed_group_specs.json").expect("Unable to open seed_group_specs.json")
+    } else {
+        fs::read_to_string(format!(
+            "{}/src/seeder/seed_group_specs.json",
+            manifest_dir.unwrap()
+        ))
+        .expect("Unable to open seed_group_specs.json")
+    };
+
+    serde_json::from_str(&group_specs).expect("Unable to parse seed_group_specs.json")
+}
 /// Calculate the score for a group
 fn calculate_group_score(group_type: GroupType, contract_inputs: &[&str]) -> i64 {
     let asset_with_prices = get_assets_with_prices();
@@ -34,6 +62,7 @@ fn calculate_group_score(group_type: GroupType, contract_inputs: &[&str]) -> i64
     match group_type {
         GroupType::EarlyHolder => contract.fdv_usd.unwrap().round() as i64,
         GroupType::Whale => contract.fdv_usd.unwrap().round() as i64,
+        GroupType::BalanceHolder => contract.fdv_usd.unwrap().round() as i64,
         GroupType::Believer => contract.fdv_usd.unwrap().round() as i64,
         GroupType::AllHolders => contract.fp_usd.unwrap().round() as i64,
         GroupType::Ticker => contract.fdv_usd.unwrap().round() as i64,
@@ -64,6 +93,13 @@ pub fn get_seed_groups() -> Vec<Group> {
         .cloned()
         .collect::<Vec<ContractData>>();
 
+    // Get all contracts to build the balance holder groups from
+    let balance_holder_contracts = seed_contracts
+        .iter()
+        .filter(|c| c.derive_groups.contains(&GroupType::BalanceHolder))
+        .cloned()
+        .collect::<Vec<ContractData>>();
+
     // Get all contracts to build the all holders groups from
     let all_holders_contracts = seed_contracts
         .iter()
@@ -98,6 +134,16 @@ pub fn get_seed_groups() -> Vec<Group> {
         groups.push(group);
     }
 
+    // Build balance holder groups
+    for contract in balance_holder_contracts.clone() {
+        let name = format!("{} holder", contract.name.clone());
+        let contract = Contract::from_contract_data(contract);
+        let score = calculate_group_score(GroupType::BalanceHolder, &[&contract.address]);
+        let group = Group::new(name, GroupType::BalanceHolder, vec![contract], score);
+
+        groups.push(group);
+    }
+
     // Build All holders groups
     for contract in all_holders_contracts.clone() {
         let contract = Contract::from_contract_data(contract);
@@ -213,5 +259,42 @@ pub fn get_seed_groups() -> Vec<Group> {
         0,
     ));
 
+    // Add declarative groups
+    for group_spec in get_seed_group_specs() {
+        let spec = match GroupSpec::from_json(&group_spec.spec.to_string()) {
+            Ok(spec) => spec,
+            Err(e) => panic!("Invalid spec of group {}: {}", group_spec.name, e),
+        };
+
+        let contract_inputs = spec
+            .contract_ids()
+            .iter()
+            .map(|contract_id| {
+                let contract = seed_contracts
+                    .iter()
+                    .find(|c| c.id == Some(*contract_id))
+                    .unwrap_or_else(|| {
+                        panic!(
+                            "Contract id {} specified in group spec not found in seed_contracts.json",
+                            contract_id
+                        )
+                    });
+
+                Contract::from_contract_data(contract.clone())
+            })
+            .collect::<Vec<Contract>>();
+
+        if let Err(e) = spec.check_contracts(&contract_inputs) {
+            panic!("Invalid spec of group {}: {}", group_spec.name, e);
+        }
+
+        groups.push(Group::new_declarative(
+            group_spec.name,
+            spec,
+            contract_inputs,
+            0,
+        ));
+    }
+
     groups
 }
@@ -0,0 +1,736 @@
+use super::error::{FORBIDDEN_CODE, RATE_LIMITED_CODE, UNAUTHORIZED_CODE};
+use super::rest::{json_response, rest_method, RestError};
+use crate::shutdown::Shutdown;
+use futures::future::{ready, Either, Ready};
+use hyper::{header, Body, Request, Response, StatusCode};
+use jsonrpsee::server::middleware::rpc::RpcServiceT;
+use jsonrpsee::server::MethodResponse;
+use jsonrpsee::types::{ErrorObjectOwned, Request as RpcRequest};
+use log::{error, info};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    future::Future,
+    net::IpAddr,
+    pin::Pin,
+    str::FromStr,
+    sync::{Arc, Mutex, RwLock},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+use tower::{Layer, Service};
+
+/// The methods that are expensive to serve (e.g. return the multi-MB Merkle tree protobufs,
+/// look up many addresses, verify proofs or keep a subscription open),
+/// which can only be called with an API key
+const PROTECTED_METHODS: [&str; 10] = [
+    "getGroupLatestMerkleTree",
+    "getGroupMerkleTree",
+    "getLatestMerkleTrees",
+    "getMerkleProof",
+    "getMembershipAt",
+    "getGroupChanges",
+    "getAddressesGroups",
+    "verifyMembershipProof",
+    "registerNullifier",
+    "subscribe",
+];
+
+const API_KEY_HEADER: &str = "x-api-key";
+
+/// The query parameter of the API key, for the WebSocket clients that can't set headers
+pub const API_KEY_QUERY_PARAM: &str = "api_key";
+
+const FORWARDED_FOR_HEADER: &str = "x-forwarded-for";
+
+/// The calls per minute of each IP address without an API key
+const DEFAULT_ANONYMOUS_RATE_LIMIT_PER_MINUTE: u32 = 60;
+
+/// Interval between loading the API keys and saving the usage counters
+const SYNC_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Returns the hash the API key is stored as
+pub fn hash_api_key(api_key: &str) -> String {
+    hex::encode(Sha256::digest(api_key.as_bytes()))
+}
+
+/// Parse the environment variable `name`, or return `default` if it isn't set
+fn env_var<T: FromStr>(name: &str, default: T) -> T {
+    std::env::var(name).ok().map_or(default, |value| {
+        value
+            .parse()
+            .unwrap_or_else(|_| panic!("Invalid {} {}", name, value))
+    })
+}
+
+/// An API key that isn't revoked
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiKey {
+    pub id: i32,
+    pub name: String,
+    /// The methods the key can call. All methods if empty.
+    pub allowed_methods: Vec<String>,
+    /// The sustained number of calls per minute, which is also the burst size
+    pub rate_limit_per_minute: u32,
+}
+
+impl ApiKey {
+    fn allows(&self, method: &str) -> bool {
+        self.allowed_methods.is_empty() || self.allowed_methods.iter().any(|m| m == method)
+    }
+}
+
+/// The caller of the methods of a request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Caller {
+    pub api_key: Option<String>,
+    /// The IP address the calls without an API key are rate limited by
+    pub ip: IpAddr,
+}
+
+/// A token bucket that holds at most `rate_per_minute` tokens,
+/// refilled at `rate_per_minute` tokens per minute
+#[derive(Debug, Clone)]
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate_per_minute: u32, now: Instant) -> Self {
+        Self {
+            tokens: rate_per_minute as f64,
+            last_refill: now,
+        }
+    }
+
+    /// Take `n` tokens, or return how long to wait until there are enough tokens
+    fn try_take(&mut self, n: u32, rate_per_minute: u32, now: Instant) -> Result<(), Duration> {
+        let rate_per_sec = rate_per_minute as f64 / 60.0;
+
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate_per_sec).min(rate_per_minute as f64);
+        self.last_refill = now;
+
+        if self.tokens >= n as f64 {
+            self.tokens -= n as f64;
+            Ok(())
+        } else if rate_per_sec == 0.0 {
+            Err(SYNC_INTERVAL)
+        } else {
+            Err(Duration::from_secs_f64(
+                (n as f64 - self.tokens) / rate_per_sec,
+            ))
+        }
+    }
+
+    /// Returns true if the bucket has refilled completely,
+    /// in which case it's the same as a new bucket
+    fn is_full(&self, now: Instant) -> bool {
+        now.saturating_duration_since(self.last_refill) >= Duration::from_secs(60)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum AuthError {
+    /// The API key is missing or invalid
+    Unauthorized(String),
+    /// The API key can't call the method
+    Forbidden(String),
+    /// The API key or the IP address exceeded its rate limit
+    RateLimited(Duration),
+}
+
+impl AuthError {
+    fn into_response(self) -> Response<Body> {
+        let (status, message) = match &self {
+            AuthError::Unauthorized(message) => (StatusCode::UNAUTHORIZED, message.clone()),
+            AuthError::Forbidden(message) => (StatusCode::FORBIDDEN, message.clone()),
+            AuthError::RateLimited(_) => (
+                StatusCode::TOO_MANY_REQUESTS,
+                "Rate limit exceeded".to_string(),
+            ),
+        };
+
+        let body = serde_json::to_vec(&RestError { error: message }).unwrap();
+        let mut response = json_response(status, body);
+
+        if let AuthError::RateLimited(retry_after) = self {
+            response.headers_mut().insert(
+                header::RETRY_AFTER,
+                retry_after.as_secs().max(1).to_string().parse().unwrap(),
+            );
+        }
+
+        response
+    }
+}
+
+impl From<AuthError> for ErrorObjectOwned {
+    fn from(err: AuthError) -> Self {
+        match err {
+            AuthError::Unauthorized(message) => {
+                ErrorObjectOwned::owned(UNAUTHORIZED_CODE, message, None::<()>)
+            }
+            AuthError::Forbidden(message) => {
+                ErrorObjectOwned::owned(FORBIDDEN_CODE, message, None::<()>)
+            }
+            AuthError::RateLimited(retry_after) => ErrorObjectOwned::owned(
+                RATE_LIMITED_CODE,
+                "Rate limit exceeded",
+                Some(json!({ "retryAfter": retry_after.as_secs().max(1) })),
+            ),
+        }
+    }
+}
+
+/// The API keys with their rate limits and usage counters.
+/// The keys are loaded from Postgres and the usage is saved to Postgres every `SYNC_INTERVAL`,
+/// so a revoked key stops working within a minute.
+pub struct ApiKeys {
+    /// Require an API key for all methods, instead of only for the `PROTECTED_METHODS`
+    require_api_key: bool,
+    /// The calls per minute of each IP address without an API key
+    anonymous_rate_limit_per_minute: u32,
+    /// The number of proxies in front of the server that append to `X-Forwarded-For`
+    proxy_hops: usize,
+    /// The keys by hash
+    keys: RwLock<HashMap<String, ApiKey>>,
+    /// The rate limit of each key by id
+    buckets: Mutex<HashMap<i32, TokenBucket>>,
+    /// The rate limit of each IP address without an API key
+    ip_buckets: Mutex<HashMap<IpAddr, TokenBucket>>,
+    /// The number of calls of each key and method since the usage was last saved
+    usage: Mutex<HashMap<(i32, String), i64>>,
+}
+
+impl ApiKeys {
+    pub fn new(
+        require_api_key: bool,
+        anonymous_rate_limit_per_minute: u32,
+        proxy_hops: usize,
+    ) -> Self {
+        Self {
+            require_api_key,
+            anonymous_rate_limit_per_minute,
+            proxy_hops,
+            keys: RwLock::new(HashMap::new()),
+            buckets: Mutex::new(HashMap::new()),
+            ip_buckets: Mutex::new(HashMap::new()),
+            usage: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn from_env() -> Self {
+        let require_api_key = env_var("REQUIRE_API_KEY", false);
+        let anonymous_rate_limit_per_minute = env_var(
+            "ANONYMOUS_RATE_LIMIT_PER_MINUTE",
+            DEFAULT_ANONYMOUS_RATE_LIMIT_PER_MINUTE,
+        );
+        let proxy_hops = env_var("PROXY_HOPS", 0);
+
+        if require_api_key {
+            info!("Requiring an API key for all methods");
+        } else {
+            info!(
+                "Requiring an API key for {}, and limiting the other calls to {} per minute per IP address",
+                PROTECTED_METHODS.join(", "),
+                anonymous_rate_limit_per_minute
+            );
+        }
+
+        Self::new(require_api_key, anonymous_rate_limit_per_minute, proxy_hops)
+    }
+
+    /// Load the API keys that aren't revoked from Postgres
+    pub async fn load(
+        &self,
+        pg_client: &tokio_postgres::Client,
+    ) -> Result<(), tokio_postgres::Error> {
+        let rows = pg_client
+            .query(
+                r#"SELECT "id", "name", "keyHash", "allowedMethods", "rateLimitPerMinute" FROM "ApiKey" WHERE "revokedAt" IS NULL"#,
+                &[],
+            )
+            .await?;
+
+        let keys = rows
+            .iter()
+            .map(|row| {
+                let key_hash: String = row.get("keyHash");
+                let allowed_methods: Option<Vec<String>> = row.get("allowedMethods");
+                let rate_limit_per_minute: i32 = row.get("rateLimitPerMinute");
+
+                let api_key = ApiKey {
+                    id: row.get("id"),
+                    name: row.get("name"),
+                    allowed_methods: allowed_methods.unwrap_or_default(),
+                    rate_limit_per_minute: rate_limit_per_minute.max(0) as u32,
+                };
+
+                (key_hash, api_key)
+            })
+            .collect::<HashMap<String, ApiKey>>();
+
+        *self.keys.write().unwrap() = keys;
+
+        Ok(())
+    }
+
+    /// Add the usage counted since the last call to the daily usage in Postgres
+    pub async fn save_usage(
+        &self,
+        pg_client: &tokio_postgres::Client,
+    ) -> Result<(), tokio_postgres::Error> {
+        let usage = std::mem::take(&mut *self.usage.lock().unwrap());
+
+        if usage.is_empty() {
+            return Ok(());
+        }
+
+        let (keys, counts): (Vec<(i32, String)>, Vec<i64>) = usage.into_iter().unzip();
+        let (api_key_ids, methods): (Vec<i32>, Vec<String>) = keys.into_iter().unzip();
+
+        let result = pg_client
+            .execute(
+                r#"
+                INSERT INTO "ApiKeyUsage" ("apiKeyId", "method", "date", "count", "updatedAt")
+                SELECT "apiKeyId", "method", CURRENT_DATE, "count", NOW()
+                FROM UNNEST($1::integer[], $2::text[], $3::bigint[]) AS u("apiKeyId", "method", "count")
+                ON CONFLICT ("apiKeyId", "method", "date")
+                DO UPDATE SET "count" = "ApiKeyUsage"."count" + EXCLUDED."count", "updatedAt" = NOW()
+                "#,
+                &[&api_key_ids, &methods, &counts],
+            )
+            .await;
+
+        if let Err(err) = result {
+            // Keep the usage to save it next time
+            let mut usage = self.usage.lock().unwrap();
+            for ((api_key_id, method), count) in api_key_ids.into_iter().zip(methods).zip(counts) {
+                *usage.entry((api_key_id, method)).or_insert(0) += count;
+            }
+
+            return Err(err);
+        }
+
+        Ok(())
+    }
+
+    /// Drop the rate limits of the IP addresses that haven't called for a minute
+    fn prune_ip_buckets(&self, now: Instant) {
+        self.ip_buckets
+            .lock()
+            .unwrap()
+            .retain(|_, bucket| !bucket.is_full(now));
+    }
+
+    /// Reload the API keys and save the usage every `SYNC_INTERVAL`.
+    /// Once the shutdown is requested, the usage counted since the last sync is saved and it returns.
+    pub async fn sync(self: Arc<Self>, pg_client: Arc<tokio_postgres::Client>, shutdown: Shutdown) {
+        let mut interval = tokio::time::interval(SYNC_INTERVAL);
+
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = shutdown.requested() => break,
+            }
+
+            if let Err(err) = self.load(&pg_client).await {
+                error!("Failed to load the API keys: {:?}", err);
+            }
+
+            if let Err(err) = self.save_usage(&pg_client).await {
+                error!("Failed to save the API key usage: {:?}", err);
+            }
+
+            self.prune_ip_buckets(Instant::now());
+        }
+
+        if let Err(err) = self.save_usage(&pg_client).await {
+            error!("Failed to save the API key usage: {:?}", err);
+        }
+    }
+
+    /// Get the caller of a request from the API key in the `X-Api-Key` header
+    /// or the `api_key` query parameter, and the IP address of the client
+    pub fn caller(&self, req: &Request<Body>, remote_ip: IpAddr) -> Caller {
+        Caller {
+            api_key: get_api_key(req),
+            ip: client_ip(req, remote_ip, self.proxy_hops),
+        }
+    }
+
+    /// Check that the caller can call the method and take the call from its rate limit
+    fn authorize(&self, caller: &Caller, method: &str) -> Result<(), AuthError> {
+        let api_key = match &caller.api_key {
+            Some(api_key) => self
+                .keys
+                .read()
+                .unwrap()
+                .get(&hash_api_key(api_key))
+                .cloned()
+                .ok_or_else(|| AuthError::Unauthorized("Invalid API key".to_string()))?,
+            None => {
+                if self.require_api_key {
+                    return Err(AuthError::Unauthorized(
+                        "An API key is required".to_string(),
+                    ));
+                }
+
+                if PROTECTED_METHODS.contains(&method) {
+                    return Err(AuthError::Unauthorized(format!(
+                        "An API key is required to call {}",
+                        method
+                    )));
+                }
+
+                return self
+                    .ip_buckets
+                    .lock()
+                    .unwrap()
+                    .entry(caller.ip)
+                    .or_insert_with(|| {
+                        TokenBucket::new(self.anonymous_rate_limit_per_minute, Instant::now())
+                    })
+                    .try_take(1, self.anonymous_rate_limit_per_minute, Instant::now())
+                    .map_err(AuthError::RateLimited);
+            }
+        };
+
+        if !api_key.allows(method) {
+            return Err(AuthError::Forbidden(format!(
+                "The API key can't call {}",
+                method
+            )));
+        }
+
+        self.buckets
+            .lock()
+            .unwrap()
+            .entry(api_key.id)
+            .or_insert_with(|| TokenBucket::new(api_key.rate_limit_per_minute, Instant::now()))
+            .try_take(1, api_key.rate_limit_per_minute, Instant::now())
+            .map_err(AuthError::RateLimited)?;
+
+        *self
+            .usage
+            .lock()
+            .unwrap()
+            .entry((api_key.id, method.to_string()))
+            .or_insert(0) += 1;
+
+        Ok(())
+    }
+}
+
+/// Get the API key from the `X-Api-Key` header or the `api_key` query parameter
+fn get_api_key(req: &Request<Body>) -> Option<String> {
+    if let Some(api_key) = req
+        .headers()
+        .get(API_KEY_HEADER)
+        .and_then(|api_key| api_key.to_str().ok())
+    {
+        return Some(api_key.to_string());
+    }
+
+    req.uri().query().and_then(|query| {
+        query.split('&').find_map(|param| {
+            param
+                .strip_prefix(API_KEY_QUERY_PARAM)
+                .and_then(|value| value.strip_prefix('='))
+                .map(|api_key| api_key.to_string())
+        })
+    })
+}
+
+/// Get the IP address of the client.
+/// Each proxy appends the address it received the request from to `X-Forwarded-For`,
+/// so the client's address is the one appended by the outermost of the `proxy_hops` proxies.
+/// The addresses before it are set by the client and can't be trusted.
+fn client_ip(req: &Request<Body>, remote_ip: IpAddr, proxy_hops: usize) -> IpAddr {
+    if proxy_hops == 0 {
+        return remote_ip;
+    }
+
+    req.headers()
+        .get(FORWARDED_FOR_HEADER)
+        .and_then(|forwarded_for| forwarded_for.to_str().ok())
+        .and_then(|forwarded_for| {
+            let addresses = forwarded_for.split(',').collect::<Vec<&str>>();
+
+            addresses
+                .len()
+                .checked_sub(proxy_hops)
+                .and_then(|i| addresses[i].trim().parse().ok())
+        })
+        .unwrap_or(remote_ip)
+}
+
+/// Checks the API key of each JSON-RPC call, including the calls in batch requests
+/// and the calls over WebSocket connections
+#[derive(Clone)]
+pub struct RpcAuthLayer {
+    api_keys: Arc<ApiKeys>,
+    caller: Arc<Caller>,
+}
+
+impl RpcAuthLayer {
+    pub fn new(api_keys: Arc<ApiKeys>, caller: Arc<Caller>) -> Self {
+        Self { api_keys, caller }
+    }
+}
+
+impl<S> Layer<S> for RpcAuthLayer {
+    type Service = RpcAuthService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RpcAuthService {
+            inner,
+            api_keys: self.api_keys.clone(),
+            caller: self.caller.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RpcAuthService<S> {
+    inner: S,
+    api_keys: Arc<ApiKeys>,
+    caller: Arc<Caller>,
+}
+
+impl<'a, S> RpcServiceT<'a> for RpcAuthService<S>
+where
+    S: RpcServiceT<'a>,
+{
+    type Future = Either<S::Future, Ready<MethodResponse>>;
+
+    fn call(&self, request: RpcRequest<'a>) -> Self::Future {
+        match self.api_keys.authorize(&self.caller, request.method_name()) {
+            Ok(()) => Either::Left(self.inner.call(request)),
+            Err(err) => Either::Right(ready(MethodResponse::error(
+                request.id(),
+                ErrorObjectOwned::from(err),
+            ))),
+        }
+    }
+}
+
+/// Checks the API key of the REST requests with the method of the same resource.
+/// The JSON-RPC calls are checked by the `RpcAuthLayer`.
+#[derive(Clone)]
+pub struct RestAuthLayer {
+    api_keys: Arc<ApiKeys>,
+    caller: Arc<Caller>,
+}
+
+impl RestAuthLayer {
+    pub fn new(api_keys: Arc<ApiKeys>, caller: Arc<Caller>) -> Self {
+        Self { api_keys, caller }
+    }
+}
+
+impl<S> Layer<S> for RestAuthLayer {
+    type Service = RestAuthService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RestAuthService {
+            inner,
+            api_keys: self.api_keys.clone(),
+            caller: self.caller.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RestAuthService<S> {
+    inner: S,
+    api_keys: Arc<ApiKeys>,
+    caller: Arc<Caller>,
+}
+
+impl<S> Service<Request<Body>> for RestAuthService<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>>,
+    S::Future: Send + 'static,
+{
+    type Response = Response<Body>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let result = match rest_method(&req) {
+            Some(method) => self.api_keys.authorize(&self.caller, method),
+            None => Ok(()),
+        };
+
+        match result {
+            Ok(()) => Box::pin(self.inner.call(req)),
+            Err(err) => Box::pin(async move { Ok(err.into_response()) }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn api_keys(require_api_key: bool, allowed_methods: Vec<String>) -> ApiKeys {
+        let api_keys = ApiKeys::new(require_api_key, 2, 0);
+
+        api_keys.keys.write().unwrap().insert(
+            hash_api_key("key"),
+            ApiKey {
+                id: 1,
+                name: "test".to_string(),
+                allowed_methods,
+                rate_limit_per_minute: 2,
+            },
+        );
+
+        api_keys
+    }
+
+    fn caller(api_key: Option<&str>, ip: [u8; 4]) -> Caller {
+        Caller {
+            api_key: api_key.map(|api_key| api_key.to_string()),
+            ip: IpAddr::from(ip),
+        }
+    }
+
+    #[test]
+    fn test_token_bucket() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(60, now);
+
+        assert_eq!(bucket.try_take(60, 60, now), Ok(()));
+        assert_eq!(bucket.try_take(1, 60, now), Err(Duration::from_secs(1)));
+
+        // One token is refilled per second
+        let now = now + Duration::from_secs(2);
+        assert_eq!(bucket.try_take(2, 60, now), Ok(()));
+        assert!(bucket.try_take(1, 60, now).is_err());
+        assert!(!bucket.is_full(now));
+
+        // The bucket doesn't fill beyond the rate per minute
+        let now = now + Duration::from_secs(3600);
+        assert!(bucket.is_full(now));
+        assert_eq!(bucket.try_take(60, 60, now), Ok(()));
+        assert!(bucket.try_take(1, 60, now).is_err());
+    }
+
+    #[test]
+    fn test_authorize() {
+        let api_keys = api_keys(false, vec![]);
+        let anonymous = caller(None, [1, 1, 1, 1]);
+
+        // Only the protected methods require an API key
+        assert_eq!(api_keys.authorize(&anonymous, "getGroups"), Ok(()));
+        assert!(PROTECTED_METHODS.contains(&"registerNullifier"));
+        for method in PROTECTED_METHODS {
+            assert!(matches!(
+                api_keys.authorize(&anonymous, method),
+                Err(AuthError::Unauthorized(_))
+            ));
+        }
+        assert!(matches!(
+            api_keys.authorize(&caller(Some("invalid"), [1, 1, 1, 1]), "getGroups"),
+            Err(AuthError::Unauthorized(_))
+        ));
+
+        // The calls without an API key take from the rate limit of the IP address
+        assert_eq!(api_keys.authorize(&anonymous, "getCreddd"), Ok(()));
+        assert!(matches!(
+            api_keys.authorize(&anonymous, "getGroups"),
+            Err(AuthError::RateLimited(_))
+        ));
+        assert_eq!(
+            api_keys.authorize(&caller(None, [2, 2, 2, 2]), "getGroups"),
+            Ok(())
+        );
+
+        // The calls with an API key take from the rate limit of the key
+        let with_key = caller(Some("key"), [1, 1, 1, 1]);
+        assert_eq!(
+            api_keys.authorize(&with_key, "getLatestMerkleTrees"),
+            Ok(())
+        );
+        assert_eq!(
+            api_keys.authorize(&with_key, "getLatestMerkleTrees"),
+            Ok(())
+        );
+        assert!(matches!(
+            api_keys.authorize(&caller(Some("key"), [3, 3, 3, 3]), "getGroups"),
+            Err(AuthError::RateLimited(_))
+        ));
+
+        assert_eq!(
+            api_keys
+                .usage
+                .lock()
+                .unwrap()
+                .get(&(1, "getLatestMerkleTrees".to_string())),
+            Some(&2)
+        );
+
+        // The rate limits of the IP addresses that haven't called for a minute are dropped
+        api_keys.prune_ip_buckets(Instant::now());
+        assert_eq!(api_keys.ip_buckets.lock().unwrap().len(), 2);
+        api_keys.prune_ip_buckets(Instant::now() + Duration::from_secs(60));
+        assert!(api_keys.ip_buckets.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_authorize_allowed_methods() {
+        let api_keys = api_keys(true, vec!["getGroups".to_string()]);
+
+        assert!(matches!(
+            api_keys.authorize(&caller(None, [1, 1, 1, 1]), "getGroups"),
+            Err(AuthError::Unauthorized(_))
+        ));
+        assert_eq!(
+            api_keys.authorize(&caller(Some("key"), [1, 1, 1, 1]), "getGroups"),
+            Ok(())
+        );
+        assert!(matches!(
+            api_keys.authorize(&caller(Some("key"), [1, 1, 1, 1]), "getGroupMerkleTree"),
+            Err(AuthError::Forbidden(_))
+        ));
+    }
+
+    #[test]
+    fn test_client_ip() {
+        let remote_ip = IpAddr::from(Ipv4Addr::LOCALHOST);
+        let req = |forwarded_for: &str| {
+            Request::builder()
+                .header(FORWARDED_FOR_HEADER, forwarded_for)
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        // The header is ignored when the server isn't behind a proxy
+        assert_eq!(client_ip(&req("1.1.1.1"), remote_ip, 0), remote_ip);
+
+        // The addresses set by the client are skipped
+        assert_eq!(
+            client_ip(&req("1.1.1.1, 2.2.2.2"), remote_ip, 1),
+            IpAddr::from([2, 2, 2, 2])
+        );
+        assert_eq!(
+            client_ip(&req("1.1.1.1, 2.2.2.2, 3.3.3.3"), remote_ip, 2),
+            IpAddr::from([2, 2, 2, 2])
+        );
+
+        assert_eq!(client_ip(&req("2.2.2.2"), remote_ip, 2), remote_ip);
+        assert_eq!(client_ip(&req("invalid"), remote_ip, 1), remote_ip);
+    }
+}
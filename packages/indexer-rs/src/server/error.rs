@@ -0,0 +1,114 @@
+use jsonrpsee::types::{
+    error::{INTERNAL_ERROR_CODE, INVALID_PARAMS_CODE},
+    ErrorObjectOwned, Params,
+};
+use serde::de::DeserializeOwned;
+
+/// The error code of requests for a resource that doesn't exist
+pub const NOT_FOUND_CODE: i32 = -32001;
+
+/// The error code of requests without a valid API key
+pub const UNAUTHORIZED_CODE: i32 = -32002;
+
+/// The error code of requests for methods the API key can't call
+pub const FORBIDDEN_CODE: i32 = -32003;
+
+/// The error code of requests over the rate limit of the API key
+pub const RATE_LIMITED_CODE: i32 = -32005;
+
+/// An error of a JSON-RPC method
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RpcError {
+    /// The parameters are missing or malformed
+    InvalidParams(String),
+    /// The parameters are valid but what they refer to doesn't exist
+    NotFound(String),
+    Internal,
+}
+
+impl RpcError {
+    pub fn invalid_params(message: impl Into<String>) -> Self {
+        RpcError::InvalidParams(message.into())
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        RpcError::NotFound(message.into())
+    }
+
+    pub fn internal_error() -> Self {
+        RpcError::Internal
+    }
+}
+
+impl From<RpcError> for ErrorObjectOwned {
+    fn from(err: RpcError) -> Self {
+        match err {
+            RpcError::InvalidParams(message) => {
+                ErrorObjectOwned::owned(INVALID_PARAMS_CODE, message, None::<()>)
+            }
+            RpcError::NotFound(message) => {
+                ErrorObjectOwned::owned(NOT_FOUND_CODE, message, None::<()>)
+            }
+            RpcError::Internal => {
+                ErrorObjectOwned::owned(INTERNAL_ERROR_CODE, "Internal error", None::<()>)
+            }
+        }
+    }
+}
+
+impl From<tokio_postgres::Error> for RpcError {
+    fn from(_: tokio_postgres::Error) -> Self {
+        RpcError::Internal
+    }
+}
+
+/// Parse the positional parameters of a request.
+/// A request without parameters has no positional parameters.
+pub fn parse_params<T: DeserializeOwned>(params: &Params<'_>) -> Result<Vec<T>, RpcError> {
+    if params.as_str().is_none() {
+        return Ok(vec![]);
+    }
+
+    params
+        .parse::<Vec<T>>()
+        .map_err(|_| RpcError::invalid_params("Invalid parameters"))
+}
+
+/// Parse a hex encoded 20 byte address
+pub fn parse_address(address: &str) -> Result<crate::Address, RpcError> {
+    hex::decode(address.trim_start_matches("0x"))
+        .ok()
+        .and_then(|address| address.try_into().ok())
+        .ok_or_else(|| RpcError::invalid_params("Invalid address"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_params() {
+        let params: Vec<String> = parse_params(&Params::new(Some(r#"["0x01"]"#))).unwrap();
+        assert_eq!(params, vec!["0x01".to_string()]);
+
+        let params: Vec<String> = parse_params(&Params::new(None)).unwrap();
+        assert!(params.is_empty());
+
+        // Malformed parameters are an error instead of a panic
+        assert_eq!(
+            parse_params::<String>(&Params::new(Some("[1]"))),
+            Err(RpcError::invalid_params("Invalid parameters"))
+        );
+        assert!(parse_params::<String>(&Params::new(Some(r#"{"a": 1}"#))).is_err());
+
+        assert_eq!(
+            parse_address("0x0000000000000000000000000000000000000001").unwrap(),
+            {
+                let mut address = [0; 20];
+                address[19] = 1;
+                address
+            }
+        );
+        assert!(parse_address("0x01").is_err());
+    }
+}
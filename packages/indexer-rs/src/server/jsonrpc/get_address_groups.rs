@@ -1,8 +1,12 @@
 use super::GroupData;
-use crate::{address_groups::AddressGroups, GroupType};
-use jsonrpc_http_server::jsonrpc_core::{Error as JsonRpcError, Params, Value};
-use serde_json::json;
-use std::sync::Arc;
+use crate::server::error::{parse_address, parse_params, RpcError};
+use crate::{address_groups::AddressGroups, Address, GroupType};
+use jsonrpsee::types::Params;
+use serde_json::{json, Value};
+use std::{collections::HashSet, sync::Arc};
+
+/// Maximum number of addresses in a `getAddressesGroups` request
+const MAX_BATCH_ADDRESSES: usize = 1000;
 
 pub type GetGroupsReturnType = Vec<GroupData>;
 
@@ -33,41 +37,106 @@ async fn get_groups(
         .collect::<Vec<GroupData>>())
 }
 
-pub async fn get_address_groups(
-    params: Params,
+/// Get the recordable groups an address is a member of
+pub async fn get_groups_of_address(
+    address: Address,
     pg_client: &tokio_postgres::Client,
     rocksdb_conn: Arc<rocksdb::DB>,
-) -> Result<Value, JsonRpcError> {
-    let params: Vec<String> = params.parse().unwrap();
+) -> Result<GetGroupsReturnType, tokio_postgres::Error> {
+    let mut groups = get_groups_of_addresses(&[address], pg_client, rocksdb_conn).await?;
 
-    if params.len() != 1 {
-        return Err(JsonRpcError::invalid_params("Expected 1 parameter"));
-    }
+    Ok(groups.pop().unwrap_or_default())
+}
 
-    let address = params[0].clone();
-    let address = hex::decode(address.trim_start_matches("0x")).unwrap();
+/// Get the recordable groups of each address, in the order of the addresses,
+/// with one RocksDB multi-get and one Postgres query
+pub async fn get_groups_of_addresses(
+    addresses: &[Address],
+    pg_client: &tokio_postgres::Client,
+    rocksdb_conn: Arc<rocksdb::DB>,
+) -> Result<Vec<GetGroupsReturnType>, tokio_postgres::Error> {
+    let address_groups = AddressGroups::get_many(addresses, rocksdb_conn);
 
-    if address.len() != 20 {
-        return Err(JsonRpcError::invalid_params("Invalid address"));
-    }
+    // Get the groups of all addresses at once
+    let group_ids = address_groups
+        .iter()
+        .flat_map(|address_groups| address_groups.group_ids.iter())
+        .collect::<HashSet<&[u8; 32]>>()
+        .into_iter()
+        .map(hex::encode)
+        .collect::<Vec<String>>();
 
-    let address_groups = AddressGroups::get(address.try_into().unwrap(), rocksdb_conn);
+    let groups = if group_ids.is_empty() {
+        vec![]
+    } else {
+        get_groups(&group_ids, pg_client).await?
+    };
 
-    let group_ids: Result<GetGroupsReturnType, tokio_postgres::Error> = match address_groups {
-        Some(address_groups) => {
+    Ok(address_groups
+        .iter()
+        .map(|address_groups| {
             let group_ids = address_groups
                 .group_ids
                 .iter()
-                .map(|id| hex::encode(id))
-                .collect::<Vec<String>>();
+                .map(hex::encode)
+                .collect::<HashSet<String>>();
 
-            get_groups(&group_ids, &pg_client).await
-        }
-        None => Ok(vec![]),
-    };
+            groups
+                .iter()
+                .filter(|group| group_ids.contains(&group.id))
+                .cloned()
+                .collect()
+        })
+        .collect())
+}
+
+pub async fn get_address_groups(
+    params: Params<'_>,
+    pg_client: &tokio_postgres::Client,
+    rocksdb_conn: Arc<rocksdb::DB>,
+) -> Result<Value, RpcError> {
+    let params: Vec<String> = parse_params(&params)?;
+
+    if params.len() != 1 {
+        return Err(RpcError::invalid_params("Expected 1 parameter"));
+    }
+
+    let address = parse_address(&params[0])?;
+
+    let group_ids = get_groups_of_address(address, pg_client, rocksdb_conn).await;
 
     match group_ids {
         Ok(group_ids) => Ok(json!(group_ids)),
-        Err(_) => return Err(JsonRpcError::internal_error()),
+        Err(_) => return Err(RpcError::internal_error()),
+    }
+}
+
+/// Get the groups of multiple addresses at once.
+/// Returns the groups of each address in the order of the addresses.
+pub async fn get_addresses_groups(
+    params: Params<'_>,
+    pg_client: &tokio_postgres::Client,
+    rocksdb_conn: Arc<rocksdb::DB>,
+) -> Result<Value, RpcError> {
+    let params: Vec<Vec<String>> = parse_params(&params)?;
+
+    if params.len() != 1 {
+        return Err(RpcError::invalid_params("Expected 1 parameter"));
+    }
+
+    if params[0].len() > MAX_BATCH_ADDRESSES {
+        return Err(RpcError::invalid_params(format!(
+            "Expected at most {} addresses",
+            MAX_BATCH_ADDRESSES
+        )));
     }
+
+    let addresses = params[0]
+        .iter()
+        .map(|address| parse_address(address))
+        .collect::<Result<Vec<Address>, RpcError>>()?;
+
+    let groups = get_groups_of_addresses(&addresses, pg_client, rocksdb_conn).await?;
+
+    Ok(json!(groups))
 }
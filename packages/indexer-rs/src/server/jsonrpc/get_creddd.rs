@@ -1,20 +1,38 @@
 use super::GroupData;
 use crate::GroupType;
-use jsonrpc_http_server::jsonrpc_core::{Error as JsonRpcError, Params, Value};
-use serde_json::json;
+use crate::server::error::{parse_params, RpcError};
+use jsonrpsee::types::Params;
+use serde_json::{json, Value};
 
 pub type GetCredddReturnType = GroupData;
 
-pub async fn get_creddd(params: Params, pg_client: &tokio_postgres::Client) -> Result<Value, JsonRpcError> {
-    let params: Vec<String> = params.parse().unwrap();
+pub async fn get_creddd(params: Params<'_>, pg_client: &tokio_postgres::Client) -> Result<Value, RpcError> {
+    let params: Vec<String> = parse_params(&params)?;
 
     if params.len() != 1 {
-        return Err(JsonRpcError::invalid_params("Expected 1 parameter"));
+        return Err(RpcError::invalid_params("Expected 1 parameter"));
     }
 
     let creddd_id = params[0].clone();
 
-    let result = pg_client
+    let result = query_group(pg_client, &creddd_id).await;
+
+    if result.is_err() {
+        return Err(RpcError::internal_error());
+    }
+
+    match result.unwrap() {
+        Some(group_data) => Ok(json!(group_data)),
+        None => Err(RpcError::not_found("No group found for the given group id")),
+    }
+}
+
+/// Get the group with `group_id`
+pub async fn query_group(
+    pg_client: &tokio_postgres::Client,
+    group_id: &str,
+) -> Result<Option<GroupData>, tokio_postgres::Error> {
+    let rows = pg_client
         .query(
             r#"
             SELECT
@@ -26,33 +44,21 @@ pub async fn get_creddd(params: Params, pg_client: &tokio_postgres::Client) -> R
             WHERE
                 "id" = $1
             "#,
-            &[&creddd_id],
+            &[&group_id],
         )
-        .await;
-
-    if result.is_err() {
-        return Err(JsonRpcError::internal_error());
-    }
-
-    let rows = result.unwrap();
-
-    if rows.len() == 0 {
-        return Err(JsonRpcError::invalid_params(
-            "No group found for the given Merkle root",
-        ));
-    }
-
-    let row = rows.get(0).unwrap();
+        .await?;
 
-    let group_id: String = row.get("id");
-    let group_name: String = row.get("displayName");
-    let group_type: GroupType = row.get("typeId");
+    let group_data = rows.get(0).map(|row| {
+        let group_id: String = row.get("id");
+        let group_name: String = row.get("displayName");
+        let group_type: GroupType = row.get("typeId");
 
-    let group_data = GroupData {
-        id: group_id,
-        display_name: group_name,
-        type_id: group_type,
-    };
+        GroupData {
+            id: group_id,
+            display_name: group_name,
+            type_id: group_type,
+        }
+    });
 
-    Ok(json!(group_data))
+    Ok(group_data)
 }
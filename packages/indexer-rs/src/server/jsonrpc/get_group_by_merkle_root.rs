@@ -1,18 +1,19 @@
 use super::GroupData;
+use crate::server::error::{parse_params, RpcError};
 use crate::GroupType;
-use jsonrpc_http_server::jsonrpc_core::{Error as JsonRpcError, Params, Value};
-use serde_json::json;
+use jsonrpsee::types::Params;
+use serde_json::{json, Value};
 
 pub type GetGroupByMerkleRootReturnType = GroupData;
 
 pub async fn get_group_by_merkle_root(
-    params: Params,
+    params: Params<'_>,
     pg_client: &tokio_postgres::Client,
-) -> Result<Value, JsonRpcError> {
-    let params: Vec<String> = params.parse().unwrap();
+) -> Result<Value, RpcError> {
+    let params: Vec<String> = parse_params(&params)?;
 
     if params.len() != 1 {
-        return Err(JsonRpcError::invalid_params("Expected 1 parameter"));
+        return Err(RpcError::invalid_params("Expected 1 parameter"));
     }
 
     let merkle_root = params[0].clone();
@@ -35,13 +36,13 @@ pub async fn get_group_by_merkle_root(
         .await;
 
     if result.is_err() {
-        return Err(JsonRpcError::internal_error());
+        return Err(RpcError::internal_error());
     }
 
     let rows = result.unwrap();
 
     if rows.len() == 0 {
-        return Err(JsonRpcError::invalid_params(
+        return Err(RpcError::not_found(
             "No group found for the given Merkle root",
         ));
     }
@@ -0,0 +1,143 @@
+use crate::server::error::{parse_params, RpcError};
+use crate::BlockNum;
+use jsonrpsee::types::Params;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+/// Maximum number of added and removed members returned at once.
+/// The changes are paged by the block number and the address,
+/// so the changes of a block can span multiple pages.
+const MAX_CHANGES: i64 = 10000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GroupChangeData {
+    /// The block number of the tree the members were added or removed in
+    pub block_number: BlockNum,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+/// Where the next page of the changes starts
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GroupChangesCursor {
+    /// The block number of the last change of the page, to pass as `sinceBlock`
+    pub since_block: BlockNum,
+    /// The address of the last change of the page, to pass as `cursor`
+    pub cursor: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GroupChangesPage {
+    pub changes: Vec<GroupChangeData>,
+    /// Where the next page starts, or `None` if there are no more changes
+    pub next_page: Option<GroupChangesCursor>,
+}
+
+pub type GetGroupChangesReturnType = GroupChangesPage;
+
+/// Get the members added to and removed from a group in the trees after a block number,
+/// in ascending order of block number and address.
+/// If `cursor` is given, the changes of `sinceBlock` after the `cursor` address are also returned.
+pub async fn get_group_changes(
+    params: Params<'_>,
+    pg_client: &tokio_postgres::Client,
+) -> Result<Value, RpcError> {
+    let params: Vec<Value> = parse_params(&params)?;
+
+    if params.len() != 2 && params.len() != 3 {
+        return Err(RpcError::invalid_params("Expected 2 or 3 parameters"));
+    }
+
+    let group_id = params[0]
+        .as_str()
+        .ok_or_else(|| RpcError::invalid_params("Invalid group id"))?;
+
+    let since_block = params[1]
+        .as_u64()
+        .ok_or_else(|| RpcError::invalid_params("Invalid block number"))?;
+
+    let cursor = match params.get(2) {
+        Some(cursor) => Some(
+            cursor
+                .as_str()
+                .ok_or_else(|| RpcError::invalid_params("Invalid cursor"))?,
+        ),
+        None => None,
+    };
+
+    let result = pg_client
+        .query(
+            r#"
+            SELECT
+                "blockNumber",
+                "address",
+                "isAdded"
+            FROM
+                "GroupMemberChange"
+            WHERE
+                "groupId" = $1
+                AND (
+                    "blockNumber" > $2
+                    OR ("blockNumber" = $2 AND "address" > $3)
+                )
+            ORDER BY
+                "blockNumber" ASC,
+                "address" ASC
+            LIMIT $4
+            "#,
+            &[
+                &group_id,
+                &(since_block.min(i64::MAX as u64) as i64),
+                &cursor,
+                // One more change than the page tells if there's a next page
+                &(MAX_CHANGES + 1),
+            ],
+        )
+        .await;
+
+    if result.is_err() {
+        return Err(RpcError::internal_error());
+    }
+
+    let rows = result.unwrap();
+    let has_next_page = rows.len() as i64 > MAX_CHANGES;
+
+    let mut changes: Vec<GroupChangeData> = vec![];
+    let mut last_change = None;
+
+    for row in rows.iter().take(MAX_CHANGES as usize) {
+        let block_number: i64 = row.get("blockNumber");
+        let address: String = row.get("address");
+        let is_added: bool = row.get("isAdded");
+
+        let block_number = block_number as BlockNum;
+
+        if changes.last().map(|change| change.block_number) != Some(block_number) {
+            changes.push(GroupChangeData {
+                block_number,
+                added: vec![],
+                removed: vec![],
+            });
+        }
+
+        let change = changes.last_mut().unwrap();
+
+        last_change = Some((block_number, address.clone()));
+
+        if is_added {
+            change.added.push(address);
+        } else {
+            change.removed.push(address);
+        }
+    }
+
+    let next_page = match last_change {
+        Some((since_block, cursor)) if has_next_page => Some(GroupChangesCursor {
+            since_block,
+            cursor,
+        }),
+        _ => None,
+    };
+
+    Ok(json!(GroupChangesPage { changes, next_page }))
+}
@@ -1,7 +1,8 @@
+use crate::server::error::{parse_params, RpcError};
 use crate::BlockNum;
-use jsonrpc_http_server::jsonrpc_core::{Error as JsonRpcError, Params, Value};
+use jsonrpsee::types::Params;
 use serde::{Deserialize, Serialize};
-use serde_json::json;
+use serde_json::{json, Value};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MerkleTreeData {
@@ -9,13 +10,13 @@ pub struct MerkleTreeData {
 }
 
 pub async fn get_group_merkle_tree(
-    params: Params,
+    params: Params<'_>,
     pg_client: &tokio_postgres::Client,
-) -> Result<Value, JsonRpcError> {
-    let params: Vec<String> = params.parse().unwrap();
+) -> Result<Value, RpcError> {
+    let params: Vec<String> = parse_params(&params)?;
 
     if params.len() != 2 {
-        return Err(JsonRpcError::invalid_params("Expected 2 parameter"));
+        return Err(RpcError::invalid_params("Expected 2 parameter"));
     }
 
     let merkle_root = params[0].clone();
@@ -37,13 +38,13 @@ pub async fn get_group_merkle_tree(
         .await;
 
     if result.is_err() {
-        return Err(JsonRpcError::internal_error());
+        return Err(RpcError::internal_error());
     }
 
     let rows = result.unwrap();
 
     if rows.len() == 0 {
-        return Err(JsonRpcError::invalid_params(
+        return Err(RpcError::not_found(
             "No Merkle tree found for the given Merkle root and group id",
         ));
     }
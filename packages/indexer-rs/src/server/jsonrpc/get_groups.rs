@@ -1,13 +1,71 @@
 use super::GroupData;
+use crate::contract::chain_name;
+use crate::eth_rpc::Chain;
+use crate::server::error::{parse_address, parse_params, RpcError};
 use crate::GroupType;
-use jsonrpc_http_server::jsonrpc_core::{Error as JsonRpcError, Params, Value};
-use serde_json::json;
+use jsonrpsee::types::Params;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::str::FromStr;
 
-pub async fn get_groups(
-    _params: Params,
+/// Maximum number of groups returned in a page
+pub const MAX_GROUPS_LIMIT: u32 = 1000;
+
+/// The optional filter and page of `getGroups`.
+/// The groups are ordered by id, and the next page starts after the `cursor` group id,
+/// which is the id of the last group of the previous page.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GroupsFilter {
+    /// Return the groups after this group id
+    pub cursor: Option<String>,
+    /// Maximum number of groups to return. All groups are returned if not set.
+    pub limit: Option<u32>,
+    pub type_id: Option<GroupType>,
+    /// Return the groups with a contract on this chain (e.g. "mainnet", "base")
+    pub chain: Option<String>,
+    /// Return the groups of this contract address
+    pub contract: Option<String>,
+    /// Return the groups with at least this score
+    pub min_score: Option<u64>,
+}
+
+fn parse_filter(params: &Params<'_>) -> Result<GroupsFilter, RpcError> {
+    let mut params: Vec<GroupsFilter> = parse_params(params)?;
+
+    if params.len() > 1 {
+        return Err(RpcError::invalid_params("Expected at most 1 parameter"));
+    }
+
+    Ok(params.pop().unwrap_or_default())
+}
+
+/// Get the recordable groups that match the filter, in ascending order of id
+pub async fn query_groups(
     pg_client: &tokio_postgres::Client,
-) -> Result<Value, JsonRpcError> {
-    let result = pg_client
+    filter: &GroupsFilter,
+) -> Result<Vec<GroupData>, RpcError> {
+    let chain = filter
+        .chain
+        .as_deref()
+        .map(Chain::from_str)
+        .transpose()
+        .map_err(|_| RpcError::invalid_params("Invalid chain"))?
+        .map(chain_name);
+
+    let contract = filter
+        .contract
+        .as_deref()
+        .map(parse_address)
+        .transpose()?
+        .map(|address| format!("0x{}", hex::encode(address)));
+
+    let min_score = filter
+        .min_score
+        .map(|score| score.min(i64::MAX as u64) as i64);
+    let limit = filter.limit.map(|limit| limit.min(MAX_GROUPS_LIMIT) as i64);
+
+    let rows = pg_client
         .query(
             r#"
             SELECT
@@ -17,33 +75,97 @@ pub async fn get_groups(
             FROM
                 "Group"
             WHERE
-                state = 'Recordable'
+                "state" = 'Recordable'
+                AND ($1::text IS NULL OR "id" > $1)
+                AND ($2::"GroupType" IS NULL OR "typeId" = $2)
+                AND ($3::text IS NULL OR EXISTS (
+                    SELECT 1 FROM "Contract"
+                    WHERE "Contract"."id" = ANY("Group"."contractInputs") AND "Contract"."chain" = $3
+                ))
+                AND ($4::text IS NULL OR EXISTS (
+                    SELECT 1 FROM "Contract"
+                    WHERE "Contract"."id" = ANY("Group"."contractInputs") AND LOWER("Contract"."address") = $4
+                ))
+                AND ($5::bigint IS NULL OR "score" >= $5)
+            ORDER BY
+                "id" ASC
+            LIMIT $6
             "#,
-            &[],
+            &[
+                &filter.cursor,
+                &filter.type_id,
+                &chain,
+                &contract,
+                &min_score,
+                &limit,
+            ],
         )
-        .await;
-
-    match result {
-        Ok(rows) => {
-            let groups = rows
-                .iter()
-                .map(|row| {
-                    let id: String = row.get("id");
-                    let display_name: String = row.get("displayName");
-                    let type_id: GroupType = row.get("typeId");
-
-                    GroupData {
-                        id,
-                        display_name,
-                        type_id: type_id as GroupType,
-                    }
-                })
-                .collect::<Vec<GroupData>>();
-
-            Ok(json!(groups))
-        }
-        Err(e) => {
-            return Err(JsonRpcError::internal_error());
-        }
+        .await?;
+
+    let groups = rows
+        .iter()
+        .map(|row| {
+            let id: String = row.get("id");
+            let display_name: String = row.get("displayName");
+            let type_id: GroupType = row.get("typeId");
+
+            GroupData {
+                id,
+                display_name,
+                type_id,
+            }
+        })
+        .collect::<Vec<GroupData>>();
+
+    Ok(groups)
+}
+
+/// Get the recordable groups, optionally filtered and paginated by a `GroupsFilter`
+pub async fn get_groups(
+    params: Params<'_>,
+    pg_client: &tokio_postgres::Client,
+) -> Result<Value, RpcError> {
+    let filter = parse_filter(&params)?;
+
+    let groups = query_groups(pg_client, &filter).await?;
+
+    Ok(json!(groups))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_filter() {
+        // No filter returns all groups
+        assert_eq!(
+            parse_filter(&Params::new(None)),
+            Ok(GroupsFilter::default())
+        );
+        assert_eq!(
+            parse_filter(&Params::new(Some("[]"))),
+            Ok(GroupsFilter::default())
+        );
+
+        let params = Params::new(Some(
+            r#"[{ "cursor": "abc", "limit": 10, "type_id": "Whale", "min_score": 100 }]"#,
+        ));
+        assert_eq!(
+            parse_filter(&params),
+            Ok(GroupsFilter {
+                cursor: Some("abc".to_string()),
+                limit: Some(10),
+                type_id: Some(GroupType::Whale),
+                min_score: Some(100),
+                ..Default::default()
+            })
+        );
+
+        let params = Params::new(Some(r#"[{ "unknown": 1 }]"#));
+        assert!(parse_filter(&params).is_err());
+
+        let params = Params::new(Some(r#"[{}, {}]"#));
+        assert!(parse_filter(&params).is_err());
     }
 }
@@ -1,21 +1,39 @@
 use super::{GroupData, MerkleTree};
+use crate::server::error::{parse_params, RpcError};
 use crate::GroupType;
-use jsonrpc_http_server::jsonrpc_core::{Error as JsonRpcError, Params, Value};
-use serde_json::json;
+use jsonrpsee::types::Params;
+use serde_json::{json, Value};
 
 pub type GetCredddReturnType = GroupData;
 
 pub async fn get_latest_merkle_trees(
-    params: Params,
+    params: Params<'_>,
     pg_client: &tokio_postgres::Client,
-) -> Result<Value, JsonRpcError> {
-    let params: Vec<String> = params.parse().unwrap();
+) -> Result<Value, RpcError> {
+    let params: Vec<String> = parse_params(&params)?;
 
     if params.len() != 0 {
-        return Err(JsonRpcError::invalid_params("Expected no parameter"));
+        return Err(RpcError::invalid_params("Expected no parameter"));
     }
 
-    let result = pg_client
+    let result = query_latest_merkle_trees(pg_client, None).await;
+
+    if result.is_err() {
+        return Err(RpcError::internal_error());
+    }
+
+    let merkle_trees = result.unwrap();
+
+    Ok(json!(merkle_trees))
+}
+
+/// Get the latest Merkle tree with a bloom filter of each recordable group,
+/// or only of the group with `group_id`
+pub async fn query_latest_merkle_trees(
+    pg_client: &tokio_postgres::Client,
+    group_id: Option<&str>,
+) -> Result<Vec<MerkleTree>, tokio_postgres::Error> {
+    let rows = pg_client
         .query(
             r#"
             SELECT DISTINCT ON ("Group".id)
@@ -33,19 +51,14 @@ pub async fn get_latest_merkle_trees(
                 LEFT JOIN "Group" ON "MerkleTree"."groupId" = "Group".id
                 WHERE "MerkleTree"."bloomFilter" IS NOT NULL
                 AND "Group".state = 'Recordable'
+                AND ($1::text IS NULL OR "Group".id = $1)
             ORDER BY
                 "Group".id,
                 "MerkleTree"."blockNumber" DESC
             "#,
-            &[],
+            &[&group_id],
         )
-        .await;
-
-    if result.is_err() {
-        return Err(JsonRpcError::internal_error());
-    }
-
-    let rows = result.unwrap();
+        .await?;
 
     let merkle_trees = rows
         .iter()
@@ -78,5 +91,5 @@ pub async fn get_latest_merkle_trees(
         })
         .collect::<Vec<MerkleTree>>();
 
-    Ok(json!(merkle_trees))
+    Ok(merkle_trees)
 }
@@ -0,0 +1,56 @@
+use crate::server::error::{parse_address, parse_params, RpcError};
+use crate::{tree_archive::get_archived_tree_at, BlockNum};
+use jsonrpsee::types::Params;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MembershipData {
+    pub is_member: bool,
+    /// The block number the tree the membership is of was built at
+    pub block_number: BlockNum,
+    pub merkle_root: String,
+}
+
+pub type GetMembershipAtReturnType = MembershipData;
+
+/// Get whether an address was a member of a group at a block number,
+/// from the tree of the group that was the latest at the block
+pub async fn get_membership_at(
+    params: Params<'_>,
+    pg_client: &tokio_postgres::Client,
+) -> Result<Value, RpcError> {
+    let params: Vec<Value> = parse_params(&params)?;
+
+    if params.len() != 3 {
+        return Err(RpcError::invalid_params("Expected 3 parameters"));
+    }
+
+    let group_id = params[0]
+        .as_str()
+        .ok_or_else(|| RpcError::invalid_params("Invalid group id"))?;
+
+    let address = params[1]
+        .as_str()
+        .ok_or_else(|| RpcError::invalid_params("Invalid address"))
+        .and_then(parse_address)?;
+
+    let block_number = params[2]
+        .as_u64()
+        .ok_or_else(|| RpcError::invalid_params("Invalid block number"))?;
+
+    let archived_tree = get_archived_tree_at(pg_client, group_id, block_number)
+        .await
+        .map_err(|_| RpcError::internal_error())?
+        .ok_or_else(|| {
+            RpcError::not_found("No Merkle tree found for the given group id and block number")
+        })?;
+
+    let membership_data = MembershipData {
+        is_member: archived_tree.contains(&address),
+        block_number: archived_tree.block_number,
+        merkle_root: archived_tree.merkle_root,
+    };
+
+    Ok(json!(membership_data))
+}
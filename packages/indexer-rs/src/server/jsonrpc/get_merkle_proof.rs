@@ -0,0 +1,191 @@
+use crate::server::error::{parse_address, parse_params, RpcError};
+use crate::{
+    merkle_tree_proto::MerkleTree,
+    tree_archive::get_archived_tree_at,
+    tree_store::{leaf_from_proto, merkle_proof_from_proto},
+    BlockNum,
+};
+use jsonrpsee::types::Params;
+use prost::Message;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MerkleProofData {
+    pub tree_id: i32,
+    pub merkle_root: String,
+    /// The block number the latest tree is up to date at,
+    /// or the block number the tree at a block number was built at
+    pub block_number: BlockNum,
+    pub tree_depth: usize,
+    /// The hex encoded siblings from the bottom of the tree,
+    /// concatenated as the `merkle_siblings` the prover takes
+    pub merkle_siblings: String,
+    /// The hex encoded indices, which are 1 where the path goes through the right child,
+    /// concatenated as the `merkle_indices` the prover takes
+    pub merkle_indices: String,
+    /// The hex encoded leaf of the address as computed in the circuit
+    pub leaf: String,
+    /// The hex encoded balance the leaf commits to in a balance tree,
+    /// which is the `balance` the prover takes
+    pub balance: Option<String>,
+}
+
+pub type GetMerkleProofReturnType = MerkleProofData;
+
+/// Returns the id, the root, the block number and the protobuf of the latest tree of a group
+async fn get_latest_tree(
+    pg_client: &tokio_postgres::Client,
+    group_id: &str,
+) -> Result<(i32, String, BlockNum, MerkleTree), RpcError> {
+    let rows = pg_client
+        .query(
+            r#"
+            SELECT
+                "id",
+                "merkleRoot",
+                "blockNumber",
+                "treeProtoBuf"
+            FROM
+                "MerkleTree"
+            WHERE
+                "groupId" = $1
+                AND "treeProtoBuf" IS NOT NULL
+            ORDER BY
+                "blockNumber" DESC
+            LIMIT 1
+            "#,
+            &[&group_id],
+        )
+        .await
+        .map_err(|_| RpcError::internal_error())?;
+
+    let row = rows
+        .first()
+        .ok_or_else(|| RpcError::not_found("No Merkle tree found for the given group id"))?;
+
+    let tree_proto_buf: Vec<u8> = row.get("treeProtoBuf");
+    let tree =
+        MerkleTree::decode(tree_proto_buf.as_slice()).map_err(|_| RpcError::internal_error())?;
+
+    let block_number: i64 = row.get("blockNumber");
+
+    Ok((
+        row.get("id"),
+        row.get("merkleRoot"),
+        block_number as BlockNum,
+        tree,
+    ))
+}
+
+/// Returns the id, the root, the block number and the protobuf of the tree of a group
+/// that was the latest at `block_number`, which is the same tree `getMembershipAt` uses.
+/// The block numbers of the saved trees move forward while they're up to date,
+/// so the tree is looked up in the archive by the block it was built at.
+async fn get_tree_at(
+    pg_client: &tokio_postgres::Client,
+    group_id: &str,
+    block_number: BlockNum,
+) -> Result<(i32, String, BlockNum, MerkleTree), RpcError> {
+    let archived_tree = get_archived_tree_at(pg_client, group_id, block_number)
+        .await
+        .map_err(|_| RpcError::internal_error())?
+        .ok_or_else(|| {
+            RpcError::not_found("The Merkle tree at the given block number is not available")
+        })?;
+
+    let rows = pg_client
+        .query(
+            r#"
+            SELECT
+                "id"
+            FROM
+                "MerkleTree"
+            WHERE
+                "groupId" = $1
+                AND "merkleRoot" = $2
+            ORDER BY
+                "blockNumber" DESC
+            LIMIT 1
+            "#,
+            &[&group_id, &archived_tree.merkle_root],
+        )
+        .await
+        .map_err(|_| RpcError::internal_error())?;
+
+    let row = rows.first().ok_or_else(|| {
+        RpcError::not_found("The Merkle tree at the given block number is not available")
+    })?;
+
+    Ok((
+        row.get("id"),
+        archived_tree.merkle_root,
+        archived_tree.block_number,
+        archived_tree.tree,
+    ))
+}
+
+/// Get the Merkle proof of an address in the latest tree of a group,
+/// or in the tree that was the latest at a block number
+pub async fn get_merkle_proof(
+    params: Params<'_>,
+    pg_client: &tokio_postgres::Client,
+) -> Result<Value, RpcError> {
+    let params: Vec<Value> = parse_params(&params)?;
+
+    if params.len() != 2 && params.len() != 3 {
+        return Err(RpcError::invalid_params("Expected 2 or 3 parameters"));
+    }
+
+    let group_id = params[0]
+        .as_str()
+        .ok_or_else(|| RpcError::invalid_params("Invalid group id"))?;
+
+    let address = params[1]
+        .as_str()
+        .ok_or_else(|| RpcError::invalid_params("Invalid address"))
+        .and_then(parse_address)?;
+
+    let block_number = match params.get(2) {
+        Some(block_number) => Some(
+            block_number
+                .as_u64()
+                .ok_or_else(|| RpcError::invalid_params("Invalid block number"))?,
+        ),
+        None => None,
+    };
+
+    let (tree_id, merkle_root, tree_block_number, tree) = match block_number {
+        Some(block_number) => get_tree_at(pg_client, group_id, block_number).await?,
+        None => get_latest_tree(pg_client, group_id).await?,
+    };
+
+    let not_in_tree = || RpcError::not_found("The address is not in the Merkle tree");
+
+    let merkle_proof = merkle_proof_from_proto(&tree, &address).ok_or_else(not_in_tree)?;
+    let (leaf, balance) = leaf_from_proto(&tree, &address).ok_or_else(not_in_tree)?;
+
+    // The indices are field elements in the prover
+    let merkle_indices = merkle_proof
+        .indices
+        .iter()
+        .map(|is_right| {
+            let mut index = [0u8; 32];
+            index[31] = *is_right as u8;
+            index
+        })
+        .collect::<Vec<[u8; 32]>>();
+
+    let merkle_proof_data = MerkleProofData {
+        tree_id,
+        merkle_root,
+        block_number: tree_block_number,
+        tree_depth: merkle_proof.siblings.len(),
+        merkle_siblings: format!("0x{}", hex::encode(merkle_proof.siblings.concat())),
+        merkle_indices: format!("0x{}", hex::encode(merkle_indices.concat())),
+        leaf: format!("0x{}", hex::encode(leaf)),
+        balance: balance.map(|balance| format!("0x{}", hex::encode(balance.to_bytes_be()))),
+    };
+
+    Ok(json!(merkle_proof_data))
+}
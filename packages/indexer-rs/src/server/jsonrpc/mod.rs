@@ -1,22 +1,28 @@
 pub mod get_address_groups;
 pub mod get_creddd;
 pub mod get_group_by_merkle_root;
+pub mod get_group_changes;
 pub mod get_latest_merkle_trees;
 pub mod get_group_latest_merkle_tree;
 pub mod get_group_merkle_tree;
 pub mod get_groups;
+pub mod get_membership_at;
+pub mod get_merkle_proof;
+pub mod register_nullifier;
+pub mod verify_membership_proof;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 use crate::GroupType;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct GroupData {
     pub id: String,
     pub display_name: String,
     pub type_id: GroupType,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct MerkleTree {
     id: i32,
     bloom_filter: Vec<u8>,
@@ -0,0 +1,112 @@
+use super::verify_membership_proof::{get_membership_proof_verdict, MembershipProofVerdict};
+use crate::server::error::{parse_params, RpcError};
+use jsonrpsee::types::Params;
+use num_bigint::BigUint;
+use serde_json::{json, Value};
+
+pub type RegisterNullifierReturnType = MembershipProofVerdict;
+
+// Parse a hex encoded message hash
+fn parse_msg_hash(msg_hash: &str) -> Option<BigUint> {
+    BigUint::parse_bytes(msg_hash.trim_start_matches("0x").as_bytes(), 16)
+}
+
+/// Check that the proof is for the scope of the application.
+/// A signer can sign any message, so a proof for another scope would have another nullifier.
+fn check_scope(verdict: &MembershipProofVerdict, expected_msg_hash: &str) -> Result<(), RpcError> {
+    let expected_msg_hash = parse_msg_hash(expected_msg_hash)
+        .ok_or_else(|| RpcError::invalid_params("Invalid message hash"))?;
+
+    if verdict.msg_hash.as_deref().and_then(parse_msg_hash) != Some(expected_msg_hash) {
+        return Err(RpcError::invalid_params(
+            "The proof isn't for the given scope",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Verify a membership proof and record its nullifier,
+/// so the signer can't use another proof in the same scope.
+/// The scope is the hash of the signed message, so applications define the scope
+/// by the message they ask to sign, e.g. with the group id to allow one use per group,
+/// and pass the hash of the message to reject the proofs for the other messages.
+/// The nullifier is the same for each proof of a wallet that signs deterministically (RFC 6979).
+pub async fn register_nullifier(
+    params: Params<'_>,
+    pg_client: &tokio_postgres::Client,
+) -> Result<Value, RpcError> {
+    let params: Vec<String> = parse_params(&params)?;
+
+    if params.len() != 2 {
+        return Err(RpcError::invalid_params("Expected 2 parameters"));
+    }
+
+    let verdict = get_membership_proof_verdict(&params[0], pg_client).await?;
+
+    if !verdict.is_valid {
+        return Err(RpcError::invalid_params(
+            verdict.error.unwrap_or("Invalid proof".to_string()),
+        ));
+    }
+
+    if verdict.nullifier.is_none() {
+        return Err(RpcError::invalid_params("The proof has no nullifier"));
+    }
+
+    check_scope(&verdict, &params[1])?;
+
+    let scope = verdict.scope.clone().unwrap();
+    let nullifier = verdict.nullifier.clone().unwrap();
+    let group_id = verdict.group.as_ref().unwrap().id.clone();
+
+    let result = pg_client
+        .execute(
+            r#"
+            INSERT INTO "Nullifier" ("scope", "nullifier", "groupId") VALUES ($1, $2, $3)
+            ON CONFLICT ("scope", "nullifier") DO NOTHING
+            "#,
+            &[&scope, &nullifier, &group_id],
+        )
+        .await;
+
+    if result.is_err() {
+        return Err(RpcError::internal_error());
+    }
+
+    // The nullifier was already used if nothing was inserted
+    if result.unwrap() == 0 {
+        return Err(RpcError::invalid_params(
+            "The nullifier was already used in the scope",
+        ));
+    }
+
+    Ok(json!(verdict))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_check_scope() {
+        let verdict = MembershipProofVerdict {
+            is_valid: true,
+            error: None,
+            merkle_root: None,
+            msg_hash: Some("0x1234".to_string()),
+            tree_depth: None,
+            scope: None,
+            nullifier: None,
+            group: None,
+            block_number: None,
+        };
+
+        assert!(check_scope(&verdict, "0x1234").is_ok());
+        assert!(check_scope(&verdict, "0x001234").is_ok());
+
+        // A proof for another scope is rejected
+        assert!(check_scope(&verdict, "0x5678").is_err());
+        assert!(check_scope(&verdict, "not hex").is_err());
+    }
+}
@@ -0,0 +1,162 @@
+use super::GroupData;
+use crate::server::error::{parse_params, RpcError};
+use crate::{BlockNum, GroupType};
+use circuits::membership::{verify, MembershipProof};
+use jsonrpsee::types::Params;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MembershipProofVerdict {
+    /// Whether the proof is valid and its Merkle root is a tree of a group
+    pub is_valid: bool,
+    /// The reason the proof is invalid
+    pub error: Option<String>,
+    pub merkle_root: Option<String>,
+    pub msg_hash: Option<String>,
+    pub tree_depth: Option<usize>,
+    /// The scope of the nullifier, which is the hash of the signed message
+    pub scope: Option<String>,
+    /// The nullifier of the signer in the scope (`None` for proofs without a nullifier)
+    pub nullifier: Option<String>,
+    pub group: Option<GroupData>,
+    /// The block number the tree of the Merkle root is up to date at
+    pub block_number: Option<BlockNum>,
+}
+
+impl MembershipProofVerdict {
+    fn invalid(error: String) -> Self {
+        MembershipProofVerdict {
+            is_valid: false,
+            error: Some(error),
+            merkle_root: None,
+            msg_hash: None,
+            tree_depth: None,
+            scope: None,
+            nullifier: None,
+            group: None,
+            block_number: None,
+        }
+    }
+}
+
+pub type VerifyMembershipProofReturnType = MembershipProofVerdict;
+
+pub async fn verify_membership_proof(
+    params: Params<'_>,
+    pg_client: &tokio_postgres::Client,
+) -> Result<Value, RpcError> {
+    let params: Vec<String> = parse_params(&params)?;
+
+    if params.len() != 1 {
+        return Err(RpcError::invalid_params("Expected 1 parameter"));
+    }
+
+    let verdict = get_membership_proof_verdict(&params[0], pg_client).await?;
+
+    Ok(json!(verdict))
+}
+
+/// Verify a hex encoded membership proof and resolve its Merkle root to a group
+pub async fn get_membership_proof_verdict(
+    proof: &str,
+    pg_client: &tokio_postgres::Client,
+) -> Result<MembershipProofVerdict, RpcError> {
+    let proof_bytes = hex::decode(proof.trim_start_matches("0x"));
+
+    if proof_bytes.is_err() {
+        return Err(RpcError::invalid_params("Invalid proof hex"));
+    }
+
+    let proof_bytes = proof_bytes.unwrap();
+
+    // Verifying the proof is CPU bound, so it's done outside of the async runtime
+    let public_inputs = tokio::task::spawn_blocking(move || {
+        MembershipProof::from_bytes(&proof_bytes).and_then(|proof| verify(&proof))
+    })
+    .await;
+
+    if public_inputs.is_err() {
+        return Err(RpcError::internal_error());
+    }
+
+    let public_inputs = match public_inputs.unwrap() {
+        Ok(public_inputs) => public_inputs,
+        Err(err) => return Ok(MembershipProofVerdict::invalid(err.to_string())),
+    };
+
+    let merkle_root = format!("0x{}", hex::encode(public_inputs.merkle_root_bytes()));
+    let msg_hash = format!("0x{}", public_inputs.msg_hash.to_str_radix(16));
+    let scope = public_inputs
+        .scope_bytes()
+        .map(|scope| format!("0x{}", hex::encode(scope)));
+    let nullifier = public_inputs
+        .nullifier_bytes()
+        .map(|nullifier| format!("0x{}", hex::encode(nullifier)));
+
+    // Resolve the Merkle root to a group.
+    // The root is only valid for a tree of the depth the proof was generated for,
+    // and the latest tree with the root is used if the root didn't change across blocks.
+    let result = pg_client
+        .query(
+            r#"
+            SELECT
+                "Group".id,
+                "Group"."displayName",
+                "Group"."typeId",
+                "MerkleTree"."blockNumber"
+            FROM
+                "MerkleTree"
+                JOIN "Group" ON "MerkleTree"."groupId" = "Group".id
+            WHERE
+                "MerkleTree"."merkleRoot" = $1
+                AND "MerkleTree"."treeDepth" = $2
+            ORDER BY
+                "MerkleTree"."blockNumber" DESC,
+                "MerkleTree".id DESC
+            LIMIT 1
+            "#,
+            &[&merkle_root, &(public_inputs.tree_depth as i32)],
+        )
+        .await;
+
+    if result.is_err() {
+        return Err(RpcError::internal_error());
+    }
+
+    let rows = result.unwrap();
+
+    let mut verdict = MembershipProofVerdict {
+        is_valid: false,
+        error: None,
+        merkle_root: Some(merkle_root),
+        msg_hash: Some(msg_hash),
+        tree_depth: Some(public_inputs.tree_depth),
+        scope,
+        nullifier,
+        group: None,
+        block_number: None,
+    };
+
+    match rows.get(0) {
+        Some(row) => {
+            let group_id: String = row.get("id");
+            let group_name: String = row.get("displayName");
+            let group_type: GroupType = row.get("typeId");
+            let block_number: i64 = row.get("blockNumber");
+
+            verdict.is_valid = true;
+            verdict.group = Some(GroupData {
+                id: group_id,
+                display_name: group_name,
+                type_id: group_type,
+            });
+            verdict.block_number = Some(block_number as BlockNum);
+        }
+        None => {
+            verdict.error = Some("No group found for the Merkle root".to_string());
+        }
+    }
+
+    Ok(verdict)
+}
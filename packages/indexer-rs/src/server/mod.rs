@@ -1,2 +1,6 @@
 pub mod server;
-pub mod jsonrpc;
\ No newline at end of file
+pub mod auth;
+pub mod error;
+pub mod jsonrpc;
+pub mod rest;
+pub mod subscriptions;
@@ -0,0 +1,617 @@
+use super::{
+    auth::API_KEY_QUERY_PARAM,
+    error::RpcError,
+    jsonrpc::{
+        get_address_groups::get_address_groups,
+        get_creddd::query_group,
+        get_group_by_merkle_root::get_group_by_merkle_root,
+        get_groups::{query_groups, GroupsFilter, MAX_GROUPS_LIMIT},
+        get_latest_merkle_trees::query_latest_merkle_trees,
+        GroupData, MerkleTree,
+    },
+    server::ServerContext,
+};
+use crate::GroupType;
+use hyper::{header, Body, Method, Request, Response, StatusCode};
+use jsonrpsee::types::Params;
+use schemars::{generate::SchemaSettings, JsonSchema, SchemaGenerator};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+use tower::{Layer, Service};
+
+/// The body of the error responses
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct RestError {
+    pub error: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Route {
+    /// `/groups`
+    Groups,
+    /// `/groups/{id}`
+    Group(String),
+    /// `/groups/{id}/trees/latest`
+    GroupLatestTree(String),
+    /// `/addresses/{address}/groups`
+    AddressGroups(String),
+    /// `/roots/{root}`
+    Root(String),
+    /// `/openapi.json`
+    OpenApi,
+}
+
+impl Route {
+    fn parse(path: &str) -> Option<Route> {
+        let segments = path.trim_matches('/').split('/').collect::<Vec<&str>>();
+
+        match segments.as_slice() {
+            ["groups"] => Some(Route::Groups),
+            ["groups", id] => Some(Route::Group(id.to_string())),
+            ["groups", id, "trees", "latest"] => Some(Route::GroupLatestTree(id.to_string())),
+            ["addresses", address, "groups"] => Some(Route::AddressGroups(address.to_string())),
+            ["roots", root] => Some(Route::Root(root.to_string())),
+            ["openapi.json"] => Some(Route::OpenApi),
+            _ => None,
+        }
+    }
+
+    /// The JSON-RPC method of the same resource
+    fn method(&self) -> Option<&'static str> {
+        match self {
+            Route::Groups => Some("getGroups"),
+            Route::Group(_) => Some("getCreddd"),
+            Route::GroupLatestTree(_) => Some("getGroupLatestMerkleTree"),
+            Route::AddressGroups(_) => Some("getAddressGroups"),
+            Route::Root(_) => Some("getGroupByMerkleRoot"),
+            Route::OpenApi => None,
+        }
+    }
+}
+
+/// Returns true if the request is for a REST route
+pub fn is_rest_request(req: &Request<Body>) -> bool {
+    matches!(*req.method(), Method::GET | Method::HEAD) && Route::parse(req.uri().path()).is_some()
+}
+
+/// The JSON-RPC method a REST request is served by,
+/// so the same API key checks apply to both
+pub fn rest_method(req: &Request<Body>) -> Option<&'static str> {
+    if !is_rest_request(req) {
+        return None;
+    }
+
+    Route::parse(req.uri().path()).and_then(|route| route.method())
+}
+
+/// Returns the decoded query parameters of a request
+fn query_params(query: Option<&str>) -> Vec<(String, String)> {
+    let mut url = surf::Url::parse("http://localhost/").unwrap();
+    url.set_query(query);
+
+    url.query_pairs().into_owned().collect()
+}
+
+/// Parse the query parameters of `/groups` into a filter,
+/// e.g. `/groups?type_id=Whale&limit=100&cursor=<id>`
+fn parse_groups_query(params: &[(String, String)]) -> Result<GroupsFilter, RpcError> {
+    let mut filter = GroupsFilter::default();
+
+    for (name, value) in params {
+        let invalid = || RpcError::invalid_params(format!("Invalid {}", name));
+
+        match name.as_str() {
+            "cursor" => filter.cursor = Some(value.clone()),
+            "limit" => filter.limit = Some(value.parse().map_err(|_| invalid())?),
+            "type_id" => {
+                filter.type_id = Some(serde_json::from_value(json!(value)).map_err(|_| invalid())?)
+            }
+            "chain" => filter.chain = Some(value.clone()),
+            "contract" => filter.contract = Some(value.clone()),
+            "min_score" => filter.min_score = Some(value.parse().map_err(|_| invalid())?),
+            API_KEY_QUERY_PARAM => {}
+            _ => {
+                return Err(RpcError::invalid_params(format!(
+                    "Unknown query parameter {}",
+                    name
+                )))
+            }
+        }
+    }
+
+    Ok(filter)
+}
+
+/// Returns the `Link` header value of the page after the `cursor` group id,
+/// with the same filter as the current page.
+/// The API key isn't repeated in the link, so it doesn't end up in the logs of caches and proxies.
+fn next_groups_link(params: &[(String, String)], cursor: &str) -> String {
+    let mut url = surf::Url::parse("http://localhost/groups").unwrap();
+    url.query_pairs_mut()
+        .extend_pairs(
+            params
+                .iter()
+                .filter(|(name, _)| name != "cursor" && name != API_KEY_QUERY_PARAM),
+        )
+        .append_pair("cursor", cursor);
+
+    format!("<{}?{}>; rel=\"next\"", url.path(), url.query().unwrap())
+}
+
+/// Get a page of the groups, and the `Link` header of the next page if the page is full
+async fn get_groups_page(
+    query: Option<&str>,
+    ctx: &ServerContext,
+) -> Result<(Value, Option<String>), RpcError> {
+    let params = query_params(query);
+    let filter = parse_groups_query(&params)?;
+
+    let groups = query_groups(&ctx.pg_client, &filter).await?;
+
+    let is_full_page = filter.limit.map_or(false, |limit| {
+        groups.len() >= limit.min(MAX_GROUPS_LIMIT) as usize
+    });
+    let next = groups
+        .last()
+        .filter(|_| is_full_page)
+        .map(|group| next_groups_link(&params, &group.id));
+
+    Ok((json!(groups), next))
+}
+
+/// Get the response of a route from the JSON-RPC handler of the same resource,
+/// and the `Link` header of the next page for the paginated routes
+async fn get_route(
+    route: &Route,
+    query: Option<&str>,
+    ctx: &ServerContext,
+) -> Result<(Value, Option<String>), RpcError> {
+    let value = match route {
+        Route::Groups => return get_groups_page(query, ctx).await,
+        Route::Group(id) => query_group(&ctx.pg_client, id)
+            .await?
+            .map(|group| json!(group))
+            .ok_or_else(|| RpcError::not_found("No group found for the given group id")),
+        Route::GroupLatestTree(id) => query_latest_merkle_trees(&ctx.pg_client, Some(id))
+            .await?
+            .pop()
+            .map(|merkle_tree| json!(merkle_tree))
+            .ok_or_else(|| RpcError::not_found("No Merkle tree found for the given group id")),
+        Route::AddressGroups(address) => {
+            let params = json!([address]).to_string();
+            get_address_groups(
+                Params::new(Some(&params)),
+                &ctx.pg_client,
+                ctx.rocksdb_conn.clone(),
+            )
+            .await
+        }
+        Route::Root(root) => {
+            let params = json!([root]).to_string();
+            get_group_by_merkle_root(Params::new(Some(&params)), &ctx.pg_client).await
+        }
+        Route::OpenApi => Ok(openapi_document()),
+    }?;
+
+    Ok((value, None))
+}
+
+/// Returns the strong ETag of a response body
+fn etag(body: &[u8]) -> String {
+    format!("\"{}\"", hex::encode(&Sha256::digest(body)[..16]))
+}
+
+/// Returns true if the `If-None-Match` header matches the ETag
+fn matches_etag(if_none_match: &str, etag: &str) -> bool {
+    if_none_match
+        .split(',')
+        .map(|tag| tag.trim().trim_start_matches("W/"))
+        .any(|tag| tag == etag || tag == "*")
+}
+
+pub fn json_response(status: StatusCode, body: Vec<u8>) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body))
+        .unwrap()
+}
+
+async fn respond(req: &Request<Body>, route: &Route, ctx: &ServerContext) -> Response<Body> {
+    match get_route(route, req.uri().query(), ctx).await {
+        Ok((value, next)) => {
+            let body = serde_json::to_vec(&value).unwrap();
+            let etag = etag(&body);
+
+            let is_not_modified = req
+                .headers()
+                .get(header::IF_NONE_MATCH)
+                .and_then(|if_none_match| if_none_match.to_str().ok())
+                .map_or(false, |if_none_match| matches_etag(if_none_match, &etag));
+
+            let mut response = if is_not_modified {
+                Response::builder()
+                    .status(StatusCode::NOT_MODIFIED)
+                    .body(Body::empty())
+                    .unwrap()
+            } else {
+                json_response(StatusCode::OK, body)
+            };
+
+            response
+                .headers_mut()
+                .insert(header::ETAG, etag.parse().unwrap());
+
+            if let Some(next) = next {
+                response
+                    .headers_mut()
+                    .insert(header::LINK, next.parse().unwrap());
+            }
+
+            response
+        }
+        Err(err) => {
+            let (status, error) = match err {
+                RpcError::InvalidParams(message) => (StatusCode::BAD_REQUEST, message),
+                RpcError::NotFound(message) => (StatusCode::NOT_FOUND, message),
+                RpcError::Internal => (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Internal error".to_string(),
+                ),
+            };
+
+            json_response(status, serde_json::to_vec(&RestError { error }).unwrap())
+        }
+    }
+}
+
+/// Returns an OpenAPI GET operation with the path parameters and the schema of the response
+fn get_operation(
+    generator: &mut SchemaGenerator,
+    summary: &str,
+    path_params: &[(&str, &str)],
+    response: Value,
+) -> Value {
+    let parameters = path_params
+        .iter()
+        .map(|(name, description)| {
+            json!({
+                "name": name,
+                "in": "path",
+                "required": true,
+                "description": description,
+                "schema": { "type": "string" },
+            })
+        })
+        .collect::<Vec<Value>>();
+
+    let error = generator.subschema_for::<RestError>();
+    let error_response = |description: &str| {
+        json!({
+            "description": description,
+            "content": { "application/json": { "schema": error } },
+        })
+    };
+
+    json!({
+        "get": {
+            "summary": summary,
+            "parameters": parameters,
+            "responses": {
+                "200": {
+                    "description": "OK",
+                    "headers": {
+                        "ETag": { "schema": { "type": "string" } },
+                    },
+                    "content": { "application/json": { "schema": response } },
+                },
+                "304": { "description": "Not modified since the ETag in If-None-Match" },
+                "400": error_response("Invalid parameters"),
+                "404": error_response("Not found"),
+                "500": error_response("Internal error"),
+            },
+        }
+    })
+}
+
+/// The OpenAPI query parameters of `/groups`, which are the fields of `GroupsFilter`
+fn groups_query_params(generator: &mut SchemaGenerator) -> Value {
+    let string = json!({ "type": "string" });
+    let integer = json!({ "type": "integer", "minimum": 0 });
+    let limit_description = format!(
+        "Maximum number of groups to return, at most {}. All groups are returned if not set.",
+        MAX_GROUPS_LIMIT
+    );
+
+    let params = [
+        (
+            "cursor",
+            "Return the groups after this group id. \
+            Set to the id of the last group of the previous page, as in the `next` link.",
+            string.clone(),
+        ),
+        ("limit", limit_description.as_str(), integer.clone()),
+        (
+            "type_id",
+            "Return the groups of this type",
+            json!(generator.subschema_for::<GroupType>()),
+        ),
+        (
+            "chain",
+            "Return the groups with a contract on this chain (e.g. \"mainnet\", \"base\")",
+            string.clone(),
+        ),
+        (
+            "contract",
+            "Return the groups of this hex encoded contract address",
+            string,
+        ),
+        (
+            "min_score",
+            "Return the groups with at least this score",
+            integer,
+        ),
+    ];
+
+    params
+        .into_iter()
+        .map(|(name, description, schema)| {
+            json!({
+                "name": name,
+                "in": "query",
+                "required": false,
+                "description": description,
+                "schema": schema,
+            })
+        })
+        .collect()
+}
+
+/// The OpenAPI document of the REST API, with the schemas generated from the response types
+pub fn openapi_document() -> Value {
+    let mut generator = SchemaSettings::openapi3().into_generator();
+
+    let group = json!(generator.subschema_for::<GroupData>());
+    let groups = json!(generator.subschema_for::<Vec<GroupData>>());
+    let merkle_tree = json!(generator.subschema_for::<MerkleTree>());
+
+    let mut groups_operation = get_operation(
+        &mut generator,
+        "Get the recordable groups, in ascending order of id",
+        &[],
+        groups.clone(),
+    );
+    groups_operation["get"]["parameters"] = groups_query_params(&mut generator);
+    groups_operation["get"]["responses"]["200"]["headers"]["Link"] = json!({
+        "description": "The link to the next page with `rel=\"next\"`, if `limit` is set and the page is full",
+        "schema": { "type": "string" },
+    });
+
+    let paths = json!({
+        "/groups": groups_operation,
+        "/groups/{id}": get_operation(
+            &mut generator,
+            "Get a group",
+            &[("id", "The group id")],
+            group.clone(),
+        ),
+        "/groups/{id}/trees/latest": get_operation(
+            &mut generator,
+            "Get the latest Merkle tree of a group",
+            &[("id", "The group id")],
+            merkle_tree,
+        ),
+        "/addresses/{address}/groups": get_operation(
+            &mut generator,
+            "Get the groups an address is a member of",
+            &[("address", "The hex encoded address")],
+            groups,
+        ),
+        "/roots/{root}": get_operation(
+            &mut generator,
+            "Get the group of a Merkle root",
+            &[("root", "The hex encoded Merkle root")],
+            group,
+        ),
+    });
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "creddd API",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": paths,
+        "components": {
+            "schemas": generator.take_definitions(true),
+        },
+    })
+}
+
+/// Serves the REST API in front of the JSON-RPC server.
+/// The requests that aren't for a REST route are passed on to the JSON-RPC server.
+#[derive(Clone)]
+pub struct RestLayer {
+    ctx: Arc<ServerContext>,
+}
+
+impl RestLayer {
+    pub fn new(ctx: Arc<ServerContext>) -> Self {
+        Self { ctx }
+    }
+}
+
+impl<S> Layer<S> for RestLayer {
+    type Service = RestService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RestService {
+            inner,
+            ctx: self.ctx.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RestService<S> {
+    inner: S,
+    ctx: Arc<ServerContext>,
+}
+
+impl<S> Service<Request<Body>> for RestService<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>>,
+    S::Error: Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response<Body>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let route = match *req.method() {
+            Method::GET | Method::HEAD => Route::parse(req.uri().path()),
+            _ => None,
+        };
+
+        match route {
+            Some(route) => {
+                let ctx = self.ctx.clone();
+                Box::pin(async move { Ok(respond(&req, &route, &ctx).await) })
+            }
+            None => Box::pin(self.inner.call(req)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_route() {
+        assert_eq!(Route::parse("/groups"), Some(Route::Groups));
+        assert_eq!(
+            Route::parse("/groups/0x01/"),
+            Some(Route::Group("0x01".to_string()))
+        );
+        assert_eq!(
+            Route::parse("/groups/0x01/trees/latest"),
+            Some(Route::GroupLatestTree("0x01".to_string()))
+        );
+        assert_eq!(
+            Route::parse("/addresses/0x02/groups"),
+            Some(Route::AddressGroups("0x02".to_string()))
+        );
+        assert_eq!(
+            Route::parse("/roots/0x03"),
+            Some(Route::Root("0x03".to_string()))
+        );
+
+        // The JSON-RPC requests are at the root
+        assert_eq!(Route::parse("/"), None);
+        assert_eq!(Route::parse("/groups/0x01/trees"), None);
+    }
+
+    #[test]
+    fn test_route_method() {
+        assert_eq!(Route::Groups.method(), Some("getGroups"));
+        assert_eq!(
+            Route::GroupLatestTree("0x01".to_string()).method(),
+            Some("getGroupLatestMerkleTree")
+        );
+        assert_eq!(Route::OpenApi.method(), None);
+    }
+
+    #[test]
+    fn test_parse_groups_query() {
+        assert_eq!(
+            parse_groups_query(&query_params(None)),
+            Ok(GroupsFilter::default())
+        );
+
+        let params = query_params(Some(
+            "cursor=a%2Fb&limit=10&type_id=Whale&chain=base&min_score=100&api_key=key",
+        ));
+        assert_eq!(
+            parse_groups_query(&params),
+            Ok(GroupsFilter {
+                cursor: Some("a/b".to_string()),
+                limit: Some(10),
+                type_id: Some(GroupType::Whale),
+                chain: Some("base".to_string()),
+                min_score: Some(100),
+                ..Default::default()
+            })
+        );
+
+        assert!(parse_groups_query(&query_params(Some("limit=-1"))).is_err());
+        assert!(parse_groups_query(&query_params(Some("type_id=Unknown"))).is_err());
+        assert!(parse_groups_query(&query_params(Some("unknown=1"))).is_err());
+    }
+
+    #[test]
+    fn test_next_groups_link() {
+        let params = query_params(Some("type_id=Whale&limit=2&cursor=a&api_key=key"));
+
+        assert_eq!(
+            next_groups_link(&params, "b c"),
+            "</groups?type_id=Whale&limit=2&cursor=b+c>; rel=\"next\""
+        );
+    }
+
+    #[test]
+    fn test_etag() {
+        let etag = etag(b"[]");
+
+        assert!(matches_etag(&etag, &etag));
+        assert!(matches_etag(&format!("\"other\", W/{}", etag), &etag));
+        assert!(matches_etag("*", &etag));
+        assert!(!matches_etag("\"other\"", &etag));
+    }
+
+    #[test]
+    fn test_openapi_document() {
+        let document = openapi_document();
+
+        let schemas = document["components"]["schemas"].as_object().unwrap();
+        assert!(schemas.contains_key("GroupData"));
+        assert!(schemas.contains_key("MerkleTree"));
+        assert!(schemas.contains_key("RestError"));
+
+        assert_eq!(
+            document["paths"]["/groups/{id}"]["get"]["responses"]["200"]["content"]
+                ["application/json"]["schema"]["$ref"],
+            "#/components/schemas/GroupData"
+        );
+
+        let groups_params = document["paths"]["/groups"]["get"]["parameters"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|param| param["name"].as_str().unwrap())
+            .collect::<Vec<&str>>();
+        assert_eq!(
+            groups_params,
+            [
+                "cursor",
+                "limit",
+                "type_id",
+                "chain",
+                "contract",
+                "min_score"
+            ]
+        );
+    }
+}
@@ -1,80 +1,228 @@
+use crate::server::auth::{ApiKeys, RestAuthLayer, RpcAuthLayer};
 use crate::server::jsonrpc::get_group_latest_merkle_tree::get_group_latest_merkle_tree;
-use crate::server::jsonrpc::get_latest_merkle_trees::{self, get_latest_merkle_trees};
+use crate::server::jsonrpc::get_latest_merkle_trees::get_latest_merkle_trees;
 use crate::server::jsonrpc::{
-    get_address_groups::get_address_groups, get_creddd::get_creddd,
+    get_address_groups::{get_address_groups, get_addresses_groups},
+    get_creddd::get_creddd,
     get_group_by_merkle_root::get_group_by_merkle_root,
-    get_group_merkle_tree::get_group_merkle_tree, get_groups::get_groups,
+    get_group_changes::get_group_changes,
+    get_group_merkle_tree::get_group_merkle_tree,
+    get_groups::get_groups,
+    get_membership_at::get_membership_at,
+    get_merkle_proof::get_merkle_proof,
+    register_nullifier::register_nullifier,
+    verify_membership_proof::verify_membership_proof,
 };
-use jsonrpc_http_server::jsonrpc_core::*;
-use jsonrpc_http_server::*;
-use log::info;
+use crate::server::rest::RestLayer;
+use crate::server::subscriptions::subscribe;
+use crate::shutdown::Shutdown;
+use crate::tree_events::TreeEventEmitter;
+use hyper::server::conn::AddrStream;
+use hyper::service::{make_service_fn, service_fn};
+use jsonrpsee::server::middleware::rpc::RpcServiceBuilder;
+use jsonrpsee::server::{stop_channel, Methods, RpcModule, Server};
+use log::{error, info};
 use rocksdb::DB;
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::SocketAddr;
 use std::sync::Arc;
+use tower::Service;
+use tower_http::cors::CorsLayer;
+
+/// Maximum size of the JSON-RPC responses.
+/// `getLatestMerkleTrees` returns the bloom filters of all groups,
+/// which exceeds the 10 MiB jsonrpsee defaults to.
+const MAX_RESPONSE_BODY_SIZE: u32 = 256 * 1024 * 1024;
+
+/// The resources shared by the JSON-RPC methods and the REST API
+#[derive(Clone)]
+pub struct ServerContext {
+    pub pg_client: Arc<tokio_postgres::Client>,
+    pub rocksdb_conn: Arc<DB>,
+    /// The tree events the subscriptions are sent from
+    pub tree_events: Arc<TreeEventEmitter>,
+}
 
-pub async fn start_server(rocksdb_conn: Arc<DB>, pg_client: Arc<tokio_postgres::Client>) {
-    let mut io = IoHandler::default();
-
-    let pg_client_moved = pg_client.clone();
-    io.add_method("getAddressGroups", move |params: Params| {
-        let rocksdb_conn = rocksdb_conn.clone();
-        let pg_client_moved = pg_client_moved.clone();
-
-        async move { get_address_groups(params, &pg_client_moved, rocksdb_conn).await }
-    });
-
-    let pg_client_moved = pg_client.clone();
-    io.add_method("getGroupByMerkleRoot", move |params: Params| {
-        let pg_client_moved = pg_client_moved.clone();
-
-        async move { get_group_by_merkle_root(params, &pg_client_moved).await }
-    });
-
-    let pg_client_moved = pg_client.clone();
-    io.add_method("getGroupMerkleTree", move |params: Params| {
-        let pg_client_moved = pg_client_moved.clone();
-
-        async move { get_group_merkle_tree(params, &pg_client_moved).await }
-    });
-
-    let pg_client_moved = pg_client.clone();
-    io.add_method("getCreddd", move |params: Params| {
-        let pg_client_moved = pg_client_moved.clone();
-
-        async move { get_creddd(params, &pg_client_moved).await }
-    });
-
-    let pg_client_moved = pg_client.clone();
-    io.add_method("getGroups", move |params: Params| {
-        let pg_client_moved = pg_client_moved.clone();
-
-        async move { get_groups(params, &pg_client_moved).await }
-    });
-
-    let pg_client_moved = pg_client.clone();
-    io.add_method("getLatestMerkleTrees", move |params: Params| {
-        let pg_client_moved = pg_client_moved.clone();
-
-        async move { get_latest_merkle_trees(params, &pg_client_moved).await }
-    });
-
-    let pg_client_moved = pg_client.clone();
-    io.add_method("getGroupLatestMerkleTree", move |params: Params| {
-        let pg_client_moved = pg_client_moved.clone();
+/// Create the JSON-RPC methods and subscriptions
+pub fn rpc_module(ctx: ServerContext) -> RpcModule<ServerContext> {
+    let mut module = RpcModule::new(ctx);
+
+    module
+        .register_async_method("getAddressGroups", |params, ctx| async move {
+            get_address_groups(params, &ctx.pg_client, ctx.rocksdb_conn.clone()).await
+        })
+        .unwrap();
+
+    module
+        .register_async_method("getAddressesGroups", |params, ctx| async move {
+            get_addresses_groups(params, &ctx.pg_client, ctx.rocksdb_conn.clone()).await
+        })
+        .unwrap();
+
+    module
+        .register_async_method("getGroupByMerkleRoot", |params, ctx| async move {
+            get_group_by_merkle_root(params, &ctx.pg_client).await
+        })
+        .unwrap();
+
+    module
+        .register_async_method("getGroupMerkleTree", |params, ctx| async move {
+            get_group_merkle_tree(params, &ctx.pg_client).await
+        })
+        .unwrap();
+
+    module
+        .register_async_method("getCreddd", |params, ctx| async move {
+            get_creddd(params, &ctx.pg_client).await
+        })
+        .unwrap();
+
+    module
+        .register_async_method("getGroups", |params, ctx| async move {
+            get_groups(params, &ctx.pg_client).await
+        })
+        .unwrap();
+
+    module
+        .register_async_method("getLatestMerkleTrees", |params, ctx| async move {
+            get_latest_merkle_trees(params, &ctx.pg_client).await
+        })
+        .unwrap();
+
+    module
+        .register_async_method("getGroupLatestMerkleTree", |params, ctx| async move {
+            get_group_latest_merkle_tree(params, &ctx.pg_client).await
+        })
+        .unwrap();
+
+    module
+        .register_async_method("verifyMembershipProof", |params, ctx| async move {
+            verify_membership_proof(params, &ctx.pg_client).await
+        })
+        .unwrap();
+
+    module
+        .register_async_method("registerNullifier", |params, ctx| async move {
+            register_nullifier(params, &ctx.pg_client).await
+        })
+        .unwrap();
+
+    module
+        .register_async_method("getMerkleProof", |params, ctx| async move {
+            get_merkle_proof(params, &ctx.pg_client).await
+        })
+        .unwrap();
+
+    module
+        .register_async_method("getMembershipAt", |params, ctx| async move {
+            get_membership_at(params, &ctx.pg_client).await
+        })
+        .unwrap();
+
+    module
+        .register_async_method("getGroupChanges", |params, ctx| async move {
+            get_group_changes(params, &ctx.pg_client).await
+        })
+        .unwrap();
+
+    // The notifications are sent as `subscription` with the subscription id
+    module
+        .register_subscription("subscribe", "subscription", "unsubscribe", subscribe)
+        .unwrap();
+
+    module
+}
 
-        async move { get_group_latest_merkle_tree(params, &pg_client_moved).await }
-    });
+/// Serve the JSON-RPC methods over HTTP and WebSocket, and the REST API,
+/// on the same port until `shutdown` resolves
+pub async fn start_server(ctx: ServerContext, shutdown: impl Future<Output = ()>) {
+    let api_keys = Arc::new(ApiKeys::from_env());
+    api_keys
+        .load(&ctx.pg_client)
+        .await
+        .expect("Failed to load the API keys");
+
+    // The API key sync is stopped after the server, so the usage of the last requests is saved
+    let (api_keys_sync_trigger, api_keys_sync_shutdown) = Shutdown::new();
+    let api_keys_sync = tokio::spawn(
+        api_keys
+            .clone()
+            .sync(ctx.pg_client.clone(), api_keys_sync_shutdown),
+    );
+
+    let rest_layer = RestLayer::new(Arc::new(ctx.clone()));
+    let methods: Methods = rpc_module(ctx).into();
 
     let port = std::env::var("PORT").unwrap_or_else(|_| "3030".to_string());
 
-    let addr = format!("0.0.0.0:{}", port);
+    let addr = format!("0.0.0.0:{}", port)
+        .parse::<SocketAddr>()
+        .expect("Invalid PORT");
     info!("Starting server at {}", addr);
 
-    let server = ServerBuilder::new(io)
-        .cors(DomainsValidation::AllowOnly(vec![
-            AccessControlAllowOrigin::Any,
-        ]))
-        .start_http(&addr.parse().unwrap())
-        .expect("Unable to start RPC server");
-
-    server.wait();
+    let (stop_handle, server_handle) = stop_channel();
+
+    // The services are built for each request, to check the API key of the caller
+    let make_service = {
+        let api_keys = api_keys.clone();
+        let stop_handle = stop_handle.clone();
+
+        make_service_fn(move |conn: &AddrStream| {
+            let remote_ip = conn.remote_addr().ip();
+            let api_keys = api_keys.clone();
+            let rest_layer = rest_layer.clone();
+            let methods = methods.clone();
+            let stop_handle = stop_handle.clone();
+
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req| {
+                    let caller = Arc::new(api_keys.caller(&req, remote_ip));
+
+                    // Allow requests from any origin, and check the API key of the REST requests
+                    let http_middleware = tower::ServiceBuilder::new()
+                        .layer(CorsLayer::permissive())
+                        .layer(RestAuthLayer::new(api_keys.clone(), caller.clone()))
+                        .layer(rest_layer.clone());
+
+                    // Check the API key of each JSON-RPC call over HTTP and WebSocket
+                    let rpc_middleware =
+                        RpcServiceBuilder::new().layer(RpcAuthLayer::new(api_keys.clone(), caller));
+
+                    let mut service = Server::builder()
+                        .max_response_body_size(MAX_RESPONSE_BODY_SIZE)
+                        .set_http_middleware(http_middleware)
+                        .set_rpc_middleware(rpc_middleware)
+                        .to_service_builder()
+                        .build(methods.clone(), stop_handle.clone());
+
+                    async move { service.call(req).await }
+                }))
+            }
+        })
+    };
+
+    let server = hyper::Server::try_bind(&addr)
+        .expect("Unable to start RPC server")
+        .serve(make_service)
+        .with_graceful_shutdown(stop_handle.shutdown());
+
+    let server = tokio::spawn(server);
+
+    shutdown.await;
+
+    // Stop accepting connections and wait for the requests in progress to finish
+    info!("Shutting down the server");
+    let _ = server_handle.stop();
+
+    match server.await {
+        Ok(Err(err)) => error!("Server error: {:?}", err),
+        Err(err) => error!("Server thread failed: {:?}", err),
+        Ok(Ok(())) => {}
+    }
+
+    api_keys_sync_trigger.trigger();
+    if let Err(err) = api_keys_sync.await {
+        error!("API key sync thread failed: {:?}", err);
+    }
 }
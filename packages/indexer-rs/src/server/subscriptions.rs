@@ -0,0 +1,270 @@
+use super::{
+    error::{parse_address, parse_params, RpcError},
+    jsonrpc::{get_address_groups::get_groups_of_address, GroupData},
+    server::ServerContext,
+};
+use crate::{address_groups::AddressGroups, tree_events::TreeEvent, Address, BlockNum};
+use jsonrpsee::{
+    core::SubscriptionResult,
+    server::{PendingSubscriptionSink, SubscriptionMessage, SubscriptionSink},
+    types::Params,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::Arc;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+/// A new Merkle root of a group, sent to the `groupRoots` subscribers
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GroupRootData {
+    pub group_id: String,
+    pub merkle_root: String,
+    pub block_number: BlockNum,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Subscription {
+    /// The new Merkle roots of all groups
+    GroupRoots,
+    /// The groups of an address, whenever they change
+    AddressGroups(Address),
+}
+
+fn parse_subscription(params: &Params<'_>) -> Result<Subscription, RpcError> {
+    let params: Vec<Value> = parse_params(params)?;
+
+    match params.first().and_then(|topic| topic.as_str()) {
+        Some("groupRoots") if params.len() == 1 => Ok(Subscription::GroupRoots),
+        Some("addressGroups") if params.len() == 2 => {
+            let address = params[1]
+                .as_str()
+                .ok_or_else(|| RpcError::invalid_params("Invalid address"))
+                .and_then(parse_address)?;
+
+            Ok(Subscription::AddressGroups(address))
+        }
+        _ => Err(RpcError::invalid_params(
+            r#"Expected ["groupRoots"] or ["addressGroups", address]"#,
+        )),
+    }
+}
+
+/// Handle `subscribe(["groupRoots"])` and `subscribe(["addressGroups", address])`
+pub async fn subscribe(
+    params: Params<'static>,
+    pending: PendingSubscriptionSink,
+    ctx: Arc<ServerContext>,
+) -> SubscriptionResult {
+    let subscription = match parse_subscription(&params) {
+        Ok(subscription) => subscription,
+        Err(err) => {
+            pending.reject(err).await;
+            return Ok(());
+        }
+    };
+
+    // Subscribe to the events before accepting, so no event is missed in between
+    let events = ctx.tree_events.subscribe();
+    let sink = pending.accept().await?;
+
+    match subscription {
+        Subscription::GroupRoots => send_group_roots(sink, events).await,
+        Subscription::AddressGroups(address) => {
+            send_address_groups(sink, events, address, &ctx).await
+        }
+    }
+}
+
+/// Wait for the next tree event, or `None` if the subscriber has unsubscribed
+async fn next_event(
+    sink: &SubscriptionSink,
+    events: &mut broadcast::Receiver<TreeEvent>,
+) -> Option<Result<TreeEvent, RecvError>> {
+    tokio::select! {
+        _ = sink.closed() => None,
+        event = events.recv() => Some(event),
+    }
+}
+
+async fn send_group_roots(
+    sink: SubscriptionSink,
+    mut events: broadcast::Receiver<TreeEvent>,
+) -> SubscriptionResult {
+    while let Some(event) = next_event(&sink, &mut events).await {
+        match event {
+            Ok(TreeEvent::TreeSaved {
+                group_id,
+                block_number,
+                merkle_root,
+                ..
+            }) => {
+                let group_root = GroupRootData {
+                    group_id,
+                    merkle_root,
+                    block_number,
+                };
+
+                sink.send(SubscriptionMessage::from_json(&group_root)?)
+                    .await?;
+            }
+            // The roots missed by falling behind are skipped
+            Ok(_) | Err(RecvError::Lagged(_)) => {}
+            Err(RecvError::Closed) => break,
+        }
+    }
+
+    Ok(())
+}
+
+fn group_ids(groups: &[GroupData]) -> Vec<&str> {
+    groups.iter().map(|group| group.id.as_str()).collect()
+}
+
+/// Returns true if the address was added to the group by the saved tree.
+/// The address -> group ids mapping only grows, and is up to date when `TreeSaved` is received,
+/// so the groups of the address only change if the saved group is new to the mapping.
+fn is_added_to_group(
+    address: Address,
+    group_id: &str,
+    groups: &[GroupData],
+    rocksdb_conn: Arc<rocksdb::DB>,
+) -> bool {
+    if groups.iter().any(|group| group.id == group_id) {
+        return false;
+    }
+
+    let group_id_bytes = match hex::decode(group_id).map(<[u8; 32]>::try_from) {
+        Ok(Ok(group_id_bytes)) => group_id_bytes,
+        _ => return false,
+    };
+
+    AddressGroups::get(address, rocksdb_conn).map_or(false, |address_groups| {
+        address_groups.group_ids.contains(&group_id_bytes)
+    })
+}
+
+async fn send_address_groups(
+    sink: SubscriptionSink,
+    mut events: broadcast::Receiver<TreeEvent>,
+    address: Address,
+    ctx: &ServerContext,
+) -> SubscriptionResult {
+    // Send the current groups first
+    let mut groups =
+        get_groups_of_address(address, &ctx.pg_client, ctx.rocksdb_conn.clone()).await?;
+    sink.send(SubscriptionMessage::from_json(&groups)?).await?;
+
+    while let Some(event) = next_event(&sink, &mut events).await {
+        // The events are filtered in memory, so Postgres is only queried
+        // when the groups of the address have changed
+        let new_groups = match event {
+            Ok(TreeEvent::TreeSaved { group_id, .. }) => {
+                if !is_added_to_group(address, &group_id, &groups, ctx.rocksdb_conn.clone()) {
+                    continue;
+                }
+
+                get_groups_of_address(address, &ctx.pg_client, ctx.rocksdb_conn.clone()).await?
+            }
+            Ok(TreeEvent::GroupUnrecordable { group_id, .. }) => groups
+                .iter()
+                .filter(|group| group.id != group_id)
+                .cloned()
+                .collect(),
+            // The groups may have changed in the events missed by falling behind
+            Err(RecvError::Lagged(_)) => {
+                get_groups_of_address(address, &ctx.pg_client, ctx.rocksdb_conn.clone()).await?
+            }
+            Ok(TreeEvent::TreeRefreshed { .. }) => continue,
+            Err(RecvError::Closed) => break,
+        };
+
+        if group_ids(&new_groups) != group_ids(&groups) {
+            sink.send(SubscriptionMessage::from_json(&new_groups)?)
+                .await?;
+            groups = new_groups;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{test_utils::delete_all, GroupType, ROCKSDB_PATH};
+    use rocksdb::{Options, DB};
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_parse_subscription() {
+        let params = Params::new(Some(r#"["groupRoots"]"#));
+        assert_eq!(parse_subscription(&params), Ok(Subscription::GroupRoots));
+
+        let params = Params::new(Some(
+            r#"["addressGroups", "0x0101010101010101010101010101010101010101"]"#,
+        ));
+        assert_eq!(
+            parse_subscription(&params),
+            Ok(Subscription::AddressGroups([1; 20]))
+        );
+
+        let params = Params::new(Some(r#"["addressGroups", "0x01"]"#));
+        assert!(parse_subscription(&params).is_err());
+
+        let params = Params::new(Some(r#"["unknown"]"#));
+        assert!(parse_subscription(&params).is_err());
+    }
+
+    #[test]
+    fn test_is_added_to_group() {
+        const TEST_ROCKSDB_PATH: &str = "test_is_added_to_group";
+
+        let mut rocksdb_options = Options::default();
+        rocksdb_options.create_if_missing(true);
+
+        let rocksdb_client = DB::open(
+            &rocksdb_options,
+            format!("{}/{}", ROCKSDB_PATH, TEST_ROCKSDB_PATH),
+        )
+        .unwrap();
+
+        let rocksdb_client = Arc::new(rocksdb_client);
+
+        // Delete all records from the test db
+        delete_all(&rocksdb_client);
+
+        let address: Address = [3; 20];
+        let group_ids = HashSet::from([[1u8; 32], [2u8; 32]]);
+        AddressGroups::create(address, group_ids, rocksdb_client.clone());
+
+        let groups = vec![GroupData {
+            id: hex::encode([1u8; 32]),
+            display_name: "Group 1".to_string(),
+            type_id: GroupType::Static,
+        }];
+
+        // The address already has the group
+        assert!(!is_added_to_group(
+            address,
+            &hex::encode([1u8; 32]),
+            &groups,
+            rocksdb_client.clone()
+        ));
+
+        // The address was added to the group
+        assert!(is_added_to_group(
+            address,
+            &hex::encode([2u8; 32]),
+            &groups,
+            rocksdb_client.clone()
+        ));
+
+        // The address isn't a member of the group
+        assert!(!is_added_to_group(
+            address,
+            &hex::encode([3u8; 32]),
+            &groups,
+            rocksdb_client.clone()
+        ));
+    }
+}
@@ -0,0 +1,101 @@
+use std::time::Duration;
+use tokio::sync::watch;
+
+/// Requests the tasks holding a `Shutdown` to stop
+pub struct ShutdownTrigger {
+    sender: watch::Sender<bool>,
+}
+
+impl ShutdownTrigger {
+    pub fn trigger(&self) {
+        // Sending only fails when all the tasks have already stopped
+        let _ = self.sender.send(true);
+    }
+}
+
+/// The shutdown signal of the long-running tasks.
+/// The tasks check it between their sync cycles, so a cycle that's in progress
+/// (e.g. saving a tree) always runs to completion.
+#[derive(Clone)]
+pub struct Shutdown {
+    receiver: watch::Receiver<bool>,
+}
+
+impl Shutdown {
+    pub fn new() -> (ShutdownTrigger, Self) {
+        let (sender, receiver) = watch::channel(false);
+        (ShutdownTrigger { sender }, Self { receiver })
+    }
+
+    pub fn is_requested(&self) -> bool {
+        *self.receiver.borrow()
+    }
+
+    /// Resolves once the shutdown is requested
+    pub async fn requested(&self) {
+        let mut receiver = self.receiver.clone();
+        if receiver.wait_for(|requested| *requested).await.is_err() {
+            // The trigger was dropped without requesting the shutdown
+            std::future::pending::<()>().await;
+        }
+    }
+
+    /// Sleep for `duration`, or until the shutdown is requested
+    pub async fn sleep(&self, duration: Duration) {
+        tokio::select! {
+            _ = tokio::time::sleep(duration) => {}
+            _ = self.requested() => {}
+        }
+    }
+}
+
+/// Resolves on Ctrl-C or SIGTERM
+pub async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c().await.unwrap();
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .unwrap()
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Instant;
+
+    #[tokio::test]
+    async fn test_shutdown() {
+        let (trigger, shutdown) = Shutdown::new();
+        assert!(!shutdown.is_requested());
+
+        let waiting = shutdown.clone();
+        let sleeping = tokio::spawn(async move {
+            let start = Instant::now();
+            waiting.sleep(Duration::from_secs(300)).await;
+            start.elapsed()
+        });
+
+        trigger.trigger();
+
+        // The sleep is cut short by the shutdown
+        assert!(sleeping.await.unwrap() < Duration::from_secs(300));
+        assert!(shutdown.is_requested());
+
+        // The shutdown stays requested
+        shutdown.requested().await;
+    }
+}
@@ -1,7 +1,8 @@
 use log::{debug, error, info};
 use std::sync::Arc;
+use std::time::Instant;
 
-use crate::{group::get_all_groups, BlockNum, Error};
+use crate::{eth_rpc::EthRpcClient, group::get_all_groups, shutdown::Shutdown, BlockNum, Error};
 
 const STATUS_CHECK_INTERVAL_SECS: u64 = 300; // 5 minutes
 
@@ -50,12 +51,43 @@ async fn log_group_block_heights(pg_client: Arc<tokio_postgres::Client>) -> Resu
     Ok(())
 }
 
-pub async fn start_status_logger(pg_client: Arc<tokio_postgres::Client>) {
-    loop {
+async fn log_rpc_endpoint_health(eth_client: &EthRpcClient) {
+    let now = Instant::now();
+
+    for (chain, label, health) in eth_client.endpoint_health().await {
+        info!(
+            "[STATUS] RPC {:?} {} requests: {}, error rate: {:.1}%, 429s: {}, latency: {}, {}",
+            chain,
+            label,
+            health.requests,
+            health.error_rate() * 100.0,
+            health.rate_limited,
+            health
+                .latency_ms
+                .map_or("-".to_string(), |latency| format!("{:.0}ms", latency)),
+            if health.is_ejected(now) {
+                "ejected"
+            } else {
+                "healthy"
+            }
+        );
+    }
+}
+
+pub async fn start_status_logger(
+    pg_client: Arc<tokio_postgres::Client>,
+    eth_client: Arc<EthRpcClient>,
+    shutdown: Shutdown,
+) {
+    while !shutdown.is_requested() {
         if let Err(e) = log_group_block_heights(pg_client.clone()).await {
             error!("[STATUS] Error logging group block heights: {:?}", e);
         }
 
-        tokio::time::sleep(tokio::time::Duration::from_secs(STATUS_CHECK_INTERVAL_SECS)).await;
+        log_rpc_endpoint_health(&eth_client).await;
+
+        shutdown
+            .sleep(tokio::time::Duration::from_secs(STATUS_CHECK_INTERVAL_SECS))
+            .await;
     }
 }
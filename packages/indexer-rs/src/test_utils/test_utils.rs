@@ -1,8 +1,13 @@
 use crate::{
     contract::{Contract, ContractType},
+    erc20_transfer_event::Erc20TransferEvent,
     eth_rpc::Chain,
-    Address, ROCKSDB_PATH,
+    merkle_tree_proto::{LeafFormat, MerkleTree, MerkleTreeLayer, MerkleTreeNode},
+    rocksdb_key::{KeyType, RocksDbKey, ERC20_TRANSFER_EVENT_ID},
+    Address, BlockNum, ContractId, TxIndex, ROCKSDB_PATH,
 };
+use num_bigint::BigUint;
+use prost::Message;
 use rocksdb::{IteratorMode, Options, WriteBatch, DB};
 use std::{env, fs::File, sync::Arc};
 
@@ -15,6 +20,7 @@ pub fn erc20_test_contract() -> Contract {
         name: "CorgiAI".to_string(),
         deployed_block: 18540899,
         chain: Chain::Mainnet,
+        events: vec![],
     }
 }
 
@@ -27,6 +33,7 @@ pub fn erc721_test_contract() -> Contract {
         name: "The187".to_string(),
         deployed_block: 17052667,
         chain: Chain::Mainnet,
+        events: vec![],
     }
 }
 
@@ -39,6 +46,7 @@ pub fn erc1155_test_contract() -> Contract {
         name: "Crypto: The Game Players".to_string(),
         deployed_block: 11088633,
         chain: Chain::Base,
+        events: vec![],
     }
 }
 
@@ -101,3 +109,49 @@ pub fn init_test_rocksdb(test_name: &str) -> Arc<DB> {
 
     Arc::new(rocksdb_conn)
 }
+
+/// Store a synthetic ERC20 transfer log as if it was synced by `LogSyncEngine`
+pub fn put_erc20_transfer(
+    rocksdb_client: &DB,
+    contract_id: ContractId,
+    block_num: BlockNum,
+    tx_index: TxIndex,
+    from: Address,
+    to: Address,
+    value: u64,
+) {
+    let key = RocksDbKey {
+        block_num: Some(block_num),
+        tx_index: Some(tx_index),
+        ..RocksDbKey::new_start_key(KeyType::EventLog, ERC20_TRANSFER_EVENT_ID, contract_id)
+    };
+
+    let value = Erc20TransferEvent {
+        from: from.to_vec(),
+        to: to.to_vec(),
+        value: BigUint::from(value).to_bytes_be(),
+    };
+
+    rocksdb_client
+        .put(key.to_bytes(), value.encode_to_vec())
+        .unwrap();
+}
+
+/// Build a tree protobuf from the `(index, node)` pairs of each layer, from the leaves up
+pub fn proto_tree(layers: &[&[(u32, &[u8])]], leaf_format: LeafFormat) -> MerkleTree {
+    MerkleTree {
+        layers: layers
+            .iter()
+            .map(|nodes| MerkleTreeLayer {
+                nodes: nodes
+                    .iter()
+                    .map(|(index, node)| MerkleTreeNode {
+                        node: node.to_vec(),
+                        index: *index,
+                    })
+                    .collect(),
+            })
+            .collect(),
+        leaf_format: leaf_format as i32,
+    }
+}
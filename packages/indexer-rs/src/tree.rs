@@ -1,19 +1,29 @@
 extern crate merkle_tree as merkle_tree_lib;
-use crate::merkle_tree_proto::{self, MerkleTreeLayer};
-use crate::utils::dev_addresses;
-use crate::{Address, BlockNum, GroupId, GroupType};
+use crate::eth_rpc::ChainBlocks;
+use crate::group_changes::{
+    member_changes, prune_member_changes, save_member_changes, MemberChanges,
+};
+use crate::tree_archive::{
+    archive_tree, get_archived_tree_at, prune_archive, ArchivedTree, RetentionPolicy,
+};
+use crate::tree_store::TreeStore;
+use crate::utils::{dev_addresses, get_chain_id};
+use crate::{Address, BlockNum, Error, GroupId, GroupType};
 use bloomfilter::Bloom;
 use log::{info, warn};
-use merkle_tree_lib::ark_ff::{BigInteger, Field, PrimeField};
+use merkle_tree_lib::ark_ff::PrimeField;
 use merkle_tree_lib::ark_secp256k1::Fq;
-use merkle_tree_lib::poseidon::constants::secp256k1_w3;
-use merkle_tree_lib::tree::MerkleTree;
 use num_bigint::BigUint;
 use prost::Message;
+use rocksdb::DB;
+use std::collections::HashMap;
 use std::env;
+use std::sync::Arc;
 
-const TREE_DEPTH: usize = 18;
-const TREE_WIDTH: usize = 3;
+/// The tree depths there are circuits for, and the depth of the trees built
+/// before the depth became configurable per group
+pub use circuits::{DEFAULT_TREE_DEPTH, TREE_DEPTHS};
+pub const TREE_WIDTH: usize = 3;
 const BLOOM_FILTER_FP_RATE: f64 = 0.005;
 
 fn to_hex(fe: Fq) -> String {
@@ -53,12 +63,18 @@ pub async fn get_group_latest_merkle_tree(
     }
 }
 
-/// Build a merkle tree from a list of addresses
+/// Update the stored merkle tree of a group to a list of addresses.
+/// With `balances`, the tree is a balance tree whose leaves commit to the balances of the addresses.
+/// The changes are staged in the returned tree, and only stored once `TreeStore::commit` is called.
 pub fn build_tree(
     group_id: GroupId,
     group_type: GroupType,
+    tree_depth: usize,
     addresses: &mut Vec<Address>,
-) -> Option<MerkleTree<Fq, TREE_WIDTH>> {
+    balances: Option<&HashMap<Address, BigUint>>,
+    rocksdb_client: Arc<DB>,
+) -> Option<TreeStore> {
+    // New members are inserted in the sorted order
     addresses.sort();
 
     let is_render = env::var("RENDER").is_ok_and(|var| var == "true");
@@ -76,96 +92,95 @@ pub fn build_tree(
         warn!("Not enough addresses to build a tree for {}", group_id);
         None
     } else {
-        let mut tree = MerkleTree::<Fq, TREE_WIDTH>::new(secp256k1_w3());
-
-        let leaves = &addresses
-            .iter()
-            .map(|chunk| {
-                let mut address = [0u8; 32];
-                address[12..].copy_from_slice(chunk);
-                Fq::from(BigUint::from_bytes_be(&address))
-            })
-            .collect::<Vec<Fq>>();
-
-        // Pad the leaves to equal the size of the tree
-        let mut padded_leaves = leaves.clone();
-        padded_leaves.resize(1 << TREE_DEPTH, Fq::ZERO);
-
-        for address in padded_leaves {
-            tree.insert(address);
+        let mut tree = TreeStore::new(rocksdb_client, &group_id, tree_depth);
+
+        let changed_leaves = match balances {
+            Some(balances) => tree.update_balances(addresses, balances),
+            None => tree.update(addresses),
+        };
+
+        match changed_leaves {
+            Some(changed_leaves) => {
+                info!(
+                    "${} {} leaves of the Merkle tree changed",
+                    group_id,
+                    changed_leaves.len()
+                );
+                Some(tree)
+            }
+            None => {
+                warn!(
+                    "Too many addresses to build a tree of depth {} for {}: {}",
+                    tree_depth,
+                    group_id,
+                    addresses.len()
+                );
+                None
+            }
         }
+    }
+}
 
-        tree.finish();
+/// Convert the block numbers of each chain to the `chainIds` and `chainBlockNumbers` columns
+fn to_chain_block_columns(chain_blocks: &ChainBlocks) -> (Vec<i32>, Vec<i64>) {
+    let mut chain_blocks = chain_blocks
+        .iter()
+        .map(|(chain, block_number)| (get_chain_id(*chain) as i32, *block_number as i64))
+        .collect::<Vec<(i32, i64)>>();
 
-        Some(tree)
-    }
+    chain_blocks.sort();
+
+    chain_blocks.into_iter().unzip()
 }
 
+/// Update the block numbers a tree is up to date at
 pub async fn update_tree_block_num(
     tree_id: i32,
     block_number: BlockNum,
+    chain_blocks: &ChainBlocks,
     pg_client: &tokio_postgres::Client,
 ) -> Result<(), tokio_postgres::Error> {
+    let (chain_ids, chain_block_numbers) = to_chain_block_columns(chain_blocks);
+
     let statement = r#"
-        UPDATE "MerkleTree" SET "blockNumber" = $1
+        UPDATE "MerkleTree" SET "blockNumber" = $1, "chainIds" = $3, "chainBlockNumbers" = $4
         WHERE "id" = $2
         "#;
 
     pg_client
-        .query(statement, &[&(block_number as i64), &tree_id])
+        .query(
+            statement,
+            &[
+                &(block_number as i64),
+                &tree_id,
+                &chain_ids,
+                &chain_block_numbers,
+            ],
+        )
         .await?;
 
     Ok(())
 }
 
+/// Save a new tree of a group.
+/// `latest_archived_tree` caches the latest archived tree of the group across the saves,
+/// and it's reconstructed from the archive if it's empty (e.g. after a restart or a failed save).
+/// Returns the members added and removed since the previous tree,
+/// which are empty for the first tree of the group.
+#[allow(clippy::too_many_arguments)]
 pub async fn save_tree(
     addresses: &[Address],
-    tree: MerkleTree<Fq, TREE_WIDTH>,
+    tree: &TreeStore,
     group_id: GroupId,
     pg_client: &tokio_postgres::Client,
     block_number: i64,
-) -> Result<(), tokio_postgres::Error> {
-    let mut poseidon = tree.poseidon.clone();
-    let merkle_root_hex = to_hex(tree.root.unwrap());
-
-    let mut precomputed = vec![Fq::ZERO];
-    for i in 0..tree.depth.unwrap() {
-        let hash = MerkleTree::hash(&mut poseidon, &[precomputed[i]; 2]);
-        precomputed.push(hash);
-    }
-
-    let layers: Vec<MerkleTreeLayer> = tree
-        .layers
-        .iter()
-        .enumerate()
-        .map(|(layer_i, layer): (usize, &Vec<Fq>)| {
-            let nodes_proto = layer
-                .iter()
-                .enumerate()
-                .flat_map(|(i, fe)| {
-                    let mut node = fe.into_bigint().to_bytes_be();
-
-                    if *fe == precomputed[layer_i] {
-                        None
-                    } else {
-                        if layer_i == 0 {
-                            node = node[12..].to_vec();
-                        }
-
-                        Some(merkle_tree_proto::MerkleTreeNode {
-                            node,
-                            index: i as u32,
-                        })
-                    }
-                })
-                .collect::<Vec<merkle_tree_proto::MerkleTreeNode>>();
-
-            merkle_tree_proto::MerkleTreeLayer { nodes: nodes_proto }
-        })
-        .collect();
-
-    let tree_protobuf = merkle_tree_proto::MerkleTree { layers };
-    let tree_bytes = tree_protobuf.encode_to_vec();
+    chain_blocks: &ChainBlocks,
+    retention: &RetentionPolicy,
+    latest_archived_tree: &mut Option<ArchivedTree>,
+) -> Result<MemberChanges, Error> {
+    let merkle_root_hex = to_hex(tree.root());
+    let tree_proto = tree.to_proto();
+    let tree_bytes = tree_proto.encode_to_vec();
 
     let mut bloom = Bloom::new_for_fp_rate(addresses.len(), BLOOM_FILTER_FP_RATE);
 
@@ -190,11 +205,13 @@ pub async fn save_tree(
     let num_bits = bloom.number_of_bits();
     let bloom_bytes = bloom.bitmap();
     let num_leaves = addresses.len() as i32;
+    let (chain_ids, chain_block_numbers) = to_chain_block_columns(chain_blocks);
+    let tree_depth = tree.depth() as i32;
 
     // Save the tree to the database
     let statement = r#"
-        INSERT INTO "MerkleTree" ("groupId", "blockNumber", "merkleRoot", "treeProtoBuf", "bloomFilter", "bloomSipKeys", "bloomNumHashes", "bloomNumBits", "numLeaves", "updatedAt")
-        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, NOW())
+        INSERT INTO "MerkleTree" ("groupId", "blockNumber", "merkleRoot", "treeProtoBuf", "bloomFilter", "bloomSipKeys", "bloomNumHashes", "bloomNumBits", "numLeaves", "chainIds", "chainBlockNumbers", "treeDepth", "updatedAt")
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, NOW())
         ON CONFLICT ("groupId", "blockNumber") DO NOTHING
         "#;
 
@@ -211,33 +228,83 @@ pub async fn save_tree(
                 &(num_hashes as i32),
                 &(num_bits as i32),
                 &(num_leaves as i32),
+                &chain_ids,
+                &chain_block_numbers,
+                &tree_depth,
             ],
         )
         .await?;
 
-    // Delete unused trees older than the current block number
+    // The cache is only kept if the save succeeds
+    let previous_tree = match latest_archived_tree.take() {
+        Some(archived_tree) if archived_tree.block_number <= block_number as BlockNum => {
+            Some(archived_tree)
+        }
+        _ => get_archived_tree_at(pg_client, &group_id, block_number as BlockNum).await?,
+    };
+
+    // Save the members added and removed since the previous tree.
+    // There's nothing to compare the first archived tree of a group to.
+    let changes = previous_tree
+        .as_ref()
+        .map(|previous_tree| member_changes(&previous_tree.tree, &tree_proto))
+        .unwrap_or_default();
+
+    if !changes.is_empty() {
+        info!(
+            "${} {} members added and {} removed at block {}",
+            group_id,
+            changes.added.len(),
+            changes.removed.len(),
+            block_number
+        );
+
+        save_member_changes(pg_client, &group_id, block_number as BlockNum, &changes).await?;
+    }
 
-    let statement = r#"
-        DELETE
-        FROM
-            "MerkleTree"
-        WHERE
-            id NOT in(
-                SELECT
-                    "treeId" FROM "FidAttestation"
-                UNION
-                SELECT
-                    "treeId" FROM "IntrinsicCreddd"
-                )
-            AND "groupId" = $1
-            AND "blockNumber" < $2
-        "#;
+    // Append the tree to the archive, from which the old trees can be reconstructed
+    let archived_tree = archive_tree(
+        pg_client,
+        &group_id,
+        block_number as BlockNum,
+        &merkle_root_hex,
+        &tree_proto,
+        previous_tree,
+    )
+    .await?;
+
+    // Delete the unused trees that are older than the retention period.
+    // The whole history is kept if there's no retention period.
+    if let Some(retention_days) = retention.retention_days {
+        let statement = r#"
+            DELETE
+            FROM
+                "MerkleTree"
+            WHERE
+                id NOT in(
+                    SELECT
+                        "treeId" FROM "FidAttestation"
+                    UNION
+                    SELECT
+                        "treeId" FROM "IntrinsicCreddd"
+                    )
+                AND "groupId" = $1
+                AND "blockNumber" < $2
+                AND "createdAt" < NOW() - make_interval(days => $3)
+            "#;
+
+        pg_client
+            .query(
+                statement,
+                &[&group_id, &block_number, &(retention_days as i32)],
+            )
+            .await?;
+    }
 
-    pg_client
-        .query(statement, &[&group_id, &block_number])
-        .await?;
+    prune_archive(pg_client, &group_id, retention).await?;
+    prune_member_changes(pg_client, &group_id, retention).await?;
 
-    // Nullify old trees for the group
+    // Nullify old trees for the group, which can be reconstructed from the archive
 
     let statement = r#"
             UPDATE
@@ -260,5 +327,7 @@ pub async fn save_tree(
         group_id, block_number
     );
 
-    Ok(())
+    *latest_archived_tree = Some(archived_tree);
+
+    Ok(changes)
 }
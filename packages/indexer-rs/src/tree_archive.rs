@@ -0,0 +1,390 @@
+use crate::{
+    merkle_tree_proto::{MerkleTree, MerkleTreeLayer, MerkleTreeNode},
+    tree_store::proto_leaf_address,
+    Address, BlockNum, Error, IndexerError,
+};
+use prost::Message;
+use std::collections::BTreeMap;
+
+/// Number of diffs between the full snapshots of the layers in the archive of a group.
+/// A tree is reconstructed from the latest snapshot before it, so this bounds the diffs to apply.
+const SNAPSHOT_INTERVAL: usize = 64;
+
+/// Compression level of the archived layers (0-10)
+const COMPRESSION_LEVEL: u8 = 6;
+
+/// How long the history of the trees is kept, read from `TREE_RETENTION_DAYS`.
+/// The whole history is kept if it isn't set.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    pub retention_days: Option<u32>,
+}
+
+impl RetentionPolicy {
+    pub fn from_env() -> Self {
+        let retention_days = std::env::var("TREE_RETENTION_DAYS").ok().map(|days| {
+            days.parse()
+                .unwrap_or_else(|_| panic!("Invalid TREE_RETENTION_DAYS {}", days))
+        });
+
+        Self { retention_days }
+    }
+}
+
+/// A tree reconstructed from the archive
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArchivedTree {
+    /// The block number the tree was built at
+    pub block_number: BlockNum,
+    pub merkle_root: String,
+    pub tree: MerkleTree,
+    /// Number of diffs applied on top of the snapshot the tree was reconstructed from
+    pub num_diffs: usize,
+}
+
+impl ArchivedTree {
+    /// Returns true if `address` has a leaf in the tree.
+    /// The commitment to the delegations in a delegation tree isn't the leaf of an address.
+    pub fn contains(&self, address: &Address) -> bool {
+        self.tree.layers.first().map_or(false, |leaves| {
+            leaves.nodes.iter().any(|node| {
+                proto_leaf_address(self.tree.leaf_format(), &node.node).as_ref() == Some(address)
+            })
+        })
+    }
+}
+
+fn to_layer_maps(tree: &MerkleTree) -> Vec<BTreeMap<u32, &[u8]>> {
+    tree.layers
+        .iter()
+        .map(|layer| {
+            layer
+                .nodes
+                .iter()
+                .map(|node| (node.index, node.node.as_slice()))
+                .collect()
+        })
+        .collect()
+}
+
+fn to_layer(nodes: BTreeMap<u32, Vec<u8>>) -> MerkleTreeLayer {
+    MerkleTreeLayer {
+        nodes: nodes
+            .into_iter()
+            .map(|(index, node)| MerkleTreeNode { node, index })
+            .collect(),
+    }
+}
+
+/// Returns the nodes of `new` that aren't the same in `old`, layer by layer.
+/// The nodes of `old` that aren't in `new` are empty in the diff.
+/// Returns `None` if the trees have different depths.
+pub fn diff_layers(old: &MerkleTree, new: &MerkleTree) -> Option<MerkleTree> {
+    if old.layers.len() != new.layers.len() {
+        return None;
+    }
+
+    let old_layers = to_layer_maps(old);
+    let new_layers = to_layer_maps(new);
+
+    let layers = old_layers
+        .iter()
+        .zip(&new_layers)
+        .map(|(old_nodes, new_nodes)| {
+            let mut diff = BTreeMap::new();
+
+            for (index, node) in new_nodes {
+                if old_nodes.get(index) != Some(node) {
+                    diff.insert(*index, node.to_vec());
+                }
+            }
+
+            for index in old_nodes.keys() {
+                if !new_nodes.contains_key(index) {
+                    diff.insert(*index, vec![]);
+                }
+            }
+
+            to_layer(diff)
+        })
+        .collect();
+
+    Some(MerkleTree {
+        layers,
+        leaf_format: new.leaf_format,
+    })
+}
+
+/// Apply a diff made by `diff_layers` to `tree`.
+/// Returns `None` if the diff is of a tree of another depth.
+pub fn apply_diff(tree: &MerkleTree, diff: &MerkleTree) -> Option<MerkleTree> {
+    if tree.layers.len() != diff.layers.len() {
+        return None;
+    }
+
+    let layers = tree
+        .layers
+        .iter()
+        .zip(&diff.layers)
+        .map(|(layer, layer_diff)| {
+            let mut nodes = layer
+                .nodes
+                .iter()
+                .map(|node| (node.index, node.node.clone()))
+                .collect::<BTreeMap<u32, Vec<u8>>>();
+
+            for node in &layer_diff.nodes {
+                if node.node.is_empty() {
+                    nodes.remove(&node.index);
+                } else {
+                    nodes.insert(node.index, node.node.clone());
+                }
+            }
+
+            to_layer(nodes)
+        })
+        .collect();
+
+    Some(MerkleTree {
+        layers,
+        leaf_format: diff.leaf_format,
+    })
+}
+
+fn compress(tree: &MerkleTree) -> Vec<u8> {
+    miniz_oxide::deflate::compress_to_vec(&tree.encode_to_vec(), COMPRESSION_LEVEL)
+}
+
+fn decompress(bytes: &[u8]) -> Result<MerkleTree, Error> {
+    let bytes = miniz_oxide::inflate::decompress_to_vec(bytes)
+        .map_err(|_| Error::Indexer(IndexerError::InvalidTreeArchive))?;
+
+    MerkleTree::decode(bytes.as_slice()).map_err(|_| IndexerError::InvalidTreeArchive.into())
+}
+
+/// Reconstruct the tree of a group that was the latest at `block_number`.
+/// Returns `None` if the archive has no tree of the group at or before the block.
+pub async fn get_archived_tree_at(
+    pg_client: &tokio_postgres::Client,
+    group_id: &str,
+    block_number: BlockNum,
+) -> Result<Option<ArchivedTree>, Error> {
+    // The latest snapshot at or before the block, followed by the diffs after it
+    let rows = pg_client
+        .query(
+            r#"
+            SELECT
+                "blockNumber",
+                "merkleRoot",
+                "isSnapshot",
+                "layers"
+            FROM
+                "TreeArchive"
+            WHERE
+                "groupId" = $1
+                AND "blockNumber" <= $2
+                AND "blockNumber" >= (
+                    SELECT
+                        MAX("blockNumber")
+                    FROM
+                        "TreeArchive"
+                    WHERE
+                        "groupId" = $1
+                        AND "isSnapshot"
+                        AND "blockNumber" <= $2
+                )
+            ORDER BY
+                "blockNumber" ASC
+            "#,
+            &[&group_id, &(block_number.min(i64::MAX as BlockNum) as i64)],
+        )
+        .await?;
+
+    let mut archived_tree: Option<ArchivedTree> = None;
+
+    for row in rows {
+        let block_number: i64 = row.get("blockNumber");
+        let merkle_root: String = row.get("merkleRoot");
+        let is_snapshot: bool = row.get("isSnapshot");
+        let layers: Vec<u8> = row.get("layers");
+
+        let layers = decompress(&layers)?;
+
+        let (tree, num_diffs) = match archived_tree {
+            Some(archived_tree) if !is_snapshot => {
+                let tree = apply_diff(&archived_tree.tree, &layers)
+                    .ok_or(IndexerError::InvalidTreeArchive)?;
+                (tree, archived_tree.num_diffs + 1)
+            }
+            _ => (layers, 0),
+        };
+
+        archived_tree = Some(ArchivedTree {
+            block_number: block_number as BlockNum,
+            merkle_root,
+            tree,
+            num_diffs,
+        });
+    }
+
+    Ok(archived_tree)
+}
+
+/// Append a tree of a group to the archive.
+/// The tree is stored as the diff from `previous_tree`, the latest archived tree of the group,
+/// or as a full snapshot every `SNAPSHOT_INTERVAL` trees and when the depth changes.
+/// Returns the latest archived tree of the group after the tree is appended,
+/// so the next tree can be diffed from it without reconstructing it from the archive.
+pub async fn archive_tree(
+    pg_client: &tokio_postgres::Client,
+    group_id: &str,
+    block_number: BlockNum,
+    merkle_root: &str,
+    tree: &MerkleTree,
+    previous_tree: Option<ArchivedTree>,
+) -> Result<ArchivedTree, Error> {
+    let diff = match &previous_tree {
+        Some(previous_tree) if previous_tree.merkle_root == merkle_root => {
+            return Ok(previous_tree.clone())
+        }
+        Some(previous_tree) if previous_tree.num_diffs + 1 < SNAPSHOT_INTERVAL => {
+            diff_layers(&previous_tree.tree, tree)
+        }
+        _ => None,
+    };
+
+    let is_snapshot = diff.is_none();
+    let layers = compress(diff.as_ref().unwrap_or(tree));
+
+    pg_client
+        .execute(
+            r#"
+            INSERT INTO "TreeArchive" ("groupId", "blockNumber", "merkleRoot", "isSnapshot", "layers")
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT ("groupId", "blockNumber") DO NOTHING
+            "#,
+            &[
+                &group_id,
+                &(block_number as i64),
+                &merkle_root,
+                &is_snapshot,
+                &layers,
+            ],
+        )
+        .await?;
+
+    let num_diffs = match previous_tree {
+        Some(previous_tree) if !is_snapshot => previous_tree.num_diffs + 1,
+        _ => 0,
+    };
+
+    Ok(ArchivedTree {
+        block_number,
+        merkle_root: merkle_root.to_string(),
+        tree: tree.clone(),
+        num_diffs,
+    })
+}
+
+/// Delete the archived trees of a group that are older than the retention period.
+/// The latest snapshot before the end of the period is kept to reconstruct the trees after it.
+pub async fn prune_archive(
+    pg_client: &tokio_postgres::Client,
+    group_id: &str,
+    retention: &RetentionPolicy,
+) -> Result<(), Error> {
+    let retention_days = match retention.retention_days {
+        Some(retention_days) => retention_days as i32,
+        None => return Ok(()),
+    };
+
+    pg_client
+        .execute(
+            r#"
+            DELETE FROM
+                "TreeArchive"
+            WHERE
+                "groupId" = $1
+                AND "blockNumber" < (
+                    SELECT
+                        MAX("blockNumber")
+                    FROM
+                        "TreeArchive"
+                    WHERE
+                        "groupId" = $1
+                        AND "isSnapshot"
+                        AND "createdAt" < NOW() - make_interval(days => $2)
+                )
+            "#,
+            &[&group_id, &retention_days],
+        )
+        .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{merkle_tree_proto::LeafFormat, test_utils::proto_tree};
+
+    fn tree(layers: &[&[(u32, &[u8])]]) -> MerkleTree {
+        proto_tree(layers, LeafFormat::Address)
+    }
+
+    #[test]
+    fn test_diff_layers() {
+        let old = tree(&[
+            &[(0, &[1; 20]), (1, &[2; 20]), (2, &[3; 20])],
+            &[(0, &[10; 32])],
+        ]);
+        let new = tree(&[
+            &[(0, &[1; 20]), (2, &[4; 20]), (3, &[5; 20])],
+            &[(0, &[11; 32])],
+        ]);
+
+        let diff = diff_layers(&old, &new).unwrap();
+
+        // Only the changed nodes are in the diff, and the removed nodes are empty
+        assert_eq!(
+            diff,
+            tree(&[&[(1, &[]), (2, &[4; 20]), (3, &[5; 20])], &[(0, &[11; 32])]])
+        );
+
+        assert_eq!(apply_diff(&old, &diff).unwrap(), new);
+
+        // The compressed diff decompresses to the same diff
+        assert_eq!(decompress(&compress(&diff)).unwrap(), diff);
+
+        // Trees of different depths can't be diffed
+        let deeper = tree(&[&[(0, &[1; 20])], &[(0, &[12; 32])], &[(0, &[13; 32])]]);
+        assert!(diff_layers(&old, &deeper).is_none());
+        assert!(apply_diff(&deeper, &diff).is_none());
+    }
+
+    #[test]
+    fn test_archived_tree_contains() {
+        let archived_tree = ArchivedTree {
+            block_number: 1,
+            merkle_root: String::new(),
+            tree: proto_tree(
+                &[&[
+                    (0, &[1; 20]),
+                    (1, &[[2; 20].as_slice(), &[3; 20], &[0]].concat()),
+                    (15, &[4; 32]),
+                ]],
+                LeafFormat::Delegation,
+            ),
+            num_diffs: 0,
+        };
+
+        assert!(archived_tree.contains(&[1; 20]));
+
+        // Delegated leaves have the wallet and the kind after the address
+        assert!(archived_tree.contains(&[2; 20]));
+        assert!(!archived_tree.contains(&[3; 20]));
+
+        // The commitment to the delegations isn't a leaf of an address
+        assert!(!archived_tree.contains(&[4; 20]));
+    }
+}
@@ -0,0 +1,498 @@
+use crate::{BlockNum, Error};
+use hmac::{Hmac, Mac};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    io::AsyncWriteExt,
+    sync::{broadcast, mpsc},
+};
+
+/// Number of times a webhook is retried after the first attempt fails
+const WEBHOOK_MAX_RETRIES: u32 = 5;
+/// The delay before the first retry of a webhook, which doubles on each retry
+const WEBHOOK_RETRY_DELAY_SECS: u64 = 1;
+
+/// An event about the tree of a group, emitted by `TreeSyncEngine`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TreeEvent {
+    /// A tree with a new Merkle root was saved
+    TreeSaved {
+        group_id: String,
+        block_number: BlockNum,
+        merkle_root: String,
+        num_leaves: usize,
+        /// Number of members added since the previous tree
+        num_added: usize,
+        /// Number of members removed since the previous tree
+        num_removed: usize,
+    },
+    /// The latest tree is still up to date, and its block number was updated
+    TreeRefreshed {
+        group_id: String,
+        block_number: BlockNum,
+        merkle_root: String,
+    },
+    /// The group was marked as `Unrecordable` and its trees are no longer synced
+    GroupUnrecordable {
+        group_id: String,
+        block_number: BlockNum,
+    },
+}
+
+impl TreeEvent {
+    pub fn group_id(&self) -> &str {
+        match self {
+            TreeEvent::TreeSaved { group_id, .. }
+            | TreeEvent::TreeRefreshed { group_id, .. }
+            | TreeEvent::GroupUnrecordable { group_id, .. } => group_id,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+/// A destination of the tree events
+pub trait TreeEventSink: Send + Sync {
+    /// Returns the name of the sink for logging
+    fn name(&self) -> &str;
+    /// Deliver an event to the sink
+    async fn send(&self, event: &TreeEvent) -> Result<(), Error>;
+}
+
+/// Returns the hex encoded HMAC-SHA256 of `payload`
+fn sign(secret: &[u8], payload: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC can take a key of any size");
+    mac.update(payload);
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Posts the events as JSON to a URL.
+/// The `X-Cred-Signature` header is the HMAC-SHA256 of `{X-Cred-Timestamp}.{body}`
+/// keyed with the secret, so the receiver can check the event is from the indexer.
+pub struct WebhookSink {
+    client: surf::Client,
+    url: String,
+    secret: String,
+}
+
+impl WebhookSink {
+    pub fn new(url: String, secret: String) -> Self {
+        let client: surf::Client = surf::Config::new()
+            .set_timeout(Some(Duration::from_secs(30)))
+            .try_into()
+            .unwrap();
+
+        Self {
+            client,
+            url,
+            secret,
+        }
+    }
+
+    async fn post(&self, body: &str) -> Result<(), Error> {
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+            .to_string();
+
+        let signature = sign(
+            self.secret.as_bytes(),
+            format!("{}.{}", timestamp, body).as_bytes(),
+        );
+
+        let res = self
+            .client
+            .post(&self.url)
+            .header("Content-Type", "application/json")
+            .header("X-Cred-Timestamp", timestamp)
+            .header("X-Cred-Signature", format!("sha256={}", signature))
+            .body_string(body.to_string())
+            .await?;
+
+        if !res.status().is_success() {
+            return Err(surf::Error::from_str(
+                res.status(),
+                format!("Webhook responded with {}", res.status()),
+            )
+            .into());
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl TreeEventSink for WebhookSink {
+    fn name(&self) -> &str {
+        "webhook"
+    }
+
+    async fn send(&self, event: &TreeEvent) -> Result<(), Error> {
+        let body = serde_json::to_string(event).unwrap();
+
+        let mut retries = 0;
+        loop {
+            match self.post(&body).await {
+                Ok(()) => return Ok(()),
+                Err(err) if retries < WEBHOOK_MAX_RETRIES => {
+                    let delay = WEBHOOK_RETRY_DELAY_SECS << retries;
+                    error!(
+                        "${} Webhook failed: {:?}. Retrying in {}s",
+                        event.group_id(),
+                        err,
+                        delay
+                    );
+                    tokio::time::sleep(Duration::from_secs(delay)).await;
+                    retries += 1;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+/// Sends the events as JSON payloads of Postgres `NOTIFY` on a channel
+pub struct PgNotifySink {
+    pg_client: Arc<tokio_postgres::Client>,
+    channel: String,
+}
+
+impl PgNotifySink {
+    pub fn new(pg_client: Arc<tokio_postgres::Client>, channel: String) -> Self {
+        Self { pg_client, channel }
+    }
+}
+
+#[async_trait::async_trait]
+impl TreeEventSink for PgNotifySink {
+    fn name(&self) -> &str {
+        "pg_notify"
+    }
+
+    async fn send(&self, event: &TreeEvent) -> Result<(), Error> {
+        let payload = serde_json::to_string(event).unwrap();
+
+        self.pg_client
+            .execute("SELECT pg_notify($1, $2)", &[&self.channel, &payload])
+            .await?;
+
+        Ok(())
+    }
+}
+
+/// Appends the events to a file as JSON lines, for local testing
+pub struct FileSink {
+    path: String,
+}
+
+impl FileSink {
+    pub fn new(path: String) -> Self {
+        Self { path }
+    }
+}
+
+#[async_trait::async_trait]
+impl TreeEventSink for FileSink {
+    fn name(&self) -> &str {
+        "file"
+    }
+
+    async fn send(&self, event: &TreeEvent) -> Result<(), Error> {
+        let mut line = serde_json::to_string(event).unwrap();
+        line.push('\n');
+
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+
+        file.write_all(line.as_bytes()).await?;
+        // The write may still be in progress when the file is dropped
+        file.flush().await?;
+
+        Ok(())
+    }
+}
+
+/// Number of events an in-process subscriber can fall behind before it misses events
+const BROADCAST_CAPACITY: usize = 1024;
+
+/// Number of events of a group that can wait to be delivered to a sink.
+/// The events emitted while the queue is full are dropped.
+const SINK_QUEUE_CAPACITY: usize = 64;
+
+/// The queues of the events of each group to a sink.
+/// The events of a group are delivered in order by a task of its own,
+/// so a sink that's slow for a group doesn't hold up the events of the other groups.
+struct SinkQueues {
+    sink: Arc<dyn TreeEventSink>,
+    queues: Mutex<HashMap<String, mpsc::Sender<TreeEvent>>>,
+}
+
+impl SinkQueues {
+    fn new(sink: Arc<dyn TreeEventSink>) -> Self {
+        Self {
+            sink,
+            queues: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Start the task that delivers the events of a group to the sink
+    fn spawn_queue(&self) -> mpsc::Sender<TreeEvent> {
+        let (sender, mut receiver) = mpsc::channel::<TreeEvent>(SINK_QUEUE_CAPACITY);
+        let sink = self.sink.clone();
+
+        tokio::spawn(async move {
+            while let Some(event) = receiver.recv().await {
+                if let Err(err) = sink.send(&event).await {
+                    error!(
+                        "${} Failed to send the tree event to the {} sink: {:?}",
+                        event.group_id(),
+                        sink.name(),
+                        err
+                    );
+                }
+            }
+        });
+
+        sender
+    }
+
+    /// Queue an event without waiting. The event is dropped if the queue of its group is full.
+    fn push(&self, event: TreeEvent) {
+        let mut queues = self.queues.lock().unwrap();
+
+        let queue = queues
+            .entry(event.group_id().to_string())
+            .or_insert_with(|| self.spawn_queue());
+
+        if let Err(mpsc::error::TrySendError::Full(event)) = queue.try_send(event) {
+            error!(
+                "${} The {} sink is behind, dropping the tree event: {:?}",
+                event.group_id(),
+                self.sink.name(),
+                event
+            );
+        }
+    }
+}
+
+/// Fans out the tree events to the sinks and the in-process subscribers.
+/// The sinks deliver the events in background tasks, so a slow sink doesn't hold up the tree sync.
+///
+/// The delivery is at most once: the events are only queued in memory,
+/// so they're lost on a restart, dropped when a sink falls `SINK_QUEUE_CAPACITY` events behind for a group,
+/// and dropped after `WEBHOOK_MAX_RETRIES` retries of a webhook.
+/// The receivers can catch up on the missed changes with `getGroupChanges`.
+pub struct TreeEventEmitter {
+    sinks: Vec<SinkQueues>,
+    broadcast: broadcast::Sender<TreeEvent>,
+}
+
+impl Default for TreeEventEmitter {
+    fn default() -> Self {
+        Self::new(vec![])
+    }
+}
+
+impl TreeEventEmitter {
+    pub fn new(sinks: Vec<Box<dyn TreeEventSink>>) -> Self {
+        let sinks = sinks
+            .into_iter()
+            .map(|sink| SinkQueues::new(Arc::from(sink)))
+            .collect();
+
+        let (broadcast, _) = broadcast::channel(BROADCAST_CAPACITY);
+
+        Self { sinks, broadcast }
+    }
+
+    /// Create the sinks configured with the environment variables:
+    /// - `TREE_EVENTS_WEBHOOK_URL` and `TREE_EVENTS_WEBHOOK_SECRET` for the webhook sink
+    /// - `TREE_EVENTS_PG_CHANNEL` for the Postgres `NOTIFY` sink
+    /// - `TREE_EVENTS_FILE` for the file sink
+    pub fn from_env(pg_client: Arc<tokio_postgres::Client>) -> Self {
+        let mut sinks: Vec<Box<dyn TreeEventSink>> = vec![];
+
+        if let Ok(url) = std::env::var("TREE_EVENTS_WEBHOOK_URL") {
+            let secret = std::env::var("TREE_EVENTS_WEBHOOK_SECRET")
+                .expect("TREE_EVENTS_WEBHOOK_SECRET must be set with TREE_EVENTS_WEBHOOK_URL");
+            sinks.push(Box::new(WebhookSink::new(url, secret)));
+        }
+
+        if let Ok(channel) = std::env::var("TREE_EVENTS_PG_CHANNEL") {
+            sinks.push(Box::new(PgNotifySink::new(pg_client, channel)));
+        }
+
+        if let Ok(path) = std::env::var("TREE_EVENTS_FILE") {
+            sinks.push(Box::new(FileSink::new(path)));
+        }
+
+        for sink in &sinks {
+            info!("Sending tree events to the {} sink", sink.name());
+        }
+
+        Self::new(sinks)
+    }
+
+    /// Receive the events emitted from now on in the same process
+    pub fn subscribe(&self) -> broadcast::Receiver<TreeEvent> {
+        self.broadcast.subscribe()
+    }
+
+    pub fn emit(&self, event: TreeEvent) {
+        for sink in &self.sinks {
+            sink.push(event.clone());
+        }
+
+        // Sending fails only if there are no subscribers
+        let _ = self.broadcast.send(event);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sign() {
+        // Test case 2 of RFC 4231
+        assert_eq!(
+            sign(b"Jefe", b"what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_file_sink() {
+        let path = std::env::temp_dir().join(format!("tree_events_{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let events = vec![
+            TreeEvent::TreeSaved {
+                group_id: "group".to_string(),
+                block_number: 1,
+                merkle_root: "0x01".to_string(),
+                num_leaves: 3,
+                num_added: 2,
+                num_removed: 1,
+            },
+            TreeEvent::GroupUnrecordable {
+                group_id: "group".to_string(),
+                block_number: 2,
+            },
+        ];
+
+        let sink = FileSink::new(path.to_str().unwrap().to_string());
+        for event in &events {
+            sink.send(event).await.unwrap();
+        }
+
+        let lines = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let lines = lines.lines().collect::<Vec<&str>>();
+        assert!(lines[1].starts_with(r#"{"type":"group_unrecordable","#));
+
+        let sent_events = lines
+            .iter()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect::<Vec<TreeEvent>>();
+
+        assert_eq!(sent_events, events);
+    }
+
+    /// A sink that records the events, and holds the events of the "slow" group until it's released
+    struct TestSink {
+        sent: Arc<Mutex<Vec<TreeEvent>>>,
+        release: Arc<tokio::sync::Semaphore>,
+    }
+
+    #[async_trait::async_trait]
+    impl TreeEventSink for TestSink {
+        fn name(&self) -> &str {
+            "test"
+        }
+
+        async fn send(&self, event: &TreeEvent) -> Result<(), Error> {
+            if event.group_id() == "slow" {
+                self.release.acquire().await.unwrap().forget();
+            }
+
+            self.sent.lock().unwrap().push(event.clone());
+            Ok(())
+        }
+    }
+
+    fn refreshed(group_id: &str, block_number: BlockNum) -> TreeEvent {
+        TreeEvent::TreeRefreshed {
+            group_id: group_id.to_string(),
+            block_number,
+            merkle_root: "0x01".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_emitter_queues() {
+        let sent = Arc::new(Mutex::new(vec![]));
+        let release = Arc::new(tokio::sync::Semaphore::new(0));
+
+        let emitter = TreeEventEmitter::new(vec![Box::new(TestSink {
+            sent: sent.clone(),
+            release: release.clone(),
+        })]);
+
+        // Fill the queue of the slow group, which drops the events after it
+        let num_slow_events = SINK_QUEUE_CAPACITY + 10;
+        for block_number in 0..num_slow_events {
+            emitter.emit(refreshed("slow", block_number as BlockNum));
+        }
+
+        // The events of the other groups are delivered while the slow group is held up
+        emitter.emit(refreshed("fast", 1));
+        emitter.emit(refreshed("fast", 2));
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while sent.lock().unwrap().len() < 2 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(
+            *sent.lock().unwrap(),
+            vec![refreshed("fast", 1), refreshed("fast", 2)]
+        );
+
+        // The queued events of the slow group are delivered in order when it's released.
+        // The test runtime doesn't run the delivery task until the test awaits,
+        // so all the events were emitted while the queue was full.
+        release.add_permits(num_slow_events);
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while sent.lock().unwrap().len() < 2 + SINK_QUEUE_CAPACITY {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        // The dropped events aren't delivered
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let slow_events = sent.lock().unwrap()[2..].to_vec();
+        assert_eq!(slow_events.len(), SINK_QUEUE_CAPACITY);
+        for (block_number, event) in slow_events.iter().enumerate() {
+            assert_eq!(*event, refreshed("slow", block_number as BlockNum));
+        }
+    }
+}
@@ -0,0 +1,1192 @@
+extern crate merkle_tree as merkle_tree_lib;
+use crate::merkle_proof_proto::MerkleProof;
+use crate::merkle_tree_proto::{self, LeafFormat, MerkleTreeLayer, MerkleTreeNode};
+use crate::rocksdb_key::KeyType;
+use crate::tree::{DEFAULT_TREE_DEPTH, TREE_WIDTH};
+use crate::{Address, GroupId};
+use circuits::balance_membership::balance_leaf_bytes;
+use merkle_tree_lib::ark_ff::{BigInteger, Field, PrimeField};
+use merkle_tree_lib::ark_secp256k1::Fq;
+use merkle_tree_lib::poseidon::constants::secp256k1_w3;
+use merkle_tree_lib::tree::MerkleTree;
+use num_bigint::BigUint;
+use rocksdb::{Direction, IteratorMode, WriteBatch, DB};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::sync::Arc;
+
+/// Index of a leaf in the tree
+pub type LeafIndex = u32;
+
+// The keys of a tree store are the concatenation of the following fields:
+// [KeyType::TreeStore (1 byte)][group id (32 bytes)][record (1 byte)][record key]
+// where the record key is
+// - Meta: empty
+// - Position: [address (20 bytes)]
+// - Node: [layer (1 byte)][index (4 bytes)]
+// - FreePosition: [index (4 bytes)]
+// - Depth: empty
+// - Balance: [address (20 bytes)]
+// - Delegation: [address (20 bytes)]
+// and the value of a Delegation is [wallet (20 bytes)][kind (1 byte)]
+
+/// Type of a record in a tree store
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Record {
+    /// The number of leaf positions that have been used
+    Meta = 0,
+    /// The leaf index of a member
+    Position = 1,
+    /// A node that isn't the root of an empty subtree
+    Node = 2,
+    /// A leaf position of a removed member that can be reused
+    FreePosition = 3,
+    /// The depth of the tree
+    Depth = 4,
+    /// The balance a member's leaf commits to in a balance tree
+    Balance = 5,
+    /// The wallet a member is in the tree on behalf of
+    Delegation = 6,
+}
+
+/// How a member that's in the tree on behalf of a wallet is related to the wallet
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum DelegationKind {
+    /// An owner of a Safe
+    SafeOwner = 0,
+    /// A hot wallet delegated by the wallet in a delegate registry
+    Delegate = 1,
+}
+
+impl DelegationKind {
+    /// Returns `None` if `value` isn't a delegation kind
+    fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(DelegationKind::SafeOwner),
+            1 => Some(DelegationKind::Delegate),
+            _ => None,
+        }
+    }
+}
+
+/// The wallet a member is in the tree on behalf of
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Delegation {
+    pub wallet: Address,
+    pub kind: DelegationKind,
+}
+
+/// The number of bits the balances in the leaves of a balance tree fit in.
+/// The balance proofs can only compare balances of this size.
+pub use circuits::BALANCE_BITS as MAX_LEAF_BALANCE_BITS;
+
+fn to_fe(bytes: &[u8]) -> Fq {
+    Fq::from(BigUint::from_bytes_be(bytes))
+}
+
+/// Returns the roots of the empty subtrees at each layer of a tree of `depth`
+fn compute_empty_nodes(hasher: &mut MerkleTree<Fq, TREE_WIDTH>, depth: usize) -> Vec<Fq> {
+    let mut empty_nodes = vec![Fq::ZERO];
+    for i in 0..depth {
+        let hash = MerkleTree::hash(&mut hasher.poseidon, &[empty_nodes[i]; 2]);
+        empty_nodes.push(hash);
+    }
+
+    empty_nodes
+}
+
+/// Returns the index of the leaf that commits to the delegations, which is the last leaf.
+/// The position isn't given to the members.
+pub fn delegations_leaf_index(depth: usize) -> LeafIndex {
+    ((1u64 << depth) - 1) as LeafIndex
+}
+
+/// Compute the leaf that commits to the delegations of the members,
+/// which is the Poseidon hash chain of `Poseidon(address, wallet ‖ kind)` in the order of the addresses.
+/// The leaf is empty if there are no delegations, so it doesn't change the roots of the other trees.
+fn delegations_leaf<'a>(
+    hasher: &mut MerkleTree<Fq, TREE_WIDTH>,
+    delegations: impl IntoIterator<Item = (&'a Address, &'a Delegation)>,
+) -> Fq {
+    let delegations = delegations.into_iter().collect::<BTreeMap<_, _>>();
+
+    delegations
+        .into_iter()
+        .fold(Fq::ZERO, |commitment, (address, delegation)| {
+            let value = [delegation.wallet.as_slice(), &[delegation.kind as u8]].concat();
+            let hash = MerkleTree::hash(&mut hasher.poseidon, &[to_fe(address), to_fe(&value)]);
+
+            MerkleTree::hash(&mut hasher.poseidon, &[hash, commitment])
+        })
+}
+
+/// Compute the leaf of `address` with `balance` in a balance tree,
+/// in the encoding of the balance membership circuit.
+/// Returns `None` if the balance doesn't fit in `MAX_LEAF_BALANCE_BITS`.
+fn balance_leaf(address: &[u8], balance: &BigUint) -> Option<Fq> {
+    let address = address.try_into().ok()?;
+    balance_leaf_bytes(address, balance)
+        .ok()
+        .map(|leaf| to_fe(&leaf))
+}
+
+/// Returns the leaf a node of the leaf layer of a tree protobuf stands for.
+/// See `TreeStore::to_proto` for the formats of the nodes.
+fn proto_leaf(leaf_format: LeafFormat, node: &[u8]) -> Option<Fq> {
+    match (leaf_format, node.len()) {
+        // An address, or an address followed by the wallet and the kind of the delegation
+        (LeafFormat::Address, 20) | (LeafFormat::Delegation, 20 | 41) => Some(to_fe(&node[..20])),
+        // The commitment to the delegations
+        (LeafFormat::Delegation, 32) => Some(to_fe(node)),
+        // An address followed by the balance in a balance tree
+        (LeafFormat::Balance, 52) => {
+            balance_leaf(&node[..20], &BigUint::from_bytes_be(&node[20..]))
+        }
+        _ => None,
+    }
+}
+
+/// Returns the address of the member a node of the leaf layer of a tree protobuf is the leaf of.
+/// Returns `None` for the commitment to the delegations, which isn't the leaf of a member.
+pub fn proto_leaf_address(leaf_format: LeafFormat, node: &[u8]) -> Option<Address> {
+    match (leaf_format, node.len()) {
+        (LeafFormat::Delegation, 32) => None,
+        _ => node.get(..20)?.try_into().ok(),
+    }
+}
+
+/// Compute the Merkle proof of `address` from a tree protobuf made by `TreeStore::to_proto`.
+/// The roots of the empty subtrees, which are omitted from the layers, are recomputed.
+/// The siblings are 32-byte big-endian field elements from the bottom of the tree,
+/// and the indices are true where the path goes through the right child.
+/// Returns `None` if the address isn't a leaf of the tree.
+pub fn merkle_proof_from_proto(
+    tree: &merkle_tree_proto::MerkleTree,
+    address: &Address,
+) -> Option<MerkleProof> {
+    // The last layer is the root
+    let depth = tree.layers.len().checked_sub(1)?;
+
+    let leaf = tree.layers[0]
+        .nodes
+        .iter()
+        .find(|node| node.node.get(..20) == Some(address.as_slice()))?;
+
+    let mut hasher = MerkleTree::<Fq, TREE_WIDTH>::new(secp256k1_w3());
+    let empty_nodes = compute_empty_nodes(&mut hasher, depth);
+
+    let mut index = leaf.index;
+    let mut siblings = vec![];
+    let mut indices = vec![];
+
+    for (layer, nodes) in tree.layers[..depth].iter().enumerate() {
+        let sibling = nodes.nodes.iter().find(|node| node.index == index ^ 1);
+
+        let sibling = match sibling {
+            Some(sibling) if layer == 0 => proto_leaf(tree.leaf_format(), &sibling.node)?,
+            Some(sibling) => to_fe(&sibling.node),
+            None => empty_nodes[layer],
+        };
+
+        siblings.push(sibling.into_bigint().to_bytes_be());
+        indices.push(index & 1 == 1);
+
+        index >>= 1;
+    }
+
+    Some(MerkleProof {
+        address: address.to_vec(),
+        siblings,
+        indices,
+    })
+}
+
+/// Returns the leaf of `address` in a tree protobuf made by `TreeStore::to_proto`
+/// as a 32-byte big-endian field element computed as in the circuit,
+/// along with the balance the leaf commits to in a balance tree.
+/// Returns `None` if the address isn't a leaf of the tree.
+pub fn leaf_from_proto(
+    tree: &merkle_tree_proto::MerkleTree,
+    address: &Address,
+) -> Option<(Vec<u8>, Option<BigUint>)> {
+    let node = tree
+        .layers
+        .first()?
+        .nodes
+        .iter()
+        .find(|node| node.node.get(..20) == Some(address.as_slice()))?;
+
+    let leaf = proto_leaf(tree.leaf_format(), &node.node)?;
+    let balance = match tree.leaf_format() {
+        LeafFormat::Balance => Some(BigUint::from_bytes_be(&node.node[20..])),
+        _ => None,
+    };
+
+    Some((leaf.into_bigint().to_bytes_be(), balance))
+}
+
+/// Returns the balance a leaf commits to.
+/// Larger balances are capped, which keeps the proofs of any threshold that fits valid.
+pub fn to_leaf_balance(balance: &BigUint) -> BigUint {
+    if balance.bits() > MAX_LEAF_BALANCE_BITS as u64 {
+        (BigUint::from(1u8) << MAX_LEAF_BALANCE_BITS) - 1u8
+    } else {
+        balance.clone()
+    }
+}
+
+/// Changes to a tree store that aren't written to RocksDB yet
+#[derive(Default)]
+struct StagedChanges {
+    /// The ranges of keys that are deleted before `changes` are applied
+    deleted_ranges: Vec<(Vec<u8>, Vec<u8>)>,
+    /// The values of the keys that are put, or `None` for the deleted keys
+    changes: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+}
+
+impl StagedChanges {
+    fn put(&mut self, key: Vec<u8>, value: impl AsRef<[u8]>) {
+        self.changes.insert(key, Some(value.as_ref().to_vec()));
+    }
+
+    fn delete(&mut self, key: Vec<u8>) {
+        self.changes.insert(key, None);
+    }
+
+    /// Delete the keys from `from` (inclusive) to `to` (exclusive)
+    fn delete_range(&mut self, from: Vec<u8>, to: Vec<u8>) {
+        self.changes
+            .retain(|key, _| key.as_slice() < from.as_slice() || key.as_slice() >= to.as_slice());
+        self.deleted_ranges.push((from, to));
+    }
+
+    /// Returns true if the stored value of `key` is deleted by a range deletion
+    fn is_range_deleted(&self, key: &[u8]) -> bool {
+        self.deleted_ranges
+            .iter()
+            .any(|(from, to)| from.as_slice() <= key && key < to.as_slice())
+    }
+
+    /// Apply `other` on top of the changes
+    fn merge(&mut self, other: StagedChanges) {
+        for (from, to) in other.deleted_ranges {
+            self.delete_range(from, to);
+        }
+
+        self.changes.extend(other.changes);
+    }
+
+    fn to_batch(&self) -> WriteBatch {
+        let mut batch = WriteBatch::default();
+
+        for (from, to) in &self.deleted_ranges {
+            batch.delete_range(from, to);
+        }
+
+        for (key, value) in &self.changes {
+            match value {
+                Some(value) => batch.put(key, value),
+                None => batch.delete(key),
+            }
+        }
+
+        batch
+    }
+}
+
+/// A Merkle tree of the members of a group that is persisted in RocksDB
+/// and updated incrementally.
+///
+/// Members keep their leaf positions while they stay in the group,
+/// and new members take the positions of removed members first,
+/// so only the paths of the changed leaves are recomputed and change in the cached proofs.
+///
+/// The leaves are the addresses of the members,
+/// or `Poseidon(address, balance)` in a balance tree, which is updated with `update_balances`.
+///
+/// The changes are staged in memory until `commit` is called,
+/// so the stored tree stays at the last saved tree if saving the new tree fails.
+pub struct TreeStore {
+    rocksdb_client: Arc<DB>,
+    group_id: [u8; 32],
+    depth: usize,
+    staged: StagedChanges,
+    /// Used for the Poseidon hasher of the tree
+    hasher: MerkleTree<Fq, TREE_WIDTH>,
+    /// The roots of the empty subtrees at each layer
+    empty_nodes: Vec<Fq>,
+}
+
+impl TreeStore {
+    pub fn new(rocksdb_client: Arc<DB>, group_id: &GroupId, depth: usize) -> Self {
+        let group_id_bytes = hex::decode(group_id)
+            .ok()
+            .and_then(|group_id| group_id.try_into().ok())
+            .unwrap_or_else(|| panic!("Invalid group id {}", group_id));
+
+        let mut hasher = MerkleTree::<Fq, TREE_WIDTH>::new(secp256k1_w3());
+        let empty_nodes = compute_empty_nodes(&mut hasher, depth);
+
+        let mut tree = Self {
+            rocksdb_client,
+            group_id: group_id_bytes,
+            depth,
+            staged: StagedChanges::default(),
+            hasher,
+            empty_nodes,
+        };
+
+        // The leaf positions and the nodes of another depth can't be reused,
+        // so the tree is built from scratch when the depth of the group changes
+        if tree.stored_depth() != depth {
+            let group_prefix = [[KeyType::TreeStore as u8].as_slice(), &tree.group_id].concat();
+
+            tree.staged
+                .delete_range(group_prefix.clone(), [group_prefix, vec![u8::MAX]].concat());
+            tree.staged
+                .put(tree.record_prefix(Record::Depth), [depth as u8]);
+        }
+
+        tree
+    }
+
+    /// Write the staged changes to RocksDB
+    pub fn commit(&mut self) {
+        let staged = std::mem::take(&mut self.staged);
+        self.rocksdb_client.write(staged.to_batch()).unwrap();
+    }
+
+    /// Returns the value of `key` with the staged changes applied
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        if let Some(value) = self.staged.changes.get(key) {
+            return value.clone();
+        }
+
+        if self.staged.is_range_deleted(key) {
+            return None;
+        }
+
+        self.rocksdb_client.get(key).unwrap()
+    }
+
+    /// Returns the keys under `prefix` and their values with the staged changes applied,
+    /// in the order of the keys
+    fn iter_prefix(&self, prefix: &[u8]) -> impl Iterator<Item = (Vec<u8>, Vec<u8>)> {
+        let mut values = self
+            .rocksdb_client
+            .iterator(IteratorMode::From(prefix, Direction::Forward))
+            .map(|item| item.unwrap())
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .filter(|(key, _)| {
+                !self.staged.changes.contains_key(key.as_ref())
+                    && !self.staged.is_range_deleted(key)
+            })
+            .map(|(key, value)| (key.to_vec(), value.to_vec()))
+            .collect::<BTreeMap<Vec<u8>, Vec<u8>>>();
+
+        let staged = self
+            .staged
+            .changes
+            .range(prefix.to_vec()..)
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .filter_map(|(key, value)| Some((key.clone(), value.clone()?)));
+
+        values.extend(staged);
+        values.into_iter()
+    }
+
+    /// The depth of the tree
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Returns the depth the tree is stored with.
+    /// Trees stored before the depth was recorded have the default depth.
+    fn stored_depth(&self) -> usize {
+        self.get(&self.record_prefix(Record::Depth))
+            .map_or(DEFAULT_TREE_DEPTH, |value| value[0] as usize)
+    }
+
+    /// The maximum number of leaves
+    pub fn capacity(&self) -> usize {
+        1 << self.depth
+    }
+
+    fn record_prefix(&self, record: Record) -> Vec<u8> {
+        let mut key = vec![KeyType::TreeStore as u8];
+        key.extend_from_slice(&self.group_id);
+        key.push(record as u8);
+        key
+    }
+
+    fn position_key(&self, address: &Address) -> Vec<u8> {
+        let mut key = self.record_prefix(Record::Position);
+        key.extend_from_slice(address);
+        key
+    }
+
+    fn layer_prefix(&self, layer: usize) -> Vec<u8> {
+        let mut key = self.record_prefix(Record::Node);
+        key.push(layer as u8);
+        key
+    }
+
+    fn node_key(&self, layer: usize, index: LeafIndex) -> Vec<u8> {
+        let mut key = self.layer_prefix(layer);
+        key.extend_from_slice(&index.to_be_bytes());
+        key
+    }
+
+    fn balance_key(&self, address: &Address) -> Vec<u8> {
+        let mut key = self.record_prefix(Record::Balance);
+        key.extend_from_slice(address);
+        key
+    }
+
+    fn delegation_key(&self, address: &Address) -> Vec<u8> {
+        let mut key = self.record_prefix(Record::Delegation);
+        key.extend_from_slice(address);
+        key
+    }
+
+    fn free_position_key(&self, index: LeafIndex) -> Vec<u8> {
+        let mut key = self.record_prefix(Record::FreePosition);
+        key.extend_from_slice(&index.to_be_bytes());
+        key
+    }
+
+    /// Returns the number of leaf positions that have been used
+    fn num_used_positions(&self) -> LeafIndex {
+        self.get(&self.record_prefix(Record::Meta))
+            .map_or(0, |value| {
+                LeafIndex::from_be_bytes(value[..].try_into().unwrap())
+            })
+    }
+
+    /// Returns the leaf index of each member
+    pub fn positions(&self) -> HashMap<Address, LeafIndex> {
+        let prefix = self.record_prefix(Record::Position);
+
+        self.iter_prefix(&prefix)
+            .map(|(key, value)| {
+                let address = key[prefix.len()..].try_into().unwrap();
+                let index = LeafIndex::from_be_bytes(value[..].try_into().unwrap());
+                (address, index)
+            })
+            .collect()
+    }
+
+    /// Returns the leaf index of `address` if it's a member
+    pub fn position(&self, address: &Address) -> Option<LeafIndex> {
+        self.get(&self.position_key(address))
+            .map(|value| LeafIndex::from_be_bytes(value[..].try_into().unwrap()))
+    }
+
+    /// Returns the balance each member's leaf commits to.
+    /// Empty unless the tree is a balance tree.
+    pub fn balances(&self) -> HashMap<Address, BigUint> {
+        let prefix = self.record_prefix(Record::Balance);
+
+        self.iter_prefix(&prefix)
+            .map(|(key, value)| {
+                let address = key[prefix.len()..].try_into().unwrap();
+                (address, BigUint::from_bytes_be(&value))
+            })
+            .collect()
+    }
+
+    /// Returns the wallet each member that's in the tree on behalf of a wallet is delegated by.
+    /// Delegations of unknown kinds are skipped.
+    pub fn delegations(&self) -> HashMap<Address, Delegation> {
+        let prefix = self.record_prefix(Record::Delegation);
+
+        self.iter_prefix(&prefix)
+            .filter_map(|(key, value)| {
+                let address = key[prefix.len()..].try_into().unwrap();
+                let delegation = Delegation {
+                    wallet: value.get(..20)?.try_into().unwrap(),
+                    kind: DelegationKind::from_u8(*value.get(20)?)?,
+                };
+                Some((address, delegation))
+            })
+            .collect()
+    }
+
+    /// Replace the wallets the members are in the tree on behalf of.
+    /// The leaves of the members are their addresses either way,
+    /// and the delegations are committed to in the last leaf, so they're bound to the root.
+    pub fn set_delegations(&mut self, delegations: &HashMap<Address, Delegation>) {
+        let prefix = self.record_prefix(Record::Delegation);
+
+        let mut batch = StagedChanges::default();
+        batch.delete_range(prefix.clone(), [prefix, vec![u8::MAX; 21]].concat());
+
+        for (address, delegation) in delegations {
+            let value = [delegation.wallet.as_slice(), &[delegation.kind as u8]].concat();
+            batch.put(self.delegation_key(address), value);
+        }
+
+        let leaf = delegations_leaf(&mut self.hasher, delegations);
+        let leaves = BTreeMap::from([(delegations_leaf_index(self.depth), leaf)]);
+
+        self.update_nodes(leaves, &mut batch);
+        self.staged.merge(batch);
+    }
+
+    fn free_positions(&self) -> BTreeSet<LeafIndex> {
+        let prefix = self.record_prefix(Record::FreePosition);
+
+        self.iter_prefix(&prefix)
+            .map(|(key, _)| LeafIndex::from_be_bytes(key[prefix.len()..].try_into().unwrap()))
+            .collect()
+    }
+
+    /// Returns the node at `index` of `layer`
+    fn node(&self, layer: usize, index: LeafIndex) -> Fq {
+        self.get(&self.node_key(layer, index))
+            .map_or(self.empty_nodes[layer], |value| to_fe(&value))
+    }
+
+    /// Returns the root of the tree
+    pub fn root(&self) -> Fq {
+        self.node(self.depth, 0)
+    }
+
+    /// Compute the leaf of `address` with `balance` in a balance tree.
+    /// The balances are capped by `to_leaf_balance`, so they always fit.
+    fn balance_leaf(&self, address: &Address, balance: &BigUint) -> Fq {
+        balance_leaf(address, balance).expect("The leaf balances are capped")
+    }
+
+    /// Update the leaves of the tree to `members`.
+    /// Members that are already in the tree keep their positions,
+    /// and new members are inserted in the given order into the positions of removed members first.
+    /// Returns the indices of the changed leaves,
+    /// or `None` if the members don't fit in the tree.
+    pub fn update(&mut self, members: &[Address]) -> Option<Vec<LeafIndex>> {
+        self.update_leaves(members, None)
+    }
+
+    /// Update the leaves of a balance tree to `members` with their balances in `balances`.
+    /// The positions are assigned as in `update`,
+    /// and the leaves of the members whose balance changed are also updated.
+    pub fn update_balances(
+        &mut self,
+        members: &[Address],
+        balances: &HashMap<Address, BigUint>,
+    ) -> Option<Vec<LeafIndex>> {
+        let balances = members
+            .iter()
+            .map(|address| {
+                let balance = balances.get(address).cloned().unwrap_or_default();
+                (*address, to_leaf_balance(&balance))
+            })
+            .collect::<HashMap<Address, BigUint>>();
+
+        self.update_leaves(members, Some(&balances))
+    }
+
+    fn update_leaves(
+        &mut self,
+        members: &[Address],
+        balances: Option<&HashMap<Address, BigUint>>,
+    ) -> Option<Vec<LeafIndex>> {
+        let positions = self.positions();
+        let member_set = members.iter().collect::<HashSet<&Address>>();
+
+        let removed = positions
+            .iter()
+            .filter(|(address, _)| !member_set.contains(address))
+            .collect::<Vec<(&Address, &LeafIndex)>>();
+
+        let mut seen = HashSet::new();
+        let added = members
+            .iter()
+            .filter(|address| !positions.contains_key(*address) && seen.insert(*address))
+            .collect::<Vec<&Address>>();
+
+        let mut free_positions = self.free_positions();
+        let mut num_used_positions = self.num_used_positions();
+
+        // The last leaf is reserved for the commitment to the delegations
+        let available = free_positions.len()
+            + removed.len()
+            + (self.capacity() - 1).saturating_sub(num_used_positions as usize);
+
+        if added.len() > available {
+            return None;
+        }
+
+        let mut batch = StagedChanges::default();
+
+        // The leaves that changed
+        let mut leaves = BTreeMap::new();
+
+        let mut delegations = self.delegations();
+
+        for (address, index) in removed {
+            // The commitment is updated if a delegated member is removed
+            if delegations.remove(address).is_some() {
+                let leaf = delegations_leaf(&mut self.hasher, &delegations);
+                leaves.insert(delegations_leaf_index(self.depth), leaf);
+            }
+
+            batch.delete(self.position_key(address));
+            batch.delete(self.balance_key(address));
+            batch.delete(self.delegation_key(address));
+            free_positions.insert(*index);
+            leaves.insert(*index, Fq::ZERO);
+        }
+
+        for address in added {
+            let index = free_positions.pop_first().unwrap_or_else(|| {
+                num_used_positions += 1;
+                num_used_positions - 1
+            });
+
+            batch.put(self.position_key(address), index.to_be_bytes());
+
+            let leaf = match balances {
+                Some(balances) => {
+                    let balance = &balances[address];
+                    batch.put(self.balance_key(address), balance.to_bytes_be());
+                    self.balance_leaf(address, balance)
+                }
+                None => {
+                    let mut leaf = [0u8; 32];
+                    leaf[12..].copy_from_slice(address);
+                    to_fe(&leaf)
+                }
+            };
+
+            leaves.insert(index, leaf);
+        }
+
+        // The leaves of the remaining members change if their balances changed
+        if let Some(balances) = balances {
+            let stored_balances = self.balances();
+
+            for (address, index) in &positions {
+                let balance = match balances.get(address) {
+                    Some(balance) if stored_balances.get(address) != Some(balance) => balance,
+                    _ => continue,
+                };
+
+                batch.put(self.balance_key(address), balance.to_bytes_be());
+                let leaf = self.balance_leaf(address, balance);
+                leaves.insert(*index, leaf);
+            }
+        }
+
+        let changed_leaves = leaves.keys().copied().collect::<Vec<LeafIndex>>();
+
+        // Replace the free positions
+        let free_position_prefix = self.record_prefix(Record::FreePosition);
+        batch.delete_range(
+            free_position_prefix.clone(),
+            [free_position_prefix, vec![u8::MAX; 5]].concat(),
+        );
+
+        for index in free_positions {
+            batch.put(self.free_position_key(index), []);
+        }
+
+        batch.put(
+            self.record_prefix(Record::Meta),
+            num_used_positions.to_be_bytes(),
+        );
+
+        self.update_nodes(leaves, &mut batch);
+        self.staged.merge(batch);
+
+        Some(changed_leaves)
+    }
+
+    /// Stage the changed leaves and the nodes on their paths in `batch`.
+    /// The paths are recomputed layer by layer,
+    /// so the nodes shared by the paths are only computed once.
+    fn update_nodes(&mut self, leaves: BTreeMap<LeafIndex, Fq>, batch: &mut StagedChanges) {
+        let mut nodes = leaves;
+        for layer in 0..=self.depth {
+            for (index, node) in &nodes {
+                if *node == self.empty_nodes[layer] {
+                    batch.delete(self.node_key(layer, *index));
+                } else {
+                    batch.put(
+                        self.node_key(layer, *index),
+                        node.into_bigint().to_bytes_be(),
+                    );
+                }
+            }
+
+            if layer == self.depth {
+                break;
+            }
+
+            let parents = nodes
+                .keys()
+                .map(|index| index / 2)
+                .collect::<BTreeSet<LeafIndex>>();
+
+            let mut parent_nodes = BTreeMap::new();
+            for parent in parents {
+                let child = |index: LeafIndex| {
+                    nodes
+                        .get(&index)
+                        .copied()
+                        .unwrap_or_else(|| self.node(layer, index))
+                };
+
+                let (left, right) = (child(parent * 2), child(parent * 2 + 1));
+
+                let parent_node =
+                    if left == self.empty_nodes[layer] && right == self.empty_nodes[layer] {
+                        self.empty_nodes[layer + 1]
+                    } else {
+                        MerkleTree::hash(&mut self.hasher.poseidon, &[left, right])
+                    };
+
+                parent_nodes.insert(parent, parent_node);
+            }
+
+            nodes = parent_nodes;
+        }
+    }
+
+    /// Convert the tree to the protobuf that's served to the clients.
+    /// The roots of empty subtrees are omitted from the layers,
+    /// and the `LeafFormat` of the proto tells the formats of the leaves below apart.
+    /// The leaves of a balance tree are the addresses followed by the balances in 32 bytes,
+    /// so the clients can compute the leaves to prove their balances.
+    /// The leaves of the members that are in the tree on behalf of a wallet
+    /// are the addresses followed by the wallets and the `DelegationKind` in a byte,
+    /// so the clients can tell the Safe owners and the delegates apart,
+    /// and the last leaf is the commitment to the delegations in 32 bytes.
+    pub fn to_proto(&self) -> merkle_tree_proto::MerkleTree {
+        let balances = self.balances();
+        let delegations = self.delegations();
+        let addresses = if balances.is_empty() && delegations.is_empty() {
+            HashMap::new()
+        } else {
+            self.positions()
+                .into_iter()
+                .map(|(address, index)| (index, address))
+                .collect::<HashMap<LeafIndex, Address>>()
+        };
+
+        let layers = (0..=self.depth)
+            .map(|layer| {
+                let prefix = self.layer_prefix(layer);
+
+                let nodes = self
+                    .iter_prefix(&prefix)
+                    .map(|(key, value)| {
+                        let index =
+                            LeafIndex::from_be_bytes(key[prefix.len()..].try_into().unwrap());
+
+                        // The leaves are the addresses, with the balances in a balance tree
+                        let node = match addresses.get(&index) {
+                            Some(address) if layer == 0 => {
+                                let mut node = address.to_vec();
+
+                                if let Some(balance) = balances.get(address) {
+                                    let balance = balance.to_bytes_be();
+                                    node.extend_from_slice(&[0u8; 32][balance.len()..]);
+                                    node.extend_from_slice(&balance);
+                                } else if let Some(delegation) = delegations.get(address) {
+                                    node.extend_from_slice(&delegation.wallet);
+                                    node.push(delegation.kind as u8);
+                                }
+
+                                node
+                            }
+                            _ if layer == 0 && index == delegations_leaf_index(self.depth) => {
+                                value.to_vec()
+                            }
+                            _ if layer == 0 => value[12..].to_vec(),
+                            _ => value.to_vec(),
+                        };
+
+                        MerkleTreeNode { node, index }
+                    })
+                    .collect();
+
+                MerkleTreeLayer { nodes }
+            })
+            .collect();
+
+        let leaf_format = if !balances.is_empty() {
+            LeafFormat::Balance
+        } else if !delegations.is_empty() {
+            LeafFormat::Delegation
+        } else {
+            LeafFormat::Address
+        };
+
+        merkle_tree_proto::MerkleTree {
+            layers,
+            leaf_format: leaf_format as i32,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::init_test_rocksdb;
+
+    const DEPTH: usize = 4;
+
+    /// Compute the root by building the whole tree from the leaves at their positions
+    fn full_root(tree: &TreeStore) -> Fq {
+        let mut leaves = vec![Fq::ZERO; tree.capacity()];
+        for (address, index) in tree.positions() {
+            let mut leaf = [0u8; 32];
+            leaf[12..].copy_from_slice(&address);
+            leaves[index as usize] = to_fe(&leaf);
+        }
+
+        let mut full_tree = MerkleTree::<Fq, TREE_WIDTH>::new(secp256k1_w3());
+        for leaf in leaves {
+            full_tree.insert(leaf);
+        }
+        full_tree.finish();
+
+        full_tree.root.unwrap()
+    }
+
+    #[test]
+    fn test_tree_store_commit() {
+        let db = init_test_rocksdb("test_tree_store_commit");
+        let group_id = hex::encode([1; 32]);
+
+        let members = (1..=10).map(|i| [i; 20]).collect::<Vec<Address>>();
+        let mut tree = TreeStore::new(db.clone(), &group_id, DEPTH);
+        tree.update(&members).unwrap();
+        let root = tree.root();
+
+        // Nothing is stored until the changes are committed
+        let stored = TreeStore::new(db.clone(), &group_id, DEPTH);
+        assert!(stored.positions().is_empty());
+        assert_eq!(stored.root(), stored.empty_nodes[DEPTH]);
+
+        tree.commit();
+
+        let stored = TreeStore::new(db.clone(), &group_id, DEPTH);
+        assert_eq!(stored.positions(), tree.positions());
+        assert_eq!(stored.root(), root);
+
+        // Uncommitted changes are dropped, and the stored tree stays at the last commit
+        let mut tree = TreeStore::new(db.clone(), &group_id, DEPTH);
+        tree.update(&members[..5]).unwrap();
+        tree.set_delegations(&HashMap::from([(
+            [1; 20],
+            Delegation {
+                wallet: [100; 20],
+                kind: DelegationKind::SafeOwner,
+            },
+        )]));
+        assert_ne!(tree.root(), root);
+        assert_eq!(tree.delegations().len(), 1);
+        drop(tree);
+
+        let mut stored = TreeStore::new(db.clone(), &group_id, DEPTH);
+        assert_eq!(stored.root(), root);
+        assert!(stored.delegations().is_empty());
+
+        // The staged changes are applied on top of the stored tree
+        assert_eq!(stored.update(&members[..5]).unwrap(), vec![5, 6, 7, 8, 9]);
+        assert_eq!(stored.root(), full_root(&stored));
+        assert_eq!(stored.to_proto().layers[0].nodes.len(), 5);
+    }
+
+    #[test]
+    fn test_tree_store() {
+        let db = init_test_rocksdb("test_tree_store");
+        let mut tree = TreeStore::new(db.clone(), &hex::encode([1; 32]), DEPTH);
+
+        let members = (1..=10).map(|i| [i; 20]).collect::<Vec<Address>>();
+        assert_eq!(tree.update(&members).unwrap().len(), 10);
+        assert_eq!(tree.root(), full_root(&tree));
+
+        // Members are inserted in the given order
+        assert_eq!(tree.position(&[3; 20]), Some(2));
+
+        // Remove two members and add one
+        let mut new_members = members.clone();
+        new_members.retain(|member| *member != [3; 20] && *member != [7; 20]);
+        new_members.push([20; 20]);
+
+        let positions = tree.positions();
+        assert_eq!(tree.update(&new_members), Some(vec![2, 6]));
+        assert_eq!(tree.root(), full_root(&tree));
+
+        // The new member takes the first free position, and the others keep their positions
+        assert_eq!(tree.position(&[20; 20]), Some(2));
+        assert_eq!(tree.position(&[7; 20]), None);
+        for member in &new_members[..new_members.len() - 1] {
+            assert_eq!(tree.position(member), positions.get(member).copied());
+        }
+
+        // Nothing changes if the members are the same
+        assert_eq!(tree.update(&new_members), Some(vec![]));
+
+        // The proto has the addresses as the leaves, and the root as the last layer
+        let proto = tree.to_proto();
+        assert_eq!(proto.layers.len(), DEPTH + 1);
+        assert_eq!(proto.layers[0].nodes.len(), new_members.len());
+        assert_eq!(
+            proto.layers[DEPTH].nodes[0].node,
+            tree.root().into_bigint().to_bytes_be()
+        );
+
+        // The members must fit in the tree
+        let too_many_members = (1..=17).map(|i| [i; 20]).collect::<Vec<Address>>();
+        assert!(tree.update(&too_many_members).is_none());
+    }
+
+    #[test]
+    fn test_tree_store_balances() {
+        let db = init_test_rocksdb("test_tree_store_balances");
+        let mut tree = TreeStore::new(db.clone(), &hex::encode([1; 32]), DEPTH);
+
+        let members = (1..=10).map(|i| [i; 20]).collect::<Vec<Address>>();
+        let mut balances = members
+            .iter()
+            .map(|member| (*member, BigUint::from(member[0] as u32 * 100)))
+            .collect::<HashMap<Address, BigUint>>();
+
+        assert_eq!(tree.update_balances(&members, &balances).unwrap().len(), 10);
+
+        // The leaves commit to the addresses and the balances
+        let mut leaves = vec![Fq::ZERO; tree.capacity()];
+        for (address, index) in tree.positions() {
+            leaves[index as usize] = tree.balance_leaf(&address, &balances[&address]);
+        }
+
+        let mut full_tree = MerkleTree::<Fq, TREE_WIDTH>::new(secp256k1_w3());
+        for leaf in leaves {
+            full_tree.insert(leaf);
+        }
+        full_tree.finish();
+
+        assert_eq!(tree.root(), full_tree.root.unwrap());
+
+        // Only the leaf of the member whose balance changed is updated
+        balances.insert([3; 20], BigUint::from(1u32));
+        assert_eq!(tree.update_balances(&members, &balances), Some(vec![2]));
+        assert_eq!(tree.balances()[&[3; 20]], BigUint::from(1u32));
+        assert_eq!(tree.update_balances(&members, &balances), Some(vec![]));
+
+        // Balances that don't fit in the leaves are capped
+        balances.insert([4; 20], BigUint::from(1u32) << 200);
+        tree.update_balances(&members, &balances).unwrap();
+        assert_eq!(
+            tree.balances()[&[4; 20]].bits(),
+            MAX_LEAF_BALANCE_BITS as u64
+        );
+
+        // The proto has the addresses followed by the balances as the leaves
+        let proto = tree.to_proto();
+        let leaf = proto.layers[0]
+            .nodes
+            .iter()
+            .find(|node| node.index == 2)
+            .unwrap();
+        assert_eq!(leaf.node[..20], [3; 20]);
+        assert_eq!(
+            BigUint::from_bytes_be(&leaf.node[20..]),
+            BigUint::from(1u32)
+        );
+        assert_eq!(leaf.node.len(), 52);
+        assert_eq!(proto.leaf_format(), LeafFormat::Balance);
+
+        // The leaves are encoded as in the balance membership circuit
+        let circuit_leaf = to_fe(&balance_leaf_bytes(&[3; 20], &BigUint::from(1u32)).unwrap());
+        assert_eq!(tree.node(0, 2), circuit_leaf);
+        assert_eq!(
+            proto_leaf(LeafFormat::Balance, &leaf.node),
+            Some(circuit_leaf)
+        );
+        assert_eq!(proto_leaf(LeafFormat::Address, &leaf.node), None);
+
+        // The balance of a removed member is removed
+        tree.update_balances(&members[1..], &balances).unwrap();
+        assert!(!tree.balances().contains_key(&[1; 20]));
+    }
+
+    #[test]
+    fn test_tree_store_delegations() {
+        let db = init_test_rocksdb("test_tree_store_delegations");
+        let mut tree = TreeStore::new(db.clone(), &hex::encode([1; 32]), DEPTH);
+
+        let members = (1..=10).map(|i| [i; 20]).collect::<Vec<Address>>();
+        tree.update(&members).unwrap();
+
+        let safe = Delegation {
+            wallet: [100; 20],
+            kind: DelegationKind::SafeOwner,
+        };
+        let vault = Delegation {
+            wallet: [101; 20],
+            kind: DelegationKind::Delegate,
+        };
+
+        let root = tree.root();
+        let delegations = HashMap::from([([2; 20], safe), ([3; 20], vault)]);
+        tree.set_delegations(&delegations);
+
+        // The delegations are committed to in the last leaf, and the leaves of the members don't change
+        let delegations_index = delegations_leaf_index(DEPTH);
+        let commitment = delegations_leaf(&mut tree.hasher, &delegations);
+        assert_ne!(commitment, Fq::ZERO);
+        assert_eq!(tree.node(0, delegations_index), commitment);
+        assert_eq!(tree.node(0, 1), to_fe(&[2; 20]));
+        assert_ne!(tree.root(), root);
+
+        // The leaf of a delegated member has the wallet and the kind after the address
+        let proto = tree.to_proto();
+        let leaves = proto.layers[0]
+            .nodes
+            .iter()
+            .map(|node| (node.index, node.node.clone()))
+            .collect::<HashMap<LeafIndex, Vec<u8>>>();
+        assert_eq!(leaves[&1], [[2; 20].as_slice(), &[100; 20], &[0]].concat());
+        assert_eq!(leaves[&2], [[3; 20].as_slice(), &[101; 20], &[1]].concat());
+        assert_eq!(leaves[&0], [1; 20].to_vec());
+        assert_eq!(
+            leaves[&delegations_index],
+            commitment.into_bigint().to_bytes_be()
+        );
+        assert_eq!(proto.leaf_format(), LeafFormat::Delegation);
+
+        // The delegations are replaced
+        tree.set_delegations(&HashMap::from([([3; 20], safe)]));
+        assert_eq!(tree.delegations(), HashMap::from([([3; 20], safe)]));
+
+        // The delegation of a removed member is removed, and the tree is as without delegations
+        tree.update(&members[..2]).unwrap();
+        assert!(tree.delegations().is_empty());
+        assert_eq!(tree.node(0, delegations_index), Fq::ZERO);
+        assert_eq!(tree.root(), full_root(&tree));
+
+        // The members don't take the leaf of the commitment
+        let members = (1..=16).map(|i| [i; 20]).collect::<Vec<Address>>();
+        assert!(tree.update(&members).is_none());
+        tree.update(&members[..15]).unwrap();
+
+        // The proof of the member next to the commitment goes through the commitment
+        tree.set_delegations(&delegations);
+        let proto = tree.to_proto();
+        let member = tree
+            .positions()
+            .into_iter()
+            .find(|(_, index)| *index == delegations_index - 1)
+            .unwrap()
+            .0;
+        let proof = merkle_proof_from_proto(&proto, &member).unwrap();
+        assert_eq!(proof.siblings[0], commitment.into_bigint().to_bytes_be());
+        assert_eq!(proof_root(to_fe(&member), &proof), tree.root());
+    }
+
+    /// Compute the root from a leaf and its Merkle proof
+    fn proof_root(leaf: Fq, proof: &MerkleProof) -> Fq {
+        let mut hasher = MerkleTree::<Fq, TREE_WIDTH>::new(secp256k1_w3());
+
+        let mut node = leaf;
+        for (sibling, is_right) in proof.siblings.iter().zip(&proof.indices) {
+            let sibling = to_fe(sibling);
+            let children = if *is_right {
+                [sibling, node]
+            } else {
+                [node, sibling]
+            };
+            node = MerkleTree::hash(&mut hasher.poseidon, &children);
+        }
+
+        node
+    }
+
+    #[test]
+    fn test_merkle_proof_from_proto() {
+        let db = init_test_rocksdb("test_merkle_proof_from_proto");
+        let mut tree = TreeStore::new(db.clone(), &hex::encode([1; 32]), DEPTH);
+
+        // Leave some subtrees empty
+        let members = (1..=5).map(|i| [i; 20]).collect::<Vec<Address>>();
+        tree.update(&members).unwrap();
+        tree.set_delegations(&HashMap::from([(
+            [2; 20],
+            Delegation {
+                wallet: [100; 20],
+                kind: DelegationKind::Delegate,
+            },
+        )]));
+
+        let proto = tree.to_proto();
+
+        for member in &members {
+            let proof = merkle_proof_from_proto(&proto, member).unwrap();
+            assert_eq!(proof.siblings.len(), DEPTH);
+            assert!(proof.siblings.iter().all(|sibling| sibling.len() == 32));
+
+            let mut leaf = [0u8; 32];
+            leaf[12..].copy_from_slice(member);
+            assert_eq!(proof_root(to_fe(&leaf), &proof), tree.root());
+        }
+
+        assert!(merkle_proof_from_proto(&proto, &[6; 20]).is_none());
+
+        // The sibling leaves of a balance tree are the hashes of the addresses and the balances
+        let mut balance_tree = TreeStore::new(db.clone(), &hex::encode([2; 32]), DEPTH);
+        let balances = members
+            .iter()
+            .map(|member| (*member, BigUint::from(member[0] as u32 * 100)))
+            .collect::<HashMap<Address, BigUint>>();
+        balance_tree.update_balances(&members, &balances).unwrap();
+
+        let proto = balance_tree.to_proto();
+        let proof = merkle_proof_from_proto(&proto, &[1; 20]).unwrap();
+        let leaf = balance_tree.balance_leaf(&[1; 20], &balances[&[1; 20]]);
+        assert_eq!(proof_root(leaf, &proof), balance_tree.root());
+
+        // The leaf is returned as in the circuit, with the balance it commits to
+        assert_eq!(
+            leaf_from_proto(&proto, &[1; 20]),
+            Some((
+                leaf.into_bigint().to_bytes_be(),
+                Some(balances[&[1; 20]].clone())
+            ))
+        );
+        assert_eq!(
+            leaf_from_proto(&tree.to_proto(), &[1; 20]),
+            Some((to_fe(&[1; 20]).into_bigint().to_bytes_be(), None))
+        );
+        assert!(leaf_from_proto(&proto, &[6; 20]).is_none());
+    }
+
+    #[test]
+    fn test_tree_store_depth_change() {
+        let db = init_test_rocksdb("test_tree_store_depth_change");
+        let group_id = hex::encode([1; 32]);
+
+        let members = (1..=10).map(|i| [i; 20]).collect::<Vec<Address>>();
+        let mut tree = TreeStore::new(db.clone(), &group_id, DEPTH);
+        tree.update(&members).unwrap();
+        tree.commit();
+
+        // The tree is kept if the depth is the same
+        let tree = TreeStore::new(db.clone(), &group_id, DEPTH);
+        assert_eq!(tree.positions().len(), members.len());
+
+        // The tree is built from scratch if the depth changes
+        let mut tree = TreeStore::new(db.clone(), &group_id, DEPTH + 1);
+        assert!(tree.positions().is_empty());
+        assert_eq!(tree.root(), tree.empty_nodes[DEPTH + 1]);
+
+        // More members fit in the deeper tree
+        let members = (1..=20).map(|i| [i; 20]).collect::<Vec<Address>>();
+        assert_eq!(tree.update(&members).unwrap().len(), 20);
+        assert_eq!(tree.root(), full_root(&tree));
+        assert_eq!(tree.to_proto().layers.len(), DEPTH + 2);
+    }
+}
@@ -1,15 +1,24 @@
 use crate::{
     address_groups::AddressGroups,
-    eth_rpc::EthRpcClient,
+    delegate_registries::DelegateRegistries,
+    eth_rpc::{ChainBlocks, EthRpcClient},
     group::{update_group_state, Group},
     processors::GroupIndexer,
+    safe_owners::{to_delegations, SafeOwners},
+    shutdown::Shutdown,
     tree::{build_tree, get_group_latest_merkle_tree, save_tree, update_tree_block_num},
+    tree_archive::{ArchivedTree, RetentionPolicy},
+    tree_events::{TreeEvent, TreeEventEmitter},
+    tree_store::{Delegation, DelegationKind},
     utils::to_hex,
-    Address, BlockNum, Error, GroupState, IndexerError,
+    Address, Error, GroupState, IndexerError,
 };
 use log::{error, info};
 use rand::{rngs::OsRng, seq::SliceRandom};
-use std::{collections::HashSet, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
 use tokio::sync::Semaphore;
 
 const INDEXING_INTERVAL_SECS: u64 = 300; // 5 minutes
@@ -21,6 +30,14 @@ pub struct TreeSyncEngine {
     pub rocksdb_client: Arc<rocksdb::DB>,
     pub eth_client: Arc<EthRpcClient>,
     pub semaphore: Arc<Semaphore>,
+    /// The registries to add the delegates of the members from, if the group includes delegates
+    pub delegate_registries: Option<DelegateRegistries>,
+    /// How long the history of the trees of the group is kept
+    pub retention: RetentionPolicy,
+    /// Where the events about the tree of the group are sent
+    pub events: Arc<TreeEventEmitter>,
+    /// The latest archived tree of the group, which the next tree is diffed from
+    latest_archived_tree: Mutex<Option<ArchivedTree>>,
 }
 
 impl TreeSyncEngine {
@@ -39,8 +56,26 @@ impl TreeSyncEngine {
             rocksdb_client,
             eth_client,
             semaphore,
+            delegate_registries: None,
+            retention: RetentionPolicy::from_env(),
+            events: Arc::new(TreeEventEmitter::default()),
+            latest_archived_tree: Mutex::new(None),
         }
     }
+
+    /// Add the hot wallets the members delegate to in `delegate_registries` to the tree.
+    /// Only the registries on the chains of the group are used,
+    /// so the tree doesn't depend on the blocks of other chains.
+    pub fn with_delegate_registries(mut self, delegate_registries: DelegateRegistries) -> Self {
+        self.delegate_registries = Some(delegate_registries.on_chains(&self.indexer.chains()));
+        self
+    }
+
+    /// Send the events about the tree of the group to the sinks of `events`
+    pub fn with_events(mut self, events: Arc<TreeEventEmitter>) -> Self {
+        self.events = events;
+        self
+    }
 }
 
 impl TreeSyncEngine {
@@ -72,22 +107,126 @@ impl TreeSyncEngine {
         }
     }
 
-    /// Sync the tree to a specific block number
-    async fn sync_to_block(&self, block_number: BlockNum) -> Result<(), Error> {
-        // Get the members of the group at the given block number
-        let members = self.indexer.get_members(block_number).await?;
+    /// Get the wallet each owner of the Safes among the members is added to the tree on behalf of
+    async fn get_safe_owner_delegations(
+        &self,
+        members: &HashSet<Address>,
+        blocks: &ChainBlocks,
+    ) -> Result<HashMap<Address, Address>, Error> {
+        let chain = self.indexer.chain();
+        let safe_owners =
+            SafeOwners::new(self.rocksdb_client.clone(), self.eth_client.clone(), chain);
+
+        let wallets = members.iter().copied().collect::<Vec<Address>>();
+        let owners = safe_owners.get_owners_at(&wallets, blocks[&chain]).await?;
+
+        Ok(to_delegations(&owners, members))
+    }
+
+    /// Get the wallet each address that's added to the tree on behalf of a member is delegated by.
+    /// An address that's both a Safe owner and a delegate is added as the Safe owner.
+    async fn get_delegations(
+        &self,
+        members: &HashSet<Address>,
+        blocks: &ChainBlocks,
+    ) -> Result<HashMap<Address, Delegation>, Error> {
+        let mut delegations = HashMap::new();
+
+        if self.group.include_safe_owners {
+            for (owner, wallet) in self.get_safe_owner_delegations(members, blocks).await? {
+                let kind = DelegationKind::SafeOwner;
+                delegations.insert(owner, Delegation { wallet, kind });
+            }
+        }
+
+        if let Some(delegate_registries) = &self.delegate_registries {
+            let delegates = delegate_registries
+                .get_delegates_at(members, &self.group.contract_inputs, blocks)
+                .await?;
+
+            for (delegate, vault) in to_delegations(&delegates, members) {
+                let kind = DelegationKind::Delegate;
+                delegations.entry(delegate).or_insert(Delegation {
+                    wallet: vault,
+                    kind,
+                });
+            }
+        }
+
+        Ok(delegations)
+    }
+
+    /// Returns true if the logs the indexer and the delegate registries depend on are ready
+    async fn is_ready(&self) -> Result<bool, Error> {
+        if let Some(delegate_registries) = &self.delegate_registries {
+            if !delegate_registries.is_ready().await? {
+                return Ok(false);
+            }
+        }
+
+        self.indexer.is_ready().await
+    }
+
+    /// Get the latest block number of each chain the indexer depends on.
+    /// The delegate registries are only used on these chains.
+    async fn get_latest_blocks(&self) -> Result<ChainBlocks, Error> {
+        let mut blocks = ChainBlocks::new();
+        for chain in self.indexer.chains() {
+            blocks.insert(chain, self.eth_client.get_block_number(chain).await?);
+        }
+
+        Ok(blocks)
+    }
+
+    /// Sync the tree to the given block number of each chain.
+    /// The tree is recorded at the block number of the indexer's primary chain,
+    /// along with the block numbers of all chains it depends on.
+    async fn sync_to_blocks(&self, blocks: &ChainBlocks) -> Result<(), Error> {
+        let block_number = blocks[&self.indexer.chain()];
+
+        // Get the members of the group at the given block numbers
+        let members = self.indexer.get_members_at(blocks).await?;
+
+        // Get the balances of the members if the tree is a balance tree
+        let balances = self.indexer.get_member_balances_at(blocks).await?;
+
+        // Add the owners of the Safes among the members and the delegates of the members
+        // on behalf of their wallets.
+        // The leaves of balance trees commit to the balances of the members, so they can't be delegated.
+        let delegations = if balances.is_none() {
+            self.get_delegations(&members, blocks).await?
+        } else {
+            HashMap::new()
+        };
+
+        // Only the members themselves are sanity checked
+        let direct_members = members.iter().copied().collect::<Vec<Address>>();
 
         // Convert the members to a vector
-        let mut members = members.iter().copied().collect::<Vec<Address>>();
+        let mut members = members
+            .iter()
+            .chain(delegations.keys())
+            .copied()
+            .collect::<Vec<Address>>();
 
         // Build the merkle tree
-        let merkle_tree = build_tree(self.group.id.clone(), self.group.group_type, &mut members);
+        let merkle_tree = build_tree(
+            self.group.id.clone(),
+            self.group.group_type,
+            self.group.tree_depth,
+            &mut members,
+            balances.as_ref(),
+            self.rocksdb_client.clone(),
+        );
 
         if merkle_tree.is_none() {
             return Ok(());
         }
 
-        let merkle_tree = merkle_tree.unwrap();
+        // The changes to the stored tree are only committed after the tree is saved,
+        // so the next sync is compared to the last saved tree if anything below fails
+        let mut merkle_tree = merkle_tree.unwrap();
+        merkle_tree.set_delegations(&delegations);
 
         // Get the latest merkle root for the group
         let group_latest_merkle_tree =
@@ -97,18 +236,26 @@ impl TreeSyncEngine {
         // If they are the same, then the tree is already up to date
         // and we don't need to save the new tree.
         if let Some((tree_id, merkle_root)) = group_latest_merkle_tree {
-            if merkle_root == to_hex(merkle_tree.root.unwrap()) {
+            if merkle_root == to_hex(merkle_tree.root()) {
                 info!(
                     "${} Tree already up to date at block {}",
                     self.group.name, block_number
                 );
 
                 // Update the block number of the tree
-                update_tree_block_num(tree_id, block_number, &self.pg_client).await?;
+                update_tree_block_num(tree_id, block_number, blocks, &self.pg_client).await?;
+
+                merkle_tree.commit();
 
                 // Update the address -> group ids mapping
                 self.save_address_groups(&members);
 
+                self.events.emit(TreeEvent::TreeRefreshed {
+                    group_id: self.group.id.clone(),
+                    block_number,
+                    merkle_root,
+                });
+
                 return Ok(());
             }
         }
@@ -117,8 +264,10 @@ impl TreeSyncEngine {
 
         // Sanity check the eligibility of a few members
         let mut rng = OsRng::default();
-        let members_to_check: Vec<Address> =
-            members.choose_multiple(&mut rng, 5).cloned().collect();
+        let members_to_check: Vec<Address> = direct_members
+            .choose_multiple(&mut rng, 5)
+            .cloned()
+            .collect();
 
         let sanity_check_result = self
             .indexer
@@ -131,30 +280,57 @@ impl TreeSyncEngine {
                 self.group.name, block_number
             );
         } else {
-            save_tree(
+            // The lock isn't held while the tree is saved
+            let mut latest_archived_tree = self.latest_archived_tree.lock().unwrap().take();
+
+            let changes = save_tree(
                 &members,
-                merkle_tree,
+                &merkle_tree,
                 self.group.id.clone(),
                 &self.pg_client,
                 block_number as i64,
+                blocks,
+                &self.retention,
+                &mut latest_archived_tree,
             )
-            .await?;
+            .await;
+
+            *self.latest_archived_tree.lock().unwrap() = latest_archived_tree;
+            let changes = changes?;
+
+            merkle_tree.commit();
 
             // Update the address -> group ids mapping
             self.save_address_groups(&members);
+
+            // The address -> group ids mapping is up to date when the event is received
+            self.events.emit(TreeEvent::TreeSaved {
+                group_id: self.group.id.clone(),
+                block_number,
+                merkle_root: to_hex(merkle_tree.root()),
+                num_leaves: members.len(),
+                num_added: changes.added.len(),
+                num_removed: changes.removed.len(),
+            });
         }
 
         Ok(())
     }
 
-    /// Start the sync job
-    pub async fn sync(&self) {
-        loop {
-            let permit = self.semaphore.acquire().await;
+    /// Start the sync job, which runs until the shutdown is requested
+    pub async fn sync(&self, shutdown: &Shutdown) {
+        // The block numbers the tree was last synced to
+        let mut synced_blocks: Option<ChainBlocks> = None;
+
+        while !shutdown.is_requested() {
+            let permit = tokio::select! {
+                permit = self.semaphore.acquire() => permit,
+                _ = shutdown.requested() => break,
+            };
 
             if permit.is_err() {
                 error!("${} Semaphore acquire error: {:?}", self.group.name, permit);
-                tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+                shutdown.sleep(std::time::Duration::from_secs(5)).await;
                 continue;
             }
 
@@ -162,7 +338,7 @@ impl TreeSyncEngine {
 
             // Check if the indexer is ready or not
 
-            let is_ready = self.indexer.is_ready().await;
+            let is_ready = self.is_ready().await;
 
             if is_ready.is_err() {
                 drop(permit);
@@ -171,41 +347,56 @@ impl TreeSyncEngine {
                     self.group.name,
                     is_ready.err().unwrap()
                 );
-                tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+                shutdown.sleep(std::time::Duration::from_secs(5)).await;
                 continue;
             }
 
             if !is_ready.unwrap() {
                 drop(permit);
                 info!("${} Waiting for the indexer...", self.group.name);
-                tokio::time::sleep(std::time::Duration::from_secs(INDEXING_INTERVAL_SECS)).await;
+                shutdown
+                    .sleep(std::time::Duration::from_secs(INDEXING_INTERVAL_SECS))
+                    .await;
                 continue;
             }
 
-            // Get the latest block number
-            let latest_block = self.eth_client.get_block_number(self.indexer.chain()).await;
+            // Get the latest block number of each chain
+            let latest_blocks = self.get_latest_blocks().await;
 
-            if latest_block.is_err() {
+            if latest_blocks.is_err() {
                 drop(permit);
                 error!(
                     "${} get_block_number Error: {:?}",
                     self.group.name,
-                    latest_block.err().unwrap()
+                    latest_blocks.err().unwrap()
                 );
-                tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+                shutdown.sleep(std::time::Duration::from_secs(1)).await;
                 continue;
             }
 
-            let latest_block = latest_block.unwrap();
+            let latest_blocks = latest_blocks.unwrap();
+            let latest_block = latest_blocks[&self.indexer.chain()];
 
-            // Sync to the latest block
-            let sync_to_block_result = self.sync_to_block(latest_block).await;
+            // The members can only change if any of the chains has new blocks
+            if synced_blocks.as_ref() == Some(&latest_blocks) {
+                drop(permit);
+                info!("${} No new blocks since the last sync", self.group.name);
+                shutdown
+                    .sleep(std::time::Duration::from_secs(INDEXING_INTERVAL_SECS))
+                    .await;
+                continue;
+            }
+
+            // Sync to the latest blocks
+            let sync_to_block_result = self.sync_to_blocks(&latest_blocks).await;
 
             // If `get_members` return incorrect state error,
             // mark the group as unrecordable and break the loop
 
             match sync_to_block_result {
-                Ok(_) => {}
+                Ok(_) => {
+                    synced_blocks = Some(latest_blocks);
+                }
                 Err(Error::Indexer(IndexerError::InvalidBalance)) => {
                     error!("${} Invalid balance {:?}", self.group.name, latest_block);
                     drop(permit);
@@ -215,6 +406,11 @@ impl TreeSyncEngine {
                         .await
                         .unwrap();
 
+                    self.events.emit(TreeEvent::GroupUnrecordable {
+                        group_id: self.group.id.clone(),
+                        block_number: latest_block,
+                    });
+
                     break;
                 }
                 Err(err) => {
@@ -227,7 +423,9 @@ impl TreeSyncEngine {
 
             drop(permit);
 
-            tokio::time::sleep(std::time::Duration::from_secs(INDEXING_INTERVAL_SECS)).await;
+            shutdown
+                .sleep(std::time::Duration::from_secs(INDEXING_INTERVAL_SECS))
+                .await;
         }
     }
 }
@@ -4,6 +4,7 @@ use crate::{
     contract_event_iterator::ContractEventIterator,
     erc1155_transfer_event, erc20_transfer_event, erc721_transfer_event,
     eth_rpc::{Chain, EthRpcClient},
+    generic_event,
     log_sync_engine::CHUNK_SIZE,
     rocksdb_key::{KeyType, RocksDbKey},
     synched_chunks_iterator::SynchedChunksIterator,
@@ -224,6 +225,11 @@ pub fn decode_erc1155_transfer_batch_event(value: &[u8]) -> ERC1155TransferBatch
     }
 }
 
+/// Decode generic event protobuf bytes to GenericEvent
+pub fn decode_generic_event(value: &[u8]) -> generic_event::GenericEvent {
+    generic_event::GenericEvent::decode(&mut Cursor::new(&value)).unwrap()
+}
+
 /// Count the number of synched logs for a given contract event
 pub fn count_synched_logs(
     rocksdb_conn: &DB,
@@ -302,6 +308,12 @@ pub fn get_group_id(group_type: GroupType, contract_inputs: &[String]) -> String
         GroupType::FarcasterPowerUsers => {
             hasher.update(b"FarcasterPowerUsers");
         }
+        GroupType::BalanceHolder => {
+            hasher.update(b"BalanceHolder");
+        }
+        GroupType::Declarative => {
+            panic!("The ids of declarative groups are derived from their spec");
+        }
         _ => {
             panic!("Unsupported group type {:?}", group_type);
         }
@@ -0,0 +1,71 @@
+import logger from '@/lib/backend/logger';
+import { CREDDD_RPC_URL } from '@/lib/backend/credddRpc';
+import { getClientIp, takeRateLimit } from '@/lib/backend/rateLimit';
+import { NextRequest } from 'next/server';
+
+// The methods the browser calls, and the calls per minute of each client.
+// The API key of the portal should only be allowed these methods and the ones the backend calls
+// (`getGroupByMerkleRoot`), so the indexer rejects the other methods even if the key leaks.
+const PROXIED_METHODS = new Map<string, number>([
+  ['getAddressGroups', 60],
+  ['getCreddd', 60],
+  // The Merkle trees are multiple MB, and a client only needs them once per proof
+  ['getGroupLatestMerkleTree', 10],
+  ['getLatestMerkleTrees', 5],
+]);
+
+/**
+ * Forward a JSON-RPC call from the browser to the indexer with the API key
+ */
+export async function POST(req: NextRequest) {
+  const body = await req.json();
+
+  const rateLimitPerMinute =
+    typeof body?.method === 'string'
+      ? PROXIED_METHODS.get(body.method)
+      : undefined;
+
+  if (rateLimitPerMinute === undefined) {
+    return Response.json(
+      {
+        jsonrpc: '2.0',
+        error: { code: -32601, message: 'Method not found' },
+        id: body?.id ?? null,
+      },
+      { status: 403 }
+    );
+  }
+
+  // Limit the calls of each client, since the indexer sees all of them as the portal's API key
+  const clientIp = getClientIp(req);
+  if (!takeRateLimit(`${clientIp}:${body.method}`, rateLimitPerMinute)) {
+    return Response.json(
+      {
+        jsonrpc: '2.0',
+        error: { code: -32005, message: 'Rate limit exceeded' },
+        id: body.id ?? null,
+      },
+      { status: 429 }
+    );
+  }
+
+  const response = await fetch(CREDDD_RPC_URL, {
+    method: 'POST',
+    headers: {
+      'Content-Type': 'application/json',
+      'X-Api-Key': process.env.CREDDD_API_KEY ?? '',
+    },
+    body: JSON.stringify(body),
+  });
+
+  if (!response.ok) {
+    logger.error(`Indexer responded with ${response.status} to ${body.method}`);
+  }
+
+  return new Response(response.body, {
+    status: response.status,
+    headers: {
+      'Content-Type': 'application/json',
+    },
+  });
+}
@@ -18,7 +18,7 @@ import {
 import * as circuit from 'circuit-node/circuits_embedded';
 import { addUserCreddd } from '@/lib/backend/userCreddd';
 import privy from '@/lib/backend/privy';
-import credddRpcClient from '@/lib/credddRpc';
+import credddRpcClient from '@/lib/backend/credddRpc';
 import { addWriterToRoom } from '@cred/firebase-admin';
 import { FARCASTER_1_ROOM_CREDDD, FARCASTER_1_ROOM_ID } from '@cred/shared';
 
@@ -13,7 +13,7 @@ import { addWriterToRoom, app } from '@cred/firebase-admin';
 import privy, { isAuthenticated } from '@/lib/backend/privy';
 import * as neynar from '@/lib/backend/neynar';
 import { addUserConnectedAddress } from '@/lib/backend/connectedAddress';
-import credddRpcClient from '@/lib/credddRpc';
+import credddRpcClient from '@/lib/backend/credddRpc';
 import { SignInResponse } from '@/types';
 
 const db = getFirestore(app);
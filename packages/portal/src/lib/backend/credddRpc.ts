@@ -0,0 +1,9 @@
+import { createRpcClient } from '@cred/shared';
+
+export const CREDDD_RPC_URL = 'https://cred-indexer-41hd.onrender.com';
+//export const CREDDD_RPC_URL = 'http://0.0.0.0:3030';
+
+// The API key is only used on the server, so it's never sent to the browser
+const rpcClient = createRpcClient(CREDDD_RPC_URL, process.env.CREDDD_API_KEY);
+
+export default rpcClient;
@@ -0,0 +1,52 @@
+import { NextRequest } from 'next/server';
+
+const WINDOW_MS = 60 * 1000;
+
+// Calls of each client in the current window, by the key the limit is counted by
+const windows = new Map<string, { start: number; count: number }>();
+
+let lastSweep = 0;
+
+/**
+ * Returns the IP address of the client the request is from
+ */
+export const getClientIp = (req: NextRequest): string =>
+  req.ip ??
+  req.headers.get('x-forwarded-for')?.split(',')[0].trim() ??
+  'unknown';
+
+/**
+ * Count a call of the client with `key` and return whether it's within `limitPerMinute`.
+ * The counts are kept in the memory of the instance,
+ * so each instance of the portal limits the calls it serves.
+ */
+export const takeRateLimit = (
+  key: string,
+  limitPerMinute: number
+): boolean => {
+  const now = Date.now();
+
+  // Drop the ended windows once a minute, so the map doesn't grow with every client
+  if (now - lastSweep >= WINDOW_MS) {
+    for (const [clientKey, clientWindow] of windows) {
+      if (now - clientWindow.start >= WINDOW_MS) {
+        windows.delete(clientKey);
+      }
+    }
+    lastSweep = now;
+  }
+
+  let clientWindow = windows.get(key);
+  if (!clientWindow || now - clientWindow.start >= WINDOW_MS) {
+    clientWindow = { start: now, count: 0 };
+  }
+
+  if (clientWindow.count >= limitPerMinute) {
+    return false;
+  }
+
+  clientWindow.count += 1;
+  windows.set(key, clientWindow);
+
+  return true;
+};
@@ -1,6 +1,6 @@
 import { createRpcClient } from '@cred/shared';
 
-const rpcClient = createRpcClient('https://cred-indexer-41hd.onrender.com');
-//const rpcClient = createRpcClient('http://0.0.0.0:3030');
+// The calls are proxied to the indexer by `/api/creddd-rpc`, which adds the API key
+const rpcClient = createRpcClient('/api/creddd-rpc');
 
 export default rpcClient;
@@ -3,17 +3,30 @@ import {
   GetCredddReturnType,
   GetLatestMerkleTreesReturnType,
   GetGroupMerkleTreeReturnType,
+  GetGroupChangesReturnType,
+  GetMembershipAtReturnType,
+  GetMerkleProofReturnType,
   Group,
+  GroupsFilter,
+  VerifyMembershipProofReturnType,
 } from './types';
 
-export const createRpcClient = (rpcUrl: string) => new JsonRpcClient(rpcUrl);
+/**
+ * `apiKey` is sent in the `X-Api-Key` header.
+ * It's required to call the methods that return Merkle trees, proofs and group changes,
+ * and the calls without it are rate limited by IP address.
+ * Only use it on servers, since it can't be kept secret in the browser.
+ */
+export const createRpcClient = (rpcUrl: string, apiKey?: string) =>
+  new JsonRpcClient(rpcUrl, apiKey);
 
 class JsonRpcClient {
   private axiosInstance: AxiosInstance;
 
-  constructor(rpcUrl: string) {
+  constructor(rpcUrl: string, apiKey?: string) {
     this.axiosInstance = axios.create({
       baseURL: rpcUrl,
+      headers: apiKey ? { 'X-Api-Key': apiKey } : {},
     });
   }
 
@@ -53,6 +66,16 @@ class JsonRpcClient {
     return await this.call<Group[]>('getAddressGroups', [address]);
   }
 
+  /**
+   * Returns the groups of each address, in the order of the addresses.
+   * At most 1000 addresses can be passed at once
+   */
+  public async getAddressesGroups(
+    addresses: `0x${string}`[]
+  ): Promise<Group[][]> {
+    return await this.call<Group[][]>('getAddressesGroups', [addresses]);
+  }
+
   public async getGroupByMerkleRoot(merkleRoot: `0x${string}`): Promise<Group> {
     return await this.call<Group>('getGroupByMerkleRoot', [merkleRoot]);
   }
@@ -78,8 +101,25 @@ class JsonRpcClient {
     return await this.call<GetCredddReturnType>('getCreddd', [credddId]);
   }
 
-  public async getGroups(): Promise<Group[]> {
-    return await this.call<Group[]>('getGroups', []);
+  /**
+   * Returns the groups in ascending order of id, optionally filtered.
+   * To get the next page, pass the id of the last group of a page as `cursor`
+   */
+  public async getGroups(filter?: GroupsFilter): Promise<Group[]> {
+    if (!filter) {
+      return await this.call<Group[]>('getGroups', []);
+    }
+
+    return await this.call<Group[]>('getGroups', [
+      {
+        cursor: filter.cursor,
+        limit: filter.limit,
+        type_id: filter.typeId,
+        chain: filter.chain,
+        contract: filter.contract,
+        min_score: filter.minScore,
+      },
+    ]);
   }
 
   public async getLatestMerkleTrees(): Promise<GetLatestMerkleTreesReturnType> {
@@ -92,4 +132,93 @@ class JsonRpcClient {
   public async getGroupLatestMerkleTree(groupId: string) {
     return await this.call<ArrayBuffer>('getGroupLatestMerkleTree', [groupId]);
   }
+
+  /**
+   * Returns the Merkle proof of an address in the latest tree of a group,
+   * or in the tree that was the latest at `blockNumber`, as in `getMembershipAt`
+   */
+  public async getMerkleProof({
+    groupId,
+    address,
+    blockNumber,
+  }: {
+    groupId: string;
+    address: `0x${string}`;
+    blockNumber?: number;
+  }): Promise<GetMerkleProofReturnType> {
+    const params: any[] = [groupId, address];
+    if (blockNumber !== undefined) {
+      params.push(blockNumber);
+    }
+
+    return await this.call<GetMerkleProofReturnType>('getMerkleProof', params);
+  }
+
+  /**
+   * Get the members added to and removed from a group in the trees after `sinceBlock`.
+   * At most 10000 changes are returned at once, so pass the `since_block` and the `cursor`
+   * of `next_page` to get the rest
+   */
+  public async getGroupChanges({
+    groupId,
+    sinceBlock,
+    cursor,
+  }: {
+    groupId: string;
+    sinceBlock: number;
+    cursor?: `0x${string}`;
+  }): Promise<GetGroupChangesReturnType> {
+    const params: any[] = [groupId, sinceBlock];
+    if (cursor !== undefined) {
+      params.push(cursor);
+    }
+
+    return await this.call<GetGroupChangesReturnType>('getGroupChanges', params);
+  }
+
+  /**
+   * Get whether `address` was a member of a group at `blockNumber`
+   */
+  public async getMembershipAt({
+    groupId,
+    address,
+    blockNumber,
+  }: {
+    groupId: string;
+    address: `0x${string}`;
+    blockNumber: number;
+  }): Promise<GetMembershipAtReturnType> {
+    return await this.call<GetMembershipAtReturnType>('getMembershipAt', [
+      groupId,
+      address,
+      blockNumber,
+    ]);
+  }
+
+  /**
+   * Verifies a membership proof and resolves its Merkle root to a group
+   */
+  public async verifyMembershipProof(
+    proof: `0x${string}`
+  ): Promise<VerifyMembershipProofReturnType> {
+    return await this.call<VerifyMembershipProofReturnType>(
+      'verifyMembershipProof',
+      [proof]
+    );
+  }
+
+  /**
+   * Verifies a membership proof and records its nullifier.
+   * `msgHash` is the hash of the message the application asked to sign, which is the scope.
+   * Throws if the proof is for another scope or the nullifier was already used in the scope.
+   */
+  public async registerNullifier(
+    proof: `0x${string}`,
+    msgHash: `0x${string}`
+  ): Promise<VerifyMembershipProofReturnType> {
+    return await this.call<VerifyMembershipProofReturnType>(
+      'registerNullifier',
+      [proof, msgHash]
+    );
+  }
 }
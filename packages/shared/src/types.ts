@@ -22,3 +22,74 @@ export interface MerkleTreeWithBloomFilter {
 }
 
 export type GetLatestMerkleTreesReturnType = MerkleTreeWithBloomFilter[];
+
+export interface VerifyMembershipProofReturnType {
+  is_valid: boolean;
+  error: string | null;
+  merkle_root: string | null;
+  msg_hash: string | null;
+  tree_depth: number | null;
+  scope: string | null;
+  nullifier: string | null;
+  group: GetCredddReturnType | null;
+  block_number: number | null;
+}
+
+export interface GetMerkleProofReturnType {
+  tree_id: number;
+  merkle_root: string;
+  block_number: number;
+  tree_depth: number;
+  // Hex encoded 32-byte siblings, concatenated as the prover's `merkle_siblings`
+  merkle_siblings: `0x${string}`;
+  // Hex encoded 32-byte indices (1 for the right child), concatenated as the prover's `merkle_indices`
+  merkle_indices: `0x${string}`;
+  // Hex encoded leaf of the address as computed in the circuit
+  leaf: `0x${string}`;
+  // Hex encoded balance the leaf commits to in a balance tree
+  balance: `0x${string}` | null;
+}
+
+export interface GroupsFilter {
+  // Id of the last group of the previous page
+  cursor?: string;
+  // Maximum number of groups to return (at most 1000). All groups are returned if not set
+  limit?: number;
+  typeId?: string;
+  // Chain of the group's contracts (e.g. "mainnet", "base")
+  chain?: string;
+  // Address of one of the group's contracts
+  contract?: `0x${string}`;
+  minScore?: number;
+}
+
+export interface GroupChange {
+  // Block number of the tree the members were added or removed in
+  block_number: number;
+  added: `0x${string}`[];
+  removed: `0x${string}`[];
+}
+
+export interface GetGroupChangesReturnType {
+  changes: GroupChange[];
+  // The `sinceBlock` and `cursor` of the next page, or null if there are no more changes
+  next_page: {
+    since_block: number;
+    cursor: `0x${string}`;
+  } | null;
+}
+
+// Sent to the `subscribe(["groupRoots"])` WebSocket subscribers when a group has a new tree.
+// `subscribe(["addressGroups", address])` subscribers are sent the `Group[]` of the address.
+export interface GroupRoot {
+  group_id: string;
+  merkle_root: string;
+  block_number: number;
+}
+
+export interface GetMembershipAtReturnType {
+  is_member: boolean;
+  // Block number the tree the membership is of was built at
+  block_number: number;
+  merkle_root: string;
+}